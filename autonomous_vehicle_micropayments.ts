@@ -2,8 +2,32 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { AutonomousVehiclePayments } from "../target/types/autonomous_vehicle_payments";
 import { expect } from "chai";
-import { PublicKey, SystemProgram, Keypair, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import {
+  PublicKey,
+  SystemProgram,
+  Keypair,
+  LAMPORTS_PER_SOL,
+  Transaction,
+  sendAndConfirmTransaction,
+  Ed25519Program,
+} from "@solana/web3.js";
+import {
+  createAccount,
+  createInitializeMintInstruction,
+  createInitializePermanentDelegateInstruction,
+  createInitializeTransferFeeConfigInstruction,
+  createMint,
+  ExtensionType,
+  getAccount,
+  getMint,
+  getMintLen,
+  getTransferFeeConfig,
+  mintTo,
+  TOKEN_2022_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+} from "@solana/spl-token";
 import { BN } from "@coral-xyz/anchor";
+import { createHash } from "crypto";
 
 describe("autonomous_vehicle_payments", () => {
   const provider = anchor.AnchorProvider.env();
@@ -16,14 +40,159 @@ describe("autonomous_vehicle_payments", () => {
   let customer: Keypair;
   let vehicleOperator: Keypair;
   let configPDA: PublicKey;
+  let treasuryPDA: PublicKey;
   let vehiclePDA: PublicKey;
   let deliveryPDA: PublicKey;
   let escrowPDA: PublicKey;
+  let regionPDA: PublicKey;
+  let operatorProfilePDA: PublicKey;
+  let usdFeedPDA: PublicKey;
 
   const vehicleId = "AV-001";
   const deliveryId = new BN(12345);
   const paymentAmount = new BN(1 * LAMPORTS_PER_SOL);
   const feeBps = 250;
+  const regionId = 1;
+
+  const findDeliveryPDAs = (customerKey: PublicKey, id: BN): [PublicKey, PublicKey] => {
+    const [delivery] = PublicKey.findProgramAddressSync(
+      [Buffer.from("delivery"), customerKey.toBuffer(), id.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [escrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), customerKey.toBuffer(), id.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    return [delivery, escrow];
+  };
+
+  const findDailyStatsPDA = (): PublicKey => {
+    const day = Math.floor(Date.now() / 1000 / 86400);
+    const dayBuffer = Buffer.alloc(4);
+    dayBuffer.writeUInt32LE(day);
+    const [dailyStats] = PublicKey.findProgramAddressSync(
+      [Buffer.from("daily"), dayBuffer],
+      program.programId
+    );
+    return dailyStats;
+  };
+
+  // Orders spread over 16 GlobalStats shards by id
+  const findGlobalStatsPDA = (id: BN): PublicKey =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("global_stats"), Buffer.from([id.modn(16)])],
+      program.programId
+    )[0];
+
+  const findCustomerPDA = (wallet: PublicKey): PublicKey =>
+    PublicKey.findProgramAddressSync([Buffer.from("customer"), wallet.toBuffer()], program.programId)[0];
+
+  const bookOrder = async (orderCustomer: Keypair, id: BN, amount: BN = paymentAmount) => {
+    const [delivery, escrow] = findDeliveryPDAs(orderCustomer.publicKey, id);
+    await program.methods
+      .createDeliveryOrder(id, amount, "40.7128,-74.0060", "40.7589,-73.9851", regionId, 0, null, null, null)
+      .accountsPartial({
+        delivery,
+        escrow,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(id),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: orderCustomer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([orderCustomer])
+      .rpc();
+  };
+
+  // Takes a booked order through acceptance, pickup, drop-off and the
+  // customer's confirmation, ready to settle
+  const deliverOrder = async (orderCustomer: Keypair, id: BN, vehicle: PublicKey, operator: Keypair) => {
+    const [delivery] = findDeliveryPDAs(orderCustomer.publicKey, id);
+    await program.methods
+      .acceptDelivery(id)
+      .accountsPartial({
+        delivery,
+        vehicle,
+        geoBucket: null,
+        openOrders: null,
+        fleet: null,
+        region: regionPDA,
+        config: configPDA,
+        dispatcherKey: null,
+        operator: operator.publicKey,
+      })
+      .signers([operator])
+      .rpc();
+    await program.methods
+      .confirmPickup(id, Array(32).fill(0))
+      .accountsPartial({ delivery, vehicle, dispatcherKey: null, signer: operator.publicKey })
+      .signers([operator])
+      .rpc();
+    await program.methods
+      .markDelivered(id)
+      .accountsPartial({ delivery, vehicle, operator: operator.publicKey })
+      .signers([operator])
+      .rpc();
+    await program.methods
+      .confirmDelivery(id)
+      .accountsPartial({ delivery, customer: orderCustomer.publicKey })
+      .signers([orderCustomer])
+      .rpc();
+  };
+
+  // Channel voucher bytes: channel || nonce || cumulative amount, signed
+  // off-chain by the customer
+  const voucher = (channel: PublicKey, nonce: BN, cumulative: BN) =>
+    Buffer.concat([channel.toBuffer(), nonce.toArrayLike(Buffer, "le", 8), cumulative.toArrayLike(Buffer, "le", 8)]);
+  const signed = (signer: Keypair, message: Buffer) =>
+    Ed25519Program.createInstructionWithPrivateKey({ privateKey: signer.secretKey, message });
+  const expectVoucherRejected = async (settle: Promise<string>, reason = "InvalidVoucher") => {
+    try {
+      await settle;
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include(reason);
+    }
+  };
+
+  // A vehicle of its own, with a funded operator and an operator profile
+  const registerOperatedVehicle = async (id: string): Promise<{ vehicle: PublicKey; operator: Keypair; profile: PublicKey }> => {
+    const operator = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(operator.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const [vehicle] = PublicKey.findProgramAddressSync([Buffer.from("vehicle"), Buffer.from(id)], program.programId);
+    const [profile] = PublicKey.findProgramAddressSync(
+      [Buffer.from("operator"), operator.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .registerVehicle(id, operator.publicKey, { latE6: 40_712_800, lngE6: -74_006_000 }, { road: {} })
+      .accountsPartial({
+        vehicle,
+        config: configPDA,
+        ownershipMint: null,
+        ownershipToken: null,
+        stake: null,
+        staker: null,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .initializeOperatorProfile()
+      .accountsPartial({ operatorProfile: profile, operator: operator.publicKey, systemProgram: SystemProgram.programId })
+      .signers([operator])
+      .rpc();
+    return { vehicle, operator, profile };
+  };
 
   before(async () => {
     authority = Keypair.generate();
@@ -43,7 +212,12 @@ describe("autonomous_vehicle_payments", () => {
     );
 
     [configPDA] = PublicKey.findProgramAddressSync(
-      [Buffer.from("config"), authority.publicKey.toBuffer()],
+      [Buffer.from("config")],
+      program.programId
+    );
+
+    [treasuryPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("treasury"), configPDA.toBuffer()],
       program.programId
     );
 
@@ -69,34 +243,258 @@ describe("autonomous_vehicle_payments", () => {
       ],
       program.programId
     );
+
+    [operatorProfilePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("operator"), vehicleOperator.publicKey.toBuffer()],
+      program.programId
+    );
+
+    [regionPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("region"), new BN(regionId).toArrayLike(Buffer, "le", 2)],
+      program.programId
+    );
+
+    [usdFeedPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("price_feed"), Buffer.from("USD")],
+      program.programId
+    );
   });
 
   it("Initialize Config", async () => {
+    const [programDataPDA] = PublicKey.findProgramAddressSync(
+      [program.programId.toBuffer()],
+      new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    );
+
+    // Only the program's upgrade authority can claim the config
+    try {
+      await program.methods
+        .initializeConfig(feeBps, treasury.publicKey, 50, 24)
+        .accountsPartial({
+          config: configPDA,
+          authority: authority.publicKey,
+          program: program.programId,
+          programData: programDataPDA,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("Unauthorized");
+    }
+
+    // The deployer initializes, then hands the authority to the admin key
     await program.methods
-      .initializeConfig(feeBps, treasury.publicKey)
+      .initializeConfig(feeBps, treasury.publicKey, 50, 24)
       .accountsPartial({
         config: configPDA,
-        authority: authority.publicKey,
+        authority: provider.wallet.publicKey,
+        program: program.programId,
+        programData: programDataPDA,
         systemProgram: SystemProgram.programId,
       })
+      .rpc();
+    await program.methods
+      .proposeAuthority(authority.publicKey)
+      .accountsPartial({
+        config: configPDA,
+        authority: provider.wallet.publicKey,
+      })
+      .rpc();
+    await program.methods
+      .acceptAuthority()
+      .accountsPartial({
+        config: configPDA,
+        newAuthority: authority.publicKey,
+      })
       .signers([authority])
       .rpc();
 
     const config = await program.account.config.fetch(configPDA);
+    expect(config.authority.toString()).to.equal(authority.publicKey.toString());
     expect(config.isActive).to.be.true;
     expect(config.isPaused).to.be.false;
     expect(Number(config.feeBps)).to.equal(feeBps);
     expect(config.treasury.toString()).to.equal(treasury.publicKey.toString());
+
+    const [feeHistoryPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("fee_history"), configPDA.toBuffer()],
+      program.programId
+    );
+    const feeHistory = await program.account.feeHistory.fetch(feeHistoryPDA);
+    expect(feeHistory.entries.length).to.equal(1);
+    expect(feeHistory.entries[0].newBps).to.equal(feeBps);
+  });
+
+  it("Rejects fee changes beyond the per-window step", async () => {
+    try {
+      await program.methods
+        .queueConfigChange({ feeAndTreasury: { feeBps: feeBps + 51, treasury: treasury.publicKey, delayHours: 48 } })
+        .accountsPartial({
+          config: configPDA,
+          proposal: null,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("FeeChangeTooLarge");
+    }
+
+    const config = await program.account.config.fetch(configPDA);
+    expect(Number(config.feeBps)).to.equal(feeBps);
+  });
+
+  it("Rejects fees above the platform cap", async () => {
+    try {
+      await program.methods
+        .queueConfigChange({ feeAndTreasury: { feeBps: 1001, treasury: treasury.publicKey, delayHours: 48 } })
+        .accountsPartial({
+          config: configPDA,
+          proposal: null,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("FeeTooHigh");
+    }
+  });
+
+  it("Refuses a config change delay below the minimum", async () => {
+    try {
+      await program.methods
+        .queueConfigChange({ feeAndTreasury: { feeBps, treasury: treasury.publicKey, delayHours: 0 } })
+        .accountsPartial({
+          config: configPDA,
+          proposal: null,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("InvalidParameter");
+    }
+  });
+
+  it("Holds class fee tiers to the same per-window step", async () => {
+    try {
+      await program.methods
+        .queueConfigChange({ classFee: { class: { drone: {} }, feeBps: feeBps + 51 } })
+        .accountsPartial({
+          config: configPDA,
+          proposal: null,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("FeeChangeTooLarge");
+    }
+
+    const config = await program.account.config.fetch(configPDA);
+    expect(config.pendingConfigChange).to.be.null;
+  });
+
+  it("Refuses fee recipient lists with gaps or duplicates", async () => {
+    const levy = Keypair.generate().publicKey;
+    const empty = { recipient: PublicKey.default, bps: 0 };
+    for (const recipients of [
+      [empty, { recipient: levy, bps: 500 }, empty, empty, empty],
+      [{ recipient: levy, bps: 500 }, { recipient: levy, bps: 500 }, empty, empty, empty],
+      [{ recipient: levy, bps: 10001 }, empty, empty, empty, empty],
+    ]) {
+      try {
+        await program.methods
+          .queueConfigChange({ feeRecipients: { recipients } })
+          .accountsPartial({
+            config: configPDA,
+            proposal: null,
+            authority: authority.publicKey,
+          })
+          .signers([authority])
+          .rpc();
+        expect.fail("Should have failed");
+      } catch (error) {
+        expect(error.message).to.include("InvalidParameter");
+      }
+    }
+  });
+
+  it("Only distributes a fee share to the wallet configured for the slot", async () => {
+    try {
+      await program.methods
+        .distributeFeeShare(0)
+        .accountsPartial({
+          treasury: treasuryPDA,
+          config: configPDA,
+          recipient: Keypair.generate().publicKey,
+        })
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("FeeRecipientsRequired");
+    }
+
+    const ledger = await program.account.treasury.fetch(treasuryPDA);
+    expect(ledger.recipientOwed.every((owed) => Number(owed) === 0)).to.be.true;
+  });
+
+  it("Holds queued fee changes until the timelock passes", async () => {
+    await program.methods
+      .queueConfigChange({ feeAndTreasury: { feeBps: feeBps + 10, treasury: treasury.publicKey, delayHours: 48 } })
+      .accountsPartial({
+        config: configPDA,
+        proposal: null,
+        authority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    let config = await program.account.config.fetch(configPDA);
+    expect(config.pendingConfigChange.change.feeAndTreasury.feeBps).to.equal(feeBps + 10);
+
+    try {
+      await program.methods
+        .executeConfigChange()
+        .accountsPartial({ config: configPDA })
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("ConfigChangeTimelockActive");
+    }
+
+    await program.methods
+      .cancelConfigChange()
+      .accountsPartial({
+        config: configPDA,
+        authority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    config = await program.account.config.fetch(configPDA);
+    expect(config.pendingConfigChange).to.be.null;
+    expect(Number(config.feeBps)).to.equal(feeBps);
   });
 
   it("Register Vehicle", async () => {
-    const location = "40.7128,-74.0060";
+    const position = { latE6: 40_712_800, lngE6: -74_006_000 };
 
     await program.methods
-      .registerVehicle(vehicleId, vehicleOperator.publicKey, location)
+      .registerVehicle(vehicleId, vehicleOperator.publicKey, position, { road: {} })
       .accountsPartial({
         vehicle: vehiclePDA,
         config: configPDA,
+        ownershipMint: null,
+        ownershipToken: null,
+        stake: null,
+        staker: null,
         authority: authority.publicKey,
         systemProgram: SystemProgram.programId,
       })
@@ -106,12 +504,91 @@ describe("autonomous_vehicle_payments", () => {
     const vehicle = await program.account.vehicle.fetch(vehiclePDA);
     expect(vehicle.vehicleId).to.equal(vehicleId);
     expect(vehicle.operator.toString()).to.equal(vehicleOperator.publicKey.toString());
-    expect(vehicle.location).to.equal(location);
+    expect(vehicle.ownershipMint).to.be.null;
+    expect(vehicle.position).to.deep.equal(position);
+    expect(vehicle.location).to.equal("40.712800,-74.006000");
     expect(vehicle.isActive).to.be.true;
     expect(vehicle.isBusy).to.be.false;
     expect(Number(vehicle.totalDeliveries)).to.equal(0);
   });
 
+  it("Initialize Operator Profile", async () => {
+    await program.methods
+      .initializeOperatorProfile()
+      .accountsPartial({
+        operatorProfile: operatorProfilePDA,
+        operator: vehicleOperator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+
+    const profile = await program.account.operatorProfile.fetch(operatorProfilePDA);
+    expect(profile.operator.toString()).to.equal(vehicleOperator.publicKey.toString());
+    expect(Number(profile.completedJobs)).to.equal(0);
+  });
+
+  it("Initialize Region", async () => {
+    await program.methods
+      .initializeRegion(regionId)
+      .accountsPartial({
+        region: regionPDA,
+        config: configPDA,
+        role: null,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    const region = await program.account.region.fetch(regionPDA);
+    expect(region.regionId).to.equal(regionId);
+    expect(region.queue).to.be.empty;
+  });
+
+  it("Publishes a USD price feed", async () => {
+    const usd = Array.from(Buffer.from("USD"));
+
+    await program.methods
+      .initializePriceFeed(usd, authority.publicKey)
+      .accountsPartial({
+        priceFeed: usdFeedPDA,
+        config: configPDA,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    await program.methods
+      .updatePriceFeed(usd, new BN(150_000_000))
+      .accountsPartial({
+        priceFeed: usdFeedPDA,
+        oracle: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    const feed = await program.account.priceFeed.fetch(usdFeedPDA);
+    expect(feed.microsPerSol.toNumber()).to.equal(150_000_000);
+
+    // The authority's override is only for a stalled oracle
+    try {
+      await program.methods
+        .overridePriceFeed(usd, new BN(1))
+        .accountsPartial({
+          priceFeed: usdFeedPDA,
+          config: configPDA,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("PriceFeedLive");
+    }
+  });
+
   it("Create Delivery Order", async () => {
     const pickupLocation = "40.7128,-74.0060";
     const deliveryLocation = "40.7589,-73.9851";
@@ -123,11 +600,24 @@ describe("autonomous_vehicle_payments", () => {
         deliveryId,
         paymentAmount,
         pickupLocation,
-        deliveryLocation
+        deliveryLocation,
+        regionId,
+        0,
+        null,
+        null,
+        null
       )
       .accountsPartial({
         delivery: deliveryPDA,
         escrow: escrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(deliveryId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
         config: configPDA,
         customer: customer.publicKey,
         systemProgram: SystemProgram.programId,
@@ -143,14 +633,26 @@ describe("autonomous_vehicle_payments", () => {
     expect(delivery.deliveryLocation).to.equal(deliveryLocation);
     expect(delivery.status).to.deep.equal({ pending: {} });
     expect(delivery.assignedVehicle).to.be.null;
+    expect(delivery.quoteUsdMicros.toNumber()).to.equal(150_000_000);
+    expect(delivery.quoteEurMicros.toNumber()).to.equal(0);
 
     const customerAfter = await provider.connection.getBalance(customer.publicKey);
     const maxTxFee = 20000;
     expect(customerBefore - customerAfter).to.be.greaterThanOrEqual(Number(paymentAmount));
     expect(customerBefore - customerAfter).to.be.lessThanOrEqual(Number(paymentAmount) + maxTxFee);
 
-    const escrowBalance = await provider.connection.getBalance(escrowPDA);
-    expect(escrowBalance).to.equal(Number(paymentAmount));
+    const escrow = await program.account.escrow.fetch(escrowPDA);
+    expect(Number(escrow.amount)).to.equal(Number(paymentAmount));
+    expect(escrow.delivery.toString()).to.equal(deliveryPDA.toString());
+    expect(escrow.state).to.deep.equal({ funded: {} });
+
+    const [customerProfilePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("customer"), customer.publicKey.toBuffer()],
+      program.programId
+    );
+    const profile = await program.account.customerProfile.fetch(customerProfilePDA);
+    expect(profile.customer.toString()).to.equal(customer.publicKey.toString());
+    expect(Number(profile.ordersCreated)).to.equal(1);
   });
 
   it("Accept Delivery", async () => {
@@ -159,7 +661,12 @@ describe("autonomous_vehicle_payments", () => {
       .accountsPartial({
         delivery: deliveryPDA,
         vehicle: vehiclePDA,
+        geoBucket: null,
+        openOrders: null,
+        fleet: null,
+        region: regionPDA,
         config: configPDA,
+        dispatcherKey: null,
         operator: vehicleOperator.publicKey,
       })
       .signers([vehicleOperator])
@@ -174,50 +681,306 @@ describe("autonomous_vehicle_payments", () => {
   });
 
   it("Complete Delivery", async () => {
-    const vehicleOperatorBefore = await provider.connection.getBalance(vehicleOperator.publicKey);
-    const treasuryBefore = await provider.connection.getBalance(treasury.publicKey);
+    const treasuryBefore = await provider.connection.getBalance(treasuryPDA);
 
     await program.methods
-      .completeDelivery(deliveryId)
+      .confirmPickup(deliveryId, Array(32).fill(0))
       .accountsPartial({
         delivery: deliveryPDA,
-        escrow: escrowPDA,
         vehicle: vehiclePDA,
-        vehicleOperator: vehicleOperator.publicKey,
-        treasury: treasury.publicKey,
-        config: configPDA,
-        customer: customer.publicKey,
+        dispatcherKey: null,
+        signer: vehicleOperator.publicKey,
       })
       .signers([vehicleOperator])
       .rpc();
 
-    const delivery = await program.account.delivery.fetch(deliveryPDA);
-    expect(delivery.status).to.deep.equal({ completed: {} });
-
-    const vehicle = await program.account.vehicle.fetch(vehiclePDA);
-    expect(vehicle.isBusy).to.be.false;
-    expect(Number(vehicle.totalDeliveries)).to.equal(1);
-
-    const vehicleOperatorAfter = await provider.connection.getBalance(vehicleOperator.publicKey);
-    const treasuryAfter = await provider.connection.getBalance(treasury.publicKey);
+    const pickedUp = await program.account.delivery.fetch(deliveryPDA);
+    expect(pickedUp.status).to.deep.equal({ pickedUp: {} });
+    expect(pickedUp.pickedUpAt).to.not.be.null;
+
+    await program.methods
+      .markDelivered(deliveryId)
+      .accountsPartial({
+        delivery: deliveryPDA,
+        vehicle: vehiclePDA,
+        operator: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+
+    await program.methods
+      .confirmDelivery(deliveryId)
+      .accountsPartial({
+        delivery: deliveryPDA,
+        customer: customer.publicKey,
+      })
+      .signers([customer])
+      .rpc();
+
+    await program.methods
+      .completeDelivery(deliveryId)
+      .accountsPartial({
+        delivery: deliveryPDA,
+        escrow: escrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(deliveryId),
+        vehicle: vehiclePDA,
+        geoBucket: null,
+        fleet: null,
+        fleetPayout: null,
+        dispatcherKey: null,
+        vehicleOperator: vehicleOperator.publicKey,
+        signer: vehicleOperator.publicKey,
+        operatorProfile: operatorProfilePDA,
+        treasury: treasuryPDA,
+        charity: null,
+        insurancePool: null,
+        customerReferral: null,
+        operatorReferral: null,
+        manifest: null,
+        route: null,
+        pythPrice: null,
+        receiptMint: null,
+        receiptToken: null,
+        receiptMetadata: null,
+        receiptEdition: null,
+        tokenMetadataProgram: null,
+        tokenProgram: null,
+        associatedTokenProgram: null,
+        rent: null,
+        receiptTree: null,
+        receiptTreeConfig: null,
+        bubblegumProgram: null,
+        compressionProgram: null,
+        logWrapper: null,
+        config: configPDA,
+        region: regionPDA,
+        customer: customer.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+
+    const delivery = await program.account.delivery.fetch(deliveryPDA);
+    expect(delivery.status).to.deep.equal({ completed: {} });
+
+    const vehicle = await program.account.vehicle.fetch(vehiclePDA);
+    expect(vehicle.isBusy).to.be.false;
+    expect(Number(vehicle.totalDeliveries)).to.equal(1);
+    expect(Number(vehicle.revenue)).to.equal(Number(paymentAmount) * (10000 - feeBps) / 10000);
+
+    const treasuryAfter = await provider.connection.getBalance(treasuryPDA);
 
     const expectedFee = Number(paymentAmount) * feeBps / 10000;
     const expectedPayment = Number(paymentAmount) - expectedFee;
 
-    expect(vehicleOperatorAfter - vehicleOperatorBefore).to.be.greaterThanOrEqual(expectedPayment);
     expect(treasuryAfter - treasuryBefore).to.be.greaterThanOrEqual(expectedFee);
+    const treasuryAccount = await program.account.treasury.fetch(treasuryPDA);
+    expect(Number(treasuryAccount.totalCollected)).to.equal(expectedFee);
+
+    // The operator payment accrues for a later claim
+    const [earningsPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("earnings"), vehicleOperator.publicKey.toBuffer()],
+      program.programId
+    );
+    const earnings = await program.account.earnings.fetch(earningsPDA);
+    expect(Number(earnings.accrued)).to.equal(expectedPayment);
+
+    const operatorBeforeClaim = await provider.connection.getBalance(vehicleOperator.publicKey);
+    await program.methods
+      .claimEarnings()
+      .accountsPartial({
+        earnings: earningsPDA,
+        operator: vehicleOperator.publicKey,
+        signer: vehicleOperator.publicKey,
+        dispatcherKey: null,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+    const operatorAfterClaim = await provider.connection.getBalance(vehicleOperator.publicKey);
+    expect(operatorAfterClaim - operatorBeforeClaim).to.be.greaterThan(expectedPayment - 10_000);
+
+    expect(await provider.connection.getAccountInfo(escrowPDA)).to.be.null;
+
+    const profile = await program.account.operatorProfile.fetch(operatorProfilePDA);
+    expect(Number(profile.completedJobs)).to.equal(1);
+    expect(Number(profile.grossEarnings)).to.equal(expectedPayment);
+    expect(Number(profile.feesPaid)).to.equal(expectedFee);
+
+    const [receiptPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("receipt"), deliveryPDA.toBuffer()],
+      program.programId
+    );
+    const receipt = await program.account.receipt.fetch(receiptPDA);
+    expect(Number(receipt.invoiceNumber)).to.equal(1);
+    expect(Number(receipt.platformFee)).to.equal(expectedFee);
+    expect(receipt.pickedUpAt).to.not.be.null;
+    expect(receipt.nftMint).to.be.null;
+    expect(receipt.cnftTree).to.be.null;
+
+    const [historyPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vehicle_history"), vehiclePDA.toBuffer()],
+      program.programId
+    );
+    const history = await program.account.vehicleHistory.fetch(historyPDA);
+    expect(history.entries.length).to.equal(1);
+    expect(Number(history.entries[0].deliveryId)).to.equal(Number(deliveryId));
+    expect(Number(history.entries[0].paymentAmount)).to.equal(Number(paymentAmount));
+
+    const shards = await program.account.globalStats.all();
+    const sum = (field: string) => shards.reduce((total, { account }) => total + Number(account[field]), 0);
+    expect(sum("ordersCompleted")).to.equal(1);
+    expect(sum("totalVolume")).to.equal(Number(paymentAmount));
+    expect(sum("totalFees")).to.equal(expectedFee);
+    const shard = await program.account.globalStats.fetch(findGlobalStatsPDA(deliveryId));
+    expect(shard.shard).to.equal(deliveryId.modn(16));
+  });
+
+  it("Files an insurance claim that an arbiter resolves", async () => {
+    const arbiter = Keypair.generate();
+    const [arbiterRolePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("role"), Buffer.from([2]), arbiter.publicKey.toBuffer()],
+      program.programId
+    );
+    const [insurancePoolPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("insurance_pool"), configPDA.toBuffer()],
+      program.programId
+    );
+    const [claimPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("claim"), deliveryPDA.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .initializeInsurancePool()
+      .accountsPartial({
+        insurancePool: insurancePoolPDA,
+        config: configPDA,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+    const [authorityArbiterPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("role"), Buffer.from([2]), authority.publicKey.toBuffer()],
+      program.programId
+    );
+    try {
+      await program.methods
+        .grantRole({ arbiter: {} }, authority.publicKey)
+        .accountsPartial({
+          role: authorityArbiterPDA,
+          config: configPDA,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("ArbiterNotIndependent");
+    }
+
+    await program.methods
+      .grantRole({ arbiter: {} }, arbiter.publicKey)
+      .accountsPartial({
+        role: arbiterRolePDA,
+        config: configPDA,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    try {
+      await program.methods
+        .fileClaim(deliveryId, paymentAmount.addn(1), Array(32).fill(0))
+        .accountsPartial({
+          delivery: deliveryPDA,
+          claim: claimPDA,
+          customer: customer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([customer])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("InvalidAmount");
+    }
+
+    await program.methods
+      .fileClaim(deliveryId, paymentAmount, Array(32).fill(7))
+      .accountsPartial({
+        delivery: deliveryPDA,
+        claim: claimPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+
+    // The pool has collected nothing yet, so only a rejection fits
+    await program.methods
+      .payClaim(new BN(0))
+      .accountsPartial({
+        claim: claimPDA,
+        insurancePool: insurancePoolPDA,
+        customer: customer.publicKey,
+        config: configPDA,
+        role: arbiterRolePDA,
+        arbiter: arbiter.publicKey,
+      })
+      .signers([arbiter])
+      .rpc();
+
+    const claim = await program.account.insuranceClaim.fetch(claimPDA);
+    expect(claim.status).to.deep.equal({ rejected: {} });
+    expect(claim.resolvedAt).to.not.be.null;
+  });
+
+  it("Binds vehicles only to NFTs whose supply is fixed", async () => {
+    const nftVehicleId = "NFT-VEHICLE-001";
+    const [nftVehiclePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vehicle"), Buffer.from(nftVehicleId)],
+      program.programId
+    );
+    const nft = await createMint(provider.connection, authority, authority.publicKey, null, 0);
+    const holding = await createAccount(provider.connection, vehicleOperator, nft, vehicleOperator.publicKey);
+    await mintTo(provider.connection, authority, nft, holding, authority, 1);
 
-    const escrowBalance = await provider.connection.getBalance(escrowPDA);
-    expect(escrowBalance).to.be.lessThanOrEqual(10000);
+    // The mint authority could still mint a second copy
+    try {
+      await program.methods
+        .registerVehicle(nftVehicleId, vehicleOperator.publicKey, { latE6: 40_712_800, lngE6: -74_006_000 }, { road: {} })
+        .accountsPartial({
+          vehicle: nftVehiclePDA,
+          config: configPDA,
+          ownershipMint: nft,
+          ownershipToken: holding,
+          stake: null,
+          staker: null,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("InvalidOwnershipNft");
+    }
+    expect(await provider.connection.getAccountInfo(nftVehiclePDA)).to.be.null;
   });
 
   it("Prevents duplicate vehicle registration", async () => {
     try {
       await program.methods
-        .registerVehicle(vehicleId, vehicleOperator.publicKey, "new location")
+        .registerVehicle(vehicleId, vehicleOperator.publicKey, { latE6: 40_730_600, lngE6: -73_986_600 }, { road: {} })
         .accountsPartial({
           vehicle: vehiclePDA,
           config: configPDA,
+          ownershipMint: null,
+          ownershipToken: null,
+          stake: null,
+          staker: null,
           authority: authority.publicKey,
           systemProgram: SystemProgram.programId,
         })
@@ -229,59 +992,2949 @@ describe("autonomous_vehicle_payments", () => {
     }
   });
 
-  it("Prevents unauthorized delivery acceptance", async () => {
-    const unauthorizedOperator = Keypair.generate();
-    const newDeliveryId = new BN(54321);
+  it("Updates the vehicle location", async () => {
+    await program.methods
+      .updateVehicleLocation({ latE6: 40_730_600, lngE6: -73_986_600 })
+      .accountsPartial({
+        vehicle: vehiclePDA,
+        oldBucket: null,
+        newBucket: null,
+        dispatcherKey: null,
+        signer: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
 
-    const [newDeliveryPDA] = PublicKey.findProgramAddressSync(
-      [
-        Buffer.from("delivery"),
-        customer.publicKey.toBuffer(),
-        newDeliveryId.toArrayLike(Buffer, "le", 8)
-      ],
-      program.programId
-    );
+    const vehicle = await program.account.vehicle.fetch(vehiclePDA);
+    expect(vehicle.position).to.deep.equal({ latE6: 40_730_600, lngE6: -73_986_600 });
+    expect(vehicle.location).to.equal("40.730600,-73.986600");
+    expect(Number(vehicle.locationUpdatedAt)).to.be.greaterThan(0);
 
-    const [newEscrowPDA] = PublicKey.findProgramAddressSync(
-      [
-        Buffer.from("escrow"),
-        customer.publicKey.toBuffer(),
-        newDeliveryId.toArrayLike(Buffer, "le", 8)
-      ],
-      program.programId
-    );
+    try {
+      await program.methods
+        .updateVehicleLocation({ latE6: 91_000_000, lngE6: 0 })
+        .accountsPartial({
+          vehicle: vehiclePDA,
+          oldBucket: null,
+          newBucket: null,
+          dispatcherKey: null,
+          signer: vehicleOperator.publicKey,
+        })
+        .signers([vehicleOperator])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("InvalidLocation");
+    }
+  });
 
+  it("Lets a session key report location until revoked", async () => {
+    const sessionKey = Keypair.generate();
+    const expiresAt = Math.floor(Date.now() / 1000) + 3600;
+
+    const PERM_COMPLETE = 1 << 1;
+    const PERM_UPDATE_LOCATION = 1 << 3;
+
+    // A key that may only complete orders cannot move the vehicle
     await program.methods
-      .createDeliveryOrder(
-        newDeliveryId,
-        paymentAmount,
-        "pickup",
-        "delivery"
-      )
+      .registerSessionKey(sessionKey.publicKey, new BN(expiresAt), PERM_COMPLETE)
       .accountsPartial({
-        delivery: newDeliveryPDA,
-        escrow: newEscrowPDA,
-        config: configPDA,
-        customer: customer.publicKey,
-        systemProgram: SystemProgram.programId,
+        vehicle: vehiclePDA,
+        operator: vehicleOperator.publicKey,
       })
-      .signers([customer])
+      .signers([vehicleOperator])
+      .rpc();
+    try {
+      await program.methods
+        .updateVehicleLocation({ latE6: 40_741_100, lngE6: -73_989_700 })
+        .accountsPartial({
+          vehicle: vehiclePDA,
+          oldBucket: null,
+          newBucket: null,
+          dispatcherKey: null,
+          signer: sessionKey.publicKey,
+        })
+        .signers([sessionKey])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("MissingPermission");
+    }
+
+    await program.methods
+      .registerSessionKey(sessionKey.publicKey, new BN(expiresAt), PERM_UPDATE_LOCATION)
+      .accountsPartial({
+        vehicle: vehiclePDA,
+        operator: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+
+    await program.methods
+      .updateVehicleLocation({ latE6: 40_741_100, lngE6: -73_989_700 })
+      .accountsPartial({
+        vehicle: vehiclePDA,
+        oldBucket: null,
+        newBucket: null,
+        dispatcherKey: null,
+        signer: sessionKey.publicKey,
+      })
+      .signers([sessionKey])
+      .rpc();
+    expect((await program.account.vehicle.fetch(vehiclePDA)).location).to.equal("40.741100,-73.989700");
+
+    await program.methods
+      .revokeSessionKey()
+      .accountsPartial({
+        vehicle: vehiclePDA,
+        operator: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
       .rpc();
 
     try {
       await program.methods
-        .acceptDelivery(newDeliveryId)
+        .updateVehicleLocation({ latE6: 40_730_600, lngE6: -73_986_600 })
         .accountsPartial({
-          delivery: newDeliveryPDA,
           vehicle: vehiclePDA,
-          config: configPDA,
-          operator: unauthorizedOperator.publicKey,
+          oldBucket: null,
+          newBucket: null,
+          dispatcherKey: null,
+          signer: sessionKey.publicKey,
         })
-        .signers([unauthorizedOperator])
+        .signers([sessionKey])
         .rpc();
       expect.fail("Should have failed");
     } catch (error) {
-      expect(error.message).to.include("unknown signer");
+      expect(error.message).to.include("Unauthorized");
     }
   });
+
+  it("Takes a vehicle offline for maintenance and back", async () => {
+    await program.methods
+      .setVehicleActive(false)
+      .accountsPartial({
+        vehicle: vehiclePDA,
+        geoBucket: null,
+        config: configPDA,
+        signer: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+    expect((await program.account.vehicle.fetch(vehiclePDA)).isActive).to.be.false;
+
+    await program.methods
+      .setVehicleActive(true)
+      .accountsPartial({
+        vehicle: vehiclePDA,
+        geoBucket: null,
+        config: configPDA,
+        signer: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+    expect((await program.account.vehicle.fetch(vehiclePDA)).isActive).to.be.true;
+  });
+
+  it("Registers a vehicle to a fleet and removes it", async () => {
+    const fleetId = 1;
+    const [fleetPDA] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("fleet"),
+        authority.publicKey.toBuffer(),
+        new BN(fleetId).toArrayLike(Buffer, "le", 4)
+      ],
+      program.programId
+    );
+
+    await program.methods
+      .createFleet(fleetId, treasury.publicKey)
+      .accountsPartial({
+        fleet: fleetPDA,
+        manager: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    await program.methods
+      .registerVehicleToFleet()
+      .accountsPartial({
+        fleet: fleetPDA,
+        vehicle: vehiclePDA,
+        manager: authority.publicKey,
+        operator: vehicleOperator.publicKey,
+      })
+      .signers([authority, vehicleOperator])
+      .rpc();
+
+    let fleet = await program.account.fleet.fetch(fleetPDA);
+    expect(fleet.vehicleCount).to.equal(1);
+    let vehicle = await program.account.vehicle.fetch(vehiclePDA);
+    expect(vehicle.fleet.toString()).to.equal(fleetPDA.toString());
+
+    await program.methods
+      .removeVehicleFromFleet()
+      .accountsPartial({
+        fleet: fleetPDA,
+        vehicle: vehiclePDA,
+        signer: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+
+    fleet = await program.account.fleet.fetch(fleetPDA);
+    expect(fleet.vehicleCount).to.equal(0);
+    vehicle = await program.account.vehicle.fetch(vehiclePDA);
+    expect(vehicle.fleet).to.be.null;
+  });
+
+  it("Prevents unauthorized delivery acceptance", async () => {
+    const unauthorizedOperator = Keypair.generate();
+    const newDeliveryId = new BN(54321);
+
+    const [newDeliveryPDA] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("delivery"),
+        customer.publicKey.toBuffer(),
+        newDeliveryId.toArrayLike(Buffer, "le", 8)
+      ],
+      program.programId
+    );
+
+    const [newEscrowPDA] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("escrow"),
+        customer.publicKey.toBuffer(),
+        newDeliveryId.toArrayLike(Buffer, "le", 8)
+      ],
+      program.programId
+    );
+
+    await program.methods
+      .createDeliveryOrder(
+        newDeliveryId,
+        paymentAmount,
+        "pickup",
+        "delivery",
+        regionId,
+        0,
+        null,
+        null,
+        null
+      )
+      .accountsPartial({
+        delivery: newDeliveryPDA,
+        escrow: newEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(newDeliveryId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+
+    try {
+      await program.methods
+        .acceptDelivery(newDeliveryId)
+        .accountsPartial({
+          delivery: newDeliveryPDA,
+          vehicle: vehiclePDA,
+          geoBucket: null,
+          openOrders: null,
+          fleet: null,
+          region: regionPDA,
+          config: configPDA,
+          dispatcherKey: null,
+          operator: unauthorizedOperator.publicKey,
+        })
+        .signers([unauthorizedOperator])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("NotVehicleOperator");
+    }
+
+    const delivery = await program.account.delivery.fetch(newDeliveryPDA);
+    expect(delivery.status).to.deep.equal({ pending: {} });
+    expect(delivery.assignedVehicle).to.be.null;
+  });
+
+  it("Holds a scheduled order until its acceptance window opens", async () => {
+    const scheduledId = new BN(54330);
+    const [scheduledPDA, scheduledEscrowPDA] = findDeliveryPDAs(customer.publicKey, scheduledId);
+    const scheduledAt = new BN(Math.floor(Date.now() / 1000) + 24 * 60 * 60);
+
+    await program.methods
+      .createDeliveryOrder(scheduledId, paymentAmount, "pickup", "delivery", regionId, 0, null, scheduledAt, null)
+      .accountsPartial({
+        delivery: scheduledPDA,
+        escrow: scheduledEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(scheduledId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+
+    try {
+      await program.methods
+        .acceptDelivery(scheduledId)
+        .accountsPartial({
+          delivery: scheduledPDA,
+          vehicle: vehiclePDA,
+          geoBucket: null,
+          openOrders: null,
+          fleet: null,
+          region: regionPDA,
+          config: configPDA,
+          dispatcherKey: null,
+          operator: vehicleOperator.publicKey,
+        })
+        .signers([vehicleOperator])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("ScheduleWindowNotOpen");
+    }
+
+    const delivery = await program.account.delivery.fetch(scheduledPDA);
+    expect(delivery.scheduledAt.toNumber()).to.equal(scheduledAt.toNumber());
+    expect(delivery.status).to.deep.equal({ pending: {} });
+  });
+
+  it("Lets a Dispatcher role holder assign orders until revoked", async () => {
+    const dispatcher = Keypair.generate();
+    const [rolePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("role"), Buffer.from([1]), dispatcher.publicKey.toBuffer()],
+      program.programId
+    );
+    const heldId = new BN(54331);
+    const [heldPDA, heldEscrowPDA] = findDeliveryPDAs(customer.publicKey, heldId);
+    const scheduledAt = new BN(Math.floor(Date.now() / 1000) + 24 * 60 * 60);
+
+    await program.methods
+      .grantRole({ dispatcher: {} }, dispatcher.publicKey)
+      .accountsPartial({
+        role: rolePDA,
+        config: configPDA,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+    const granted = await program.account.role.fetch(rolePDA);
+    expect(granted.kind).to.deep.equal({ dispatcher: {} });
+    expect(granted.holder.toString()).to.equal(dispatcher.publicKey.toString());
+
+    await program.methods
+      .createDeliveryOrder(heldId, paymentAmount, "pickup", "delivery", regionId, 0, null, scheduledAt, null)
+      .accountsPartial({
+        delivery: heldPDA,
+        escrow: heldEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(heldId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+
+    // The role alone is not enough: the operator has to let this
+    // dispatcher hand its vehicle work
+    const [dispatcherKeyPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("dispatcher_key"), vehicleOperator.publicKey.toBuffer(), dispatcher.publicKey.toBuffer()],
+      program.programId
+    );
+    try {
+      await program.methods
+        .assignDelivery(heldId)
+        .accountsPartial({
+          delivery: heldPDA,
+          openOrders: null,
+          vehicle: vehiclePDA,
+          geoBucket: null,
+          fleet: null,
+          region: regionPDA,
+          config: configPDA,
+          role: rolePDA,
+          signer: dispatcher.publicKey,
+          dispatcherKey: null,
+        })
+        .signers([dispatcher])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("Unauthorized");
+    }
+
+    await program.methods
+      .authorizeDispatcherKey(dispatcher.publicKey, "central", new BN(Math.floor(Date.now() / 1000) + 3600), 1)
+      .accountsPartial({
+        dispatcherKey: dispatcherKeyPDA,
+        operator: vehicleOperator.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+
+    // Assignment goes through the same order checks as acceptance
+    try {
+      await program.methods
+        .assignDelivery(heldId)
+        .accountsPartial({
+          delivery: heldPDA,
+          openOrders: null,
+          vehicle: vehiclePDA,
+          geoBucket: null,
+          fleet: null,
+          region: regionPDA,
+          config: configPDA,
+          role: rolePDA,
+          signer: dispatcher.publicKey,
+          dispatcherKey: dispatcherKeyPDA,
+        })
+        .signers([dispatcher])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("ScheduleWindowNotOpen");
+    }
+
+    await program.methods
+      .revokeRole({ dispatcher: {} }, dispatcher.publicKey)
+      .accountsPartial({
+        role: rolePDA,
+        config: configPDA,
+        authority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+    expect(await provider.connection.getAccountInfo(rolePDA)).to.be.null;
+
+    try {
+      await program.methods
+        .assignDelivery(heldId)
+        .accountsPartial({
+          delivery: heldPDA,
+          openOrders: null,
+          vehicle: vehiclePDA,
+          geoBucket: null,
+          fleet: null,
+          region: regionPDA,
+          config: configPDA,
+          role: null,
+          signer: dispatcher.publicKey,
+          dispatcherKey: dispatcherKeyPDA,
+        })
+        .signers([dispatcher])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("Unauthorized");
+    }
+  });
+
+  it("Merges compatible pending orders", async () => {
+    const keptId = new BN(54321);
+    const mergedId = new BN(54322);
+
+    const [keptPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("delivery"), customer.publicKey.toBuffer(), keptId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [keptEscrowPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), customer.publicKey.toBuffer(), keptId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [mergedPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("delivery"), customer.publicKey.toBuffer(), mergedId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [mergedEscrowPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), customer.publicKey.toBuffer(), mergedId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+
+    await program.methods
+      .createDeliveryOrder(mergedId, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+      .accountsPartial({
+        delivery: mergedPDA,
+        escrow: mergedEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(mergedId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+
+    const merge = (otherId: BN, otherPDA: PublicKey, otherEscrowPDA: PublicKey) =>
+      program.methods
+        .mergeDeliveries(keptId, otherId)
+        .accountsPartial({
+          delivery: keptPDA,
+          escrow: keptEscrowPDA,
+          otherDelivery: otherPDA,
+          openOrders: null,
+          voucher: null,
+          voucherUse: null,
+          otherEscrow: otherEscrowPDA,
+          config: configPDA,
+          customer: customer.publicKey,
+          globalStats: findGlobalStatsPDA(otherId),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([customer])
+        .rpc();
+
+    // An order asking for a higher rating cannot ride on the kept one
+    const pickyId = new BN(54323);
+    const [pickyPDA, pickyEscrowPDA] = findDeliveryPDAs(customer.publicKey, pickyId);
+    await program.methods
+      .createDeliveryOrder(pickyId, paymentAmount, "pickup", "delivery", regionId, 450, null, null, null)
+      .accountsPartial({
+        delivery: pickyPDA,
+        escrow: pickyEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(pickyId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+    try {
+      await merge(pickyId, pickyPDA, pickyEscrowPDA);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("DeliveriesNotMergeable");
+    }
+
+    const cancelledBefore = await program.account.globalStats.fetch(findGlobalStatsPDA(mergedId));
+    await merge(mergedId, mergedPDA, mergedEscrowPDA);
+
+    const combined = Number(paymentAmount) * 2;
+    const discount = combined * 100 / 10000;
+    const delivery = await program.account.delivery.fetch(keptPDA);
+    expect(Number(delivery.paymentAmount)).to.equal(combined - discount);
+
+    // The absorbed order is left as a closed marker and counted as cancelled
+    const merged = await program.account.closedDelivery.fetch(mergedPDA);
+    expect(Number(merged.deliveryId)).to.equal(mergedId.toNumber());
+    expect(await provider.connection.getAccountInfo(mergedEscrowPDA)).to.be.null;
+    const cancelledAfter = await program.account.globalStats.fetch(findGlobalStatsPDA(mergedId));
+    expect(Number(cancelledAfter.ordersCancelled) - Number(cancelledBefore.ordersCancelled)).to.equal(1);
+  });
+
+  it("Waitlists orders and hands them out in priority order", async () => {
+    const firstId = new BN(60001);
+    const boostedId = new BN(60002);
+    const [firstPDA, firstEscrowPDA] = findDeliveryPDAs(customer.publicKey, firstId);
+    const [boostedPDA, boostedEscrowPDA] = findDeliveryPDAs(customer.publicKey, boostedId);
+
+    for (const [id, pda, escrow] of [
+      [firstId, firstPDA, firstEscrowPDA],
+      [boostedId, boostedPDA, boostedEscrowPDA],
+    ] as [BN, PublicKey, PublicKey][]) {
+      await program.methods
+        .createDeliveryOrder(id, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+        .accountsPartial({
+          delivery: pda,
+          escrow,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(id),
+          usdFeed: usdFeedPDA,
+          eurFeed: null,
+          pythPrice: null,
+          openOrders: null,
+          voucher: null,
+          voucherUse: null,
+          config: configPDA,
+          customer: customer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([customer])
+        .rpc();
+
+      await program.methods
+        .queueDelivery(id)
+        .accountsPartial({
+          delivery: pda,
+          openOrders: null,
+          region: regionPDA,
+          config: configPDA,
+          customer: customer.publicKey,
+        })
+        .signers([customer])
+        .rpc();
+    }
+
+    await program.methods
+      .boostPriority(boostedId, new BN(LAMPORTS_PER_SOL / 10))
+      .accountsPartial({
+        delivery: boostedPDA,
+        escrow: boostedEscrowPDA,
+        config: configPDA,
+        region: regionPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+
+    try {
+      await program.methods
+        .acceptNext(firstId)
+        .accountsPartial({
+          delivery: firstPDA,
+          vehicle: vehiclePDA,
+          geoBucket: null,
+          openOrders: null,
+          fleet: null,
+          region: regionPDA,
+          config: configPDA,
+          operator: vehicleOperator.publicKey,
+        })
+        .signers([vehicleOperator])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("NotNextInQueue");
+    }
+
+    await program.methods
+      .acceptNext(boostedId)
+      .accountsPartial({
+        delivery: boostedPDA,
+        vehicle: vehiclePDA,
+        geoBucket: null,
+        openOrders: null,
+        fleet: null,
+        region: regionPDA,
+        config: configPDA,
+        operator: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+
+    const delivery = await program.account.delivery.fetch(boostedPDA);
+    expect(delivery.status).to.deep.equal({ inProgress: {} });
+
+    const region = await program.account.region.fetch(regionPDA);
+    expect(region.queue.length).to.equal(1);
+    expect(region.queue[0].delivery.toString()).to.equal(firstPDA.toString());
+    // Accepting took the boosted order off the waitlist, so only one is counted
+    expect(Number(region.totalQueued)).to.equal(1);
+  });
+
+  it("Cancels a pending order and refunds the escrow", async () => {
+    const cancelId = new BN(70001);
+    const [cancelPDA, cancelEscrowPDA] = findDeliveryPDAs(customer.publicKey, cancelId);
+
+    await program.methods
+      .createDeliveryOrder(cancelId, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+      .accountsPartial({
+        delivery: cancelPDA,
+        escrow: cancelEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(cancelId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+
+    const balanceBefore = await provider.connection.getBalance(customer.publicKey);
+
+    await program.methods
+      .cancelDelivery(cancelId)
+      .accountsPartial({
+        delivery: cancelPDA,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        escrow: cancelEscrowPDA,
+        globalStats: findGlobalStatsPDA(cancelId),
+        customer: customer.publicKey,
+      })
+      .signers([customer])
+      .rpc();
+
+    const delivery = await program.account.delivery.fetch(cancelPDA);
+    expect(delivery.status).to.deep.equal({ cancelled: {} });
+
+    const escrow = await provider.connection.getAccountInfo(cancelEscrowPDA);
+    expect(escrow).to.be.null;
+
+    const balanceAfter = await provider.connection.getBalance(customer.publicKey);
+    expect(balanceAfter - balanceBefore).to.be.greaterThan(Number(paymentAmount) - 10_000);
+
+    const [customerProfilePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("customer"), customer.publicKey.toBuffer()],
+      program.programId
+    );
+    const profile = await program.account.customerProfile.fetch(customerProfilePDA);
+    expect(Number(profile.cancellations)).to.equal(1);
+
+    await program.methods
+      .closeDelivery(cancelId)
+      .accountsPartial({
+        delivery: cancelPDA,
+        route: null,
+        customer: customer.publicKey,
+        signer: customer.publicKey,
+      })
+      .signers([customer])
+      .rpc();
+
+    // The order shrinks to a marker so its id cannot be booked again onto
+    // records keyed by its address
+    const closed = await program.account.closedDelivery.fetch(cancelPDA);
+    expect(Number(closed.deliveryId)).to.equal(Number(cancelId));
+    expect(closed.customer.toString()).to.equal(customer.publicKey.toString());
+    try {
+      await program.methods
+        .createDeliveryOrder(cancelId, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+        .accountsPartial({
+          delivery: cancelPDA,
+          escrow: cancelEscrowPDA,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(cancelId),
+          usdFeed: usdFeedPDA,
+          eurFeed: null,
+          pythPrice: null,
+          openOrders: null,
+          voucher: null,
+          voucherUse: null,
+          config: configPDA,
+          customer: customer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([customer])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("already in use");
+    }
+  });
+
+  it("Redeems a voucher once per customer and gives the use back on cancellation", async () => {
+    const shopper = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(shopper.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const code = "SPRING25";
+    const codeHash = Array.from(createHash("sha256").update(code).digest());
+    const [voucherPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("voucher"), Buffer.from(codeHash)],
+      program.programId
+    );
+    const [voucherUsePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("voucher_use"), voucherPDA.toBuffer(), shopper.publicKey.toBuffer()],
+      program.programId
+    );
+    const expiresAt = new BN(Math.floor(Date.now() / 1000) + 86400);
+
+    // A discount larger than the platform fee is refused at creation
+    try {
+      await program.methods
+        .createVoucher(codeHash, feeBps + 1, 10, expiresAt)
+        .accountsPartial({ voucher: voucherPDA, config: configPDA, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("VoucherExceedsFee");
+    }
+    await program.methods
+      .createVoucher(codeHash, 100, 10, expiresAt)
+      .accountsPartial({ voucher: voucherPDA, config: configPDA, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    const book = (id: BN) => {
+      const [orderPDA, orderEscrowPDA] = findDeliveryPDAs(shopper.publicKey, id);
+      return program.methods
+        .createDeliveryOrder(id, paymentAmount, "pickup", "delivery", regionId, 0, null, null, code)
+        .accountsPartial({
+          delivery: orderPDA,
+          escrow: orderEscrowPDA,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(id),
+          usdFeed: usdFeedPDA,
+          eurFeed: null,
+          pythPrice: null,
+          openOrders: null,
+          voucher: voucherPDA,
+          voucherUse: voucherUsePDA,
+          config: configPDA,
+          customer: shopper.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([shopper])
+        .rpc();
+    };
+
+    const firstId = new BN(70011);
+    const secondId = new BN(70012);
+    const [firstPDA, firstEscrowPDA] = findDeliveryPDAs(shopper.publicKey, firstId);
+    await book(firstId);
+    const first = await program.account.delivery.fetch(firstPDA);
+    expect(Number(first.voucherDiscount)).to.equal(Number(paymentAmount) / 100);
+    expect(first.voucher.toString()).to.equal(voucherPDA.toString());
+    expect((await program.account.voucher.fetch(voucherPDA)).uses).to.equal(1);
+    const use = await program.account.voucherUse.fetch(voucherUsePDA);
+    expect(use.delivery.toString()).to.equal(firstPDA.toString());
+
+    // The same customer cannot redeem it again while the first use stands
+    try {
+      await book(secondId);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("VoucherAlreadyUsed");
+    }
+
+    // Cancelling the order needs the voucher accounts and releases the use
+    try {
+      await program.methods
+        .cancelDelivery(firstId)
+        .accountsPartial({
+          delivery: firstPDA,
+          openOrders: null,
+          voucher: null,
+          voucherUse: null,
+          escrow: firstEscrowPDA,
+          globalStats: findGlobalStatsPDA(firstId),
+          customer: shopper.publicKey,
+        })
+        .signers([shopper])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("VoucherAccountsRequired");
+    }
+    await program.methods
+      .cancelDelivery(firstId)
+      .accountsPartial({
+        delivery: firstPDA,
+        openOrders: null,
+        voucher: voucherPDA,
+        voucherUse: voucherUsePDA,
+        escrow: firstEscrowPDA,
+        globalStats: findGlobalStatsPDA(firstId),
+        customer: shopper.publicKey,
+      })
+      .signers([shopper])
+      .rpc();
+    expect((await program.account.voucher.fetch(voucherPDA)).uses).to.equal(0);
+    expect(await provider.connection.getAccountInfo(voucherUsePDA)).to.be.null;
+
+    await book(secondId);
+    expect((await program.account.voucher.fetch(voucherPDA)).uses).to.equal(1);
+
+    // Merging the voucher order into a plain one hands the use back too
+    const plainId = new BN(70013);
+    const [plainPDA, plainEscrowPDA] = findDeliveryPDAs(shopper.publicKey, plainId);
+    const [secondPDA, secondEscrowPDA] = findDeliveryPDAs(shopper.publicKey, secondId);
+    await program.methods
+      .createDeliveryOrder(plainId, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+      .accountsPartial({
+        delivery: plainPDA,
+        escrow: plainEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(plainId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: shopper.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([shopper])
+      .rpc();
+    const mergeSecond = (voucher: PublicKey | null, voucherUse: PublicKey | null) =>
+      program.methods
+        .mergeDeliveries(plainId, secondId)
+        .accountsPartial({
+          delivery: plainPDA,
+          escrow: plainEscrowPDA,
+          otherDelivery: secondPDA,
+          openOrders: null,
+          voucher,
+          voucherUse,
+          otherEscrow: secondEscrowPDA,
+          config: configPDA,
+          customer: shopper.publicKey,
+          globalStats: findGlobalStatsPDA(secondId),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([shopper])
+        .rpc();
+    try {
+      await mergeSecond(null, null);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("VoucherAccountsRequired");
+    }
+    await mergeSecond(voucherPDA, voucherUsePDA);
+    expect((await program.account.voucher.fetch(voucherPDA)).uses).to.equal(0);
+    expect(await provider.connection.getAccountInfo(voucherUsePDA)).to.be.null;
+  });
+
+  it("Books USD-priced orders only against the configured Pyth feed", async () => {
+    const orderId = new BN(70031);
+    const [orderPDA, orderEscrowPDA] = findDeliveryPDAs(customer.publicKey, orderId);
+    const [feeHistoryPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("fee_history"), configPDA.toBuffer()],
+      program.programId
+    );
+    const bookUsd = () =>
+      program.methods
+        .createDeliveryOrderUsd(orderId, new BN(1250), "pickup", "delivery", regionId, 0, null, null)
+        .accountsPartial({
+          delivery: orderPDA,
+          escrow: orderEscrowPDA,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(orderId),
+          usdFeed: usdFeedPDA,
+          eurFeed: null,
+          pythPrice: null,
+          openOrders: null,
+          voucher: null,
+          voucherUse: null,
+          config: configPDA,
+          customer: customer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([customer])
+        .rpc();
+
+    // USD orders stay off until the authority names a Pyth SOL/USD feed
+    try {
+      await bookUsd();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("PythFeedNotConfigured");
+    }
+
+    const pythPriceUpdate = Keypair.generate().publicKey;
+    const feedId = Array(32).fill(9);
+    try {
+      await program.methods
+        .setPythFeed(pythPriceUpdate, feedId)
+        .accountsPartial({ config: configPDA, feeHistory: feeHistoryPDA, authority: customer.publicKey })
+        .signers([customer])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("Unauthorized");
+    }
+    await program.methods
+      .setPythFeed(pythPriceUpdate, feedId)
+      .accountsPartial({ config: configPDA, feeHistory: feeHistoryPDA, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    const config = await program.account.config.fetch(configPDA);
+    expect(config.pythPriceUpdate.toBase58()).to.equal(pythPriceUpdate.toBase58());
+
+    // Booking without the price update account is refused
+    try {
+      await bookUsd();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("PythFeedRequired");
+    }
+    expect(await provider.connection.getAccountInfo(orderPDA)).to.be.null;
+
+    await program.methods
+      .setPythFeed(PublicKey.default, Array(32).fill(0))
+      .accountsPartial({ config: configPDA, feeHistory: feeHistoryPDA, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+  });
+
+  it("Ends an auction when its order is cancelled during bidding", async () => {
+    const auctionId = new BN(70021);
+    const [auctionPDA, auctionEscrowPDA] = findDeliveryPDAs(customer.publicKey, auctionId);
+    await program.methods
+      .createDeliveryOrder(auctionId, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+      .accountsPartial({
+        delivery: auctionPDA,
+        escrow: auctionEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(auctionId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+    await program.methods
+      .startAuction(auctionId, new BN(300))
+      .accountsPartial({ delivery: auctionPDA, customer: customer.publicKey })
+      .signers([customer])
+      .rpc();
+    expect((await program.account.delivery.fetch(auctionPDA)).auctionEndsAt).to.not.be.null;
+
+    await program.methods
+      .cancelDelivery(auctionId)
+      .accountsPartial({
+        delivery: auctionPDA,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        escrow: auctionEscrowPDA,
+        globalStats: findGlobalStatsPDA(auctionId),
+        customer: customer.publicKey,
+      })
+      .signers([customer])
+      .rpc();
+
+    // Bidders can close their bids as soon as the order is gone
+    const cancelled = await program.account.delivery.fetch(auctionPDA);
+    expect(cancelled.status).to.deep.equal({ cancelled: {} });
+    expect(cancelled.auctionEndsAt).to.be.null;
+    expect(cancelled.lowestBidder).to.be.null;
+  });
+
+  it("Lists pending orders on the region's open-orders board", async () => {
+    const listedId = new BN(70004);
+    const [listedPDA, listedEscrowPDA] = findDeliveryPDAs(customer.publicKey, listedId);
+    const boardPage = (page: number) =>
+      PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("open_orders"),
+          new BN(regionId).toArrayLike(Buffer, "le", 2),
+          new BN(page).toArrayLike(Buffer, "le", 2),
+        ],
+        program.programId
+      )[0];
+    const openOrdersPDA = boardPage(0);
+
+    await program.methods
+      .initializeOpenOrders(regionId, 0)
+      .accountsPartial({
+        openOrders: openOrdersPDA,
+        previous: null,
+        region: regionPDA,
+        config: configPDA,
+        role: null,
+        payer: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    // The next page only opens once this one is full
+    try {
+      await program.methods
+        .initializeOpenOrders(regionId, 1)
+        .accountsPartial({
+          openOrders: boardPage(1),
+          previous: openOrdersPDA,
+          region: regionPDA,
+          config: configPDA,
+          role: null,
+          payer: customer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([customer])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("OpenOrdersNotFull");
+    }
+
+    await program.methods
+      .createDeliveryOrder(listedId, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+      .accountsPartial({
+        delivery: listedPDA,
+        escrow: listedEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(listedId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: openOrdersPDA,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+
+    let board = await program.account.openOrders.fetch(openOrdersPDA);
+    expect(board.orders.map((key) => key.toString())).to.deep.equal([listedPDA.toString()]);
+
+    try {
+      await program.methods
+        .cancelDelivery(listedId)
+        .accountsPartial({
+          delivery: listedPDA,
+          openOrders: null,
+          voucher: null,
+          voucherUse: null,
+          escrow: listedEscrowPDA,
+          globalStats: findGlobalStatsPDA(listedId),
+          customer: customer.publicKey,
+        })
+        .signers([customer])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("OpenOrdersRequired");
+    }
+
+    await program.methods
+      .cancelDelivery(listedId)
+      .accountsPartial({
+        delivery: listedPDA,
+        openOrders: openOrdersPDA,
+        voucher: null,
+        voucherUse: null,
+        escrow: listedEscrowPDA,
+        globalStats: findGlobalStatsPDA(listedId),
+        customer: customer.publicKey,
+      })
+      .signers([customer])
+      .rpc();
+
+    board = await program.account.openOrders.fetch(openOrdersPDA);
+    expect(board.orders).to.be.empty;
+
+    // Joining the waitlist takes a listed order off the board
+    const queuedId = new BN(70005);
+    const [queuedPDA, queuedEscrowPDA] = findDeliveryPDAs(customer.publicKey, queuedId);
+    await program.methods
+      .createDeliveryOrder(queuedId, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+      .accountsPartial({
+        delivery: queuedPDA,
+        escrow: queuedEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(queuedId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: openOrdersPDA,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+    await program.methods
+      .queueDelivery(queuedId)
+      .accountsPartial({
+        delivery: queuedPDA,
+        openOrders: openOrdersPDA,
+        region: regionPDA,
+        config: configPDA,
+        customer: customer.publicKey,
+      })
+      .signers([customer])
+      .rpc();
+    board = await program.account.openOrders.fetch(openOrdersPDA);
+    expect(board.orders).to.be.empty;
+    const queued = await program.account.delivery.fetch(queuedPDA);
+    expect(queued.listed).to.be.false;
+    expect(queued.status).to.deep.equal({ queued: {} });
+  });
+
+  it("Pauses and resumes new orders", async () => {
+    const pausedId = new BN(70002);
+    const [pausedPDA, pausedEscrowPDA] = findDeliveryPDAs(customer.publicKey, pausedId);
+
+    await program.methods
+      .setPaused(true)
+      .accountsPartial({
+        config: configPDA,
+        role: null,
+        authority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    try {
+      await program.methods
+        .createDeliveryOrder(pausedId, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+        .accountsPartial({
+          delivery: pausedPDA,
+          escrow: pausedEscrowPDA,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(pausedId),
+          usdFeed: usdFeedPDA,
+          eurFeed: null,
+          pythPrice: null,
+          openOrders: null,
+          voucher: null,
+          voucherUse: null,
+          config: configPDA,
+          customer: customer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([customer])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("ConfigInactive");
+    }
+
+    await program.methods
+      .setPaused(false)
+      .accountsPartial({
+        config: configPDA,
+        role: null,
+        authority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    const config = await program.account.config.fetch(configPDA);
+    expect(config.isPaused).to.be.false;
+  });
+
+  it("Books new orders at the surge price in force", async () => {
+    const surgedId = new BN(70003);
+    const [surgedPDA, surgedEscrowPDA] = findDeliveryPDAs(customer.publicKey, surgedId);
+
+    await program.methods
+      .setSurge(5000)
+      .accountsPartial({
+        config: configPDA,
+        role: null,
+        signer: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    await program.methods
+      .createDeliveryOrder(surgedId, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+      .accountsPartial({
+        delivery: surgedPDA,
+        escrow: surgedEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(surgedId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: customer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([customer])
+      .rpc();
+
+    await program.methods
+      .setSurge(0)
+      .accountsPartial({
+        config: configPDA,
+        role: null,
+        signer: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    const delivery = await program.account.delivery.fetch(surgedPDA);
+    expect(delivery.surgeBps).to.equal(5000);
+    expect(delivery.paymentAmount.toString()).to.equal(paymentAmount.muln(3).divn(2).toString());
+  });
+
+  it("Takes hazard readings only from registered weather oracles", async () => {
+    const weatherFeed = Keypair.generate();
+    const stranger = Keypair.generate();
+    const [weatherOraclePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("weather_oracle"), weatherFeed.publicKey.toBuffer()],
+      program.programId
+    );
+    const [strangerOraclePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("weather_oracle"), stranger.publicKey.toBuffer()],
+      program.programId
+    );
+    const setHazardLevel = (oracle: Keypair, weatherOracle: PublicKey, level: number) =>
+      program.methods
+        .setHazardLevel(regionId, level)
+        .accountsPartial({
+          region: regionPDA,
+          weatherOracle,
+          oracle: oracle.publicKey,
+        })
+        .signers([oracle])
+        .rpc();
+
+    await program.methods
+      .registerWeatherOracle(weatherFeed.publicKey)
+      .accountsPartial({
+        weatherOracle: weatherOraclePDA,
+        config: configPDA,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    // An unregistered signer has no oracle account to report through
+    try {
+      await setHazardLevel(stranger, strangerOraclePDA, 2);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("AccountNotInitialized");
+    }
+    try {
+      await setHazardLevel(weatherFeed, weatherOraclePDA, 4);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("InvalidParameter");
+    }
+
+    await setHazardLevel(weatherFeed, weatherOraclePDA, 2);
+    const region = await program.account.region.fetch(regionPDA);
+    expect(region.hazardLevel).to.equal(2);
+    expect(Number(region.hazardUpdatedAt)).to.be.greaterThan(0);
+
+    // Back to clear weather so later bookings carry no surcharge
+    await setHazardLevel(weatherFeed, weatherOraclePDA, 0);
+    expect((await program.account.region.fetch(regionPDA)).hazardLevel).to.equal(0);
+  });
+
+  it("Sells each map tile version once and stops sales from a suspended provider", async () => {
+    const mapVendor = Keypair.generate();
+    const price = new BN(1_000_000);
+    const tileId = new BN(4294967296);
+    const [mapProviderPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("map_provider"), mapVendor.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .registerMapProvider(mapVendor.publicKey, price)
+      .accountsPartial({
+        mapProvider: mapProviderPDA,
+        config: configPDA,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    const { vehicle, operator } = await registerOperatedVehicle("AV-MAP-1");
+    const stranger = (await registerOperatedVehicle("AV-MAP-2")).operator;
+    const [walletPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("wallet"), operator.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .depositWallet(new BN(LAMPORTS_PER_SOL / 10))
+      .accountsPartial({ wallet: walletPDA, owner: operator.publicKey, systemProgram: SystemProgram.programId })
+      .signers([operator])
+      .rpc();
+
+    const tilePDA = (versionHash: number[]) =>
+      PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("map_tile"),
+          vehicle.toBuffer(),
+          mapVendor.publicKey.toBuffer(),
+          tileId.toArrayLike(Buffer, "le", 8),
+          Buffer.from(versionHash),
+        ],
+        program.programId
+      )[0];
+    const purchase = (buyer: Keypair, versionHash: number[], maxPrice: BN = price) =>
+      program.methods
+        .purchaseMapTile(mapVendor.publicKey, tileId, versionHash, maxPrice)
+        .accountsPartial({
+          tilePurchase: tilePDA(versionHash),
+          mapProvider: mapProviderPDA,
+          providerWallet: mapVendor.publicKey,
+          vehicle,
+          wallet: PublicKey.findProgramAddressSync(
+            [Buffer.from("wallet"), buyer.publicKey.toBuffer()],
+            program.programId
+          )[0],
+          config: configPDA,
+          operator: buyer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+    const setProviderActive = (signer: Keypair, isActive: boolean) =>
+      program.methods
+        .setMapProviderActive(mapVendor.publicKey, isActive)
+        .accountsPartial({ mapProvider: mapProviderPDA, config: configPDA, authority: signer.publicKey })
+        .signers([signer])
+        .rpc();
+
+    // Only the vehicle's operator buys tiles for it, and never above its quote
+    const firstVersion = Array(32).fill(1);
+    try {
+      await purchase(stranger, firstVersion);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("Unauthorized");
+    }
+    try {
+      await purchase(operator, firstVersion, price.subn(1));
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("PriceAboveLimit");
+    }
+
+    const vendorBefore = await provider.connection.getBalance(mapVendor.publicKey);
+    await purchase(operator, firstVersion);
+    const bought = await program.account.mapTilePurchase.fetch(tilePDA(firstVersion));
+    expect(bought.versionHash).to.deep.equal(firstVersion);
+    expect(Number(bought.price)).to.equal(price.toNumber());
+    expect((await provider.connection.getBalance(mapVendor.publicKey)) - vendorBefore).to.equal(price.toNumber());
+
+    // An updated version of the same tile is a new purchase
+    const secondVersion = Array(32).fill(2);
+    await purchase(operator, secondVersion);
+    expect(Number((await program.account.mapProvider.fetch(mapProviderPDA)).tilesSold)).to.equal(2);
+
+    // Only the authority suspends a provider, and a suspended one sells nothing
+    try {
+      await setProviderActive(stranger, false);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("Unauthorized");
+    }
+    await setProviderActive(authority, false);
+    try {
+      await purchase(operator, Array(32).fill(3));
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("ProviderInactive");
+    }
+    await setProviderActive(authority, true);
+    await purchase(operator, Array(32).fill(3));
+  });
+
+  it("Lets only the authority withdraw features and close air corridors", async () => {
+    const manufacturer = Keypair.generate();
+    const featureId = new BN(7001);
+    const corridorId = 7001;
+    const [featurePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("feature"), featureId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [corridorPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("corridor"), new BN(corridorId).toArrayLike(Buffer, "le", 4)],
+      program.programId
+    );
+    await program.methods
+      .registerFeature(featureId, manufacturer.publicKey, new BN(10_000_000))
+      .accountsPartial({
+        feature: featurePDA,
+        config: configPDA,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .registerAirCorridor(corridorId, manufacturer.publicKey, new BN(5_000_000))
+      .accountsPartial({
+        corridor: corridorPDA,
+        config: configPDA,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    const { vehicle, operator } = await registerOperatedVehicle("AV-FEAT-1");
+    const setFeatureActive = (signer: Keypair, isActive: boolean) =>
+      program.methods
+        .setFeatureActive(featureId, isActive)
+        .accountsPartial({ feature: featurePDA, config: configPDA, authority: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    const setCorridorActive = (signer: Keypair, isActive: boolean) =>
+      program.methods
+        .setAirCorridorActive(corridorId, isActive)
+        .accountsPartial({ corridor: corridorPDA, config: configPDA, authority: signer.publicKey })
+        .signers([signer])
+        .rpc();
+
+    for (const attempt of [() => setFeatureActive(operator, false), () => setCorridorActive(operator, false)]) {
+      try {
+        await attempt();
+        expect.fail("Should have failed");
+      } catch (error) {
+        expect(error.message).to.include("Unauthorized");
+      }
+    }
+
+    // A withdrawn feature sells no new licenses
+    await setFeatureActive(authority, false);
+    try {
+      await program.methods
+        .purchaseFeatureLicense(featureId, 30)
+        .accountsPartial({
+          license: PublicKey.findProgramAddressSync(
+            [Buffer.from("license"), vehicle.toBuffer(), featureId.toArrayLike(Buffer, "le", 8)],
+            program.programId
+          )[0],
+          feature: featurePDA,
+          provider: manufacturer.publicKey,
+          vehicle,
+          config: configPDA,
+          operator: operator.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([operator])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("ProviderInactive");
+    }
+    expect((await program.account.feature.fetch(featurePDA)).isActive).to.be.false;
+
+    await setCorridorActive(authority, false);
+    expect((await program.account.airCorridor.fetch(corridorPDA)).isActive).to.be.false;
+    await setCorridorActive(authority, true);
+    expect((await program.account.airCorridor.fetch(corridorPDA)).isActive).to.be.true;
+  });
+
+  it("Requires an approved proposal once an approver set is configured", async () => {
+    const proposalId = new BN(1);
+    const [proposalPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("admin_action"), configPDA.toBuffer(), proposalId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+
+    await program.methods
+      .setApprovers([authority.publicKey], 1)
+      .accountsPartial({
+        config: configPDA,
+        proposal: null,
+        authority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    try {
+      await program.methods
+        .setApprovers([], 0)
+        .accountsPartial({
+          config: configPDA,
+          proposal: null,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("ApprovalRequired");
+    }
+
+    const approvers = Array(5).fill(PublicKey.default);
+    await program.methods
+      .proposeAdminAction(proposalId, { setApprovers: { approvers, threshold: 0 } })
+      .accountsPartial({
+        proposal: proposalPDA,
+        config: configPDA,
+        approver: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    await program.methods
+      .setApprovers([], 0)
+      .accountsPartial({
+        config: configPDA,
+        proposal: proposalPDA,
+        authority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    const config = await program.account.config.fetch(configPDA);
+    expect(config.approvalThreshold).to.equal(0);
+    expect((await program.account.adminProposal.fetch(proposalPDA)).executed).to.be.true;
+  });
+
+  it("Registers referrals only for newcomers referred by existing profiles", async () => {
+    const findReferralPDA = (referee: PublicKey): PublicKey =>
+      PublicKey.findProgramAddressSync([Buffer.from("referral"), referee.toBuffer()], program.programId)[0];
+    const findCustomerProfilePDA = (wallet: PublicKey): PublicKey =>
+      PublicKey.findProgramAddressSync([Buffer.from("customer"), wallet.toBuffer()], program.programId)[0];
+
+    const newcomer = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(newcomer.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const register = (referee: Keypair, referrer: PublicKey, referrerProfile: PublicKey) =>
+      program.methods
+        .registerReferral(referrer)
+        .accountsPartial({
+          referral: findReferralPDA(referee.publicKey),
+          referrerReferral: findReferralPDA(referrer),
+          referrerProfile,
+          customerProfile: findCustomerProfilePDA(referee.publicKey),
+          operatorProfile: null,
+          referee: referee.publicKey,
+        })
+        .signers([referee])
+        .rpc();
+
+    const refusals: [() => Promise<string>, string][] = [
+      [() => register(newcomer, newcomer.publicKey, findCustomerProfilePDA(newcomer.publicKey)), "SelfReferral"],
+      [() => register(newcomer, Keypair.generate().publicKey, operatorProfilePDA), "ReferrerNotFound"],
+      [() => register(customer, vehicleOperator.publicKey, operatorProfilePDA), "ReferralTooLate"],
+    ];
+    for (const [attempt, expected] of refusals) {
+      try {
+        await attempt();
+        expect.fail("Should have failed");
+      } catch (error) {
+        expect(error.message).to.include(expected);
+      }
+    }
+
+    await register(newcomer, vehicleOperator.publicKey, operatorProfilePDA);
+
+    const referral = await program.account.referral.fetch(findReferralPDA(newcomer.publicKey));
+    expect(referral.referrer.toString()).to.equal(vehicleOperator.publicKey.toString());
+    expect(Number(referral.accrued)).to.equal(0);
+    const newcomerProfile = await program.account.customerProfile.fetch(findCustomerProfilePDA(newcomer.publicKey));
+    expect(newcomerProfile.referred).to.be.true;
+
+    // The referrer cannot in turn be referred by the newcomer
+    try {
+      await register(vehicleOperator, newcomer.publicKey, findCustomerProfilePDA(newcomer.publicKey));
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("SelfReferral");
+    }
+
+    // Settling the newcomer's order needs their referral account
+    const orderId = new BN(77001);
+    const [orderPDA, orderEscrowPDA] = findDeliveryPDAs(newcomer.publicKey, orderId);
+    await program.methods
+      .createDeliveryOrder(orderId, paymentAmount, "40.7128,-74.0060", "40.7589,-73.9851", regionId, 0, null, null, null)
+      .accountsPartial({
+        delivery: orderPDA,
+        escrow: orderEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(orderId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: newcomer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([newcomer])
+      .rpc();
+    await program.methods
+      .acceptDelivery(orderId)
+      .accountsPartial({
+        delivery: orderPDA,
+        vehicle: vehiclePDA,
+        geoBucket: null,
+        openOrders: null,
+        fleet: null,
+        region: regionPDA,
+        config: configPDA,
+        dispatcherKey: null,
+        operator: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+    await program.methods
+      .confirmPickup(orderId, Array(32).fill(0))
+      .accountsPartial({ delivery: orderPDA, vehicle: vehiclePDA, dispatcherKey: null, signer: vehicleOperator.publicKey })
+      .signers([vehicleOperator])
+      .rpc();
+    await program.methods
+      .markDelivered(orderId)
+      .accountsPartial({ delivery: orderPDA, vehicle: vehiclePDA, operator: vehicleOperator.publicKey })
+      .signers([vehicleOperator])
+      .rpc();
+    await program.methods
+      .confirmDelivery(orderId)
+      .accountsPartial({ delivery: orderPDA, customer: newcomer.publicKey })
+      .signers([newcomer])
+      .rpc();
+
+    const settle = (customerReferral: PublicKey | null) =>
+      program.methods
+        .completeDelivery(orderId)
+        .accountsPartial({
+          delivery: orderPDA,
+          escrow: orderEscrowPDA,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(orderId),
+          vehicle: vehiclePDA,
+          geoBucket: null,
+          fleet: null,
+          fleetPayout: null,
+          dispatcherKey: null,
+          vehicleOperator: vehicleOperator.publicKey,
+          signer: vehicleOperator.publicKey,
+          operatorProfile: operatorProfilePDA,
+          treasury: treasuryPDA,
+          charity: null,
+          insurancePool: null,
+          customerReferral,
+          operatorReferral: null,
+          manifest: null,
+          route: null,
+          pythPrice: null,
+          receiptMint: null,
+          receiptToken: null,
+          receiptMetadata: null,
+          receiptEdition: null,
+          tokenMetadataProgram: null,
+          tokenProgram: null,
+          associatedTokenProgram: null,
+          rent: null,
+          receiptTree: null,
+          receiptTreeConfig: null,
+          bubblegumProgram: null,
+          compressionProgram: null,
+          logWrapper: null,
+          config: configPDA,
+          region: regionPDA,
+          customer: newcomer.publicKey,
+        })
+        .signers([vehicleOperator])
+        .rpc();
+
+    try {
+      await settle(null);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("ReferralRequired");
+    }
+
+    // The referral share is off on a new config, so the whole treasury part
+    // of the fee still lands in the treasury
+    const config = await program.account.config.fetch(configPDA);
+    const ledgerBefore = await program.account.treasury.fetch(treasuryPDA);
+    await settle(findReferralPDA(newcomer.publicKey));
+    const ledgerAfter = await program.account.treasury.fetch(treasuryPDA);
+    const expectedFee = Number(paymentAmount) * config.feeBps / 10000;
+    expect(Number(ledgerAfter.totalCollected) - Number(ledgerBefore.totalCollected)).to.equal(expectedFee);
+    const settled = await program.account.referral.fetch(findReferralPDA(newcomer.publicKey));
+    expect(Number(settled.totalEarned)).to.equal(0);
+  });
+
+  it("Books the customer's share of a resolved dispute as operator losses", async () => {
+    const disputant = Keypair.generate();
+    const arbiter = Keypair.generate();
+    const [arbiterRolePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("role"), Buffer.from([2]), arbiter.publicKey.toBuffer()],
+      program.programId
+    );
+    const [customerProfilePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("customer"), disputant.publicKey.toBuffer()],
+      program.programId
+    );
+    for (const wallet of [disputant, arbiter]) {
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(wallet.publicKey, 10 * LAMPORTS_PER_SOL)
+      );
+    }
+    await program.methods
+      .grantRole({ arbiter: {} }, arbiter.publicKey)
+      .accountsPartial({
+        role: arbiterRolePDA,
+        config: configPDA,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    const disputeAndResolve = async (id: BN, customerShareBps: number, operatorProfile: PublicKey | null) => {
+      const [orderPDA, orderEscrowPDA] = findDeliveryPDAs(disputant.publicKey, id);
+      const [disputePDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("dispute"), orderPDA.toBuffer()],
+        program.programId
+      );
+      await program.methods
+        .createDeliveryOrder(id, paymentAmount, "40.7128,-74.0060", "40.7589,-73.9851", regionId, 0, null, null, null)
+        .accountsPartial({
+          delivery: orderPDA,
+          escrow: orderEscrowPDA,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(id),
+          usdFeed: usdFeedPDA,
+          eurFeed: null,
+          pythPrice: null,
+          openOrders: null,
+          voucher: null,
+          voucherUse: null,
+          config: configPDA,
+          customer: disputant.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([disputant])
+        .rpc();
+      await program.methods
+        .acceptDelivery(id)
+        .accountsPartial({
+          delivery: orderPDA,
+          vehicle: vehiclePDA,
+          geoBucket: null,
+          openOrders: null,
+          fleet: null,
+          region: regionPDA,
+          config: configPDA,
+          dispatcherKey: null,
+          operator: vehicleOperator.publicKey,
+        })
+        .signers([vehicleOperator])
+        .rpc();
+      await program.methods
+        .openDispute(id, Array(32).fill(3))
+        .accountsPartial({
+          delivery: orderPDA,
+          dispute: disputePDA,
+          vehicle: vehiclePDA,
+          customerProfile: customerProfilePDA,
+          party: disputant.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([disputant])
+        .rpc();
+      await program.methods
+        .resolveDispute(id, customerShareBps)
+        .accountsPartial({
+          delivery: orderPDA,
+          escrow: orderEscrowPDA,
+          dispute: disputePDA,
+          disputeOpener: disputant.publicKey,
+          customerProfile: customerProfilePDA,
+          vehicle: vehiclePDA,
+          operatorProfile,
+          geoBucket: null,
+          stake: null,
+          fleet: null,
+          fleetPayout: null,
+          treasury: treasuryPDA,
+          charity: null,
+          insurancePool: null,
+          customerReferral: null,
+          operatorReferral: null,
+          customer: disputant.publicKey,
+          config: configPDA,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(id),
+          role: arbiterRolePDA,
+          resolver: arbiter.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([arbiter])
+        .rpc();
+    };
+
+    // The awarded half settles like a completion, so the platform still
+    // takes its fee on it; the operator forfeits the other half of its share
+    const before = await program.account.operatorProfile.fetch(operatorProfilePDA);
+    const treasuryBefore = await provider.connection.getBalance(treasuryPDA);
+    const dailyBefore = await program.account.dailyStats.fetch(findDailyStatsPDA());
+    await disputeAndResolve(new BN(77002), 5000, operatorProfilePDA);
+    const after = await program.account.operatorProfile.fetch(operatorProfilePDA);
+    // A ruling finishes the order, so it counts toward the day's completions
+    const dailyAfter = await program.account.dailyStats.fetch(findDailyStatsPDA());
+    expect(Number(dailyAfter.ordersCompleted) - Number(dailyBefore.ordersCompleted)).to.equal(1);
+    const fee = (Number(paymentAmount) * feeBps) / 10000;
+    const operatorHalf = (Number(paymentAmount) - fee) / 2;
+    expect(Number(after.disputeLosses) - Number(before.disputeLosses)).to.equal(operatorHalf);
+    expect(Number(after.grossEarnings) - Number(before.grossEarnings)).to.equal(operatorHalf);
+    expect(Number(after.feesPaid) - Number(before.feesPaid)).to.equal(fee / 2);
+    expect((await provider.connection.getBalance(treasuryPDA)) - treasuryBefore).to.equal(fee / 2);
+
+    // The profile is optional; without it the split still settles
+    await disputeAndResolve(new BN(77003), 5000, null);
+    const untouched = await program.account.operatorProfile.fetch(operatorProfilePDA);
+    expect(Number(untouched.disputeLosses)).to.equal(Number(after.disputeLosses));
+    // Resolving closes the dispute and refunds its rent to whoever opened it
+    const [resolvedDispute] = PublicKey.findProgramAddressSync(
+      [Buffer.from("dispute"), findDeliveryPDAs(disputant.publicKey, new BN(77003))[0].toBuffer()],
+      program.programId
+    );
+    expect(await provider.connection.getAccountInfo(resolvedDispute)).to.be.null;
+
+    // The ruling refunded half the order, so a claim can only cover the rest
+    const [ruledPDA] = findDeliveryPDAs(disputant.publicKey, new BN(77003));
+    const ruled = await program.account.delivery.fetch(ruledPDA);
+    expect(ruled.disputeRefundBps).to.equal(5000);
+    const [ruledClaimPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("claim"), ruledPDA.toBuffer()],
+      program.programId
+    );
+    const fileRuledClaim = (amount: BN) =>
+      program.methods
+        .fileClaim(new BN(77003), amount, Array(32).fill(5))
+        .accountsPartial({
+          delivery: ruledPDA,
+          claim: ruledClaimPDA,
+          customer: disputant.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([disputant])
+        .rpc();
+    try {
+      await fileRuledClaim(paymentAmount);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("InvalidAmount");
+    }
+    await fileRuledClaim(paymentAmount.divn(2));
+    const [insurancePoolPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("insurance_pool"), configPDA.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .payClaim(new BN(0))
+      .accountsPartial({
+        claim: ruledClaimPDA,
+        insurancePool: insurancePoolPDA,
+        customer: disputant.publicKey,
+        config: configPDA,
+        role: arbiterRolePDA,
+        arbiter: arbiter.publicKey,
+      })
+      .signers([arbiter])
+      .rpc();
+
+    // The resolved claim only closes once the order is closed, so no second
+    // claim can be filed on it
+    const closeRuledClaim = () =>
+      program.methods
+        .closeClaim(new BN(77003))
+        .accountsPartial({ claim: ruledClaimPDA, delivery: ruledPDA, customer: disputant.publicKey })
+        .signers([disputant])
+        .rpc();
+    try {
+      await closeRuledClaim();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("AccountDiscriminatorMismatch");
+    }
+    await program.methods
+      .closeDelivery(new BN(77003))
+      .accountsPartial({ delivery: ruledPDA, route: null, customer: disputant.publicKey, signer: disputant.publicKey })
+      .signers([disputant])
+      .rpc();
+    await closeRuledClaim();
+    expect(await provider.connection.getAccountInfo(ruledClaimPDA)).to.be.null;
+  });
+
+  it("Settles token orders through the shared split and refunds withdrawn ones", async () => {
+    const buyer = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(buyer.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const mint = await createMint(provider.connection, authority, authority.publicKey, null, 6);
+    const funded = 100_000_000;
+    const buyerToken = await createAccount(provider.connection, buyer, mint, buyer.publicKey);
+    await mintTo(provider.connection, authority, mint, buyerToken, authority, funded);
+    const operatorToken = await createAccount(provider.connection, vehicleOperator, mint, vehicleOperator.publicKey);
+    const config = await program.account.config.fetch(configPDA);
+    const treasuryToken = await createAccount(provider.connection, authority, mint, config.treasury, Keypair.generate());
+    const [insurancePoolPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("insurance_pool"), configPDA.toBuffer()],
+      program.programId
+    );
+    const insuranceToken = await createAccount(provider.connection, authority, mint, insurancePoolPDA, Keypair.generate());
+    const balance = async (account: PublicKey) => Number((await getAccount(provider.connection, account)).amount);
+
+    await program.methods
+      .setAcceptedMint(mint)
+      .accountsPartial({ config: configPDA, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    const book = async (id: BN) => {
+      const [orderPDA] = findDeliveryPDAs(buyer.publicKey, id);
+      const [escrowToken] = PublicKey.findProgramAddressSync(
+        [Buffer.from("escrow_token"), buyer.publicKey.toBuffer(), id.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+      await program.methods
+        .createDeliveryOrderSpl(id, new BN(25_000_000), "40.7128,-74.0060", "40.7589,-73.9851", regionId)
+        .accountsPartial({
+          delivery: orderPDA,
+          escrowToken,
+          customerToken: buyerToken,
+          mint,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(id),
+          region: regionPDA,
+          config: configPDA,
+          customer: buyer.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      return [orderPDA, escrowToken];
+    };
+
+    // A withdrawn order hands the whole escrow back and closes it
+    const cancelledId = new BN(78001);
+    const [cancelledPDA, cancelledEscrow] = await book(cancelledId);
+    expect(await balance(buyerToken)).to.be.lessThan(funded);
+    await program.methods
+      .cancelDeliverySpl(cancelledId)
+      .accountsPartial({
+        delivery: cancelledPDA,
+        openOrders: null,
+        escrowToken: cancelledEscrow,
+        customerToken: buyerToken,
+        mint,
+        customer: buyer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        globalStats: findGlobalStatsPDA(cancelledId),
+      })
+      .signers([buyer])
+      .rpc();
+    expect(await balance(buyerToken)).to.equal(funded);
+    expect(await provider.connection.getAccountInfo(cancelledEscrow)).to.be.null;
+    const cancelled = await program.account.delivery.fetch(cancelledPDA);
+    expect(cancelled.status).to.deep.equal({ cancelled: {} });
+
+    // A settled order pays out every token the escrow held
+    const settledId = new BN(78002);
+    const [settledPDA, settledEscrow] = await book(settledId);
+    const escrowed = await balance(settledEscrow);
+    await program.methods
+      .acceptDelivery(settledId)
+      .accountsPartial({
+        delivery: settledPDA,
+        vehicle: vehiclePDA,
+        geoBucket: null,
+        openOrders: null,
+        fleet: null,
+        region: regionPDA,
+        config: configPDA,
+        dispatcherKey: null,
+        operator: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+    await program.methods
+      .confirmPickup(settledId, Array(32).fill(0))
+      .accountsPartial({ delivery: settledPDA, vehicle: vehiclePDA, dispatcherKey: null, signer: vehicleOperator.publicKey })
+      .signers([vehicleOperator])
+      .rpc();
+    await program.methods
+      .markDelivered(settledId)
+      .accountsPartial({ delivery: settledPDA, vehicle: vehiclePDA, operator: vehicleOperator.publicKey })
+      .signers([vehicleOperator])
+      .rpc();
+    await program.methods
+      .confirmDelivery(settledId)
+      .accountsPartial({ delivery: settledPDA, customer: buyer.publicKey })
+      .signers([buyer])
+      .rpc();
+
+    const buyerBefore = await balance(buyerToken);
+    const [receiptPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("receipt"), settledPDA.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .completeDeliverySpl(settledId)
+      .accountsPartial({
+        delivery: settledPDA,
+        escrowToken: settledEscrow,
+        vehicle: vehiclePDA,
+        geoBucket: null,
+        fleet: null,
+        manifest: null,
+        region: regionPDA,
+        receipt: receiptPDA,
+        customerProfile: PublicKey.findProgramAddressSync(
+          [Buffer.from("customer"), buyer.publicKey.toBuffer()],
+          program.programId
+        )[0],
+        operatorProfile: operatorProfilePDA,
+        vehicleOperator: vehicleOperator.publicKey,
+        operatorToken,
+        treasuryToken,
+        customerToken: buyerToken,
+        fleetToken: null,
+        insuranceToken,
+        mint,
+        config: configPDA,
+        customer: buyer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(settledId),
+      })
+      .signers([vehicleOperator])
+      .rpc();
+
+    const operatorGot = await balance(operatorToken);
+    const feeGot = (await balance(treasuryToken)) + (await balance(insuranceToken));
+    const refunded = (await balance(buyerToken)) - buyerBefore;
+    expect(operatorGot + feeGot + refunded).to.equal(escrowed);
+    expect(await provider.connection.getAccountInfo(settledEscrow)).to.be.null;
+
+    const receipt = await program.account.receipt.fetch(receiptPDA);
+    expect(receipt.paymentMint.toString()).to.equal(mint.toString());
+    expect(Number(receipt.operatorPayment)).to.equal(operatorGot);
+    expect(Number(receipt.platformFee)).to.equal(feeGot);
+  });
+
+  it("Grosses up Token-2022 transfer fees and only takes allowed extensions", async () => {
+    const buyer = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(buyer.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    // Token-2022 mint with a 1% transfer fee, or a permanent delegate
+    const token2022Mint = async (extension: ExtensionType) => {
+      const mintKeypair = Keypair.generate();
+      const space = getMintLen([extension]);
+      const init =
+        extension === ExtensionType.TransferFeeConfig
+          ? createInitializeTransferFeeConfigInstruction(
+              mintKeypair.publicKey,
+              authority.publicKey,
+              authority.publicKey,
+              100,
+              BigInt(1_000_000_000),
+              TOKEN_2022_PROGRAM_ID
+            )
+          : createInitializePermanentDelegateInstruction(mintKeypair.publicKey, authority.publicKey, TOKEN_2022_PROGRAM_ID);
+      await sendAndConfirmTransaction(
+        provider.connection,
+        new Transaction().add(
+          SystemProgram.createAccount({
+            fromPubkey: authority.publicKey,
+            newAccountPubkey: mintKeypair.publicKey,
+            space,
+            lamports: await provider.connection.getMinimumBalanceForRentExemption(space),
+            programId: TOKEN_2022_PROGRAM_ID,
+          }),
+          init,
+          createInitializeMintInstruction(mintKeypair.publicKey, 6, authority.publicKey, null, TOKEN_2022_PROGRAM_ID)
+        ),
+        [authority, mintKeypair]
+      );
+      return mintKeypair.publicKey;
+    };
+    const account = (mint: PublicKey, owner: PublicKey) =>
+      createAccount(provider.connection, authority, mint, owner, Keypair.generate(), undefined, TOKEN_2022_PROGRAM_ID);
+    const balance = async (tokenAccount: PublicKey) =>
+      Number((await getAccount(provider.connection, tokenAccount, undefined, TOKEN_2022_PROGRAM_ID)).amount);
+    const setMask = (mask: number) =>
+      program.methods
+        .setAllowedTokenExtensions(mask)
+        .accountsPartial({ config: configPDA, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const feeMint = await token2022Mint(ExtensionType.TransferFeeConfig);
+    const buyerToken = await account(feeMint, buyer.publicKey);
+    await mintTo(provider.connection, authority, feeMint, buyerToken, authority, 100_000_000, [], undefined, TOKEN_2022_PROGRAM_ID);
+    const operatorToken = await account(feeMint, vehicleOperator.publicKey);
+    const config = await program.account.config.fetch(configPDA);
+    const treasuryToken = await account(feeMint, config.treasury);
+    const [insurancePoolPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("insurance_pool"), configPDA.toBuffer()],
+      program.programId
+    );
+    const insuranceToken = await account(feeMint, insurancePoolPDA);
+
+    const book = async (mint: PublicKey, customerToken: PublicKey, id: BN) => {
+      const [orderPDA] = findDeliveryPDAs(buyer.publicKey, id);
+      const [escrowToken] = PublicKey.findProgramAddressSync(
+        [Buffer.from("escrow_token"), buyer.publicKey.toBuffer(), id.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+      await program.methods
+        .setAcceptedMint(mint)
+        .accountsPartial({ config: configPDA, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      await program.methods
+        .createDeliveryOrderSpl(id, new BN(25_000_000), "40.7128,-74.0060", "40.7589,-73.9851", regionId)
+        .accountsPartial({
+          delivery: orderPDA,
+          escrowToken,
+          customerToken,
+          mint,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(id),
+          region: regionPDA,
+          config: configPDA,
+          customer: buyer.publicKey,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      return [orderPDA, escrowToken];
+    };
+
+    // A new config allows no extensions, so the transfer-fee mint is refused
+    try {
+      await book(feeMint, buyerToken, new BN(78101));
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("TokenExtensionNotAllowed");
+    }
+
+    // The retired permanent-delegate bit cannot be allowed...
+    try {
+      await setMask(32);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("InvalidParameter");
+    }
+    // ...and a permanent-delegate mint is refused whatever the mask says
+    await setMask(1 | 2 | 4 | 8 | 16 | 64);
+    const delegateMint = await token2022Mint(ExtensionType.PermanentDelegate);
+    const delegateToken = await account(delegateMint, buyer.publicKey);
+    try {
+      await book(delegateMint, delegateToken, new BN(78102));
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("TokenExtensionNotAllowed");
+    }
+    await setMask(1);
+
+    // The customer pays the transfer fee on the way in, so the escrow holds
+    // the full amount. Cancelling sweeps the fee withheld on the escrow to
+    // the mint before closing it
+    const cancelledId = new BN(78103);
+    const [cancelledPDA, cancelledEscrow] = await book(feeMint, buyerToken, cancelledId);
+    const cancelled = await program.account.delivery.fetch(cancelledPDA);
+    expect(await balance(cancelledEscrow)).to.equal(
+      Number(cancelled.paymentAmount) + Number(cancelled.hazardSurcharge)
+    );
+    await program.methods
+      .cancelDeliverySpl(cancelledId)
+      .accountsPartial({
+        delivery: cancelledPDA,
+        openOrders: null,
+        escrowToken: cancelledEscrow,
+        customerToken: buyerToken,
+        mint: feeMint,
+        customer: buyer.publicKey,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        globalStats: findGlobalStatsPDA(cancelledId),
+      })
+      .signers([buyer])
+      .rpc();
+    expect(await provider.connection.getAccountInfo(cancelledEscrow)).to.be.null;
+    const feeConfig = getTransferFeeConfig(
+      await getMint(provider.connection, feeMint, undefined, TOKEN_2022_PROGRAM_ID)
+    );
+    expect(Number(feeConfig.withheldAmount)).to.be.greaterThan(0);
+
+    // A settled order grosses up the operator's leg so they net their share
+    const settledId = new BN(78104);
+    const [settledPDA, settledEscrow] = await book(feeMint, buyerToken, settledId);
+    await program.methods
+      .acceptDelivery(settledId)
+      .accountsPartial({
+        delivery: settledPDA,
+        vehicle: vehiclePDA,
+        geoBucket: null,
+        openOrders: null,
+        fleet: null,
+        region: regionPDA,
+        config: configPDA,
+        dispatcherKey: null,
+        operator: vehicleOperator.publicKey,
+      })
+      .signers([vehicleOperator])
+      .rpc();
+    await program.methods
+      .confirmPickup(settledId, Array(32).fill(0))
+      .accountsPartial({ delivery: settledPDA, vehicle: vehiclePDA, dispatcherKey: null, signer: vehicleOperator.publicKey })
+      .signers([vehicleOperator])
+      .rpc();
+    await program.methods
+      .markDelivered(settledId)
+      .accountsPartial({ delivery: settledPDA, vehicle: vehiclePDA, operator: vehicleOperator.publicKey })
+      .signers([vehicleOperator])
+      .rpc();
+    await program.methods
+      .confirmDelivery(settledId)
+      .accountsPartial({ delivery: settledPDA, customer: buyer.publicKey })
+      .signers([buyer])
+      .rpc();
+    const [receiptPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("receipt"), settledPDA.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .completeDeliverySpl(settledId)
+      .accountsPartial({
+        delivery: settledPDA,
+        escrowToken: settledEscrow,
+        vehicle: vehiclePDA,
+        geoBucket: null,
+        fleet: null,
+        manifest: null,
+        region: regionPDA,
+        receipt: receiptPDA,
+        customerProfile: PublicKey.findProgramAddressSync(
+          [Buffer.from("customer"), buyer.publicKey.toBuffer()],
+          program.programId
+        )[0],
+        operatorProfile: operatorProfilePDA,
+        vehicleOperator: vehicleOperator.publicKey,
+        operatorToken,
+        treasuryToken,
+        customerToken: buyerToken,
+        fleetToken: null,
+        insuranceToken,
+        mint: feeMint,
+        config: configPDA,
+        customer: buyer.publicKey,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(settledId),
+      })
+      .signers([vehicleOperator])
+      .rpc();
+    const receipt = await program.account.receipt.fetch(receiptPDA);
+    expect(await balance(operatorToken)).to.equal(Number(receipt.operatorPayment));
+    expect(await provider.connection.getAccountInfo(settledEscrow)).to.be.null;
+
+    await setMask(0);
+  });
+
+  it("Keeps confidential orders behind a prover and their refund path to confidential escrows", async () => {
+    const shipper = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(shipper.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const mint = await createMint(provider.connection, authority, authority.publicKey, null, 6);
+    const shipperToken = await createAccount(provider.connection, shipper, mint, shipper.publicKey);
+    await program.methods
+      .setAcceptedMint(mint)
+      .accountsPartial({ config: configPDA, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    const escrowTokenFor = (id: BN) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("escrow_token"), shipper.publicKey.toBuffer(), id.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      )[0];
+
+    // Nobody can decrypt the escrow until a prover is named
+    const hiddenId = new BN(79001);
+    const [hiddenPDA] = findDeliveryPDAs(shipper.publicKey, hiddenId);
+    const zeroProof = Keypair.generate().publicKey;
+    try {
+      await program.methods
+        .createDeliveryOrderConfidential(
+          hiddenId,
+          "40.7128,-74.0060",
+          "40.7589,-73.9851",
+          regionId,
+          Array(36).fill(0),
+          Array(36).fill(0),
+          Array(36).fill(0),
+          Array(32).fill(0)
+        )
+        .accountsPartial({
+          delivery: hiddenPDA,
+          escrowToken: escrowTokenFor(hiddenId),
+          customerToken: shipperToken,
+          mint,
+          pubkeyValidityProof: zeroProof,
+          equalityProof: zeroProof,
+          ciphertextValidityProof: zeroProof,
+          rangeProof: zeroProof,
+          commitmentProof: zeroProof,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(hiddenId),
+          region: regionPDA,
+          config: configPDA,
+          customer: shipper.publicKey,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([shipper])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("ConfidentialDisabled");
+    }
+
+    // A lamport order cannot be refunded through the confidential path
+    const plainId = new BN(79002);
+    const [plainPDA, plainEscrowPDA] = findDeliveryPDAs(shipper.publicKey, plainId);
+    await program.methods
+      .createDeliveryOrder(plainId, paymentAmount, "pickup", "delivery", regionId, 0, null, null, null)
+      .accountsPartial({
+        delivery: plainPDA,
+        escrow: plainEscrowPDA,
+        dailyStats: findDailyStatsPDA(),
+        globalStats: findGlobalStatsPDA(plainId),
+        usdFeed: usdFeedPDA,
+        eurFeed: null,
+        pythPrice: null,
+        openOrders: null,
+        voucher: null,
+        voucherUse: null,
+        config: configPDA,
+        customer: shipper.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([shipper])
+      .rpc();
+    try {
+      await program.methods
+        .cancelDeliveryConfidential(plainId, [])
+        .accountsPartial({
+          delivery: plainPDA,
+          openOrders: null,
+          escrowToken: escrowTokenFor(plainId),
+          mint,
+          zeroBalanceProof: zeroProof,
+          customer: shipper.publicKey,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          globalStats: findGlobalStatsPDA(plainId),
+        })
+        .signers([shipper])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("NotConfidential");
+    }
+    const plain = await program.account.delivery.fetch(plainPDA);
+    expect(plain.status).to.deep.equal({ pending: {} });
+    expect(plain.confidential).to.equal(false);
+  });
+
+  it("Hands the authority over in two steps", async () => {
+    const successor = Keypair.generate();
+
+    await program.methods
+      .proposeAuthority(successor.publicKey)
+      .accountsPartial({
+        config: configPDA,
+        authority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    let config = await program.account.config.fetch(configPDA);
+    expect(config.authority.toString()).to.equal(authority.publicKey.toString());
+
+    await program.methods
+      .acceptAuthority()
+      .accountsPartial({
+        config: configPDA,
+        newAuthority: successor.publicKey,
+      })
+      .signers([successor])
+      .rpc();
+
+    config = await program.account.config.fetch(configPDA);
+    expect(config.authority.toString()).to.equal(successor.publicKey.toString());
+
+    // Hand it back so later tests keep using the original authority
+    await program.methods
+      .proposeAuthority(authority.publicKey)
+      .accountsPartial({
+        config: configPDA,
+        authority: successor.publicKey,
+      })
+      .signers([successor])
+      .rpc();
+    await program.methods
+      .acceptAuthority()
+      .accountsPartial({
+        config: configPDA,
+        newAuthority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+  });
+
+  it("Rejects waitlist acceptance by a wallet that does not operate the vehicle", async () => {
+    const intruder = Keypair.generate();
+    const [queuedPDA] = findDeliveryPDAs(customer.publicKey, new BN(60001));
+
+    try {
+      await program.methods
+        .acceptNext(new BN(60001))
+        .accountsPartial({
+          delivery: queuedPDA,
+          vehicle: vehiclePDA,
+          geoBucket: null,
+          openOrders: null,
+          fleet: null,
+          region: regionPDA,
+          config: configPDA,
+          operator: intruder.publicKey,
+        })
+        .signers([intruder])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("NotVehicleOperator");
+    }
+  });
+
+  it("Carries several orders on one vehicle and settles them in one batch", async () => {
+    const { vehicle, operator, profile } = await registerOperatedVehicle("AV-BATCH");
+    const ids = [new BN(88001), new BN(88002)];
+    const thirdId = new BN(88003);
+
+    // Only the operator sets how many orders the vehicle carries
+    try {
+      await program.methods
+        .setMaxActiveOrders(2)
+        .accountsPartial({ vehicle, geoBucket: null, operator: customer.publicKey })
+        .signers([customer])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("Unauthorized");
+    }
+    await program.methods
+      .setMaxActiveOrders(2)
+      .accountsPartial({ vehicle, geoBucket: null, operator: operator.publicKey })
+      .signers([operator])
+      .rpc();
+
+    for (const id of ids) {
+      await bookOrder(customer, id);
+      await deliverOrder(customer, id, vehicle, operator);
+    }
+    const carrying = await program.account.vehicle.fetch(vehicle);
+    expect(carrying.activeOrders).to.equal(2);
+    expect(carrying.isBusy).to.be.true;
+
+    // A third order is over the limit
+    await bookOrder(customer, thirdId);
+    try {
+      await deliverOrder(customer, thirdId, vehicle, operator);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("VehicleNotAvailable");
+    }
+
+    const [earningsPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("earnings"), operator.publicKey.toBuffer()],
+      program.programId
+    );
+    const [historyPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vehicle_history"), vehicle.toBuffer()],
+      program.programId
+    );
+    const [taxVaultPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("tax_vault"), configPDA.toBuffer()],
+      program.programId
+    );
+    const remainingAccounts = ids.flatMap((id) => {
+      const [delivery, escrow] = findDeliveryPDAs(customer.publicKey, id);
+      const [referral] = PublicKey.findProgramAddressSync(
+        [Buffer.from("referral"), customer.publicKey.toBuffer()],
+        program.programId
+      );
+      const [receipt] = PublicKey.findProgramAddressSync([Buffer.from("receipt"), delivery.toBuffer()], program.programId);
+      return [
+        { pubkey: delivery, isSigner: false, isWritable: true },
+        { pubkey: escrow, isSigner: false, isWritable: true },
+        { pubkey: customer.publicKey, isSigner: false, isWritable: true },
+        { pubkey: findCustomerPDA(customer.publicKey), isSigner: false, isWritable: true },
+        { pubkey: referral, isSigner: false, isWritable: true },
+        { pubkey: receipt, isSigner: false, isWritable: true },
+      ];
+    });
+    const settleBatch = (signer: Keypair) =>
+      program.methods
+        .completeDeliveriesBatch(regionId)
+        .accountsPartial({
+          vehicle,
+          geoBucket: null,
+          fleet: null,
+          fleetPayout: null,
+          vehicleOperator: signer.publicKey,
+          dispatcherKey: null,
+          earnings: earningsPDA,
+          vehicleHistory: historyPDA,
+          operatorProfile: profile,
+          dailyStats: findDailyStatsPDA(),
+          globalStats: findGlobalStatsPDA(new BN(regionId)),
+          treasury: treasuryPDA,
+          taxVault: taxVaultPDA,
+          charity: null,
+          insurancePool: null,
+          operatorReferral: null,
+          pythPrice: null,
+          config: configPDA,
+          region: regionPDA,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(remainingAccounts)
+        .signers([signer])
+        .rpc();
+
+    // Someone other than the operator or its delegates cannot settle them
+    try {
+      await settleBatch(customer);
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("Unauthorized");
+    }
+
+    await settleBatch(operator);
+    for (const id of ids) {
+      const [delivery, escrow] = findDeliveryPDAs(customer.publicKey, id);
+      expect((await program.account.delivery.fetch(delivery)).status).to.deep.equal({ completed: {} });
+      expect(await provider.connection.getAccountInfo(escrow)).to.be.null;
+    }
+    const settled = await program.account.vehicle.fetch(vehicle);
+    expect(settled.activeOrders).to.equal(0);
+    expect(settled.isBusy).to.be.false;
+    expect(Number(settled.totalDeliveries)).to.equal(2);
+    const earnings = await program.account.earnings.fetch(earningsPDA);
+    expect(Number(earnings.accrued)).to.be.greaterThan(Number(paymentAmount));
+
+    // With room again the vehicle takes the waiting order
+    await deliverOrder(customer, thirdId, vehicle, operator);
+  });
+
+  it("Holds recovery sweeps to the authority and the timelock", async () => {
+    const id = new BN(88101);
+    await bookOrder(customer, id);
+    const [delivery, escrow] = findDeliveryPDAs(customer.publicKey, id);
+    const recoveryWallet = Keypair.generate().publicKey;
+    const [recoveryPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("recovery"), configPDA.toBuffer(), escrow.toBuffer()],
+      program.programId
+    );
+
+    try {
+      await program.methods
+        .initiateRecovery(escrow, recoveryWallet)
+        .accountsPartial({
+          recovery: recoveryPDA,
+          config: configPDA,
+          proposal: null,
+          authority: customer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([customer])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("Unauthorized");
+    }
+
+    await program.methods
+      .initiateRecovery(escrow, recoveryWallet)
+      .accountsPartial({
+        recovery: recoveryPDA,
+        config: configPDA,
+        proposal: null,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+    const request = await program.account.recoveryRequest.fetch(recoveryPDA);
+    expect(request.executableAt.sub(request.initiatedAt).toNumber()).to.equal(7 * 24 * 60 * 60);
+
+    try {
+      await program.methods
+        .executeRecovery(escrow, null)
+        .accountsPartial({
+          recovery: recoveryPDA,
+          target: escrow,
+          recoveryAddress: recoveryWallet,
+          delivery,
+          mint: null,
+          recoveryToken: null,
+          tokenProgram: null,
+          config: configPDA,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have failed");
+    } catch (error) {
+      expect(error.message).to.include("RecoveryTimelockActive");
+    }
+
+    await program.methods
+      .cancelRecovery(escrow)
+      .accountsPartial({ recovery: recoveryPDA, config: configPDA, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    expect(await provider.connection.getAccountInfo(recoveryPDA)).to.be.null;
+    expect(await provider.connection.getBalance(escrow)).to.be.greaterThan(0);
+  });
+
+  it("Settles SOL vouchers only for the current opening of the channel", async () => {
+    const { vehicle, operator } = await registerOperatedVehicle("AV-CHAN");
+    const payer = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(payer.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const [channelPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("channel"), payer.publicKey.toBuffer(), vehicle.toBuffer()],
+      program.programId
+    );
+    const [noncePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("channel_nonce"), payer.publicKey.toBuffer(), vehicle.toBuffer()],
+      program.programId
+    );
+    const [earningsPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("earnings"), operator.publicKey.toBuffer()],
+      program.programId
+    );
+    const deposit = new BN(LAMPORTS_PER_SOL / 10);
+    const open = () =>
+      program.methods
+        .openChannel(deposit, new BN(3600))
+        .accountsPartial({
+          channel: channelPDA,
+          channelNonce: noncePDA,
+          vehicle,
+          config: configPDA,
+          customer: payer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([payer])
+        .rpc();
+    const settle = (cumulative: BN, verify: ReturnType<typeof signed>, caller: Keypair = operator) =>
+      program.methods
+        .settleChannel(cumulative)
+        .accountsPartial({
+          channel: channelPDA,
+          vehicle,
+          earnings: earningsPDA,
+          treasury: treasuryPDA,
+          config: configPDA,
+          instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          operator: caller.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .preInstructions([verify])
+        .signers([caller])
+        .rpc();
+    const close = (signer: Keypair) =>
+      program.methods
+        .closeChannel()
+        .accountsPartial({ channel: channelPDA, vehicle, customer: payer.publicKey, signer: signer.publicKey })
+        .signers([signer])
+        .rpc();
+
+    await open();
+    const first = await program.account.paymentChannel.fetch(channelPDA);
+    expect(first.nonce.toNumber()).to.equal(1);
+    const amount = new BN(2_500_000);
+    const good = voucher(channelPDA, first.nonce, amount);
+
+    // Signed by someone other than the customer
+    await expectVoucherRejected(settle(amount, signed(operator, good)));
+    // Signed over a different amount than the one claimed
+    await expectVoucherRejected(settle(amount, signed(payer, voucher(channelPDA, first.nonce, new BN(3_000_000)))));
+    // Signed for an earlier opening
+    await expectVoucherRejected(settle(amount, signed(payer, voucher(channelPDA, new BN(0), amount))));
+    // Only the vehicle's operator settles
+    await expectVoucherRejected(settle(amount, signed(payer, good), payer), "NotVehicleOperator");
+
+    await settle(amount, signed(payer, good));
+    expect((await program.account.paymentChannel.fetch(channelPDA)).settled.toString()).to.equal(amount.toString());
+    expect(Number((await program.account.earnings.fetch(earningsPDA)).accrued)).to.be.greaterThan(0);
+    // The same cumulative amount cannot be paid twice
+    await expectVoucherRejected(settle(amount, signed(payer, good)));
+
+    // The customer waits for expiry; the operator can close at once and
+    // the unspent deposit goes back to the customer
+    await expectVoucherRejected(close(payer), "ChannelNotExpired");
+    const before = await provider.connection.getBalance(payer.publicKey);
+    await close(operator);
+    expect(await provider.connection.getAccountInfo(channelPDA)).to.be.null;
+    expect(await provider.connection.getBalance(payer.publicKey)).to.be.greaterThan(
+      before + deposit.sub(amount).toNumber()
+    );
+
+    // Reopened straight away, even within the same second, the channel
+    // gets a new nonce, so the old voucher cannot be replayed
+    await open();
+    const second = await program.account.paymentChannel.fetch(channelPDA);
+    expect(second.nonce.toNumber()).to.equal(2);
+    expect(second.settled.toNumber()).to.equal(0);
+    await expectVoucherRejected(settle(amount, signed(payer, good)));
+    await settle(amount, signed(payer, voucher(channelPDA, second.nonce, amount)));
+    await close(operator);
+  });
+
+  it("Settles token vouchers against the token channel's own nonce", async () => {
+    const { vehicle, operator } = await registerOperatedVehicle("AV-TCHAN");
+    const payer = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(payer.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const mint = await createMint(provider.connection, authority, authority.publicKey, null, 6);
+    await program.methods
+      .setAcceptedMint(mint)
+      .accountsPartial({ config: configPDA, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    const funded = 50_000_000;
+    const payerToken = await createAccount(provider.connection, payer, mint, payer.publicKey);
+    await mintTo(provider.connection, authority, mint, payerToken, authority, funded);
+    const operatorToken = await createAccount(provider.connection, operator, mint, operator.publicKey);
+    const config = await program.account.config.fetch(configPDA);
+    const treasuryToken = await createAccount(provider.connection, authority, mint, config.treasury, Keypair.generate());
+    const balance = async (account: PublicKey) => Number((await getAccount(provider.connection, account)).amount);
+
+    const [channelPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("token_channel"), payer.publicKey.toBuffer(), vehicle.toBuffer()],
+      program.programId
+    );
+    const [vaultPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("channel_token"), channelPDA.toBuffer()],
+      program.programId
+    );
+    const [noncePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("channel_nonce"), payer.publicKey.toBuffer(), vehicle.toBuffer()],
+      program.programId
+    );
+    const deposit = new BN(20_000_000);
+    await program.methods
+      .openChannelSpl(deposit, new BN(3600))
+      .accountsPartial({
+        channel: channelPDA,
+        channelNonce: noncePDA,
+        channelToken: vaultPDA,
+        customerToken: payerToken,
+        mint,
+        vehicle,
+        config: configPDA,
+        customer: payer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([payer])
+      .rpc();
+    const channel = await program.account.tokenChannel.fetch(channelPDA);
+    expect(channel.nonce.toNumber()).to.equal(1);
+
+    const settle = (cumulative: BN, verify: ReturnType<typeof signed>) =>
+      program.methods
+        .settleChannelSpl(cumulative)
+        .accountsPartial({
+          channel: channelPDA,
+          channelToken: vaultPDA,
+          vehicle,
+          operatorToken,
+          treasuryToken,
+          mint,
+          config: configPDA,
+          instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          operator: operator.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .preInstructions([verify])
+        .signers([operator])
+        .rpc();
+    const amount = new BN(5_000_000);
+
+    // Wrong signer, wrong amount, another opening and the SOL channel's
+    // address are all refused
+    await expectVoucherRejected(settle(amount, signed(operator, voucher(channelPDA, channel.nonce, amount))));
+    await expectVoucherRejected(settle(amount, signed(payer, voucher(channelPDA, channel.nonce, new BN(6_000_000)))));
+    await expectVoucherRejected(settle(amount, signed(payer, voucher(channelPDA, new BN(2), amount))));
+    const [solChannelPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("channel"), payer.publicKey.toBuffer(), vehicle.toBuffer()],
+      program.programId
+    );
+    await expectVoucherRejected(settle(amount, signed(payer, voucher(solChannelPDA, channel.nonce, amount))));
+
+    await settle(amount, signed(payer, voucher(channelPDA, channel.nonce, amount)));
+    expect((await balance(operatorToken)) + (await balance(treasuryToken))).to.equal(amount.toNumber());
+    await expectVoucherRejected(settle(amount, signed(payer, voucher(channelPDA, channel.nonce, amount))));
+
+    await program.methods
+      .closeChannelSpl()
+      .accountsPartial({
+        channel: channelPDA,
+        channelToken: vaultPDA,
+        customerToken: payerToken,
+        mint,
+        vehicle,
+        customer: payer.publicKey,
+        signer: operator.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([operator])
+      .rpc();
+    expect(await provider.connection.getAccountInfo(vaultPDA)).to.be.null;
+    expect(await balance(payerToken)).to.equal(funded - amount.toNumber());
+  });
 });
@@ -1,20 +1,208 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::sysvar::instructions::{load_current_index_checked, load_instruction_at_checked};
+use anchor_spl::token_2022::spl_token_2022::extension::confidential_transfer::{
+    instruction as confidential_ix, ConfidentialTransferAccount, DecryptableBalance,
+};
+use anchor_spl::token_2022::spl_token_2022::extension::transfer_fee::{TransferFee, TransferFeeConfig};
+use anchor_spl::token_2022::spl_token_2022::proof::ProofLocation;
+use anchor_spl::token_2022::spl_token_2022::solana_zk_sdk::zk_elgamal_proof_program::{
+    self as zk_elgamal_proof_program,
+    proof_data::{CiphertextCommitmentEqualityProofContext, ProofType},
+    state::ProofContextState,
+};
+use anchor_spl::token_2022::Token2022;
+use anchor_spl::token_2022::spl_token_2022::extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions};
+use anchor_spl::token_2022_extensions::transfer_fee::{harvest_withheld_tokens_to_mint, HarvestWithheldTokensToMint};
+use anchor_spl::associated_token::{self, AssociatedToken};
+use mpl_bubblegum::accounts::TreeConfig;
+use mpl_bubblegum::instructions::MintV1CpiBuilder;
+use mpl_bubblegum::programs::{SPL_ACCOUNT_COMPRESSION_ID, SPL_NOOP_ID};
+use mpl_bubblegum::types::{MetadataArgs, TokenProgramVersion, TokenStandard};
+use anchor_spl::metadata::mpl_token_metadata::accounts::MasterEdition;
+use anchor_spl::metadata::mpl_token_metadata::types::DataV2;
+use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
+use anchor_spl::metadata::{
+    create_master_edition_v3, create_metadata_accounts_v3, CreateMasterEditionV3, CreateMetadataAccountsV3, Metadata,
+};
+use anchor_spl::token::{self, Token};
+use anchor_spl::token_interface::{self, CloseAccount, InitializeAccount3, Mint, TokenAccount, TokenInterface, TransferChecked};
 
 declare_id!("11111111111111111111111111111112");
 
+// Discount granted on the combined escrow when two orders are merged, 100 = 1%
+pub const MERGE_DISCOUNT_BPS: u64 = 100;
+// Maximum number of waitlisted orders held per region
+pub const MAX_QUEUE_LEN: usize = 32;
+// Completed orders needed to reach loyalty tiers 1, 2 and 3
+pub const LOYALTY_TIER_THRESHOLDS: [u64; 3] = [10, 50, 200];
+// Number of fee-rate changes retained in the FeeHistory ring
+pub const MAX_FEE_HISTORY: usize = 32;
+// Number of completed deliveries retained in a vehicle's history ring
+pub const MAX_VEHICLE_HISTORY: usize = 64;
+// Length of a DailyStats bucket
+pub const SECONDS_PER_DAY: i64 = 86_400;
+// GlobalStats is split over this many shards so order traffic does not
+// serialize on one writable account
+pub const GLOBAL_STATS_SHARDS: u64 = 16;
+// Upper bound for a region's VAT/GST rate, 5000 = 50%
+pub const MAX_TAX_BPS: u16 = 5000;
+// Carried as `schema_version` on every emitted event. Bump it when an event's
+// layout changes and keep the previous struct under a versioned name (e.g.
+// `OrderCreatedV1`) so indexers can still decode historical logs.
+pub const EVENT_SCHEMA_VERSION: u8 = 1;
+// Permission bits carried by delegated keys; a key may only call the
+// handlers whose bit is set
+pub const PERM_ACCEPT: u8 = 1 << 0;
+pub const PERM_COMPLETE: u8 = 1 << 1;
+pub const PERM_CLAIM: u8 = 1 << 2;
+pub const PERM_UPDATE_LOCATION: u8 = 1 << 3;
+pub const PERM_CANCEL: u8 = 1 << 4;
+pub const PERM_ALL: u8 = PERM_ACCEPT | PERM_COMPLETE | PERM_CLAIM | PERM_UPDATE_LOCATION | PERM_CANCEL;
+// Delay between initiating and executing an emergency recovery sweep
+pub const RECOVERY_TIMELOCK_SECONDS: i64 = 7 * SECONDS_PER_DAY;
+// Size of the config's admin approver set
+pub const MAX_APPROVERS: usize = 5;
+
+// Wallets that can take a cut of each platform fee, such as an insurance
+// pool, a referral pool or a city levy
+pub const MAX_FEE_RECIPIENTS: usize = 5;
+
+// Ceiling on the referral share so a customer's and an operator's referrer
+// together never take more than the treasury's part of a fee
+pub const MAX_REFERRAL_BPS: u16 = 5000;
+// Timelock on queued fee and treasury changes until a change sets another
+pub const DEFAULT_CONFIG_CHANGE_DELAY_SECONDS: i64 = 2 * SECONDS_PER_DAY;
+// Shortest config change delay the authority may choose, so watchers always
+// get a window to react
+pub const MIN_CONFIG_CHANGE_DELAY_HOURS: u16 = 24;
+// Longest config change delay the authority may choose (30 days)
+pub const MAX_CONFIG_CHANGE_DELAY_HOURS: u16 = 720;
+// Token-2022 mint extensions the authority may allow for token-paid orders;
+// a mint carrying any other extension (transfer hooks, non-transferable,
+// permanent delegates that could drain an escrow) is always refused. Bit 5
+// is retired and no longer accepted in the mask
+pub const TOKEN_EXT_TRANSFER_FEE: u16 = 1 << 0;
+pub const TOKEN_EXT_INTEREST_BEARING: u16 = 1 << 1;
+pub const TOKEN_EXT_METADATA: u16 = 1 << 2;
+pub const TOKEN_EXT_MINT_CLOSE_AUTHORITY: u16 = 1 << 3;
+pub const TOKEN_EXT_DEFAULT_ACCOUNT_STATE: u16 = 1 << 4;
+pub const TOKEN_EXT_CONFIDENTIAL_TRANSFER: u16 = 1 << 6;
+pub const TOKEN_EXT_ALL: u16 = TOKEN_EXT_TRANSFER_FEE | TOKEN_EXT_INTEREST_BEARING | TOKEN_EXT_METADATA
+    | TOKEN_EXT_MINT_CLOSE_AUTHORITY | TOKEN_EXT_DEFAULT_ACCOUNT_STATE | TOKEN_EXT_CONFIDENTIAL_TRANSFER;
+// A confidential escrow is funded by exactly one incoming transfer, so it
+// refuses any further pending credits
+pub const CONFIDENTIAL_ESCROW_MAX_CREDITS: u64 = 1;
+// Remaining accounts per confidential payout leg: destination token account,
+// then its equality, ciphertext validity and range proof context accounts
+pub const CONFIDENTIAL_LEG_ACCOUNTS: usize = 4;
+// Longest receipt NFT base URI; the delivery address appended to it keeps the
+// full URI within Metaplex's 200-byte limit
+pub const MAX_RECEIPT_URI_LEN: usize = 156;
+// Highest hazard level a weather oracle may report for a region
+pub const MAX_HAZARD_LEVEL: u8 = 3;
+// Share of a hazard surcharge kept by the platform; the rest goes to the operator
+pub const HAZARD_PLATFORM_SHARE_BPS: u64 = 1000;
+// Highest congestion index a traffic oracle may report; the index is the
+// percentage by which SLA deadlines are stretched
+pub const MAX_CONGESTION_INDEX: u8 = 100;
+// Oldest hazard or congestion reading still applied; past it the region is
+// treated as clear until its oracle reports again
+pub const MAX_REGION_READING_AGE_SECONDS: i64 = 3600;
+// Oldest price-feed update accepted when quoting an order
+pub const MAX_QUOTE_AGE_SECONDS: i64 = 300;
+// Oldest Pyth SOL/USD price a USD order is booked or settled against
+pub const PYTH_MAX_AGE_SECONDS: u64 = 60;
+// Widest Pyth confidence interval accepted, as a share of the price
+pub const PYTH_MAX_CONF_BPS: u64 = 100;
+// The top byte of a delivery id is the ordering device's namespace. The id
+// is the delivery PDA seed, so terminals sharing one customer wallet that
+// number orders independently in their own namespace never collide
+pub const DELIVERY_NAMESPACE_SHIFT: u32 = 56;
+// Geohash characters per GeoBucket (6 ≈ 1.2 km × 0.6 km cells)
+pub const GEOHASH_LEN: usize = 6;
+// Standard geohash base32 alphabet
+pub const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+// Idle vehicles tracked per GeoBucket
+pub const MAX_BUCKET_VEHICLES: usize = 32;
+// Pending orders listed per region's OpenOrders board
+pub const MAX_OPEN_ORDERS: usize = 64;
+// Preferred vehicles a customer may list on their profile
+pub const MAX_PREFERRED_VEHICLES: usize = 8;
+// Longest exclusive-acceptance window a customer may ask for
+pub const MAX_EXCLUSIVE_WINDOW_SECONDS: u32 = 600;
+// Packages one order may carry in its manifest
+pub const MAX_PACKAGES: usize = 16;
+// How long a vehicle must wait at pickup after its arrival is attested
+// before it can claim the sender's no-show deposit
+pub const NO_SHOW_WAIT_SECONDS: i64 = 600;
+// Wrong PINs a vehicle may submit for one order before only the customer's
+// own signature can confirm it
+pub const MAX_PIN_ATTEMPTS: u8 = 3;
+// Highest platform fee the authority may set, 1000 = 10%
+pub const MAX_FEE_BPS: u16 = 1000;
+// Highest surge premium on top of the quoted fare, 20000 = up to 3x
+pub const MAX_SURGE_BPS: u16 = 20000;
+// Largest share of the operator payment a fleet may take, 5000 = 50%
+pub const MAX_FLEET_SHARE_BPS: u16 = 5000;
+// Evidence hashes each side may attach to a dispute
+pub const MAX_DISPUTE_EVIDENCE: usize = 8;
+// How long the arbiter has to rule before anyone may settle a dispute at
+// its default split
+pub const DISPUTE_TIMEOUT_SECONDS: i64 = 14 * SECONDS_PER_DAY;
+// Star scale customers rate completed deliveries on
+pub const MAX_RATING: u8 = 5;
+// Route segments one order may be paid out over
+pub const MAX_MILESTONES: u8 = 16;
+// Stops one route may visit, the pickup included
+pub const MAX_ROUTE_STOPS: usize = 8;
+// Longest bidding window an auctioned order may stay open for
+pub const MAX_AUCTION_SECONDS: i64 = 3600;
+// How long after bidding closes the winner can still be awarded an order
+// without its vehicle account being shown as unavailable
+pub const AUCTION_AWARD_SECONDS: i64 = 3600;
+// Bytes a customer signs per channel voucher: the channel address, the
+// channel's nonce and the cumulative amount owed, both little-endian
+pub const VOUCHER_LEN: usize = 32 + 8 + 8;
+// Longest a vehicle session key may stay valid, 24 hours
+pub const MAX_SESSION_SECONDS: i64 = 24 * 60 * 60;
+// How long stake asked back stays slashable before the operator can take it
+pub const UNSTAKE_COOLDOWN_SECONDS: i64 = 7 * SECONDS_PER_DAY;
+// How long a finished order stays open for ratings and tips before anyone
+// may close it and return its rent to the customer
+pub const DELIVERY_CLOSE_GRACE_SECONDS: i64 = 30 * SECONDS_PER_DAY;
+// Orders one complete_deliveries_batch call may settle
+pub const MAX_BATCH_SETTLEMENTS: usize = 8;
+// Remaining accounts per batched order: delivery, escrow, customer,
+// customer profile, the customer's referral PDA and the receipt PDA to create
+pub const BATCH_SETTLEMENT_ACCOUNTS: usize = 6;
+
 #[program]
 pub mod autonomous_vehicle_payments {
     use super::*;
 
     // authority: Address, System administrator, 9PJ8I...3555
     // fee_bps: Number, Platform fee percentage, 250 = 2.5%
-    // treasury: Address, Fee collection address, 8KL9M...4444
+    // treasury: Address, Owner of the token accounts that collect SPL fees, 8KL9M...4444
+    // max_fee_step_bps: Number, Largest fee move allowed per window, 50 = 0.5%
+    // fee_step_period_hours: Number, Length of the fee rate-limit window, 24 = one day
     pub fn initialize_config(
         ctx: Context<InitializeConfig>,
         fee_bps: u16,
         treasury: Pubkey,
+        max_fee_step_bps: u16,
+        fee_step_period_hours: u16,
     ) -> Result<()> {
+        require!(
+            max_fee_step_bps > 0 && fee_step_period_hours > 0,
+            ErrorCode::InvalidParameter
+        );
+        require!(fee_bps <= MAX_FEE_BPS, ErrorCode::FeeTooHigh);
+        require!(treasury != Pubkey::default(), ErrorCode::InvalidTreasury);
+
         let config = &mut ctx.accounts.config;
         config.bump = ctx.bumps.config;
         config.authority = ctx.accounts.authority.key();
@@ -23,33 +211,794 @@ pub mod autonomous_vehicle_payments {
         config.fee_bps = fee_bps;
         config.treasury = treasury;
         config.version = 1;
+        config.total_fees_collected = 0;
+        config.last_revenue_epoch = 0;
+        config.fees_at_last_rollover = 0;
+        config.next_invoice_number = 1;
+        config.daily_escrow_cap = 0;
+        config.daily_payout_cap = 0;
+        config.volume_day = DailyStats::day_of(Clock::get()?.unix_timestamp);
+        config.escrowed_today = 0;
+        config.paid_out_today = 0;
+        config.max_withdrawal_per_epoch = 0;
+        config.withdrawal_epoch = 0;
+        config.withdrawn_this_epoch = 0;
+        config.pending_withdrawal_limit = 0;
+        config.pending_limit_epoch = 0;
+        config.settlement_only = false;
+        config.max_fee_step_bps = max_fee_step_bps;
+        config.fee_step_period = fee_step_period_hours as i64 * 3600;
+        config.fee_window_start = Clock::get()?.unix_timestamp;
+        config.fee_at_window_start = fee_bps;
+        config.robot_max_order_value = 0;
+        config.hazard_surcharge_bps = 0;
+        config.fee_rounding = FeeRounding::Floor;
+        config.charity = Pubkey::default();
+        config.no_show_deposit = 0;
+        config.accepted_mint = Pubkey::default();
+        config.expiry_seconds = 0;
+        config.pending_authority = Pubkey::default();
+        config.auto_release_seconds = 0;
+        config.arbiter = Pubkey::default();
+        config.tips_fee_exempt = true;
+        config.min_stake = 0;
+        config.slash_bps = 0;
+        config.abandon_seconds = 0;
+        config.schedule_window_seconds = 0;
+        config.surge_bps = 0;
+        config.surge_dispatcher = Pubkey::default();
+        config.class_fee_bps = [None; VehicleClass::COUNT];
+        config.approvers = [Pubkey::default(); MAX_APPROVERS];
+        config.approval_threshold = 0;
+        config.config_change_delay_seconds = DEFAULT_CONFIG_CHANGE_DELAY_SECONDS;
+        config.pending_config_change = None;
+        config.insurance_bps = 0;
+        config.allowed_token_extensions = 0;
+        config.confidential_prover = Pubkey::default();
+        config.receipt_nft_uri = String::new();
+        config.receipt_tree = Pubkey::default();
+        config.fee_recipients = [FeeRecipient::default(); MAX_FEE_RECIPIENTS];
+        config.referral_bps = 0;
+        config.slash_treasury_bps = 0;
+        config.pyth_price_update = Pubkey::default();
+        config.pyth_feed_id = [0; 32];
+
+        let fee_history = &mut ctx.accounts.fee_history;
+        fee_history.bump = ctx.bumps.fee_history;
+        fee_history.config = config.key();
+        fee_history.push(FeeChange {
+            old_bps: 0,
+            new_bps: fee_bps,
+            changed_at: Clock::get()?.unix_timestamp,
+            proposer: config.authority,
+            class: None,
+        });
+
+        let tax_vault = &mut ctx.accounts.tax_vault;
+        tax_vault.bump = ctx.bumps.tax_vault;
+        tax_vault.config = config.key();
+        tax_vault.total_collected = 0;
+        tax_vault.total_withdrawn = 0;
+
+        let treasury = &mut ctx.accounts.treasury;
+        treasury.bump = ctx.bumps.treasury;
+        treasury.config = config.key();
+        treasury.total_collected = 0;
+        treasury.total_withdrawn = 0;
+        treasury.recipient_owed = [0; MAX_FEE_RECIPIENTS];
+        Ok(())
+    }
+
+    // daily_escrow_cap: Number, Max lamports escrowed per day, 0 = unlimited
+    // daily_payout_cap: Number, Max lamports released per day, 0 = unlimited
+    pub fn set_volume_caps(
+        ctx: Context<UpdateConfig>,
+        daily_escrow_cap: u64,
+        daily_payout_cap: u64,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.daily_escrow_cap = daily_escrow_cap;
+        config.daily_payout_cap = daily_payout_cap;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // fee_rounding: Enum, Rounding applied to fee, penalty and split math, { roundHalfEven: {} }
+    pub fn set_fee_rounding(ctx: Context<UpdateConfig>, fee_rounding: FeeRounding) -> Result<()> {
+        ctx.accounts.config.fee_rounding = fee_rounding;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // charity: Pubkey, Account that receives customer round-ups, Pubkey::default() disables round-ups
+    pub fn set_charity(ctx: Context<UpdateConfig>, charity: Pubkey) -> Result<()> {
+        ctx.accounts.config.charity = charity;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // accepted_mint: Address, SPL mint accepted for token-paid orders, EPjFW...Dt1v (USDC), Pubkey::default() disables
+    pub fn set_accepted_mint(ctx: Context<UpdateConfig>, accepted_mint: Pubkey) -> Result<()> {
+        ctx.accounts.config.accepted_mint = accepted_mint;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // allowed_token_extensions: Number, Bitmask of TOKEN_EXT_* flags a Token-2022 accepted mint may carry, 1 = transfer fee, 0 = none
+    pub fn set_allowed_token_extensions(ctx: Context<UpdateConfig>, allowed_token_extensions: u16) -> Result<()> {
+        require!(allowed_token_extensions & !TOKEN_EXT_ALL == 0, ErrorCode::InvalidParameter);
+        ctx.accounts.config.allowed_token_extensions = allowed_token_extensions;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // confidential_prover: Address, Key that holds confidential escrows' decryption keys and settles them, 9PrV...4444, Pubkey::default() disables
+    pub fn set_confidential_prover(ctx: Context<UpdateConfig>, confidential_prover: Pubkey) -> Result<()> {
+        ctx.accounts.config.confidential_prover = confidential_prover;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // receipt_nft_uri: String, Base URI for receipt NFT metadata; the delivery address is appended, "https://receipts.example.com/", "" disables
+    pub fn set_receipt_nft_uri(ctx: Context<UpdateConfig>, receipt_nft_uri: String) -> Result<()> {
+        require!(receipt_nft_uri.len() <= MAX_RECEIPT_URI_LEN, ErrorCode::InvalidParameter);
+        ctx.accounts.config.receipt_nft_uri = receipt_nft_uri;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // price_update: Address, Pyth SOL/USD price update account USD orders are priced against, 7UVim...Ez2E, Pubkey::default() disables USD orders
+    // feed_id: Array, Pyth feed id the account must carry, the 32-byte SOL/USD id
+    pub fn set_pyth_feed(ctx: Context<UpdateConfig>, price_update: Pubkey, feed_id: [u8; 32]) -> Result<()> {
+        ctx.accounts.config.pyth_price_update = price_update;
+        ctx.accounts.config.pyth_feed_id = feed_id;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // receipt_tree: Address, Bubblegum Merkle tree delegated to the config PDA for compressed receipts, 4TrEe...8888, Pubkey::default() disables
+    pub fn set_receipt_tree(ctx: Context<UpdateConfig>, receipt_tree: Pubkey) -> Result<()> {
+        ctx.accounts.config.receipt_tree = receipt_tree;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // expiry_seconds: Number, Time an order may wait for a vehicle before anyone can expire it, 3600 = 1 hour, 0 = never
+    pub fn set_expiry_seconds(ctx: Context<UpdateConfig>, expiry_seconds: i64) -> Result<()> {
+        require!(expiry_seconds >= 0, ErrorCode::InvalidParameter);
+        ctx.accounts.config.expiry_seconds = expiry_seconds;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // arbiter: Address, Key that resolves delivery disputes, 5ST8U...7777
+    pub fn set_arbiter(ctx: Context<UpdateConfig>, arbiter: Pubkey) -> Result<()> {
+        require!(arbiter != ctx.accounts.config.authority, ErrorCode::ArbiterNotIndependent);
+        ctx.accounts.config.arbiter = arbiter;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // exempt: Boolean, Whether tips reach the operator without a platform fee, true
+    pub fn set_tips_fee_exempt(ctx: Context<UpdateConfig>, exempt: bool) -> Result<()> {
+        ctx.accounts.config.tips_fee_exempt = exempt;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // auto_release_hours: Number, Hours after drop-off before an unconfirmed order can settle anyway, 48, 0 = never
+    pub fn set_auto_release_hours(ctx: Context<UpdateConfig>, auto_release_hours: u16) -> Result<()> {
+        ctx.accounts.config.auto_release_seconds = auto_release_hours as i64 * 3600;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // window_minutes: Number, Minutes before a scheduled pickup that vehicles may accept the order, 30, 0 = only once it is due
+    pub fn set_schedule_window(ctx: Context<UpdateConfig>, window_minutes: u16) -> Result<()> {
+        ctx.accounts.config.schedule_window_seconds = window_minutes as i64 * 60;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // dispatcher: Address, Wallet holding the Dispatcher role alongside the authority and any role holders, 9xQeW...4Kp2, default = authority only
+    pub fn set_surge_dispatcher(ctx: Context<UpdateConfig>, dispatcher: Pubkey) -> Result<()> {
+        ctx.accounts.config.surge_dispatcher = dispatcher;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // surge_bps: Number, Premium added to new orders' fares, 5000 = 1.5x, 0 = off
+    pub fn set_surge(ctx: Context<SetSurge>, surge_bps: u16) -> Result<()> {
+        require!(surge_bps <= MAX_SURGE_BPS, ErrorCode::InvalidParameter);
+        ctx.accounts.config.surge_bps = surge_bps;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // kind: Enum, Privilege being granted, { treasurer: {} }
+    // holder: Address, Wallet receiving the role, 5RT2W...7777
+    pub fn grant_role(ctx: Context<GrantRole>, kind: RoleKind, holder: Pubkey) -> Result<()> {
+        // Disputes stay with someone other than the platform
+        require!(
+            kind != RoleKind::Arbiter || holder != ctx.accounts.config.authority,
+            ErrorCode::ArbiterNotIndependent
+        );
+
+        let role = &mut ctx.accounts.role;
+        role.bump = ctx.bumps.role;
+        role.kind = kind;
+        role.holder = holder;
+        role.granted_at = Clock::get()?.unix_timestamp;
+        emit!(RoleChanged {
+            schema_version: EVENT_SCHEMA_VERSION,
+            kind,
+            holder,
+            granted: true,
+        });
+        Ok(())
+    }
+
+    // kind: Enum, Privilege being revoked, { treasurer: {} }
+    // holder: Address, Wallet losing the role, 5RT2W...7777
+    pub fn revoke_role(_ctx: Context<RevokeRole>, kind: RoleKind, holder: Pubkey) -> Result<()> {
+        emit!(RoleChanged {
+            schema_version: EVENT_SCHEMA_VERSION,
+            kind,
+            holder,
+            granted: false,
+        });
+        Ok(())
+    }
+
+    // min_stake: Number, Lamports a vehicle must lock before taking work, 1000000000 = 1 SOL, 0 = none
+    // slash_bps: Number, Share of the stake forfeited per abandoned or lost job, 1000 = 10%
+    // abandon_hours: Number, Hours after acceptance before an undelivered order counts as abandoned, 24, 0 = never
+    // slash_treasury_bps: Number, Part of each slash paid to the treasury, the customer gets the rest, 2000 = 20%
+    pub fn set_stake_rules(
+        ctx: Context<UpdateConfig>,
+        min_stake: u64,
+        slash_bps: u16,
+        abandon_hours: u16,
+        slash_treasury_bps: u16,
+    ) -> Result<()> {
+        require!(slash_bps <= 10000, ErrorCode::InvalidParameter);
+        require!(slash_treasury_bps <= 10000, ErrorCode::InvalidParameter);
+        let config = &mut ctx.accounts.config;
+        config.min_stake = min_stake;
+        config.slash_bps = slash_bps;
+        config.slash_treasury_bps = slash_treasury_bps;
+        config.abandon_seconds = abandon_hours as i64 * 3600;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // no_show_deposit: Number, Refundable deposit escrowed with each new order, 5000000 = 0.005 SOL, 0 = off
+    pub fn set_no_show_deposit(ctx: Context<UpdateConfig>, no_show_deposit: u64) -> Result<()> {
+        ctx.accounts.config.no_show_deposit = no_show_deposit;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // hazard_surcharge_bps: Number, Surcharge per hazard level on new orders, 500 = 5% per level
+    pub fn set_hazard_surcharge(ctx: Context<UpdateConfig>, hazard_surcharge_bps: u16) -> Result<()> {
+        require!(hazard_surcharge_bps <= 10000, ErrorCode::InvalidParameter);
+        ctx.accounts.config.hazard_surcharge_bps = hazard_surcharge_bps;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // robot_max_order_value: Number, Largest order a robot may carry in lamports, 0 = unlimited
+    pub fn set_robot_class_rules(
+        ctx: Context<UpdateConfig>,
+        robot_max_order_value: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.robot_max_order_value = robot_max_order_value;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // code_hash: Bytes, sha256 of the promo code customers enter, [0; 32]
+    // discount_bps: Number, Share of the fare refunded out of the platform fee, at most the fee rate, 100 = 1%
+    // max_uses: Number, Orders the code can be redeemed on, 500
+    // expires_at: Number, Unix time after which the code is refused, 1767225600
+    pub fn create_voucher(
+        ctx: Context<CreateVoucher>,
+        code_hash: [u8; 32],
+        discount_bps: u16,
+        max_uses: u32,
+        expires_at: i64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        // The discount comes out of the platform fee, so it cannot promise
+        // more than the fee; settlement still caps it at each order's fee
+        require!(
+            discount_bps > 0 && discount_bps <= ctx.accounts.config.fee_bps,
+            ErrorCode::VoucherExceedsFee
+        );
+        require!(max_uses > 0, ErrorCode::InvalidParameter);
+        require!(expires_at > now, ErrorCode::InvalidParameter);
+
+        let voucher = &mut ctx.accounts.voucher;
+        voucher.bump = ctx.bumps.voucher;
+        voucher.code_hash = code_hash;
+        voucher.discount_bps = discount_bps;
+        voucher.max_uses = max_uses;
+        voucher.uses = 0;
+        voucher.expires_at = expires_at;
+        voucher.created_at = now;
+        Ok(())
+    }
+
+    pub fn initialize_insurance_pool(ctx: Context<InitializeInsurancePool>) -> Result<()> {
+        let pool = &mut ctx.accounts.insurance_pool;
+        pool.bump = ctx.bumps.insurance_pool;
+        pool.config = ctx.accounts.config.key();
+        pool.total_contributed = 0;
+        pool.total_paid = 0;
+        Ok(())
+    }
+
+    // is_paused: Boolean, Emergency stop for new orders and acceptances; settlements and refunds continue, true
+    pub fn set_paused(ctx: Context<AdminUpdate>, is_paused: bool) -> Result<()> {
+        ctx.accounts.config.is_paused = is_paused;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // is_active: Boolean, Switches the platform on or off; refunds remain available while off, false
+    pub fn set_active(ctx: Context<AdminUpdate>, is_active: bool) -> Result<()> {
+        ctx.accounts.config.is_active = is_active;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // settlement_only: Boolean, Stop new orders and acceptances while settlements continue, true
+    pub fn set_settlement_only(ctx: Context<AdminUpdate>, settlement_only: bool) -> Result<()> {
+        ctx.accounts.config.settlement_only = settlement_only;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // max_withdrawal_per_epoch: Number, Max lamports withdrawn from program vaults per epoch, 0 = unlimited
+    pub fn set_withdrawal_limit(
+        ctx: Context<UpdateConfig>,
+        max_withdrawal_per_epoch: u64,
+    ) -> Result<()> {
+        let epoch = Clock::get()?.epoch;
+        ctx.accounts.config.set_withdrawal_limit(max_withdrawal_per_epoch, epoch);
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // change: Enum, Fee or payout setting to change once the timelock passes, { feeAndTreasury: { feeBps: 300, treasury, delayHours: 48 } }
+    pub fn queue_config_change(ctx: Context<ApprovedConfigUpdate>, change: ConfigChange) -> Result<()> {
+        change.validate()?;
+        ctx.accounts.config.check_approved(
+            ctx.accounts.proposal.as_deref_mut(),
+            &AdminAction::ConfigChange { change },
+        )?;
+
+        // A new delay only governs later changes, so this one cannot
+        // shorten its own timelock
+        let now = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        match change {
+            ConfigChange::FeeAndTreasury { fee_bps, .. } => config.check_fee_step(fee_bps, now)?,
+            ConfigChange::ClassFee { class, fee_bps } => config.check_class_fee_step(class, fee_bps)?,
+            _ => {}
+        }
+        let executable_at = now
+            .checked_add(config.config_change_delay_seconds)
+            .ok_or(ErrorCode::MathOverflow)?;
+        config.pending_config_change = Some(PendingConfigChange {
+            change,
+            queued_by: ctx.accounts.authority.key(),
+            executable_at,
+        });
+        emit!(ConfigChangeQueued {
+            schema_version: EVENT_SCHEMA_VERSION,
+            config: ctx.accounts.config.key(),
+            change,
+            delay_seconds: ctx.accounts.config.config_change_delay_seconds,
+            executable_at,
+        });
+        Ok(())
+    }
+
+    pub fn execute_config_change(ctx: Context<ExecuteConfigChange>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        let change = config.pending_config_change.ok_or(ErrorCode::NoPendingConfigChange)?;
+        require!(now >= change.executable_at, ErrorCode::ConfigChangeTimelockActive);
+
+        config.pending_config_change = None;
+        match change.change {
+            ConfigChange::FeeAndTreasury { fee_bps, treasury, delay_hours } => {
+                let old_bps = config.fee_bps;
+                config.fee_bps = fee_bps;
+                config.treasury = treasury;
+                config.config_change_delay_seconds = delay_hours as i64 * 3600;
+                if old_bps != fee_bps {
+                    ctx.accounts.fee_history.push(FeeChange {
+                        old_bps,
+                        new_bps: fee_bps,
+                        changed_at: now,
+                        proposer: change.queued_by,
+                        class: None,
+                    });
+                }
+            }
+            ConfigChange::ClassFee { class, fee_bps } => {
+                let old_bps = config.fee_bps_for(class);
+                config.class_fee_bps[class.index()] = fee_bps;
+                let new_bps = config.fee_bps_for(class);
+                if old_bps != new_bps {
+                    ctx.accounts.fee_history.push(FeeChange {
+                        old_bps,
+                        new_bps,
+                        changed_at: now,
+                        proposer: change.queued_by,
+                        class: Some(class),
+                    });
+                }
+            }
+            ConfigChange::FeeRecipients { recipients } => {
+                // Shares already set aside belong to the wallet in that slot
+                // today, so they have to be distributed before it changes
+                let owed = &ctx.accounts.treasury.recipient_owed;
+                for ((old, new), owed) in config.fee_recipients.iter().zip(recipients.iter()).zip(owed) {
+                    require!(
+                        old.recipient == new.recipient || *owed == 0,
+                        ErrorCode::FeeSharesUndistributed
+                    );
+                }
+                config.fee_recipients = recipients;
+            }
+            ConfigChange::InsuranceShare { insurance_bps } => {
+                config.insurance_bps = insurance_bps;
+            }
+            ConfigChange::ReferralShare { referral_bps } => {
+                config.referral_bps = referral_bps;
+            }
+        }
+        emit!(ConfigChangeExecuted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            config: ctx.accounts.config.key(),
+            change: change.change,
+            executed_at: now,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_config_change(ctx: Context<UpdateConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(config.pending_config_change.is_some(), ErrorCode::NoPendingConfigChange);
+        config.pending_config_change = None;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // approvers: Array, Wallets that approve admin actions, [5RT2W...7777], [] = single-key admin
+    // threshold: Number, Approvals each action needs, 2, 0 with no approvers
+    pub fn set_approvers(
+        ctx: Context<ApprovedConfigUpdate>,
+        approvers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        require!(approvers.len() <= MAX_APPROVERS, ErrorCode::InvalidParameter);
+        require!(
+            (threshold == 0) == approvers.is_empty() && threshold as usize <= approvers.len(),
+            ErrorCode::InvalidParameter
+        );
+        let mut slots = [Pubkey::default(); MAX_APPROVERS];
+        for (i, approver) in approvers.iter().enumerate() {
+            require!(
+                *approver != Pubkey::default() && !approvers[..i].contains(approver),
+                ErrorCode::InvalidParameter
+            );
+            slots[i] = *approver;
+        }
+        ctx.accounts.config.check_approved(
+            ctx.accounts.proposal.as_deref_mut(),
+            &AdminAction::SetApprovers { approvers: slots, threshold },
+        )?;
+
+        let config = &mut ctx.accounts.config;
+        config.approvers = slots;
+        config.approval_threshold = threshold;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    // proposal_id: Number, Caller-chosen id for the proposal, 1
+    // action: Enum, Admin action to approve, { configChange: { feeBps: 300, treasury, delayHours: 48 } }
+    pub fn propose_admin_action(
+        ctx: Context<ProposeAdminAction>,
+        proposal_id: u64,
+        action: AdminAction,
+    ) -> Result<()> {
+        let approver = ctx.accounts.approver.key();
+        require!(ctx.accounts.config.is_approver(approver), ErrorCode::NotApprover);
+
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.bump = ctx.bumps.proposal;
+        proposal.config = ctx.accounts.config.key();
+        proposal.proposal_id = proposal_id;
+        proposal.proposer = approver;
+        proposal.action = action;
+        proposal.approved_by = [Pubkey::default(); MAX_APPROVERS];
+        proposal.approved_by[0] = approver;
+        proposal.approval_count = 1;
+        proposal.created_at = Clock::get()?.unix_timestamp;
+        proposal.executed = false;
+        emit!(AdminActionApproved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            proposal: ctx.accounts.proposal.key(),
+            approver,
+            approval_count: 1,
+        });
+        Ok(())
+    }
+
+    // proposal_id: Number, Proposal being approved, 1
+    pub fn approve_admin_action(ctx: Context<ApproveAdminAction>, proposal_id: u64) -> Result<()> {
+        let approver = ctx.accounts.approver.key();
+        require!(ctx.accounts.config.is_approver(approver), ErrorCode::NotApprover);
+
+        let proposal = &mut ctx.accounts.proposal;
+        require!(!proposal.executed, ErrorCode::ProposalExecuted);
+        let count = proposal.approval_count as usize;
+        require!(!proposal.approved_by[..count].contains(&approver), ErrorCode::AlreadyApproved);
+        // Approvals from wallets since dropped from the set keep their slot
+        require!(count < MAX_APPROVERS, ErrorCode::AlreadyApproved);
+        proposal.approved_by[count] = approver;
+        proposal.approval_count += 1;
+        emit!(AdminActionApproved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            proposal: ctx.accounts.proposal.key(),
+            approver,
+            approval_count: ctx.accounts.proposal.approval_count,
+        });
+        Ok(())
+    }
+
+    // proposal_id: Number, Executed or abandoned proposal to close, 1
+    pub fn close_admin_action(_ctx: Context<CloseAdminAction>, _proposal_id: u64) -> Result<()> {
+        Ok(())
+    }
+
+    // new_authority: Address, Key that must accept to become the administrator, 4QR7T...6666, Pubkey::default() withdraws a proposal
+    pub fn propose_authority(ctx: Context<UpdateConfig>, new_authority: Pubkey) -> Result<()> {
+        ctx.accounts.config.pending_authority = new_authority;
+        emit_config_updated(&ctx);
+        Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        require!(
+            ctx.accounts.new_authority.key() != ctx.accounts.config.arbiter,
+            ErrorCode::ArbiterNotIndependent
+        );
+        let config = &mut ctx.accounts.config;
+        config.authority = ctx.accounts.new_authority.key();
+        config.pending_authority = Pubkey::default();
+        emit_config_updated(&ctx);
         Ok(())
     }
 
     // vehicle_id: String, Unique vehicle identifier, "AV-001"
     // operator: Address, Vehicle operator wallet, 7GH8J...2222
-    // location: String, Current location coords, "40.7128,-74.0060"
+    // position: Object, Current position in millionths of a degree, { latE6: 40712800, lngE6: -74006000 }
+    // vehicle_class: Enum, Kind of vehicle, { road: {} }
     pub fn register_vehicle(
         ctx: Context<RegisterVehicle>,
         vehicle_id: String,
         operator: Pubkey,
-        location: String,
+        position: GeoPoint,
+        vehicle_class: VehicleClass,
     ) -> Result<()> {
         require!(vehicle_id.len() <= 32, ErrorCode::InvalidParameter);
-        require!(location.len() <= 64, ErrorCode::InvalidParameter);
+        position.validate()?;
 
         let config = &ctx.accounts.config;
         require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
 
+        // A vehicle bound to an NFT is operated by whoever holds it, so the
+        // named operator must hold the token at registration. Supply 1 only
+        // stays 1 when nobody can mint more: the authority is revoked, or it
+        // is the Metaplex master edition, which never mints the token again
+        let ownership_mint = match ctx.accounts.ownership_mint.as_ref() {
+            Some(mint) => {
+                let (master_edition, _) = MasterEdition::find_pda(&mint.key());
+                require!(
+                    mint.decimals == 0
+                        && mint.supply == 1
+                        && (mint.mint_authority.is_none() || mint.mint_authority == COption::Some(master_edition)),
+                    ErrorCode::InvalidOwnershipNft
+                );
+                let token = ctx.accounts.ownership_token.as_ref().ok_or(ErrorCode::InvalidOwnershipNft)?;
+                require!(
+                    token.mint == mint.key() && token.amount == 1 && token.owner == operator,
+                    ErrorCode::InvalidOwnershipNft
+                );
+                Some(mint.key())
+            }
+            None => None,
+        };
+
+        // When the platform asks for a stake the operator locks it at
+        // registration, so the vehicle can take work straight away
+        let min_stake = config.min_stake;
+        let mut staked = 0;
+        if min_stake > 0 {
+            let staker = ctx.accounts.staker.as_ref().ok_or(ErrorCode::InsufficientStake)?;
+            require!(staker.key() == operator, ErrorCode::NotVehicleOperator);
+            let stake_info = ctx.accounts.stake.as_ref().ok_or(ErrorCode::StakeAccountRequired)?;
+            let vehicle_key = ctx.accounts.vehicle.key();
+            let (stake_key, stake_bump) =
+                Pubkey::find_program_address(&[b"stake", vehicle_key.as_ref()], &crate::ID);
+            require!(stake_info.key() == stake_key, ErrorCode::StakeAccountRequired);
+
+            let system_program_info = ctx.accounts.system_program.to_account_info();
+            create_pda_account(
+                &ctx.accounts.authority.to_account_info(),
+                stake_info,
+                &system_program_info,
+                8 + VehicleStake::LEN,
+                &[b"stake", vehicle_key.as_ref(), &[stake_bump]],
+                &crate::ID,
+            )?;
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    system_program_info,
+                    anchor_lang::system_program::Transfer {
+                        from: staker.to_account_info(),
+                        to: stake_info.clone(),
+                    },
+                ),
+                min_stake,
+            )?;
+            let stake = VehicleStake {
+                bump: stake_bump,
+                vehicle: vehicle_key,
+                amount: min_stake,
+                slashed_total: 0,
+                pending_unstake: 0,
+                unstake_requested_at: 0,
+            };
+            stake.try_serialize(&mut &mut stake_info.try_borrow_mut_data()?[..])?;
+            staked = min_stake;
+        }
+
         let vehicle = &mut ctx.accounts.vehicle;
         vehicle.bump = ctx.bumps.vehicle;
         vehicle.vehicle_id = vehicle_id;
         vehicle.operator = operator;
-        vehicle.location = location;
+        vehicle.position = position;
+        vehicle.location = position.to_string();
         vehicle.is_active = true;
         vehicle.is_busy = false;
+        vehicle.active_orders = 0;
+        vehicle.max_active_orders = 1;
         vehicle.total_deliveries = 0;
         vehicle.registered_at = Clock::get()?.unix_timestamp;
+        vehicle.location_updated_at = vehicle.registered_at;
+        vehicle.suspended = false;
+        vehicle.capacity_grams = 0;
+        vehicle.battery_pct = 0;
+        vehicle.telemetry_updated_at = 0;
+        vehicle.fleet = None;
+        vehicle.agreed_fleet_share_bps = 0;
+        vehicle.staked = staked;
+        vehicle.pending_unstake = 0;
+        vehicle.session_key = Pubkey::default();
+        vehicle.session_expires_at = 0;
+        vehicle.session_permissions = 0;
+        vehicle.busy_seconds = 0;
+        vehicle.idle_seconds = 0;
+        vehicle.distance_claimed_m = 0;
+        vehicle.revenue = 0;
+        vehicle.last_status_change_at = vehicle.registered_at;
+        vehicle.last_nonce = 0;
+        vehicle.last_checkpoint_at = 0;
+        vehicle.vehicle_class = vehicle_class;
+        vehicle.geohash = [0u8; GEOHASH_LEN];
+        vehicle.away_until = 0;
+        vehicle.rating_count = 0;
+        vehicle.rating_sum = 0;
+        vehicle.min_customer_score = 0;
+        vehicle.total_tips = 0;
+        vehicle.ownership_mint = ownership_mint;
+        emit!(VehicleRegistered {
+            schema_version: EVENT_SCHEMA_VERSION,
+            vehicle: ctx.accounts.vehicle.key(),
+            vehicle_id: ctx.accounts.vehicle.vehicle_id.clone(),
+            operator: ctx.accounts.vehicle.operator,
+            vehicle_class: ctx.accounts.vehicle.vehicle_class,
+        });
+        Ok(())
+    }
+
+    // region_id: Number, Service area identifier, 1
+    pub fn initialize_region(ctx: Context<InitializeRegion>, region_id: u16) -> Result<()> {
+        let region = &mut ctx.accounts.region;
+        region.bump = ctx.bumps.region;
+        region.region_id = region_id;
+        region.total_queued = 0;
+        region.queue = Vec::new();
+        region.tax_bps = 0;
+        region.tax_inclusive = false;
+        region.robots_allowed = false;
+        region.hazard_level = 0;
+        region.hazard_updated_at = 0;
+        region.congestion_index = 0;
+        region.congestion_updated_at = 0;
+        Ok(())
+    }
+
+    // region_id: Number, Service area whose pending orders are listed, 1
+    // page: Number, Page of the region's board, 0 = first; later pages follow a full one, 1
+    pub fn initialize_open_orders(ctx: Context<InitializeOpenOrders>, region_id: u16, page: u16) -> Result<()> {
+        // Pages are contiguous so readers can walk them until one is missing,
+        // and a new page only opens once the one before it has filled up
+        if page > 0 {
+            let previous = ctx.accounts.previous.as_ref().ok_or(ErrorCode::InvalidParameter)?;
+            require!(previous.page + 1 == page, ErrorCode::InvalidParameter);
+            require!(previous.is_full(), ErrorCode::OpenOrdersNotFull);
+        }
+
+        let board = &mut ctx.accounts.open_orders;
+        board.bump = ctx.bumps.open_orders;
+        board.region_id = region_id;
+        board.page = page;
+        board.orders = Vec::new();
+        Ok(())
+    }
+
+    // region_id: Number, Service area identifier, 1
+    // tax_bps: Number, VAT/GST rate, 2000 = 20%
+    // tax_inclusive: Boolean, Whether fares already include the tax, true
+    pub fn set_region_tax(
+        ctx: Context<SetRegionTax>,
+        region_id: u16,
+        tax_bps: u16,
+        tax_inclusive: bool,
+    ) -> Result<()> {
+        require!(tax_bps <= MAX_TAX_BPS, ErrorCode::InvalidParameter);
+
+        let region = &mut ctx.accounts.region;
+        region.tax_bps = tax_bps;
+        region.tax_inclusive = tax_inclusive;
+        Ok(())
+    }
+
+    // region_id: Number, Zone being configured, 1
+    // robots_allowed: Boolean, Whether sidewalk robots may operate here, true
+    pub fn set_region_robot_access(
+        ctx: Context<SetRegionRobotAccess>,
+        region_id: u16,
+        robots_allowed: bool,
+    ) -> Result<()> {
+        ctx.accounts.region.robots_allowed = robots_allowed;
+        Ok(())
+    }
+
+    pub fn initialize_operator_profile(ctx: Context<InitializeOperatorProfile>) -> Result<()> {
+        let profile = &mut ctx.accounts.operator_profile;
+        profile.bump = ctx.bumps.operator_profile;
+        profile.operator = ctx.accounts.operator.key();
+        profile.gross_earnings = 0;
+        profile.fees_paid = 0;
+        profile.tips = 0;
+        profile.penalties = 0;
+        profile.completed_jobs = 0;
+        profile.taxes_withheld = 0;
+        profile.dispute_losses = 0;
+        profile.created_at = Clock::get()?.unix_timestamp;
+        profile.last_statement_epoch = 0;
+        profile.last_statement_at = profile.created_at;
+        profile.last_statement_totals = EarningsTotals::default();
+        profile.referred = false;
         Ok(())
     }
 
@@ -57,23 +1006,162 @@ pub mod autonomous_vehicle_payments {
     // payment_amount: Number, Payment in lamports, 1000000000 = 1 SOL
     // pickup_location: String, Pickup coordinates, "40.7128,-74.0060"
     // delivery_location: String, Delivery coordinates, "40.7589,-73.9851"
+    // region_id: Number, Service area of the pickup, 1
+    // min_rating: Number, Lowest average rating a vehicle needs to accept, 450 = 4.5 stars, 0 = any
+    // required_class: Enum, Vehicle class the order must go to, { road: {} }, null = any
+    // scheduled_at: Number, Unix time of a pre-booked pickup, 1767225600, null = as soon as possible
+    // voucher_code: String, Promo code whose sha256 names the voucher account, "SPRING25", null = none
     pub fn create_delivery_order(
         ctx: Context<CreateDeliveryOrder>,
         delivery_id: u64,
         payment_amount: u64,
         pickup_location: String,
         delivery_location: String,
+        region_id: u16,
+        min_rating: u16,
+        required_class: Option<VehicleClass>,
+        scheduled_at: Option<i64>,
+        voucher_code: Option<String>,
+    ) -> Result<()> {
+        book_delivery_order(
+            ctx,
+            OrderRequest {
+                delivery_id,
+                payment_amount,
+                pickup_location,
+                delivery_location,
+                min_rating,
+                required_class,
+                scheduled_at,
+                voucher_code,
+                usd_cents: 0,
+            },
+        )
+    }
+
+    // delivery_id: Number, Unique delivery identifier, 12345
+    // usd_cents: Number, Fare in US cents, converted to lamports at the USD price feed, 1250 = $12.50
+    // pickup_location: String, Pickup coordinates, "40.7128,-74.0060"
+    // delivery_location: String, Delivery coordinates, "40.7589,-73.9851"
+    // region_id: Number, Service area of the pickup, 1
+    // min_rating: Number, Lowest average rating a vehicle needs to accept, 450 = 4.5 stars, 0 = any
+    // required_class: Enum, Vehicle class the order must go to, { road: {} }, null = any
+    // scheduled_at: Number, Unix time of a pre-booked pickup, 1767225600, null = as soon as possible
+    pub fn create_delivery_order_usd(
+        ctx: Context<CreateDeliveryOrder>,
+        delivery_id: u64,
+        usd_cents: u64,
+        pickup_location: String,
+        delivery_location: String,
+        region_id: u16,
+        min_rating: u16,
+        required_class: Option<VehicleClass>,
+        scheduled_at: Option<i64>,
+    ) -> Result<()> {
+        require!(usd_cents > 0, ErrorCode::InvalidAmount);
+
+        // The fare is fixed in lamports at booking from the Pyth SOL/USD
+        // price; settlement checks the feed is still sound before paying out
+        let micros_per_sol = pyth_micros_per_sol(&ctx.accounts.config, ctx.accounts.pyth_price.as_ref())?;
+        let usd_micros = usd_cents
+            .checked_mul(10_000)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let payment_amount = (usd_micros as u128)
+            .checked_mul(1_000_000_000)
+            .ok_or(ErrorCode::MathOverflow)?
+            / micros_per_sol as u128;
+        let payment_amount = u64::try_from(payment_amount).map_err(|_| ErrorCode::MathOverflow)?;
+        book_delivery_order(
+            ctx,
+            OrderRequest {
+                delivery_id,
+                payment_amount,
+                pickup_location,
+                delivery_location,
+                min_rating,
+                required_class,
+                scheduled_at,
+                voucher_code: None,
+                usd_cents,
+            },
+        )
+    }
+
+    // template_id: Number, Merchant-chosen template number, 1
+    // pickup_location: String, Pickup coordinates, "40.7128,-74.0060"
+    // delivery_location: String, Drop-off coordinates, "40.7589,-73.9851"
+    // region_id: Number, Region orders from this template are booked in, 1
+    // category: Number, Merchant goods category, 3 = groceries
+    // requirements: Number, Handling requirement flags for the vehicle, 1 = refrigerated
+    // default_amount: Number, Fare used when an order does not override it, 100000000 = 0.1 SOL
+    pub fn create_template(
+        ctx: Context<CreateTemplate>,
+        template_id: u32,
+        pickup_location: String,
+        delivery_location: String,
+        region_id: u16,
+        category: u8,
+        requirements: u16,
+        default_amount: u64,
     ) -> Result<()> {
         require!(pickup_location.len() <= 64, ErrorCode::InvalidParameter);
         require!(delivery_location.len() <= 64, ErrorCode::InvalidParameter);
-        require!(payment_amount > 0, ErrorCode::InvalidAmount);
+        require!(default_amount > 0, ErrorCode::InvalidAmount);
+
+        let template = &mut ctx.accounts.template;
+        template.bump = ctx.bumps.template;
+        template.owner = ctx.accounts.owner.key();
+        template.template_id = template_id;
+        template.pickup_location = pickup_location;
+        template.delivery_location = delivery_location;
+        template.region_id = region_id;
+        template.category = category;
+        template.requirements = requirements;
+        template.default_amount = default_amount;
+        Ok(())
+    }
+
+    // template_id: Number, Template being removed, 1
+    pub fn delete_template(_ctx: Context<DeleteTemplate>, _template_id: u32) -> Result<()> {
+        Ok(())
+    }
+
+    // template_id: Number, Template to book from, 1
+    // delivery_id: Number, Unique order identifier, 12346
+    // payment_amount: Number, Fare override in lamports, 0 = template default
+    // scheduled_at: Number, Unix time of a pre-booked pickup, 1767225600, null = as soon as possible
+    pub fn create_from_template(
+        ctx: Context<CreateFromTemplate>,
+        template_id: u32,
+        delivery_id: u64,
+        payment_amount: u64,
+        scheduled_at: Option<i64>,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(scheduled_at.map_or(true, |at| at > now), ErrorCode::InvalidParameter);
 
         let config = &ctx.accounts.config;
         require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
 
+        let template = &ctx.accounts.template;
+        let payment_amount = if payment_amount == 0 { template.default_amount } else { payment_amount };
         let customer_key = ctx.accounts.customer.key();
 
-        // Escrow payment from customer
+        let surge_bps = config.surge_bps;
+        let payment_amount = config.apply_surge(payment_amount)?;
+
+        let region = &ctx.accounts.region;
+        let pricing = OrderPricing::for_region(payment_amount, region, config, &ctx.accounts.customer_profile, now)?;
+        let escrow_amount = pricing.escrow_amount;
+        ctx.accounts.config.record_escrow(escrow_amount, now)?;
+
+        let quote_usd = ctx.accounts.usd_feed.quote(payment_amount, now)?;
+        let quote_eur = match &ctx.accounts.eur_feed {
+            Some(feed) => feed.quote(payment_amount, now)?,
+            None => 0,
+        };
+
         anchor_lang::system_program::transfer(
             CpiContext::new(
                 ctx.accounts.system_program.to_account_info(),
@@ -82,107 +1170,10567 @@ pub mod autonomous_vehicle_payments {
                     to: ctx.accounts.escrow.to_account_info(),
                 },
             ),
-            payment_amount,
+            escrow_amount,
         )?;
 
+        let delivery_key = ctx.accounts.delivery.key();
+        let escrow = &mut ctx.accounts.escrow;
+        escrow.open(ctx.bumps.escrow, delivery_key, customer_key, escrow_amount);
+
         let delivery = &mut ctx.accounts.delivery;
         delivery.bump = ctx.bumps.delivery;
-        delivery.delivery_id = delivery_id;
-        delivery.customer = customer_key;
-        delivery.payment_amount = payment_amount;
-        delivery.pickup_location = pickup_location;
-        delivery.delivery_location = delivery_location;
-        delivery.status = DeliveryStatus::Pending;
-        delivery.assigned_vehicle = None;
-        delivery.created_at = Clock::get()?.unix_timestamp;
+        delivery.book(delivery_id, customer_key, payment_amount, region, &pricing, now);
+        delivery.surge_bps = surge_bps;
+        delivery.quote_usd_micros = quote_usd;
+        delivery.quote_eur_micros = quote_eur;
+        delivery.set_locations(template.pickup_location.clone(), template.delivery_location.clone());
+        delivery.category = template.category;
+        delivery.requirements = template.requirements;
+        delivery.scheduled_at = scheduled_at;
+
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.open(ctx.bumps.customer_profile, customer_key, now);
+        profile.orders_created = profile.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_created = daily_stats.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_created()?;
+        emit!(OrderCreated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            customer: ctx.accounts.delivery.customer,
+            payment_amount: ctx.accounts.delivery.payment_amount,
+            region_id: ctx.accounts.delivery.region_id,
+        });
         Ok(())
     }
 
-    // delivery_id: Number, Target delivery order, 12345
-    pub fn accept_delivery(ctx: Context<AcceptDelivery>, delivery_id: u64) -> Result<()> {
+    // delivery_id: Number, Pending delivery order to waitlist, 12345
+    pub fn queue_delivery(ctx: Context<QueueDelivery>, delivery_id: u64) -> Result<()> {
         let config = &ctx.accounts.config;
         require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
 
-        let vehicle = &ctx.accounts.vehicle;
-        require!(vehicle.is_active && !vehicle.is_busy, ErrorCode::VehicleNotAvailable);
-
+        let delivery_key = ctx.accounts.delivery.key();
         let delivery = &mut ctx.accounts.delivery;
         require!(delivery.status == DeliveryStatus::Pending, ErrorCode::InvalidDeliveryStatus);
+        require!(delivery.auction_ends_at.is_none(), ErrorCode::AuctionInProgress);
 
-        let vehicle_mut = &mut ctx.accounts.vehicle;
-        vehicle_mut.is_busy = true;
+        // A queued order is taken from the waitlist, not the board
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
 
-        delivery.status = DeliveryStatus::InProgress;
-        delivery.assigned_vehicle = Some(ctx.accounts.vehicle.key());
-        delivery.accepted_at = Some(Clock::get()?.unix_timestamp);
+        let region = &mut ctx.accounts.region;
+        require!(region.queue.len() < MAX_QUEUE_LEN, ErrorCode::QueueFull);
+
+        region.queue.push(QueueEntry {
+            delivery: delivery_key,
+            priority: delivery.priority,
+            queued_at: Clock::get()?.unix_timestamp,
+        });
+        region.total_queued = region.total_queued
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        delivery.status = DeliveryStatus::Queued;
         Ok(())
     }
 
-    // delivery_id: Number, Completed delivery order, 12345
-    pub fn complete_delivery(ctx: Context<CompleteDelivery>, delivery_id: u64) -> Result<()> {
+    // delivery_id: Number, Waiting delivery order, 12345
+    // extra_amount: Number, Additional payment in lamports, 100000000 = 0.1 SOL
+    pub fn boost_priority(
+        ctx: Context<BoostPriority>,
+        delivery_id: u64,
+        extra_amount: u64,
+    ) -> Result<()> {
+        require!(extra_amount > 0, ErrorCode::InvalidAmount);
+
         let config = &ctx.accounts.config;
         require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
 
         let delivery = &ctx.accounts.delivery;
-        require!(delivery.status == DeliveryStatus::InProgress, ErrorCode::InvalidDeliveryStatus);
         require!(
-            delivery.assigned_vehicle == Some(ctx.accounts.vehicle.key()),
-            ErrorCode::Unauthorized
+            delivery.status == DeliveryStatus::Pending || delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
         );
 
-        let customer_key = ctx.accounts.customer.key();
-        let vehicle_key = ctx.accounts.vehicle.key();
-        let config_key = ctx.accounts.config.key();
-
-        // Calculate fee and payment
-        let fee = delivery.payment_amount
-            .checked_mul(config.fee_bps as u64)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(10000)
-            .ok_or(ErrorCode::MathOverflow)?;
-        let vehicle_payment = delivery.payment_amount
-            .checked_sub(fee)
+        let escrowed_before = delivery.escrowed_total()?;
+        let payment_amount = delivery.payment_amount
+            .checked_add(extra_amount)
             .ok_or(ErrorCode::MathOverflow)?;
-
-        // Transfer payment to vehicle operator
-        let escrow_bump = [ctx.bumps.escrow];
-        let escrow_seeds = &[
-            b"escrow",
-            customer_key.as_ref(),
-            &delivery_id.to_le_bytes(),
-            &escrow_bump
-        ];
-        let signer_seeds: &[&[&[u8]]] = &[escrow_seeds];
-
-        **ctx.accounts.escrow.to_account_info().try_borrow_mut_lamports()? -= vehicle_payment;
-        **ctx.accounts.vehicle_operator.to_account_info().try_borrow_mut_lamports()? += vehicle_payment;
-
-        // Transfer fee to treasury
-        **ctx.accounts.escrow.to_account_info().try_borrow_mut_lamports()? -= fee;
-        **ctx.accounts.treasury.to_account_info().try_borrow_mut_lamports()? += fee;
+        let tax_amount = compute_tax(payment_amount, delivery.tax_bps, delivery.tax_inclusive)?;
 
         let delivery_mut = &mut ctx.accounts.delivery;
-        delivery_mut.status = DeliveryStatus::Completed;
-        delivery_mut.completed_at = Some(Clock::get()?.unix_timestamp);
-
-        let vehicle_mut = &mut ctx.accounts.vehicle;
-        vehicle_mut.is_busy = false;
-        vehicle_mut.total_deliveries = vehicle_mut.total_deliveries
-            .checked_add(1)
+        delivery_mut.payment_amount = payment_amount;
+        delivery_mut.tax_amount = tax_amount;
+        let top_up = delivery_mut.escrowed_total()?
+            .checked_sub(escrowed_before)
             .ok_or(ErrorCode::MathOverflow)?;
+        ctx.accounts.config.record_escrow(top_up, Clock::get()?.unix_timestamp)?;
 
-        Ok(())
-    }
-}
-
-#[derive(Accounts)]
+        // Top up escrow from customer
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.customer.to_account_info(),
+                    to: ctx.accounts.escrow.to_account_info(),
+                },
+            ),
+            top_up,
+        )?;
+        ctx.accounts.escrow.deposit(top_up)?;
+
+        let delivery_mut = &mut ctx.accounts.delivery;
+        delivery_mut.priority = delivery_mut.priority
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Keep the waitlist entry in sync so accept_next sees the boost
+        if delivery_mut.status == DeliveryStatus::Queued {
+            let delivery_key = delivery_mut.key();
+            let region = ctx.accounts.region.as_mut().ok_or(ErrorCode::InvalidParameter)?;
+            let entry = region.queue
+                .iter_mut()
+                .find(|entry| entry.delivery == delivery_key)
+                .ok_or(ErrorCode::InvalidParameter)?;
+            entry.priority = delivery_mut.priority;
+        }
+        Ok(())
+    }
+
+    // delivery_id: Number, Open delivery order, 12345
+    // amount: Number, Extra fare in lamports for waiting time, reroutes or surge, 50000000 = 0.05 SOL
+    pub fn top_up_escrow(ctx: Context<TopUpEscrow>, delivery_id: u64, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+
+        let delivery = &ctx.accounts.delivery;
+        require!(
+            delivery.status == DeliveryStatus::Pending
+                || delivery.status == DeliveryStatus::Queued
+                || delivery.is_underway(),
+            ErrorCode::InvalidDeliveryStatus
+        );
+
+        let escrowed_before = delivery.escrowed_total()?;
+        let payment_amount = delivery.payment_amount
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let tax_amount = compute_tax(payment_amount, delivery.tax_bps, delivery.tax_inclusive)?;
+
+        let delivery_mut = &mut ctx.accounts.delivery;
+        delivery_mut.payment_amount = payment_amount;
+        delivery_mut.tax_amount = tax_amount;
+        let top_up = delivery_mut.escrowed_total()?
+            .checked_sub(escrowed_before)
+            .ok_or(ErrorCode::MathOverflow)?;
+        ctx.accounts.config.record_escrow(top_up, Clock::get()?.unix_timestamp)?;
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.customer.to_account_info(),
+                    to: ctx.accounts.escrow.to_account_info(),
+                },
+            ),
+            top_up,
+        )?;
+        ctx.accounts.escrow.deposit(top_up)?;
+        Ok(())
+    }
+
+    // delivery_id: Number, Pending order the customer withdraws, 12345
+    pub fn cancel_delivery(ctx: Context<CancelDelivery>, delivery_id: u64) -> Result<()> {
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::Pending, ErrorCode::InvalidDeliveryStatus);
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
+        release_voucher(
+            delivery,
+            ctx.accounts.voucher.as_mut(),
+            ctx.accounts.voucher_use.as_deref(),
+        )?;
+
+        // Ending the auction lets bidders close their bids
+        delivery.reopen_auction();
+
+        // Closing the escrow returns the fare and its rent to the customer
+        delivery.status = DeliveryStatus::Cancelled;
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_cancelled()?;
+
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.cancellations = profile.cancellations
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            status: ctx.accounts.delivery.status.clone(),
+            cancelled_at: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Order nobody accepted in time, 12345
+    pub fn expire_delivery(ctx: Context<ExpireDelivery>, delivery_id: u64) -> Result<()> {
+        let expiry_seconds = ctx.accounts.config.expiry_seconds;
+        require!(expiry_seconds > 0, ErrorCode::DeliveryNotExpired);
+
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        require!(
+            delivery.status == DeliveryStatus::Pending || delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now >= delivery.expiry_starts_at().saturating_add(expiry_seconds),
+            ErrorCode::DeliveryNotExpired
+        );
+
+        if delivery.status == DeliveryStatus::Queued {
+            ctx.accounts.region.dequeue(delivery_key)?;
+        }
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
+        release_voucher(
+            delivery,
+            ctx.accounts.voucher.as_mut(),
+            ctx.accounts.voucher_use.as_deref(),
+        )?;
+        delivery.reopen_auction();
+
+        // Closing the escrow returns the fare and its rent to the customer
+        delivery.status = DeliveryStatus::Expired;
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_cancelled()?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            status: ctx.accounts.delivery.status.clone(),
+            cancelled_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Completed, cancelled or expired order to close, 12345
+    pub fn close_delivery(ctx: Context<CloseDelivery>, delivery_id: u64) -> Result<()> {
+        let info = ctx.accounts.delivery.to_account_info();
+        let delivery = Delivery::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        require!(delivery.is_finished(), ErrorCode::InvalidDeliveryStatus);
+        if delivery.route.is_some() && delivery.status != DeliveryStatus::Completed {
+            require!(ctx.accounts.route.is_some(), ErrorCode::RouteAccountRequired);
+        }
+
+        // The customer can close at once; keepers wait out the grace period.
+        // Either way the rent goes back to the customer who paid it
+        if ctx.accounts.signer.key() != delivery.customer {
+            let finished_at = delivery.completed_at.unwrap_or(delivery.created_at);
+            let closable_at = finished_at
+                .checked_add(DELIVERY_CLOSE_GRACE_SECONDS)
+                .ok_or(ErrorCode::MathOverflow)?;
+            require!(Clock::get()?.unix_timestamp >= closable_at, ErrorCode::CloseGraceNotElapsed);
+        }
+
+        // Receipts, claims, bids and receipt NFTs are keyed by the order's
+        // address and outlive it. Shrinking the order to a marker instead of
+        // closing it keeps its id from being booked again onto those records
+        let tombstone = ClosedDelivery {
+            customer: delivery.customer,
+            delivery_id,
+            closed_at: Clock::get()?.unix_timestamp,
+        };
+        shrink_account(&info, &ctx.accounts.customer.to_account_info(), 8 + ClosedDelivery::LEN)?;
+        tombstone.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        Ok(())
+    }
+
+    // delivery_id: Number, Order that absorbs the other one, 12345
+    // other_delivery_id: Number, Order that is merged and closed, 12346
+    pub fn merge_deliveries(
+        ctx: Context<MergeDeliveries>,
+        delivery_id: u64,
+        other_delivery_id: u64,
+    ) -> Result<()> {
+        require!(delivery_id != other_delivery_id, ErrorCode::InvalidParameter);
+
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+
+        let other_info = ctx.accounts.other_delivery.to_account_info();
+        let mut other = Delivery::try_deserialize(&mut &other_info.try_borrow_data()?[..])?;
+        let delivery = &ctx.accounts.delivery;
+        require!(
+            delivery.status == DeliveryStatus::Pending && other.status == DeliveryStatus::Pending,
+            ErrorCode::InvalidDeliveryStatus
+        );
+        require!(
+            delivery.pickup_location == other.pickup_location
+                && delivery.delivery_location == other.delivery_location
+                && delivery.tax_bps == other.tax_bps
+                && delivery.tax_inclusive == other.tax_inclusive
+                && delivery.package_count == 0
+                && other.package_count == 0
+                && delivery.milestone_count == 0
+                && other.milestone_count == 0
+                && delivery.route.is_none()
+                && other.route.is_none()
+                && delivery.auction_ends_at.is_none()
+                && other.auction_ends_at.is_none(),
+            ErrorCode::DeliveriesNotMergeable
+        );
+        // The surviving order keeps its own terms, so the other one has to
+        // have asked for the same vehicle, schedule, handover and surge
+        require!(
+            delivery.required_class == other.required_class
+                && delivery.min_rating == other.min_rating
+                && delivery.scheduled_at == other.scheduled_at
+                && delivery.confirmation_mode == other.confirmation_mode
+                && delivery.delivery_mode == other.delivery_mode
+                && delivery.surge_bps == other.surge_bps,
+            ErrorCode::DeliveriesNotMergeable
+        );
+
+        let combined = delivery.payment_amount
+            .checked_add(other.payment_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let discount = combined
+            .checked_mul(MERGE_DISCOUNT_BPS)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(10000)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let payment_amount = combined
+            .checked_sub(discount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let tax_amount = compute_tax(payment_amount, delivery.tax_bps, delivery.tax_inclusive)?;
+        let escrowed_before = delivery.escrowed_total()?;
+
+        let other_surcharge = other.hazard_surcharge;
+        let other_round_up = other.round_up;
+        let other_charity = other.charity;
+        // Both round-ups are paid out to one charity at settlement
+        require!(
+            other_round_up == 0 || delivery.round_up == 0 || other_charity == delivery.charity,
+            ErrorCode::InvalidCharity
+        );
+
+        let delivery_mut = &mut ctx.accounts.delivery;
+        delivery_mut.payment_amount = payment_amount;
+        delivery_mut.tax_amount = tax_amount;
+        delivery_mut.hazard_surcharge = delivery_mut.hazard_surcharge
+            .checked_add(other_surcharge)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if other_round_up > 0 {
+            delivery_mut.charity = other_charity;
+        }
+        delivery_mut.round_up = delivery_mut.round_up
+            .checked_add(other_round_up)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let moved = delivery_mut.escrowed_total()?
+            .checked_sub(escrowed_before)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let other_key = ctx.accounts.other_delivery.key();
+        if other.listed {
+            let board = ctx.accounts.open_orders.as_deref().ok_or(ErrorCode::OpenOrdersRequired)?;
+            require!(
+                board.region_id == other.region_id && board.page == other.listed_page,
+                ErrorCode::OpenOrdersRequired
+            );
+        }
+        other.unlist(other_key, ctx.accounts.open_orders.as_deref_mut())?;
+        release_voucher(
+            &other,
+            ctx.accounts.voucher.as_mut(),
+            ctx.accounts.voucher_use.as_deref(),
+        )?;
+
+        // Move what the surviving order needs out of the other escrow; closing
+        // it returns the discount and its rent to the customer
+        ctx.accounts.other_escrow.withdraw(moved)?;
+        move_lamports(
+            &ctx.accounts.other_escrow.to_account_info(),
+            &ctx.accounts.escrow.to_account_info(),
+            moved,
+        )?;
+        ctx.accounts.escrow.deposit(moved)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(other_delivery_id));
+        global_stats.record_cancelled()?;
+
+        // Like closeDelivery, the absorbed order is shrunk to a marker so its
+        // id cannot be booked again
+        let now = Clock::get()?.unix_timestamp;
+        let tombstone = ClosedDelivery {
+            customer: other.customer,
+            delivery_id: other_delivery_id,
+            closed_at: now,
+        };
+        shrink_account(&other_info, &ctx.accounts.customer.to_account_info(), 8 + ClosedDelivery::LEN)?;
+        tombstone.try_serialize(&mut &mut other_info.try_borrow_mut_data()?[..])?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: other_key,
+            status: DeliveryStatus::Cancelled,
+            cancelled_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Target delivery order, 12345
+    pub fn accept_delivery(ctx: Context<AcceptDelivery>, delivery_id: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
+
+        let vehicle = &ctx.accounts.vehicle;
+        require!(vehicle.is_active && vehicle.has_capacity(), ErrorCode::VehicleNotAvailable);
+
+        // Either the operator itself or one of its unexpired dispatcher keys
+        let now = Clock::get()?.unix_timestamp;
+        if ctx.accounts.operator.key() != vehicle.operator {
+            let dispatcher_key = ctx.accounts.dispatcher_key
+                .as_ref()
+                .ok_or(ErrorCode::NotVehicleOperator)?;
+            dispatcher_key.check(PERM_ACCEPT, now)?;
+        }
+
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::Pending, ErrorCode::InvalidDeliveryStatus);
+        require!(delivery.auction_ends_at.is_none(), ErrorCode::AuctionInProgress);
+        delivery.check_schedule(config.schedule_window_seconds, now)?;
+        config.check_vehicle_class(vehicle, &ctx.accounts.region, delivery.payment_amount)?;
+        config.check_stake(vehicle)?;
+        vehicle.check_rating(delivery.min_rating)?;
+        vehicle.check_required_class(delivery.required_class)?;
+        vehicle.check_fleet(ctx.accounts.fleet.as_deref())?;
+        ctx.accounts.customer_profile.check_score(vehicle.min_customer_score)?;
+        ctx.accounts.customer_profile.check_preferred(
+            ctx.accounts.vehicle.key(),
+            delivery.created_at,
+            now,
+        )?;
+
+        let vehicle_mut = &mut ctx.accounts.vehicle;
+        vehicle_mut.mark_busy(now)?;
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
+        delivery.status = DeliveryStatus::InProgress;
+        delivery.assigned_vehicle = Some(vehicle_key);
+        delivery.accepted_at = Some(now);
+        emit!(OrderAccepted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            vehicle: vehicle_key,
+            accepted_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Pending order the dispatcher routes to the given vehicle, 12345
+    pub fn assign_delivery(ctx: Context<AssignDelivery>, delivery_id: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
+
+        let vehicle = &ctx.accounts.vehicle;
+        require!(vehicle.is_active && vehicle.has_capacity(), ErrorCode::VehicleNotAvailable);
+
+        // The vehicle never signs, so the operator's consent is a dispatcher
+        // key issued to this dispatcher with the accept permission, and an
+        // away vehicle is not handed work
+        let now = Clock::get()?.unix_timestamp;
+        vehicle.check_delegate(
+            ctx.accounts.signer.key(),
+            ctx.accounts.dispatcher_key.as_deref(),
+            PERM_ACCEPT,
+            now,
+        )?;
+        require!(!vehicle.is_away(now), ErrorCode::VehicleAway);
+
+        let delivery = &ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::Pending, ErrorCode::InvalidDeliveryStatus);
+        require!(delivery.auction_ends_at.is_none(), ErrorCode::AuctionInProgress);
+        delivery.check_schedule(config.schedule_window_seconds, now)?;
+        config.check_vehicle_class(vehicle, &ctx.accounts.region, delivery.payment_amount)?;
+        config.check_stake(vehicle)?;
+        vehicle.check_rating(delivery.min_rating)?;
+        vehicle.check_required_class(delivery.required_class)?;
+        vehicle.check_fleet(ctx.accounts.fleet.as_deref())?;
+        ctx.accounts.customer_profile.check_score(vehicle.min_customer_score)?;
+        ctx.accounts.customer_profile.check_preferred(
+            ctx.accounts.vehicle.key(),
+            delivery.created_at,
+            now,
+        )?;
+
+        let vehicle_mut = &mut ctx.accounts.vehicle;
+        vehicle_mut.mark_busy(now)?;
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
+        delivery.status = DeliveryStatus::InProgress;
+        delivery.assigned_vehicle = Some(vehicle_key);
+        delivery.accepted_at = Some(now);
+        emit!(OrderAccepted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: delivery_key,
+            vehicle: vehicle_key,
+            accepted_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Pending order to put out for bids; its fare is the max price, 12345
+    // bidding_seconds: Number, How long vehicles may bid, 300
+    pub fn start_auction(
+        ctx: Context<StartAuction>,
+        delivery_id: u64,
+        bidding_seconds: i64,
+    ) -> Result<()> {
+        require!(
+            bidding_seconds > 0 && bidding_seconds <= MAX_AUCTION_SECONDS,
+            ErrorCode::InvalidParameter
+        );
+
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::Pending, ErrorCode::InvalidDeliveryStatus);
+        require!(delivery.auction_ends_at.is_none(), ErrorCode::AuctionInProgress);
+        require!(delivery.payment_mint == Pubkey::default(), ErrorCode::InvalidMint);
+
+        let now = Clock::get()?.unix_timestamp;
+        delivery.auction_ends_at = Some(now.checked_add(bidding_seconds).ok_or(ErrorCode::MathOverflow)?);
+        delivery.lowest_bid = 0;
+        delivery.lowest_bidder = None;
+        Ok(())
+    }
+
+    // delivery_id: Number, Auctioned order, 12345
+    // amount: Number, Price in lamports the vehicle will deliver for, 700000000 = 0.7 SOL
+    pub fn place_bid(ctx: Context<PlaceBid>, delivery_id: u64, amount: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
+
+        let now = Clock::get()?.unix_timestamp;
+        let delivery = &ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::Pending, ErrorCode::InvalidDeliveryStatus);
+        let ends_at = delivery.auction_ends_at.ok_or(ErrorCode::AuctionNotOpen)?;
+        require!(now < ends_at, ErrorCode::AuctionNotOpen);
+
+        let vehicle = &ctx.accounts.vehicle;
+        require!(vehicle.is_active, ErrorCode::VehicleNotAvailable);
+        config.check_vehicle_class(vehicle, &ctx.accounts.region, amount)?;
+        config.check_stake(vehicle)?;
+        vehicle.check_rating(delivery.min_rating)?;
+        vehicle.check_required_class(delivery.required_class)?;
+
+        // Bids only ever go down, for the order and for each vehicle
+        let bid = &ctx.accounts.bid;
+        require!(amount > 0 && amount <= delivery.payment_amount, ErrorCode::BidTooHigh);
+        require!(bid.amount == 0 || amount < bid.amount, ErrorCode::BidTooHigh);
+
+        let delivery_key = ctx.accounts.delivery.key();
+        let vehicle_key = ctx.accounts.vehicle.key();
+        let bid = &mut ctx.accounts.bid;
+        bid.bump = ctx.bumps.bid;
+        bid.delivery = delivery_key;
+        bid.vehicle = vehicle_key;
+        bid.operator = ctx.accounts.operator.key();
+        bid.amount = amount;
+        bid.placed_at = now;
+
+        // Ties go to the earlier bid
+        let delivery = &mut ctx.accounts.delivery;
+        if delivery.lowest_bidder.is_none() || amount < delivery.lowest_bid {
+            delivery.lowest_bid = amount;
+            delivery.lowest_bidder = Some(vehicle_key);
+        }
+        Ok(())
+    }
+
+    // delivery_id: Number, Auctioned order whose bidding window has closed, 12345
+    pub fn finalize_auction(ctx: Context<FinalizeAuction>, delivery_id: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let delivery = &ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::Pending, ErrorCode::InvalidDeliveryStatus);
+        let ends_at = delivery.auction_ends_at.ok_or(ErrorCode::AuctionNotOpen)?;
+        require!(now >= ends_at, ErrorCode::AuctionInProgress);
+
+        // Without bids the order stays open for acceptance at its max price
+        let Some(winner) = delivery.lowest_bidder else {
+            ctx.accounts.delivery.reopen_auction();
+            return Ok(());
+        };
+
+        // A winner that cannot take the order gives it back to the open
+        // market: shown full or inactive at once, or simply absent once the
+        // award window has passed, e.g. after the vehicle deregistered
+        let available = match ctx.accounts.vehicle.as_ref() {
+            Some(vehicle) => {
+                require!(vehicle.key() == winner, ErrorCode::Unauthorized);
+                vehicle.is_active && vehicle.has_capacity()
+            }
+            None => {
+                let award_until = ends_at
+                    .checked_add(AUCTION_AWARD_SECONDS)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                require!(now >= award_until, ErrorCode::AuctionInProgress);
+                false
+            }
+        };
+        if !available {
+            ctx.accounts.delivery.reopen_auction();
+            emit!(AuctionReopened {
+                schema_version: EVENT_SCHEMA_VERSION,
+                delivery: ctx.accounts.delivery.key(),
+                skipped_bidder: winner,
+            });
+            return Ok(());
+        }
+        let vehicle = ctx.accounts.vehicle.as_ref().ok_or(ErrorCode::Unauthorized)?;
+        if let Some(bucket) = &ctx.accounts.geo_bucket {
+            require!(bucket.geohash == vehicle.geohash, ErrorCode::GeohashMismatch);
+        }
+
+        // Re-price the order at the winning bid and hand the difference back
+        let escrowed_before = delivery.escrowed_total()?;
+        let payment_amount = delivery.lowest_bid;
+        let tax_amount = compute_tax(payment_amount, delivery.tax_bps, delivery.tax_inclusive)?;
+
+        let delivery_mut = &mut ctx.accounts.delivery;
+        delivery_mut.payment_amount = payment_amount;
+        delivery_mut.tax_amount = tax_amount;
+        let refund = escrowed_before
+            .checked_sub(delivery_mut.escrowed_total()?)
+            .ok_or(ErrorCode::MathOverflow)?;
+        ctx.accounts.escrow.withdraw(refund)?;
+        move_lamports(
+            &ctx.accounts.escrow.to_account_info(),
+            &ctx.accounts.customer.to_account_info(),
+            refund,
+        )?;
+
+        let vehicle_mut = ctx.accounts.vehicle.as_mut().ok_or(ErrorCode::Unauthorized)?;
+        vehicle_mut.mark_busy(now)?;
+        let vehicle_key = vehicle_mut.key();
+        vehicle_mut.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
+        delivery.reopen_auction();
+        delivery.status = DeliveryStatus::InProgress;
+        delivery.assigned_vehicle = Some(vehicle_key);
+        delivery.accepted_at = Some(now);
+        emit!(OrderAccepted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            vehicle: vehicle_key,
+            accepted_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Order the bid was placed on, 12345
+    pub fn close_bid(ctx: Context<CloseBid>, delivery_id: u64) -> Result<()> {
+        // Bids on an order that closeDelivery already shrank to a marker can
+        // always be closed
+        let info = ctx.accounts.delivery.to_account_info();
+        let data = info.try_borrow_data()?;
+        if let Ok(delivery) = Delivery::try_deserialize(&mut &data[..]) {
+            require!(delivery.auction_ends_at.is_none(), ErrorCode::AuctionInProgress);
+        } else {
+            ClosedDelivery::try_deserialize(&mut &data[..])?;
+        }
+        Ok(())
+    }
+
+    // delivery_id: Number, Head of the region waitlist, 12345
+    pub fn accept_next(ctx: Context<AcceptNext>, delivery_id: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
+
+        let vehicle = &ctx.accounts.vehicle;
+        require!(vehicle.is_active && vehicle.has_capacity(), ErrorCode::VehicleNotAvailable);
+
+        let delivery_key = ctx.accounts.delivery.key();
+        require!(
+            ctx.accounts.delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
+        );
+
+        // Highest priority first, oldest first among equals
+        let now = Clock::get()?.unix_timestamp;
+        let region = &mut ctx.accounts.region;
+        let next = region.next_index().ok_or(ErrorCode::QueueEmpty)?;
+        require!(region.queue[next].delivery == delivery_key, ErrorCode::NotNextInQueue);
+        ctx.accounts.delivery.check_schedule(config.schedule_window_seconds, now)?;
+        config.check_vehicle_class(vehicle, region, ctx.accounts.delivery.payment_amount)?;
+        config.check_stake(vehicle)?;
+        vehicle.check_rating(ctx.accounts.delivery.min_rating)?;
+        vehicle.check_required_class(ctx.accounts.delivery.required_class)?;
+        vehicle.check_fleet(ctx.accounts.fleet.as_deref())?;
+        ctx.accounts.customer_profile.check_score(vehicle.min_customer_score)?;
+        ctx.accounts.customer_profile.check_preferred(
+            ctx.accounts.vehicle.key(),
+            ctx.accounts.delivery.created_at,
+            now,
+        )?;
+        region.dequeue(delivery_key)?;
+
+        let vehicle_mut = &mut ctx.accounts.vehicle;
+        vehicle_mut.mark_busy(now)?;
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
+        delivery.status = DeliveryStatus::InProgress;
+        delivery.assigned_vehicle = Some(vehicle_key);
+        delivery.accepted_at = Some(now);
+        emit!(OrderAccepted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            vehicle: vehicle_key,
+            accepted_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Completed delivery order, 12345
+    pub fn complete_delivery(ctx: Context<CompleteDelivery>, delivery_id: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        // Settlements keep running while paused so escrows are never stranded
+        require!(config.is_active, ErrorCode::ConfigInactive);
+
+        let delivery = &ctx.accounts.delivery;
+        require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.assigned_vehicle == Some(ctx.accounts.vehicle.key()),
+            ErrorCode::Unauthorized
+        );
+        require!(
+            delivery.settlement_unlocked(config.auto_release_seconds, Clock::get()?.unix_timestamp),
+            ErrorCode::DeliveryNotConfirmed
+        );
+        ctx.accounts.vehicle.check_delegate(
+            ctx.accounts.signer.key(),
+            ctx.accounts.dispatcher_key.as_deref(),
+            PERM_COMPLETE,
+            Clock::get()?.unix_timestamp,
+        )?;
+        let mut failed_bps = 0;
+        if delivery.package_count > 0 {
+            let manifest = ctx.accounts.manifest.as_ref().ok_or(ErrorCode::PackagesUnsettled)?;
+            require!(manifest.all_settled(), ErrorCode::PackagesUnsettled);
+            failed_bps = manifest.failed_bps()?;
+        }
+        if delivery.route.is_some() {
+            let route = ctx.accounts.route.as_ref().ok_or(ErrorCode::StopsUnfinished)?;
+            require!(route.all_completed(), ErrorCode::StopsUnfinished);
+        }
+        if delivery.usd_cents > 0 {
+            pyth_micros_per_sol(config, ctx.accounts.pyth_price.as_ref())?;
+        }
+
+        let customer_key = ctx.accounts.customer.key();
+        let vehicle_key = ctx.accounts.vehicle.key();
+        let config_key = ctx.accounts.config.key();
+
+        let now = Clock::get()?.unix_timestamp;
+        let settlement = Settlement::for_delivery(
+            delivery,
+            config,
+            ctx.accounts.vehicle.vehicle_class,
+            ctx.accounts.region.congestion_at(now),
+            ctx.accounts.vehicle.fleet_share_bps(ctx.accounts.fleet.as_deref()),
+            now,
+        )?
+        .less_failed(config, failed_bps)?;
+        let Settlement { fee, tax, penalty, vehicle_payment, fleet_share, operator_payment, released, .. } = settlement;
+        ctx.accounts.config.record_payout(released, now)?;
+
+        let treasury_fee = settlement.pay_out(
+            &mut ctx.accounts.escrow,
+            &ctx.accounts.delivery,
+            &ctx.accounts.config,
+            ctx.accounts.fleet.as_deref(),
+            PayoutAccounts {
+                customer: ctx.accounts.customer.to_account_info(),
+                earnings: ctx.accounts.earnings.to_account_info(),
+                fleet_payout: ctx.accounts.fleet_payout.as_ref().map(|payout| payout.to_account_info()),
+                treasury: ctx.accounts.treasury.to_account_info(),
+                tax_vault: ctx.accounts.tax_vault.to_account_info(),
+                charity: ctx.accounts.charity.as_ref().map(|charity| charity.to_account_info()),
+                insurance_pool: ctx.accounts.insurance_pool.as_mut(),
+                referrals: [
+                    (ctx.accounts.customer_profile.referred, ctx.accounts.customer_referral.as_mut()),
+                    (ctx.accounts.operator_profile.referred, ctx.accounts.operator_referral.as_mut()),
+                ],
+            },
+        )?;
+        ctx.accounts.treasury.credit_fee(&ctx.accounts.config.fee_recipients, treasury_fee)?;
+        let earnings = &mut ctx.accounts.earnings;
+        earnings.open(ctx.bumps.earnings, ctx.accounts.vehicle.operator);
+        earnings.accrue(operator_payment)?;
+        let round_up = ctx.accounts.delivery.round_up;
+        // Closing the escrow then hands any dust left by rounding or stray
+        // transfers back to the customer together with its rent
+
+        let tax_vault = &mut ctx.accounts.tax_vault;
+        tax_vault.total_collected = tax_vault.total_collected
+            .checked_add(tax)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let config_mut = &mut ctx.accounts.config;
+        config_mut.total_fees_collected = config_mut.total_fees_collected
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Invoice numbers are only consumed together with a receipt, so the
+        // sequence stays gap-free even when a settlement fails
+        let invoice_number = config_mut.next_invoice_number;
+        config_mut.next_invoice_number = invoice_number
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let receipt = &mut ctx.accounts.receipt;
+        receipt.bump = ctx.bumps.receipt;
+        receipt.delivery = ctx.accounts.delivery.key();
+        receipt.invoice_number = invoice_number;
+        receipt.customer = customer_key;
+        receipt.vehicle = vehicle_key;
+        receipt.operator = ctx.accounts.vehicle.operator;
+        receipt.gross_amount = ctx.accounts.delivery.payment_amount;
+        receipt.platform_fee = fee;
+        receipt.operator_payment = operator_payment;
+        receipt.fleet_share = fleet_share;
+        receipt.tax_bps = ctx.accounts.delivery.tax_bps;
+        receipt.tax_inclusive = ctx.accounts.delivery.tax_inclusive;
+        receipt.tax_amount = tax;
+        receipt.quote_usd_micros = ctx.accounts.delivery.quote_usd_micros;
+        receipt.quote_eur_micros = ctx.accounts.delivery.quote_eur_micros;
+        receipt.charity_amount = round_up;
+        receipt.issued_at = now;
+        receipt.pickup_proof_hash = ctx.accounts.delivery.pickup_proof_hash;
+        receipt.dropoff_proof_hash = ctx.accounts.delivery.proof_hash;
+        receipt.picked_up_at = ctx.accounts.delivery.picked_up_at;
+        receipt.delivered_at = ctx.accounts.delivery.delivered_at;
+        receipt.nft_mint = None;
+        receipt.cnft_tree = None;
+        receipt.cnft_leaf_index = 0;
+        receipt.payment_mint = Pubkey::default();
+
+        // Passing the receipt mint asks for a one-of-one Metaplex NFT of the
+        // receipt in the customer's wallet. Its mint sits at
+        // ["receipt_nft", delivery], so the NFT and the receipt PDA it
+        // certifies can each be derived from the other
+        if let Some(receipt_mint) = ctx.accounts.receipt_mint.as_ref() {
+            let config = &ctx.accounts.config;
+            require!(!config.receipt_nft_uri.is_empty(), ErrorCode::ReceiptNftDisabled);
+            let (
+                Some(receipt_token),
+                Some(receipt_metadata),
+                Some(receipt_edition),
+                Some(token_metadata_program),
+                Some(token_program),
+                Some(associated_token_program),
+                Some(rent),
+            ) = (
+                ctx.accounts.receipt_token.as_ref(),
+                ctx.accounts.receipt_metadata.as_ref(),
+                ctx.accounts.receipt_edition.as_ref(),
+                ctx.accounts.token_metadata_program.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+                ctx.accounts.associated_token_program.as_ref(),
+                ctx.accounts.rent.as_ref(),
+            )
+            else {
+                return err!(ErrorCode::ReceiptNftAccountsRequired);
+            };
+
+            let delivery_key = ctx.accounts.delivery.key();
+            let (mint_key, mint_bump) =
+                Pubkey::find_program_address(&[b"receipt_nft", delivery_key.as_ref()], &crate::ID);
+            require!(receipt_mint.key() == mint_key, ErrorCode::InvalidReceiptMint);
+
+            let payer = ctx.accounts.signer.to_account_info();
+            let mint_info = receipt_mint.to_account_info();
+            let config_info = config.to_account_info();
+            let system_program_info = ctx.accounts.system_program.to_account_info();
+            let token_program_info = token_program.to_account_info();
+            let config_seeds: &[&[&[u8]]] = &[&[b"config", &[config.bump]]];
+
+            create_pda_account(
+                &payer,
+                &mint_info,
+                &system_program_info,
+                token::Mint::LEN,
+                &[b"receipt_nft", delivery_key.as_ref(), &[mint_bump]],
+                &token_program.key(),
+            )?;
+            token::initialize_mint2(
+                CpiContext::new(token_program_info.clone(), token::InitializeMint2 { mint: mint_info.clone() }),
+                0,
+                &config_info.key(),
+                Some(&config_info.key()),
+            )?;
+            associated_token::create(CpiContext::new(
+                associated_token_program.to_account_info(),
+                associated_token::Create {
+                    payer: payer.clone(),
+                    associated_token: receipt_token.to_account_info(),
+                    authority: ctx.accounts.customer.to_account_info(),
+                    mint: mint_info.clone(),
+                    system_program: system_program_info.clone(),
+                    token_program: token_program_info.clone(),
+                },
+            ))?;
+            token::mint_to(
+                CpiContext::new_with_signer(
+                    token_program_info.clone(),
+                    token::MintTo {
+                        mint: mint_info.clone(),
+                        to: receipt_token.to_account_info(),
+                        authority: config_info.clone(),
+                    },
+                    config_seeds,
+                ),
+                1,
+            )?;
+            create_metadata_accounts_v3(
+                CpiContext::new_with_signer(
+                    token_metadata_program.to_account_info(),
+                    CreateMetadataAccountsV3 {
+                        metadata: receipt_metadata.to_account_info(),
+                        mint: mint_info.clone(),
+                        mint_authority: config_info.clone(),
+                        payer: payer.clone(),
+                        update_authority: config_info.clone(),
+                        system_program: system_program_info.clone(),
+                        rent: rent.to_account_info(),
+                    },
+                    config_seeds,
+                ),
+                DataV2 {
+                    name: format!("Delivery #{}", delivery_id),
+                    symbol: "AVMR".to_string(),
+                    uri: format!("{}{}", config.receipt_nft_uri, delivery_key),
+                    seller_fee_basis_points: 0,
+                    creators: None,
+                    collection: None,
+                    uses: None,
+                },
+                false,
+                true,
+                None,
+            )?;
+            // A zero-supply master edition fixes the supply at one
+            create_master_edition_v3(
+                CpiContext::new_with_signer(
+                    token_metadata_program.to_account_info(),
+                    CreateMasterEditionV3 {
+                        edition: receipt_edition.to_account_info(),
+                        mint: mint_info,
+                        update_authority: config_info.clone(),
+                        mint_authority: config_info,
+                        payer,
+                        metadata: receipt_metadata.to_account_info(),
+                        token_program: token_program_info,
+                        system_program: system_program_info,
+                        rent: rent.to_account_info(),
+                    },
+                    config_seeds,
+                ),
+                Some(0),
+            )?;
+            ctx.accounts.receipt.nft_mint = Some(mint_key);
+        }
+
+        // The compressed alternative appends a leaf to the platform's receipt
+        // tree for a few hundred lamports instead of rent for four accounts
+        if let Some(receipt_tree) = ctx.accounts.receipt_tree.as_ref() {
+            let config = &ctx.accounts.config;
+            require!(!config.receipt_nft_uri.is_empty(), ErrorCode::ReceiptNftDisabled);
+            let (Some(tree_config), Some(bubblegum_program), Some(compression_program), Some(log_wrapper)) = (
+                ctx.accounts.receipt_tree_config.as_ref(),
+                ctx.accounts.bubblegum_program.as_ref(),
+                ctx.accounts.compression_program.as_ref(),
+                ctx.accounts.log_wrapper.as_ref(),
+            ) else {
+                return err!(ErrorCode::ReceiptNftAccountsRequired);
+            };
+
+            // The next leaf's index is the tree's mint count before this mint
+            let leaf_index = TreeConfig::from_bytes(&tree_config.try_borrow_data()?)
+                .map_err(|_| ErrorCode::InvalidReceiptTree)?
+                .num_minted;
+            let delivery_key = ctx.accounts.delivery.key();
+            let customer_info = ctx.accounts.customer.to_account_info();
+            let config_info = config.to_account_info();
+            let payer = ctx.accounts.signer.to_account_info();
+            let system_program_info = ctx.accounts.system_program.to_account_info();
+            let tree_config_info = tree_config.to_account_info();
+            let tree_info = receipt_tree.to_account_info();
+            let log_wrapper_info = log_wrapper.to_account_info();
+            let compression_program_info = compression_program.to_account_info();
+            MintV1CpiBuilder::new(&bubblegum_program.to_account_info())
+                .tree_config(&tree_config_info)
+                .leaf_owner(&customer_info)
+                .leaf_delegate(&customer_info)
+                .merkle_tree(&tree_info)
+                .payer(&payer)
+                .tree_creator_or_delegate(&config_info)
+                .log_wrapper(&log_wrapper_info)
+                .compression_program(&compression_program_info)
+                .system_program(&system_program_info)
+                .metadata(MetadataArgs {
+                    name: format!("Delivery #{}", delivery_id),
+                    symbol: "AVMR".to_string(),
+                    uri: format!("{}{}", config.receipt_nft_uri, delivery_key),
+                    seller_fee_basis_points: 0,
+                    primary_sale_happened: false,
+                    is_mutable: false,
+                    edition_nonce: None,
+                    token_standard: Some(TokenStandard::NonFungible),
+                    collection: None,
+                    uses: None,
+                    token_program_version: TokenProgramVersion::Original,
+                    creators: Vec::new(),
+                })
+                .invoke_signed(&[&[b"config", &[config.bump]]])?;
+
+            let receipt = &mut ctx.accounts.receipt;
+            receipt.cnft_tree = Some(receipt_tree.key());
+            receipt.cnft_leaf_index = leaf_index;
+        }
+
+        let delivery_mut = &mut ctx.accounts.delivery;
+        delivery_mut.status = DeliveryStatus::Completed;
+        delivery_mut.completed_at = Some(now);
+
+        let vehicle_mut = &mut ctx.accounts.vehicle;
+        vehicle_mut.mark_idle(now)?;
+        vehicle_mut.total_deliveries = vehicle_mut.total_deliveries
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        vehicle_mut.revenue = vehicle_mut.revenue
+            .checked_add(vehicle_payment)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let history = &mut ctx.accounts.vehicle_history;
+        history.open(ctx.bumps.vehicle_history, vehicle_key);
+        history.push(HistoryEntry {
+            delivery_id,
+            payment_amount: ctx.accounts.delivery.payment_amount,
+            completed_at: now,
+        });
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+        if ctx.accounts.vehicle.fleet.is_some() {
+            let fleet = ctx.accounts.fleet.as_mut().ok_or(ErrorCode::FleetAccountRequired)?;
+            fleet.record_job(vehicle_payment)?;
+        }
+
+        let profile = &mut ctx.accounts.operator_profile;
+        profile.gross_earnings = profile.gross_earnings
+            .checked_add(operator_payment)
+            .ok_or(ErrorCode::MathOverflow)?;
+        profile.fees_paid = profile.fees_paid
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+        profile.completed_jobs = profile.completed_jobs
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        profile.taxes_withheld = profile.taxes_withheld
+            .checked_add(tax)
+            .ok_or(ErrorCode::MathOverflow)?;
+        profile.penalties = profile.penalties
+            .checked_add(penalty)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let customer_profile = &mut ctx.accounts.customer_profile;
+        customer_profile.total_spend = customer_profile.total_spend
+            .checked_add(ctx.accounts.delivery.payment_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        customer_profile.orders_completed = customer_profile.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        customer_profile.loyalty_tier = CustomerProfile::tier_for(customer_profile.orders_completed);
+
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_completed = daily_stats.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_completed(ctx.accounts.delivery.payment_amount, fee)?;
+
+        emit!(OrderCompleted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            vehicle: vehicle_key,
+            operator_payment,
+            platform_fee: fee,
+            completed_at: now,
+        });
+        Ok(())
+    }
+
+    // region_id: Number, Region every order in the batch was booked in, 1
+    // remaining_accounts: Array, Per order: delivery, escrow, customer, customer profile, the customer's referral PDA and receipt PDA, [delivery, escrow, customer, customerProfile, customerReferral, receipt]
+    pub fn complete_deliveries_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CompleteDeliveriesBatch<'info>>,
+        region_id: u16,
+    ) -> Result<()> {
+        require!(ctx.accounts.config.is_active, ErrorCode::ConfigInactive);
+        let orders = ctx.remaining_accounts;
+        require!(
+            !orders.is_empty()
+                && orders.len() % BATCH_SETTLEMENT_ACCOUNTS == 0
+                && orders.len() / BATCH_SETTLEMENT_ACCOUNTS <= MAX_BATCH_SETTLEMENTS,
+            ErrorCode::InvalidBatch
+        );
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.vehicle.check_delegate(
+            ctx.accounts.vehicle_operator.key(),
+            ctx.accounts.dispatcher_key.as_deref(),
+            PERM_COMPLETE,
+            now,
+        )?;
+        ctx.accounts.vehicle_history.open(ctx.bumps.vehicle_history, vehicle_key);
+        ctx.accounts.global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(region_id as u64));
+        let earnings_info = ctx.accounts.earnings.to_account_info();
+        let payer_info = ctx.accounts.vehicle_operator.to_account_info();
+        let system_program_info = ctx.accounts.system_program.to_account_info();
+
+        let mut settled = 0u64;
+        let mut total_fee = 0u64;
+        let mut total_tax = 0u64;
+        let mut total_penalty = 0u64;
+        let mut total_vehicle_payment = 0u64;
+        let mut total_operator_payment = 0u64;
+
+        for order in orders.chunks(BATCH_SETTLEMENT_ACCOUNTS) {
+            let (delivery_info, escrow_info, customer, profile_info, referral_info, receipt_info) =
+                (&order[0], &order[1], &order[2], &order[3], &order[4], &order[5]);
+
+            let mut delivery: Account<'info, Delivery> = Account::try_from(delivery_info)?;
+            let delivery_key = delivery.key();
+            require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+            require!(delivery.assigned_vehicle == Some(vehicle_key), ErrorCode::Unauthorized);
+            require!(
+                delivery.settlement_unlocked(ctx.accounts.config.auto_release_seconds, now),
+                ErrorCode::DeliveryNotConfirmed
+            );
+            // Orders with package manifests or routes settle through complete_delivery
+            require!(
+                delivery.package_count == 0 && delivery.route.is_none() && delivery.region_id == region_id,
+                ErrorCode::InvalidBatch
+            );
+            if delivery.usd_cents > 0 {
+                pyth_micros_per_sol(&ctx.accounts.config, ctx.accounts.pyth_price.as_ref())?;
+            }
+
+            let mut escrow: Account<'info, Escrow> = Account::try_from(escrow_info)?;
+            require!(escrow.delivery == delivery_key, ErrorCode::InvalidEscrow);
+            require!(customer.key() == delivery.customer, ErrorCode::Unauthorized);
+            let mut customer_profile: Account<'info, CustomerProfile> = Account::try_from(profile_info)?;
+            require!(customer_profile.customer == delivery.customer, ErrorCode::Unauthorized);
+            let (receipt_key, receipt_bump) =
+                Pubkey::find_program_address(&[b"receipt", delivery_key.as_ref()], &crate::ID);
+            require!(receipt_info.key() == receipt_key, ErrorCode::InvalidBatch);
+
+            let settlement = Settlement::for_delivery(
+                &delivery,
+                &ctx.accounts.config,
+                ctx.accounts.vehicle.vehicle_class,
+                ctx.accounts.region.congestion_at(now),
+                ctx.accounts.vehicle.fleet_share_bps(ctx.accounts.fleet.as_deref()),
+                now,
+            )?;
+            let Settlement { fee, tax, penalty, vehicle_payment, fleet_share, operator_payment, released, .. } = settlement;
+            ctx.accounts.config.record_payout(released, now)?;
+
+            // The customer's referral is only read when their profile says
+            // they were referred
+            let mut customer_referral: Option<Account<'info, Referral>> = None;
+            if customer_profile.referred {
+                let (referral_key, _) =
+                    Pubkey::find_program_address(&[b"referral", delivery.customer.as_ref()], &crate::ID);
+                require!(referral_info.key() == referral_key, ErrorCode::ReferralRequired);
+                customer_referral = Some(Account::try_from(referral_info)?);
+            }
+
+            let treasury_fee = settlement.pay_out(
+                &mut escrow,
+                &delivery,
+                &ctx.accounts.config,
+                ctx.accounts.fleet.as_deref(),
+                PayoutAccounts {
+                    customer: customer.clone(),
+                    earnings: earnings_info.clone(),
+                    fleet_payout: ctx.accounts.fleet_payout.as_ref().map(|payout| payout.to_account_info()),
+                    treasury: ctx.accounts.treasury.to_account_info(),
+                    tax_vault: ctx.accounts.tax_vault.to_account_info(),
+                    charity: ctx.accounts.charity.as_ref().map(|charity| charity.to_account_info()),
+                    insurance_pool: ctx.accounts.insurance_pool.as_mut(),
+                    referrals: [
+                        (customer_profile.referred, customer_referral.as_mut()),
+                        (ctx.accounts.operator_profile.referred, ctx.accounts.operator_referral.as_mut()),
+                    ],
+                },
+            )?;
+            ctx.accounts.treasury.credit_fee(&ctx.accounts.config.fee_recipients, treasury_fee)?;
+            if let Some(referral) = customer_referral {
+                referral.exit(&crate::ID)?;
+            }
+            let round_up = delivery.round_up;
+            escrow.close(customer.clone())?;
+
+            create_pda_account(
+                &payer_info,
+                receipt_info,
+                &system_program_info,
+                8 + Receipt::LEN,
+                &[b"receipt", delivery_key.as_ref(), &[receipt_bump]],
+                &crate::ID,
+            )?;
+            let config_mut = &mut ctx.accounts.config;
+            let invoice_number = config_mut.next_invoice_number;
+            config_mut.next_invoice_number = invoice_number
+                .checked_add(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+            let receipt = Receipt {
+                bump: receipt_bump,
+                delivery: delivery_key,
+                invoice_number,
+                customer: delivery.customer,
+                vehicle: vehicle_key,
+                operator: ctx.accounts.vehicle.operator,
+                gross_amount: delivery.payment_amount,
+                platform_fee: fee,
+                operator_payment,
+                issued_at: now,
+                tax_bps: delivery.tax_bps,
+                tax_inclusive: delivery.tax_inclusive,
+                tax_amount: tax,
+                quote_usd_micros: delivery.quote_usd_micros,
+                quote_eur_micros: delivery.quote_eur_micros,
+                charity_amount: round_up,
+                fleet_share,
+                pickup_proof_hash: delivery.pickup_proof_hash,
+                dropoff_proof_hash: delivery.proof_hash,
+                picked_up_at: delivery.picked_up_at,
+                delivered_at: delivery.delivered_at,
+                nft_mint: None,
+                cnft_tree: None,
+                cnft_leaf_index: 0,
+                payment_mint: Pubkey::default(),
+            };
+            receipt.try_serialize(&mut &mut receipt_info.try_borrow_mut_data()?[..])?;
+
+            delivery.status = DeliveryStatus::Completed;
+            delivery.completed_at = Some(now);
+            delivery.exit(&crate::ID)?;
+            ctx.accounts.vehicle.mark_idle(now)?;
+
+            ctx.accounts.vehicle_history.push(HistoryEntry {
+                delivery_id: delivery.delivery_id,
+                payment_amount: delivery.payment_amount,
+                completed_at: now,
+            });
+            ctx.accounts.global_stats.record_completed(delivery.payment_amount, fee)?;
+
+            customer_profile.total_spend = customer_profile.total_spend
+                .checked_add(delivery.payment_amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+            customer_profile.orders_completed = customer_profile.orders_completed
+                .checked_add(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+            customer_profile.loyalty_tier = CustomerProfile::tier_for(customer_profile.orders_completed);
+            customer_profile.exit(&crate::ID)?;
+
+            if ctx.accounts.vehicle.fleet.is_some() {
+                let fleet = ctx.accounts.fleet.as_mut().ok_or(ErrorCode::FleetAccountRequired)?;
+                fleet.record_job(vehicle_payment)?;
+            }
+
+            settled = settled.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+            total_fee = total_fee.checked_add(fee).ok_or(ErrorCode::MathOverflow)?;
+            total_tax = total_tax.checked_add(tax).ok_or(ErrorCode::MathOverflow)?;
+            total_penalty = total_penalty.checked_add(penalty).ok_or(ErrorCode::MathOverflow)?;
+            total_vehicle_payment = total_vehicle_payment
+                .checked_add(vehicle_payment)
+                .ok_or(ErrorCode::MathOverflow)?;
+            total_operator_payment = total_operator_payment
+                .checked_add(operator_payment)
+                .ok_or(ErrorCode::MathOverflow)?;
+
+            emit!(OrderCompleted {
+                schema_version: EVENT_SCHEMA_VERSION,
+                delivery: delivery_key,
+                vehicle: vehicle_key,
+                operator_payment,
+                platform_fee: fee,
+                completed_at: now,
+            });
+        }
+
+        let earnings = &mut ctx.accounts.earnings;
+        earnings.open(ctx.bumps.earnings, ctx.accounts.vehicle.operator);
+        earnings.accrue(total_operator_payment)?;
+
+        let tax_vault = &mut ctx.accounts.tax_vault;
+        tax_vault.total_collected = tax_vault.total_collected
+            .checked_add(total_tax)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let config_mut = &mut ctx.accounts.config;
+        config_mut.total_fees_collected = config_mut.total_fees_collected
+            .checked_add(total_fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let vehicle_mut = &mut ctx.accounts.vehicle;
+        vehicle_mut.total_deliveries = vehicle_mut.total_deliveries
+            .checked_add(settled)
+            .ok_or(ErrorCode::MathOverflow)?;
+        vehicle_mut.revenue = vehicle_mut.revenue
+            .checked_add(total_vehicle_payment)
+            .ok_or(ErrorCode::MathOverflow)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+
+        let profile = &mut ctx.accounts.operator_profile;
+        profile.gross_earnings = profile.gross_earnings
+            .checked_add(total_operator_payment)
+            .ok_or(ErrorCode::MathOverflow)?;
+        profile.fees_paid = profile.fees_paid
+            .checked_add(total_fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+        profile.completed_jobs = profile.completed_jobs
+            .checked_add(settled)
+            .ok_or(ErrorCode::MathOverflow)?;
+        profile.taxes_withheld = profile.taxes_withheld
+            .checked_add(total_tax)
+            .ok_or(ErrorCode::MathOverflow)?;
+        profile.penalties = profile.penalties
+            .checked_add(total_penalty)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_completed = daily_stats.orders_completed
+            .checked_add(settled)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // delivery_id: Number, Completed order being rated, 12345
+    // score: Number, Stars from 1 to 5, 5
+    pub fn rate_delivery(ctx: Context<RateDelivery>, delivery_id: u64, score: u8) -> Result<()> {
+        require!(score >= 1 && score <= MAX_RATING, ErrorCode::InvalidParameter);
+
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::Completed, ErrorCode::InvalidDeliveryStatus);
+        require!(!delivery.rated, ErrorCode::AlreadyRated);
+        delivery.rated = true;
+
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.rating_count = vehicle.rating_count
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        vehicle.rating_sum = vehicle.rating_sum
+            .checked_add(score as u64)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // delivery_id: Number, Completed order being tipped, 12345
+    // amount: Number, Tip in lamports, 5000000 = 0.005 SOL
+    pub fn tip_operator(ctx: Context<TipOperator>, delivery_id: u64, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidParameter);
+        require!(
+            ctx.accounts.delivery.status == DeliveryStatus::Completed,
+            ErrorCode::InvalidDeliveryStatus
+        );
+
+        let fee = ctx.accounts.config.tip_fee(amount, ctx.accounts.vehicle.vehicle_class)?;
+        let tip = amount.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.customer.to_account_info(),
+                    to: ctx.accounts.vehicle_operator.to_account_info(),
+                },
+            ),
+            tip,
+        )?;
+        if fee > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.customer.to_account_info(),
+                        to: ctx.accounts.treasury.to_account_info(),
+                    },
+                ),
+                fee,
+            )?;
+            ctx.accounts.treasury.credit_fee(&ctx.accounts.config.fee_recipients, fee)?;
+            let config = &mut ctx.accounts.config;
+            config.total_fees_collected = config.total_fees_collected
+                .checked_add(fee)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.total_tips = vehicle.total_tips
+            .checked_add(tip)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let profile = &mut ctx.accounts.operator_profile;
+        profile.tips = profile.tips
+            .checked_add(tip)
+            .ok_or(ErrorCode::MathOverflow)?;
+        profile.fees_paid = profile.fees_paid
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // delivery_id: Number, Completed token-paid order being tipped, 12345
+    // amount: Number, Tip in base units of the order's mint, 500000 = 0.5 USDC
+    pub fn tip_operator_spl(ctx: Context<TipOperatorSpl>, delivery_id: u64, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidParameter);
+        require!(
+            ctx.accounts.delivery.status == DeliveryStatus::Completed,
+            ErrorCode::InvalidDeliveryStatus
+        );
+
+        let fee = ctx.accounts.config.tip_fee(amount, ctx.accounts.vehicle.vehicle_class)?;
+        let tip = amount.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+        // The customer covers the mint's transfer fee so the operator nets the whole tip
+        let tip_gross = gross_for_net(&ctx.accounts.mint.to_account_info(), tip)?;
+        let decimals = ctx.accounts.mint.decimals;
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.customer_token.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.operator_token.to_account_info(),
+                    authority: ctx.accounts.customer.to_account_info(),
+                },
+            ),
+            tip_gross,
+            decimals,
+        )?;
+        if fee > 0 {
+            token_interface::transfer_checked(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.customer_token.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: ctx.accounts.treasury_token.to_account_info(),
+                        authority: ctx.accounts.customer.to_account_info(),
+                    },
+                ),
+                fee,
+                decimals,
+            )?;
+        }
+
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.total_tips = vehicle.total_tips
+            .checked_add(tip)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let profile = &mut ctx.accounts.operator_profile;
+        profile.tips = profile.tips
+            .checked_add(tip)
+            .ok_or(ErrorCode::MathOverflow)?;
+        profile.fees_paid = profile.fees_paid
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // delivery_id: Number, Unique order identifier, 12345
+    // payment_amount: Number, Payment in token base units, 25000000 = 25 USDC
+    // pickup_location: String, Pickup coordinates, "40.7128,-74.0060"
+    // delivery_location: String, Drop-off coordinates, "40.7589,-73.9851"
+    // region_id: Number, Region the order is booked in, 1
+    pub fn create_delivery_order_spl(
+        ctx: Context<CreateDeliveryOrderSpl>,
+        delivery_id: u64,
+        payment_amount: u64,
+        pickup_location: String,
+        delivery_location: String,
+        region_id: u16,
+    ) -> Result<()> {
+        require!(pickup_location.len() <= 64, ErrorCode::InvalidParameter);
+        require!(delivery_location.len() <= 64, ErrorCode::InvalidParameter);
+        require!(payment_amount > 0, ErrorCode::InvalidAmount);
+
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
+        let mint_info = ctx.accounts.mint.to_account_info();
+        check_mint_extensions(&mint_info, config.allowed_token_extensions)?;
+
+        let surge_bps = config.surge_bps;
+        let payment_amount = config.apply_surge(payment_amount)?;
+
+        // Tax is remitted from the SOL tax vault, so token orders are only
+        // taken in untaxed regions
+        let region = &ctx.accounts.region;
+        require!(region.tax_bps == 0, ErrorCode::TokenTaxUnsupported);
+        let now = Clock::get()?.unix_timestamp;
+        let hazard_surcharge = region.hazard_surcharge(payment_amount, config.hazard_surcharge_bps, now)?;
+        let escrow_amount = payment_amount
+            .checked_add(hazard_surcharge)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Gross up for any transfer fee so the escrow holds the full amount
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.customer_token.to_account_info(),
+                    mint: mint_info.clone(),
+                    to: ctx.accounts.escrow_token.to_account_info(),
+                    authority: ctx.accounts.customer.to_account_info(),
+                },
+            ),
+            gross_for_net(&mint_info, escrow_amount)?,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let customer_key = ctx.accounts.customer.key();
+        let pricing = OrderPricing {
+            tax_amount: 0,
+            hazard_surcharge,
+            round_up: 0,
+            charity: Pubkey::default(),
+            no_show_deposit: 0,
+            escrow_amount,
+        };
+
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.bump = ctx.bumps.delivery;
+        delivery.book(delivery_id, customer_key, payment_amount, region, &pricing, now);
+        delivery.surge_bps = surge_bps;
+        delivery.set_locations(pickup_location, delivery_location);
+        delivery.payment_mint = ctx.accounts.mint.key();
+
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.open(ctx.bumps.customer_profile, customer_key, now);
+        profile.orders_created = profile.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_created = daily_stats.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_created()?;
+        emit!(OrderCreated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            customer: ctx.accounts.delivery.customer,
+            payment_amount: ctx.accounts.delivery.payment_amount,
+            region_id: ctx.accounts.delivery.region_id,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Token-paid order being settled, 12345
+    pub fn complete_delivery_spl(ctx: Context<CompleteDeliverySpl>, delivery_id: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active, ErrorCode::ConfigInactive);
+
+        let delivery = &ctx.accounts.delivery;
+        require!(!delivery.confidential, ErrorCode::ConfidentialOrder);
+        require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.assigned_vehicle == Some(ctx.accounts.vehicle.key()),
+            ErrorCode::Unauthorized
+        );
+        require!(
+            delivery.settlement_unlocked(config.auto_release_seconds, Clock::get()?.unix_timestamp),
+            ErrorCode::DeliveryNotConfirmed
+        );
+        let mut failed_bps = 0;
+        if delivery.package_count > 0 {
+            let manifest = ctx.accounts.manifest.as_ref().ok_or(ErrorCode::PackagesUnsettled)?;
+            require!(manifest.all_settled(), ErrorCode::PackagesUnsettled);
+            failed_bps = manifest.failed_bps()?;
+        }
+
+        // Same split as a SOL settlement, in the token's base units
+        let now = Clock::get()?.unix_timestamp;
+        let settlement = Settlement::for_delivery(
+            delivery,
+            config,
+            ctx.accounts.vehicle.vehicle_class,
+            ctx.accounts.region.congestion_at(now),
+            ctx.accounts.vehicle.fleet_share_bps(ctx.accounts.fleet.as_deref()),
+            now,
+        )?
+        .less_failed(config, failed_bps)?;
+        let Settlement { vehicle_payment, fleet_share, operator_payment, .. } = settlement;
+
+        let customer_key = ctx.accounts.customer.key();
+        let vehicle_key = ctx.accounts.vehicle.key();
+        let config_key = ctx.accounts.config.key();
+        let id_bytes = delivery_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow_token",
+            customer_key.as_ref(),
+            &id_bytes,
+            &[ctx.bumps.escrow_token],
+        ]];
+        let fee = settlement.pay_out_token(
+            &ctx.accounts.token_program,
+            &mut ctx.accounts.escrow_token,
+            &ctx.accounts.mint,
+            ctx.accounts.fleet.as_deref(),
+            TokenPayoutAccounts {
+                operator: &ctx.accounts.operator_token,
+                treasury: &ctx.accounts.treasury_token,
+                customer: &ctx.accounts.customer_token,
+                fleet: ctx.accounts.fleet_token.as_ref(),
+                insurance: ctx.accounts.insurance_token.as_ref(),
+                insurance_pool: Pubkey::find_program_address(&[b"insurance_pool", config_key.as_ref()], &crate::ID).0,
+                rent_to: ctx.accounts.customer.to_account_info(),
+            },
+            signer_seeds,
+        )?;
+
+        // Invoice numbers are only consumed together with a receipt, so the
+        // sequence stays gap-free even when a settlement fails
+        let config_mut = &mut ctx.accounts.config;
+        let invoice_number = config_mut.next_invoice_number;
+        config_mut.next_invoice_number = invoice_number
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let delivery = &ctx.accounts.delivery;
+        let receipt = &mut ctx.accounts.receipt;
+        receipt.bump = ctx.bumps.receipt;
+        receipt.delivery = delivery.key();
+        receipt.invoice_number = invoice_number;
+        receipt.customer = customer_key;
+        receipt.vehicle = vehicle_key;
+        receipt.operator = ctx.accounts.vehicle_operator.key();
+        receipt.gross_amount = delivery.payment_amount;
+        receipt.platform_fee = fee;
+        receipt.operator_payment = operator_payment;
+        receipt.fleet_share = fleet_share;
+        receipt.tax_bps = 0;
+        receipt.tax_inclusive = false;
+        receipt.tax_amount = 0;
+        receipt.quote_usd_micros = delivery.quote_usd_micros;
+        receipt.quote_eur_micros = delivery.quote_eur_micros;
+        receipt.charity_amount = 0;
+        receipt.issued_at = now;
+        receipt.pickup_proof_hash = delivery.pickup_proof_hash;
+        receipt.dropoff_proof_hash = delivery.proof_hash;
+        receipt.picked_up_at = delivery.picked_up_at;
+        receipt.delivered_at = delivery.delivered_at;
+        receipt.nft_mint = None;
+        receipt.cnft_tree = None;
+        receipt.cnft_leaf_index = 0;
+        receipt.payment_mint = delivery.payment_mint;
+
+        let delivery_mut = &mut ctx.accounts.delivery;
+        delivery_mut.status = DeliveryStatus::Completed;
+        delivery_mut.completed_at = Some(now);
+
+        let vehicle_mut = &mut ctx.accounts.vehicle;
+        vehicle_mut.mark_idle(now)?;
+        vehicle_mut.total_deliveries = vehicle_mut.total_deliveries
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+        if ctx.accounts.vehicle.fleet.is_some() {
+            let fleet = ctx.accounts.fleet.as_mut().ok_or(ErrorCode::FleetAccountRequired)?;
+            fleet.record_job(vehicle_payment)?;
+        }
+
+        // Profile amounts are kept in lamports, so a token order only adds
+        // to the counts; the receipt carries its amounts and mint
+        let profile = &mut ctx.accounts.operator_profile;
+        profile.completed_jobs = profile.completed_jobs
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let customer_profile = &mut ctx.accounts.customer_profile;
+        customer_profile.orders_completed = customer_profile.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        customer_profile.loyalty_tier = CustomerProfile::tier_for(customer_profile.orders_completed);
+
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_completed = daily_stats.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_token_completed()?;
+        emit!(OrderCompleted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            vehicle: vehicle_key,
+            operator_payment,
+            platform_fee: fee,
+            completed_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Pending token-paid order the customer withdraws, 12345
+    pub fn cancel_delivery_spl(ctx: Context<CancelDeliverySpl>, delivery_id: u64) -> Result<()> {
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::Pending, ErrorCode::InvalidDeliveryStatus);
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
+        delivery.status = DeliveryStatus::Cancelled;
+
+        refund_token_escrow(
+            &ctx.accounts.token_program,
+            &ctx.accounts.escrow_token,
+            &ctx.accounts.mint,
+            &ctx.accounts.customer_token.to_account_info(),
+            &ctx.accounts.customer.to_account_info(),
+            delivery_id,
+            ctx.bumps.escrow_token,
+        )?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_cancelled()?;
+
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.cancellations = profile.cancellations
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            status: ctx.accounts.delivery.status.clone(),
+            cancelled_at: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Token-paid order nobody accepted in time, 12345
+    pub fn expire_delivery_spl(ctx: Context<ExpireDeliverySpl>, delivery_id: u64) -> Result<()> {
+        let expiry_seconds = ctx.accounts.config.expiry_seconds;
+        require!(expiry_seconds > 0, ErrorCode::DeliveryNotExpired);
+
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        require!(
+            delivery.status == DeliveryStatus::Pending || delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now >= delivery.expiry_starts_at().saturating_add(expiry_seconds),
+            ErrorCode::DeliveryNotExpired
+        );
+
+        if delivery.status == DeliveryStatus::Queued {
+            ctx.accounts.region.dequeue(delivery_key)?;
+        }
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
+        delivery.status = DeliveryStatus::Expired;
+
+        refund_token_escrow(
+            &ctx.accounts.token_program,
+            &ctx.accounts.escrow_token,
+            &ctx.accounts.mint,
+            &ctx.accounts.customer_token.to_account_info(),
+            &ctx.accounts.customer.to_account_info(),
+            delivery_id,
+            ctx.bumps.escrow_token,
+        )?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_cancelled()?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            status: ctx.accounts.delivery.status.clone(),
+            cancelled_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Unique order identifier, 12345
+    // pickup_location: String, Pickup coordinates, "40.7128,-74.0060"
+    // delivery_location: String, Drop-off coordinates, "40.7589,-73.9851"
+    // region_id: Number, Region the order is booked in, 1
+    // escrow_zero_balance: Bytes, Escrow's zero balance encrypted under the prover's AE key, [u8; 36]
+    // customer_new_balance: Bytes, Customer's decryptable available balance after funding the escrow, [u8; 36]
+    // escrow_funded_balance: Bytes, Escrow's decryptable balance once the funding is applied, [u8; 36]
+    // amount_commitment: Bytes, Pedersen commitment to the escrowed amount, [u8; 32]
+    pub fn create_delivery_order_confidential(
+        ctx: Context<CreateDeliveryOrderConfidential>,
+        delivery_id: u64,
+        pickup_location: String,
+        delivery_location: String,
+        region_id: u16,
+        escrow_zero_balance: [u8; 36],
+        customer_new_balance: [u8; 36],
+        escrow_funded_balance: [u8; 36],
+        amount_commitment: [u8; 32],
+    ) -> Result<()> {
+        require!(pickup_location.len() <= 64, ErrorCode::InvalidParameter);
+        require!(delivery_location.len() <= 64, ErrorCode::InvalidParameter);
+
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
+        require!(config.confidential_prover != Pubkey::default(), ErrorCode::ConfidentialDisabled);
+        let mint_info = ctx.accounts.mint.to_account_info();
+        check_mint_extensions(&mint_info, config.allowed_token_extensions)?;
+        require!(ctx.accounts.region.tax_bps == 0, ErrorCode::TokenTaxUnsupported);
+
+        // The escrow needs room for its confidential balance plus whatever
+        // the mint's other extensions require of token accounts. Fee-bearing
+        // confidential transfers need a different proof set and are refused
+        let space = {
+            let data = mint_info.try_borrow_data()?;
+            let mint_state = StateWithExtensions::<anchor_spl::token_2022::spl_token_2022::state::Mint>::unpack(&data)?;
+            let mint_extensions = mint_state.get_extension_types()?;
+            require!(
+                mint_extensions.contains(&ExtensionType::ConfidentialTransferMint)
+                    && !mint_extensions.contains(&ExtensionType::TransferFeeConfig),
+                ErrorCode::ConfidentialUnsupported
+            );
+            let mut account_extensions = ExtensionType::get_required_init_account_extensions(&mint_extensions);
+            if !account_extensions.contains(&ExtensionType::ConfidentialTransferAccount) {
+                account_extensions.push(ExtensionType::ConfidentialTransferAccount);
+            }
+            ExtensionType::try_calculate_account_len::<anchor_spl::token_2022::spl_token_2022::state::Account>(
+                &account_extensions,
+            )?
+        };
+
+        let customer_key = ctx.accounts.customer.key();
+        let escrow_key = ctx.accounts.escrow_token.key();
+        let mint_key = ctx.accounts.mint.key();
+        let token_program_key = ctx.accounts.token_program.key();
+        let id_bytes = delivery_id.to_le_bytes();
+        let escrow_seeds: &[&[u8]] = &[
+            b"escrow_token",
+            customer_key.as_ref(),
+            &id_bytes,
+            &[ctx.bumps.escrow_token],
+        ];
+        let escrow_info = ctx.accounts.escrow_token.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+
+        create_pda_account(
+            &ctx.accounts.customer.to_account_info(),
+            &escrow_info,
+            &ctx.accounts.system_program.to_account_info(),
+            space,
+            escrow_seeds,
+            &token_program_key,
+        )?;
+        token_interface::initialize_account3(CpiContext::new(
+            token_program_info.clone(),
+            InitializeAccount3 {
+                account: escrow_info.clone(),
+                mint: mint_info.clone(),
+                authority: escrow_info.clone(),
+            },
+        ))?;
+
+        // The prover holds the escrow's ElGamal key; its validity proof was
+        // verified into a context account ahead of this instruction
+        invoke_token_2022(
+            confidential_ix::configure_account(
+                &token_program_key,
+                &escrow_key,
+                &mint_key,
+                &decryptable_balance(escrow_zero_balance),
+                CONFIDENTIAL_ESCROW_MAX_CREDITS,
+                &escrow_key,
+                &[],
+                ProofLocation::ContextStateAccount(ctx.accounts.pubkey_validity_proof.key),
+            )?,
+            &[
+                escrow_info.clone(),
+                mint_info.clone(),
+                ctx.accounts.pubkey_validity_proof.to_account_info(),
+                token_program_info.clone(),
+            ],
+            &[escrow_seeds],
+        )?;
+
+        // The customer funds it with a confidential transfer, proving the
+        // hidden amount against their own balance
+        invoke_token_2022(
+            confidential_ix::transfer(
+                &token_program_key,
+                &ctx.accounts.customer_token.key(),
+                &mint_key,
+                &escrow_key,
+                &decryptable_balance(customer_new_balance),
+                &customer_key,
+                &[],
+                ProofLocation::ContextStateAccount(ctx.accounts.equality_proof.key),
+                ProofLocation::ContextStateAccount(ctx.accounts.ciphertext_validity_proof.key),
+                ProofLocation::ContextStateAccount(ctx.accounts.range_proof.key),
+            )?,
+            &[
+                ctx.accounts.customer_token.to_account_info(),
+                mint_info,
+                escrow_info.clone(),
+                ctx.accounts.equality_proof.to_account_info(),
+                ctx.accounts.ciphertext_validity_proof.to_account_info(),
+                ctx.accounts.range_proof.to_account_info(),
+                ctx.accounts.customer.to_account_info(),
+                token_program_info.clone(),
+            ],
+            &[],
+        )?;
+
+        // Applying the funding now leaves its whole amount in the available
+        // balance, which the commitment is proven against. Vehicles check
+        // the commitment's opening off-chain before accepting
+        apply_confidential_pending(&token_program_info, &escrow_info, escrow_funded_balance, &[escrow_seeds])?;
+        check_escrow_commitment(&escrow_info, &ctx.accounts.commitment_proof.to_account_info(), &amount_commitment)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let pricing = OrderPricing {
+            tax_amount: 0,
+            hazard_surcharge: 0,
+            round_up: 0,
+            charity: Pubkey::default(),
+            no_show_deposit: 0,
+            escrow_amount: 0,
+        };
+
+        // Only the order's lifecycle is public; its amount stays encrypted.
+        // The surge in force is recorded so vehicles can check that the
+        // committed fare includes the premium before accepting
+        let surge_bps = ctx.accounts.config.surge_bps;
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.bump = ctx.bumps.delivery;
+        delivery.book(delivery_id, customer_key, 0, &ctx.accounts.region, &pricing, now);
+        delivery.surge_bps = surge_bps;
+        delivery.set_locations(pickup_location, delivery_location);
+        delivery.payment_mint = mint_key;
+        delivery.confidential = true;
+        delivery.amount_commitment = amount_commitment;
+
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.open(ctx.bumps.customer_profile, customer_key, now);
+        profile.orders_created = profile.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_created = daily_stats.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_created()?;
+        emit!(OrderCreated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            customer: customer_key,
+            payment_amount: 0,
+            region_id,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Confidential order being settled, 12345
+    // legs: Array, Payouts in order with the escrow's decryptable balance after each; the treasury's fee comes last and empties the escrow, [{ payee: { operator: {} }, escrowAfter }]
+    pub fn complete_delivery_confidential<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CompleteDeliveryConfidential<'info>>,
+        delivery_id: u64,
+        legs: Vec<ConfidentialLeg>,
+    ) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active, ErrorCode::ConfigInactive);
+
+        let now = Clock::get()?.unix_timestamp;
+        let delivery = &ctx.accounts.delivery;
+        require!(delivery.confidential, ErrorCode::NotConfidential);
+        require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.assigned_vehicle == Some(ctx.accounts.vehicle.key()),
+            ErrorCode::Unauthorized
+        );
+        require!(
+            delivery.settlement_unlocked(config.auto_release_seconds, now),
+            ErrorCode::DeliveryNotConfirmed
+        );
+        let mut failed_bps = 0;
+        if delivery.package_count > 0 {
+            let manifest = ctx.accounts.manifest.as_ref().ok_or(ErrorCode::PackagesUnsettled)?;
+            require!(manifest.all_settled(), ErrorCode::PackagesUnsettled);
+            failed_bps = manifest.failed_bps()?;
+        }
+        require!(
+            ctx.accounts.vehicle.fleet.is_none() || ctx.accounts.fleet.is_some(),
+            ErrorCode::FleetAccountRequired
+        );
+
+        // The prover, which alone can decrypt the escrow, is trusted to split
+        // it as a lamport settlement would, failed packages included. The
+        // program makes sure every party owed a share gets a leg, and
+        // Token-2022 checks each leg's proofs against the encrypted balance
+        let fleet_takes_share = ctx.accounts.vehicle.fleet_share_bps(ctx.accounts.fleet.as_deref()) > 0;
+        let late = delivery.late_penalty_bps > 0
+            && delivery.is_late(ctx.accounts.region.congestion_at(now), now);
+        let required = [
+            (ConfidentialPayee::Operator, true),
+            (ConfidentialPayee::Fleet, fleet_takes_share),
+            (ConfidentialPayee::InsurancePool, config.insurance_bps > 0),
+            (ConfidentialPayee::Customer, late || failed_bps > 0),
+            (ConfidentialPayee::Treasury, true),
+        ];
+        for (payee, needed) in required {
+            require!(
+                legs.iter().any(|leg| leg.payee == payee) == needed,
+                ErrorCode::InvalidConfidentialLeg
+            );
+        }
+        require!(
+            legs.last().map(|leg| leg.payee) == Some(ConfidentialPayee::Treasury),
+            ErrorCode::InvalidConfidentialLeg
+        );
+
+        let customer_key = ctx.accounts.customer.key();
+        let operator = ctx.accounts.vehicle.operator;
+        let fleet_payout = ctx.accounts.fleet.as_ref().map(|fleet| fleet.payout_destination);
+        let config_key = ctx.accounts.config.key();
+        let (insurance_pool, _) = Pubkey::find_program_address(&[b"insurance_pool", config_key.as_ref()], &crate::ID);
+        let treasury = config.treasury;
+        let id_bytes = delivery_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow_token",
+            customer_key.as_ref(),
+            &id_bytes,
+            &[ctx.bumps.escrow_token],
+        ]];
+        let escrow_info = ctx.accounts.escrow_token.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+
+        pay_confidential_legs(
+            &token_program_info,
+            &escrow_info,
+            &ctx.accounts.mint.to_account_info(),
+            &legs,
+            ctx.remaining_accounts,
+            |payee| match payee {
+                ConfidentialPayee::Operator => Some(operator),
+                ConfidentialPayee::Fleet => fleet_payout,
+                ConfidentialPayee::InsurancePool => Some(insurance_pool),
+                ConfidentialPayee::Customer => Some(customer_key),
+                ConfidentialPayee::Treasury => Some(treasury),
+            },
+            signer_seeds,
+        )?;
+        close_confidential_escrow(
+            &token_program_info,
+            &escrow_info,
+            &ctx.accounts.zero_balance_proof.to_account_info(),
+            &ctx.accounts.customer.to_account_info(),
+            signer_seeds,
+        )?;
+
+        // The receipt names the mint and the parties; its amounts stay hidden
+        let config_mut = &mut ctx.accounts.config;
+        let invoice_number = config_mut.next_invoice_number;
+        config_mut.next_invoice_number = invoice_number
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        let delivery = &ctx.accounts.delivery;
+        let receipt = &mut ctx.accounts.receipt;
+        receipt.bump = ctx.bumps.receipt;
+        receipt.delivery = delivery.key();
+        receipt.invoice_number = invoice_number;
+        receipt.customer = customer_key;
+        receipt.vehicle = vehicle_key;
+        receipt.operator = operator;
+        receipt.gross_amount = 0;
+        receipt.platform_fee = 0;
+        receipt.operator_payment = 0;
+        receipt.fleet_share = 0;
+        receipt.tax_bps = 0;
+        receipt.tax_inclusive = false;
+        receipt.tax_amount = 0;
+        receipt.quote_usd_micros = delivery.quote_usd_micros;
+        receipt.quote_eur_micros = delivery.quote_eur_micros;
+        receipt.charity_amount = 0;
+        receipt.issued_at = now;
+        receipt.pickup_proof_hash = delivery.pickup_proof_hash;
+        receipt.dropoff_proof_hash = delivery.proof_hash;
+        receipt.picked_up_at = delivery.picked_up_at;
+        receipt.delivered_at = delivery.delivered_at;
+        receipt.nft_mint = None;
+        receipt.cnft_tree = None;
+        receipt.cnft_leaf_index = 0;
+        receipt.payment_mint = delivery.payment_mint;
+
+        let delivery_mut = &mut ctx.accounts.delivery;
+        delivery_mut.status = DeliveryStatus::Completed;
+        delivery_mut.completed_at = Some(now);
+
+        let vehicle_mut = &mut ctx.accounts.vehicle;
+        vehicle_mut.mark_idle(now)?;
+        vehicle_mut.total_deliveries = vehicle_mut.total_deliveries
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+        if let Some(fleet) = ctx.accounts.fleet.as_mut() {
+            fleet.record_job(0)?;
+        }
+
+        let profile = &mut ctx.accounts.operator_profile;
+        profile.completed_jobs = profile.completed_jobs
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let customer_profile = &mut ctx.accounts.customer_profile;
+        customer_profile.orders_completed = customer_profile.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        customer_profile.loyalty_tier = CustomerProfile::tier_for(customer_profile.orders_completed);
+
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_completed = daily_stats.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_token_completed()?;
+        emit!(OrderCompleted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            vehicle: vehicle_key,
+            operator_payment: 0,
+            platform_fee: 0,
+            completed_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Pending confidential order the customer withdraws, 12345
+    // legs: Array, The single refund leg back to the customer, emptying the escrow, [{ payee: { customer: {} }, escrowAfter }]
+    pub fn cancel_delivery_confidential<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CancelDeliveryConfidential<'info>>,
+        delivery_id: u64,
+        legs: Vec<ConfidentialLeg>,
+    ) -> Result<()> {
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.confidential, ErrorCode::NotConfidential);
+        require!(delivery.status == DeliveryStatus::Pending, ErrorCode::InvalidDeliveryStatus);
+        require_keys_eq!(ctx.accounts.mint.key(), delivery.payment_mint, ErrorCode::InvalidMint);
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
+        delivery.status = DeliveryStatus::Cancelled;
+
+        // The whole escrow goes back to the customer in a single leg
+        require!(legs.len() == 1, ErrorCode::InvalidConfidentialLeg);
+        let customer_key = ctx.accounts.customer.key();
+        let id_bytes = delivery_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow_token",
+            customer_key.as_ref(),
+            &id_bytes,
+            &[ctx.bumps.escrow_token],
+        ]];
+        let escrow_info = ctx.accounts.escrow_token.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        pay_confidential_legs(
+            &token_program_info,
+            &escrow_info,
+            &ctx.accounts.mint.to_account_info(),
+            &legs,
+            ctx.remaining_accounts,
+            |payee| (payee == ConfidentialPayee::Customer).then_some(customer_key),
+            signer_seeds,
+        )?;
+        close_confidential_escrow(
+            &token_program_info,
+            &escrow_info,
+            &ctx.accounts.zero_balance_proof.to_account_info(),
+            &ctx.accounts.customer.to_account_info(),
+            signer_seeds,
+        )?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_cancelled()?;
+
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.cancellations = profile.cancellations
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            status: ctx.accounts.delivery.status.clone(),
+            cancelled_at: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Confidential order nobody accepted in time, 12345
+    // legs: Array, The single refund leg back to the customer, emptying the escrow, [{ payee: { customer: {} }, escrowAfter }]
+    pub fn expire_delivery_confidential<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExpireDeliveryConfidential<'info>>,
+        delivery_id: u64,
+        legs: Vec<ConfidentialLeg>,
+    ) -> Result<()> {
+        let expiry_seconds = ctx.accounts.config.expiry_seconds;
+        require!(expiry_seconds > 0, ErrorCode::DeliveryNotExpired);
+
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.confidential, ErrorCode::NotConfidential);
+        require!(
+            delivery.status == DeliveryStatus::Pending || delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
+        );
+        require_keys_eq!(ctx.accounts.mint.key(), delivery.payment_mint, ErrorCode::InvalidMint);
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now >= delivery.expiry_starts_at().saturating_add(expiry_seconds),
+            ErrorCode::DeliveryNotExpired
+        );
+
+        if delivery.status == DeliveryStatus::Queued {
+            ctx.accounts.region.dequeue(delivery_key)?;
+        }
+        delivery.unlist(delivery_key, ctx.accounts.open_orders.as_deref_mut())?;
+        delivery.status = DeliveryStatus::Expired;
+
+        // The whole escrow goes back to the customer in a single leg
+        require!(legs.len() == 1, ErrorCode::InvalidConfidentialLeg);
+        let customer_key = ctx.accounts.customer.key();
+        let id_bytes = delivery_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow_token",
+            customer_key.as_ref(),
+            &id_bytes,
+            &[ctx.bumps.escrow_token],
+        ]];
+        let escrow_info = ctx.accounts.escrow_token.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        pay_confidential_legs(
+            &token_program_info,
+            &escrow_info,
+            &ctx.accounts.mint.to_account_info(),
+            &legs,
+            ctx.remaining_accounts,
+            |payee| (payee == ConfidentialPayee::Customer).then_some(customer_key),
+            signer_seeds,
+        )?;
+        close_confidential_escrow(
+            &token_program_info,
+            &escrow_info,
+            &ctx.accounts.zero_balance_proof.to_account_info(),
+            &ctx.accounts.customer.to_account_info(),
+            signer_seeds,
+        )?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_cancelled()?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            status: ctx.accounts.delivery.status.clone(),
+            cancelled_at: now,
+        });
+        Ok(())
+    }
+
+    // amount: Number, Lamports to deposit, 1000000000 = 1 SOL
+    pub fn deposit_wallet(ctx: Context<DepositWallet>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.owner.to_account_info(),
+                    to: ctx.accounts.wallet.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let wallet = &mut ctx.accounts.wallet;
+        wallet.bump = ctx.bumps.wallet;
+        wallet.owner = ctx.accounts.owner.key();
+        wallet.balance = wallet.balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // amount: Number, Lamports to withdraw, 1000000000 = 1 SOL
+    pub fn withdraw_wallet(ctx: Context<WithdrawWallet>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let wallet = &mut ctx.accounts.wallet;
+        wallet.balance = wallet.balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientBalance)?;
+
+        move_lamports(
+            &ctx.accounts.wallet.to_account_info(),
+            &ctx.accounts.owner.to_account_info(),
+            amount,
+        )?;
+        Ok(())
+    }
+
+    // standing_order_id: Number, Customer-chosen identifier, 1
+    // pickup_location: String, Pickup coordinates, "40.7128,-74.0060"
+    // delivery_location: String, Delivery coordinates, "40.7589,-73.9851"
+    // region_id: Number, Service area of the pickup, 1
+    // max_price: Number, Price ceiling per delivery in lamports, 1000000000 = 1 SOL
+    // interval_seconds: Number, Minimum time between matches, 604800 = weekly
+    // runs: Number, Number of deliveries to materialize, 4
+    pub fn create_standing_order(
+        ctx: Context<CreateStandingOrder>,
+        standing_order_id: u64,
+        pickup_location: String,
+        delivery_location: String,
+        region_id: u16,
+        max_price: u64,
+        interval_seconds: i64,
+        runs: u32,
+    ) -> Result<()> {
+        require!(pickup_location.len() <= 64, ErrorCode::InvalidParameter);
+        require!(delivery_location.len() <= 64, ErrorCode::InvalidParameter);
+        require!(max_price > 0, ErrorCode::InvalidAmount);
+        require!(interval_seconds >= 0 && runs > 0, ErrorCode::InvalidParameter);
+        // Keeps matched ids (standing_order_id << 32) inside namespace 0,
+        // below the range subscriptions spawn into, and at or above 1 << 32
+        require!(standing_order_id > 0 && standing_order_id < 1 << 23, ErrorCode::InvalidParameter);
+
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
+
+        let standing_order = &mut ctx.accounts.standing_order;
+        standing_order.bump = ctx.bumps.standing_order;
+        standing_order.standing_order_id = standing_order_id;
+        standing_order.customer = ctx.accounts.customer.key();
+        standing_order.pickup_location = pickup_location;
+        standing_order.delivery_location = delivery_location;
+        standing_order.region_id = region_id;
+        standing_order.max_price = max_price;
+        standing_order.interval_seconds = interval_seconds;
+        standing_order.remaining_runs = runs;
+        standing_order.matched_count = 0;
+        standing_order.next_eligible_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // standing_order_id: Number, Standing order to close, 1
+    pub fn cancel_standing_order(
+        _ctx: Context<CancelStandingOrder>,
+        _standing_order_id: u64,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    // standing_order_id: Number, Standing order being matched, 1
+    // delivery_id: Number, Derived id of the materialized delivery, 4294967296
+    // price: Number, Payment in lamports offered by the vehicle, 800000000 = 0.8 SOL
+    pub fn auto_match(
+        ctx: Context<AutoMatch>,
+        standing_order_id: u64,
+        delivery_id: u64,
+        price: u64,
+    ) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
+
+        let vehicle = &ctx.accounts.vehicle;
+        require!(vehicle.is_active && vehicle.has_capacity(), ErrorCode::VehicleNotAvailable);
+
+        let now = Clock::get()?.unix_timestamp;
+        require!(!vehicle.is_away(now), ErrorCode::VehicleAway);
+        vehicle.check_fleet(ctx.accounts.fleet.as_deref())?;
+        let standing_order = &ctx.accounts.standing_order;
+        require!(standing_order.remaining_runs > 0, ErrorCode::StandingOrderExhausted);
+        require!(now >= standing_order.next_eligible_at, ErrorCode::StandingOrderNotDue);
+        require!(
+            delivery_id == standing_order.next_delivery_id(),
+            ErrorCode::InvalidParameter
+        );
+
+        // The surge premium counts against the customer's price cap
+        let surge_bps = config.surge_bps;
+        let price = config.apply_surge(price)?;
+        require!(price > 0 && price <= standing_order.max_price, ErrorCode::InvalidAmount);
+
+        let region = &ctx.accounts.region;
+        config.check_vehicle_class(vehicle, region, price)?;
+        config.check_stake(vehicle)?;
+        let pricing = OrderPricing::for_region(price, region, config, &ctx.accounts.customer_profile, now)?;
+        let escrow_amount = pricing.escrow_amount;
+        ctx.accounts.config.record_escrow(escrow_amount, now)?;
+
+        let quote_usd = ctx.accounts.usd_feed.quote(price, now)?;
+        let quote_eur = match &ctx.accounts.eur_feed {
+            Some(feed) => feed.quote(price, now)?,
+            None => 0,
+        };
+
+        // Fund the escrow from the customer's prepaid wallet
+        let wallet = &mut ctx.accounts.wallet;
+        wallet.balance = wallet.balance
+            .checked_sub(escrow_amount)
+            .ok_or(ErrorCode::InsufficientBalance)?;
+        move_lamports(
+            &ctx.accounts.wallet.to_account_info(),
+            &ctx.accounts.escrow.to_account_info(),
+            escrow_amount,
+        )?;
+
+        let delivery_key = ctx.accounts.delivery.key();
+        let customer_key = ctx.accounts.customer.key();
+        let escrow = &mut ctx.accounts.escrow;
+        escrow.open(ctx.bumps.escrow, delivery_key, customer_key, escrow_amount);
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        let standing_order = &mut ctx.accounts.standing_order;
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.bump = ctx.bumps.delivery;
+        delivery.book(delivery_id, standing_order.customer, price, region, &pricing, now);
+        delivery.surge_bps = surge_bps;
+        delivery.quote_usd_micros = quote_usd;
+        delivery.quote_eur_micros = quote_eur;
+        delivery.set_locations(standing_order.pickup_location.clone(), standing_order.delivery_location.clone());
+        delivery.status = DeliveryStatus::InProgress;
+        delivery.assigned_vehicle = Some(vehicle_key);
+        delivery.accepted_at = Some(now);
+
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.open(ctx.bumps.customer_profile, standing_order.customer, now);
+        profile.orders_created = profile.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_created = daily_stats.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_created()?;
+
+        standing_order.remaining_runs -= 1;
+        standing_order.matched_count = standing_order.matched_count
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        standing_order.next_eligible_at = now
+            .checked_add(standing_order.interval_seconds)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let vehicle_mut = &mut ctx.accounts.vehicle;
+        vehicle_mut.mark_busy(now)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+        emit!(OrderCreated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            customer: ctx.accounts.delivery.customer,
+            payment_amount: ctx.accounts.delivery.payment_amount,
+            region_id: ctx.accounts.delivery.region_id,
+        });
+        emit!(OrderAccepted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            vehicle: vehicle_key,
+            accepted_at: now,
+        });
+        Ok(())
+    }
+
+    // subscription_id: Number, Customer-chosen identifier, 1
+    // pickup_location: String, Pickup coordinates, "40.7128,-74.0060"
+    // delivery_location: String, Delivery coordinates, "40.7589,-73.9851"
+    // region_id: Number, Service area of the pickup, 1
+    // payment_amount: Number, Payment in lamports per delivery, 1000000000 = 1 SOL
+    // period_seconds: Number, Time between deliveries, 604800 = weekly
+    // runs: Number, Number of deliveries paid for up front, 4
+    // first_at: Number, Unix time the first delivery may be spawned, 1767225600
+    // deposit: Number, Lamports pre-funded for all runs including tax, surcharges and account rent, 4200000000 = 4.2 SOL
+    pub fn create_subscription(
+        ctx: Context<CreateSubscription>,
+        subscription_id: u64,
+        pickup_location: String,
+        delivery_location: String,
+        region_id: u16,
+        payment_amount: u64,
+        period_seconds: i64,
+        runs: u32,
+        first_at: i64,
+        deposit: u64,
+    ) -> Result<()> {
+        require!(pickup_location.len() <= 64, ErrorCode::InvalidParameter);
+        require!(delivery_location.len() <= 64, ErrorCode::InvalidParameter);
+        require!(payment_amount > 0, ErrorCode::InvalidAmount);
+        require!(period_seconds > 0 && runs > 0, ErrorCode::InvalidParameter);
+        // Spawned ids (1 << 55 | subscription_id << 32) stay in namespace 0
+        // above every standing-order match
+        require!(subscription_id < 1 << 23, ErrorCode::InvalidParameter);
+        let minimum = payment_amount
+            .checked_mul(runs as u64)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(deposit >= minimum, ErrorCode::InvalidAmount);
+
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.customer.to_account_info(),
+                    to: ctx.accounts.subscription.to_account_info(),
+                },
+            ),
+            deposit,
+        )?;
+
+        let subscription = &mut ctx.accounts.subscription;
+        subscription.bump = ctx.bumps.subscription;
+        subscription.subscription_id = subscription_id;
+        subscription.customer = ctx.accounts.customer.key();
+        subscription.pickup_location = pickup_location;
+        subscription.delivery_location = delivery_location;
+        subscription.region_id = region_id;
+        subscription.payment_amount = payment_amount;
+        subscription.period_seconds = period_seconds;
+        subscription.remaining_runs = runs;
+        subscription.spawned_count = 0;
+        subscription.next_spawn_at = first_at.max(Clock::get()?.unix_timestamp);
+        subscription.balance = deposit;
+        Ok(())
+    }
+
+    // subscription_id: Number, Subscription to top up, 1
+    // amount: Number, Lamports to add, 1000000000 = 1 SOL
+    pub fn fund_subscription(
+        ctx: Context<FundSubscription>,
+        subscription_id: u64,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.customer.to_account_info(),
+                    to: ctx.accounts.subscription.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let subscription = &mut ctx.accounts.subscription;
+        subscription.balance = subscription.balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // subscription_id: Number, Subscription to close; its unspent balance goes back to the customer, 1
+    pub fn cancel_subscription(
+        _ctx: Context<CancelSubscription>,
+        _subscription_id: u64,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    // subscription_id: Number, Subscription whose next delivery is due, 1
+    // delivery_id: Number, Derived id of the spawned delivery, 36028801313931264
+    pub fn spawn_next_order(
+        ctx: Context<SpawnNextOrder>,
+        subscription_id: u64,
+        delivery_id: u64,
+    ) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(!config.settlement_only, ErrorCode::SettlementOnly);
+
+        let now = Clock::get()?.unix_timestamp;
+        let subscription = &ctx.accounts.subscription;
+        require!(subscription.remaining_runs > 0, ErrorCode::SubscriptionExhausted);
+        require!(now >= subscription.next_spawn_at, ErrorCode::SubscriptionNotDue);
+        require!(
+            delivery_id == subscription.next_delivery_id(),
+            ErrorCode::InvalidParameter
+        );
+
+        let surge_bps = config.surge_bps;
+        let payment_amount = config.apply_surge(subscription.payment_amount)?;
+        let region = &ctx.accounts.region;
+        let pricing = OrderPricing::for_region(payment_amount, region, config, &ctx.accounts.customer_profile, now)?;
+        let escrow_amount = pricing.escrow_amount;
+        ctx.accounts.config.record_escrow(escrow_amount, now)?;
+
+        let quote_usd = ctx.accounts.usd_feed.quote(payment_amount, now)?;
+        let quote_eur = match &ctx.accounts.eur_feed {
+            Some(feed) => feed.quote(payment_amount, now)?,
+            None => 0,
+        };
+
+        // The subscription funds the escrow and pays the crank back the rent
+        // it put up for the new delivery and escrow accounts
+        let rent = Rent::get()?;
+        let rent_outlay = rent
+            .minimum_balance(8 + Delivery::LEN)
+            .checked_add(rent.minimum_balance(8 + Escrow::LEN))
+            .ok_or(ErrorCode::MathOverflow)?;
+        let subscription = &mut ctx.accounts.subscription;
+        subscription.balance = subscription.balance
+            .checked_sub(escrow_amount)
+            .ok_or(ErrorCode::InsufficientBalance)?
+            .checked_sub(rent_outlay)
+            .ok_or(ErrorCode::InsufficientBalance)?;
+        let subscription_info = ctx.accounts.subscription.to_account_info();
+        move_lamports(&subscription_info, &ctx.accounts.escrow.to_account_info(), escrow_amount)?;
+        move_lamports(&subscription_info, &ctx.accounts.cranker.to_account_info(), rent_outlay)?;
+
+        let delivery_key = ctx.accounts.delivery.key();
+        let customer_key = ctx.accounts.customer.key();
+        let escrow = &mut ctx.accounts.escrow;
+        escrow.open(ctx.bumps.escrow, delivery_key, customer_key, escrow_amount);
+
+        let subscription = &mut ctx.accounts.subscription;
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.bump = ctx.bumps.delivery;
+        delivery.book(delivery_id, customer_key, payment_amount, region, &pricing, now);
+        delivery.surge_bps = surge_bps;
+        delivery.quote_usd_micros = quote_usd;
+        delivery.quote_eur_micros = quote_eur;
+        delivery.set_locations(subscription.pickup_location.clone(), subscription.delivery_location.clone());
+
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.open(ctx.bumps.customer_profile, customer_key, now);
+        profile.orders_created = profile.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_created = daily_stats.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_created()?;
+
+        // Periods count from the schedule, not from when the crank ran
+        subscription.remaining_runs -= 1;
+        subscription.spawned_count = subscription.spawned_count
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        subscription.next_spawn_at = subscription.next_spawn_at
+            .checked_add(subscription.period_seconds)
+            .ok_or(ErrorCode::MathOverflow)?;
+        emit!(OrderCreated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            customer: ctx.accounts.delivery.customer,
+            payment_amount: ctx.accounts.delivery.payment_amount,
+            region_id: ctx.accounts.delivery.region_id,
+        });
+        Ok(())
+    }
+
+    // epoch: Number, Epoch that just ended, 612
+    pub fn finalize_statement(ctx: Context<FinalizeStatement>, epoch: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let profile = &mut ctx.accounts.operator_profile;
+        require!(epoch < clock.epoch, ErrorCode::EpochNotFinished);
+        // Only the epoch that just ended can be closed out, so a statement is
+        // never filed under an older epoch than the activity it sums
+        require!(epoch == clock.epoch - 1, ErrorCode::EpochNotPrevious);
+        require!(epoch > profile.last_statement_epoch, ErrorCode::PeriodAlreadyFinalized);
+
+        let totals = profile.totals();
+        let previous = &profile.last_statement_totals;
+
+        let statement = &mut ctx.accounts.statement;
+        statement.bump = ctx.bumps.statement;
+        statement.operator = profile.operator;
+        statement.epoch = epoch;
+        statement.period_start = profile.last_statement_at;
+        statement.period_end = clock.unix_timestamp;
+        statement.completed_jobs = totals.completed_jobs
+            .checked_sub(previous.completed_jobs)
+            .ok_or(ErrorCode::MathOverflow)?;
+        statement.gross_earnings = totals.gross_earnings
+            .checked_sub(previous.gross_earnings)
+            .ok_or(ErrorCode::MathOverflow)?;
+        statement.fees_paid = totals.fees_paid
+            .checked_sub(previous.fees_paid)
+            .ok_or(ErrorCode::MathOverflow)?;
+        statement.tips = totals.tips
+            .checked_sub(previous.tips)
+            .ok_or(ErrorCode::MathOverflow)?;
+        statement.penalties = totals.penalties
+            .checked_sub(previous.penalties)
+            .ok_or(ErrorCode::MathOverflow)?;
+        statement.taxes_withheld = totals.taxes_withheld
+            .checked_sub(previous.taxes_withheld)
+            .ok_or(ErrorCode::MathOverflow)?;
+        statement.dispute_losses = totals.dispute_losses
+            .checked_sub(previous.dispute_losses)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        profile.last_statement_epoch = epoch;
+        profile.last_statement_at = clock.unix_timestamp;
+        profile.last_statement_totals = totals;
+        Ok(())
+    }
+
+    // Pays out everything accrued from settlements since the last claim
+    pub fn claim_earnings(ctx: Context<ClaimEarnings>) -> Result<()> {
+        // A dispatcher can trigger the claim, but the lamports only ever go
+        // to the operator
+        if ctx.accounts.signer.key() != ctx.accounts.operator.key() {
+            let dispatcher_key = ctx.accounts.dispatcher_key.as_ref().ok_or(ErrorCode::Unauthorized)?;
+            dispatcher_key.check(PERM_CLAIM, Clock::get()?.unix_timestamp)?;
+        }
+        let amount = ctx.accounts.earnings.accrued;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let earnings_info = ctx.accounts.earnings.to_account_info();
+        move_lamports(&earnings_info, &ctx.accounts.operator.to_account_info(), amount)?;
+
+        let earnings = &mut ctx.accounts.earnings;
+        earnings.accrued = 0;
+        earnings.total_claimed = earnings.total_claimed
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        earnings.last_claimed_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // referrer: Address, Wallet that brought the signer to the platform, 7ReF3...2222
+    pub fn register_referral(ctx: Context<RegisterReferral>, referrer: Pubkey) -> Result<()> {
+        let referee = ctx.accounts.referee.key();
+        require!(referrer != Pubkey::default(), ErrorCode::InvalidParameter);
+        require!(referrer != referee, ErrorCode::SelfReferral);
+
+        // The referrer has to be on the platform already, as a customer or
+        // an operator
+        let referrer_profile = &ctx.accounts.referrer_profile;
+        let (customer_profile_key, _) =
+            Pubkey::find_program_address(&[b"customer", referrer.as_ref()], &crate::ID);
+        let (operator_profile_key, _) =
+            Pubkey::find_program_address(&[b"operator", referrer.as_ref()], &crate::ID);
+        require!(
+            (referrer_profile.key() == customer_profile_key || referrer_profile.key() == operator_profile_key)
+                && referrer_profile.owner == &crate::ID
+                && !referrer_profile.data_is_empty(),
+            ErrorCode::ReferrerNotFound
+        );
+
+        // A referral only covers a referee with no business yet, and it is
+        // flagged on their profiles so settlements cannot leave it out
+        let now = Clock::get()?.unix_timestamp;
+        let customer_profile = &mut ctx.accounts.customer_profile;
+        customer_profile.open(ctx.bumps.customer_profile, referee, now);
+        require!(customer_profile.orders_created == 0, ErrorCode::ReferralTooLate);
+        customer_profile.referred = true;
+        if let Some(operator_profile) = ctx.accounts.operator_profile.as_mut() {
+            require!(operator_profile.completed_jobs == 0, ErrorCode::ReferralTooLate);
+            operator_profile.referred = true;
+        }
+
+        // Two wallets may not refer each other
+        let upstream_info = ctx.accounts.referrer_referral.to_account_info();
+        if !upstream_info.data_is_empty() {
+            let upstream = Referral::try_deserialize(&mut &upstream_info.try_borrow_data()?[..])?;
+            require!(upstream.referrer != referee, ErrorCode::SelfReferral);
+        }
+
+        let referral = &mut ctx.accounts.referral;
+        referral.bump = ctx.bumps.referral;
+        referral.referee = referee;
+        referral.referrer = referrer;
+        referral.created_at = now;
+        referral.accrued = 0;
+        referral.total_earned = 0;
+        referral.total_claimed = 0;
+
+        emit!(ReferralRegistered {
+            schema_version: EVENT_SCHEMA_VERSION,
+            referee,
+            referrer,
+        });
+        Ok(())
+    }
+
+    // Pays the referrer the fee share accrued on one referee's orders
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        let amount = ctx.accounts.referral.accrued;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let referral_info = ctx.accounts.referral.to_account_info();
+        move_lamports(&referral_info, &ctx.accounts.referrer.to_account_info(), amount)?;
+
+        let referral = &mut ctx.accounts.referral;
+        referral.accrued = 0;
+        referral.total_claimed = referral.total_claimed
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // nonce: Number, Strictly increasing per-vehicle counter, 42
+    // distance_m: Number, Distance driven for a job in meters, 4200
+    pub fn record_distance(ctx: Context<RecordDistance>, nonce: u64, distance_m: u64) -> Result<()> {
+        require!(distance_m > 0, ErrorCode::InvalidParameter);
+
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.consume_nonce(nonce)?;
+        vehicle.distance_claimed_m = vehicle.distance_claimed_m
+            .checked_add(distance_m)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // epoch: Number, Epoch that just ended, whose fees are frozen, 612
+    pub fn roll_revenue_epoch(ctx: Context<RollRevenueEpoch>, epoch: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let config = &mut ctx.accounts.config;
+        require!(epoch < clock.epoch, ErrorCode::EpochNotFinished);
+        // Fees are frozen under the epoch that just ended, never a stale one
+        require!(epoch == clock.epoch - 1, ErrorCode::EpochNotPrevious);
+        require!(epoch > config.last_revenue_epoch, ErrorCode::PeriodAlreadyFinalized);
+
+        let revenue_epoch = &mut ctx.accounts.revenue_epoch;
+        revenue_epoch.bump = ctx.bumps.revenue_epoch;
+        revenue_epoch.config = config.key();
+        revenue_epoch.epoch = epoch;
+        revenue_epoch.fees_earned = config.total_fees_collected
+            .checked_sub(config.fees_at_last_rollover)
+            .ok_or(ErrorCode::MathOverflow)?;
+        revenue_epoch.cumulative_fees = config.total_fees_collected;
+        revenue_epoch.closed_at = clock.unix_timestamp;
+
+        config.last_revenue_epoch = epoch;
+        config.fees_at_last_rollover = config.total_fees_collected;
+        Ok(())
+    }
+
+    // amount: Number, Collected tax to remit in lamports, 1000000000 = 1 SOL
+    pub fn withdraw_tax(ctx: Context<WithdrawTax>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let tax_vault = &ctx.accounts.tax_vault;
+        let available = tax_vault.total_collected
+            .checked_sub(tax_vault.total_withdrawn)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(amount <= available, ErrorCode::InsufficientBalance);
+        ctx.accounts.config.record_withdrawal(amount, Clock::get()?.epoch)?;
+
+        let tax_vault = &mut ctx.accounts.tax_vault;
+        tax_vault.total_withdrawn = tax_vault.total_withdrawn
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        move_lamports(
+            &ctx.accounts.tax_vault.to_account_info(),
+            &ctx.accounts.destination.to_account_info(),
+            amount,
+        )?;
+        Ok(())
+    }
+
+    // amount: Number, Collected platform fees to withdraw in lamports, 1000000000 = 1 SOL
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(amount <= ctx.accounts.treasury.available()?, ErrorCode::InsufficientBalance);
+        ctx.accounts.config.record_withdrawal(amount, Clock::get()?.epoch)?;
+
+        let treasury = &mut ctx.accounts.treasury;
+        treasury.total_withdrawn = treasury.total_withdrawn
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        move_lamports(
+            &ctx.accounts.treasury.to_account_info(),
+            &ctx.accounts.destination.to_account_info(),
+            amount,
+        )?;
+
+        emit!(TreasuryWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            treasury: ctx.accounts.treasury.key(),
+            destination: ctx.accounts.destination.key(),
+            authority: ctx.accounts.authority.key(),
+            amount,
+        });
+        Ok(())
+    }
+
+    // slot: Number, Fee recipient slot to pay out, 0
+    pub fn distribute_fee_share(ctx: Context<DistributeFeeShare>, slot: u8) -> Result<()> {
+        let slot = slot as usize;
+        require!(slot < MAX_FEE_RECIPIENTS, ErrorCode::InvalidParameter);
+        let entry = ctx.accounts.config.fee_recipients[slot];
+        require!(
+            entry.recipient != Pubkey::default() && ctx.accounts.recipient.key() == entry.recipient,
+            ErrorCode::FeeRecipientsRequired
+        );
+        let amount = ctx.accounts.treasury.recipient_owed[slot];
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        // A wallet that cannot hold the payout rent-exempt keeps its share
+        // on the ledger rather than failing settlements
+        let recipient = &ctx.accounts.recipient;
+        let balance = recipient.lamports().checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            balance >= Rent::get()?.minimum_balance(recipient.data_len()),
+            ErrorCode::RecipientBelowRent
+        );
+
+        ctx.accounts.treasury.recipient_owed[slot] = 0;
+        move_lamports(&ctx.accounts.treasury.to_account_info(), &recipient.to_account_info(), amount)?;
+
+        // The insurance pool keeps its own books, so a share paid to it has
+        // to count as a contribution to be claimable
+        let (pool_key, _) = Pubkey::find_program_address(
+            &[b"insurance_pool", ctx.accounts.config.key().as_ref()],
+            &crate::ID,
+        );
+        if entry.recipient == pool_key {
+            let mut pool = InsurancePool::try_deserialize(&mut &recipient.try_borrow_data()?[..])?;
+            pool.contribute(amount)?;
+            pool.try_serialize(&mut &mut recipient.try_borrow_mut_data()?[..])?;
+        }
+
+        emit!(FeeShareDistributed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            treasury: ctx.accounts.treasury.key(),
+            recipient: entry.recipient,
+            amount,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Order whose payout rule is set, 12345
+    // mode: Enum, Who must confirm before escrow is released, { pin: {} }
+    // pin_hash: Bytes, sha256(delivery address || salt || PIN) with a secret 32-byte salt when mode is Pin, [0; 32] otherwise
+    pub fn set_confirmation_mode(
+        ctx: Context<SetConfirmationMode>,
+        delivery_id: u64,
+        mode: ConfirmationMode,
+        pin_hash: [u8; 32],
+    ) -> Result<()> {
+        let delivery = &mut ctx.accounts.delivery;
+        require!(
+            delivery.status == DeliveryStatus::Pending || delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
+        );
+        require!(
+            mode != ConfirmationMode::Pin || pin_hash != [0u8; 32],
+            ErrorCode::InvalidParameter
+        );
+
+        delivery.confirmation_mode = mode;
+        delivery.pin_hash = pin_hash;
+        delivery.pin_attempts = 0;
+        delivery.delivery_mode = DeliveryMode::for_confirmation(mode);
+        Ok(())
+    }
+
+    // delivery_id: Number, Order whose hand-over style is set, 12345
+    // mode: Enum, Contactless drop-off or signature at the door, { contactless: {} }
+    // pin_hash: Bytes, sha256(delivery address || salt || PIN) with a secret 32-byte salt for a PIN signature, [0; 32] for the customer's own signature
+    pub fn set_delivery_mode(
+        ctx: Context<SetDeliveryMode>,
+        delivery_id: u64,
+        mode: DeliveryMode,
+        pin_hash: [u8; 32],
+    ) -> Result<()> {
+        let delivery = &mut ctx.accounts.delivery;
+        require!(
+            delivery.status == DeliveryStatus::Pending || delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
+        );
+
+        // Contactless drop-offs are proven by a geofence/photo attestation;
+        // signature-required hand-overs need the recipient's PIN or signature
+        delivery.confirmation_mode = match mode {
+            DeliveryMode::Contactless => ConfirmationMode::Oracle,
+            DeliveryMode::SignatureRequired if pin_hash != [0u8; 32] => ConfirmationMode::Pin,
+            DeliveryMode::SignatureRequired => ConfirmationMode::Customer,
+        };
+        delivery.pin_hash = pin_hash;
+        delivery.pin_attempts = 0;
+        delivery.delivery_mode = mode;
+        Ok(())
+    }
+
+    // delivery_id: Number, Order the SLA applies to, 12345
+    // sla_seconds: Number, Time allowed from acceptance to completion, 1800 = 30 minutes
+    // late_penalty_bps: Number, Share of the operator payment refunded if late, 1000 = 10%
+    pub fn set_delivery_sla(
+        ctx: Context<SetDeliverySla>,
+        delivery_id: u64,
+        sla_seconds: u32,
+        late_penalty_bps: u16,
+    ) -> Result<()> {
+        require!(late_penalty_bps <= 10000, ErrorCode::InvalidParameter);
+
+        let delivery = &mut ctx.accounts.delivery;
+        require!(
+            delivery.status == DeliveryStatus::Pending || delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
+        );
+
+        delivery.sla_seconds = sla_seconds;
+        delivery.late_penalty_bps = late_penalty_bps;
+        Ok(())
+    }
+
+    // delivery_id: Number, Order whose route is paid per segment, 12345
+    // milestone_count: Number, Route segments including the final drop-off, 4
+    pub fn set_milestones(
+        ctx: Context<SetMilestones>,
+        delivery_id: u64,
+        milestone_count: u8,
+    ) -> Result<()> {
+        require!(
+            milestone_count >= 2 && milestone_count <= MAX_MILESTONES,
+            ErrorCode::InvalidParameter
+        );
+
+        let delivery = &mut ctx.accounts.delivery;
+        require!(
+            delivery.status == DeliveryStatus::Pending || delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
+        );
+        require!(delivery.payment_mint == Pubkey::default(), ErrorCode::InvalidMint);
+        // A route pays per stop instead
+        require!(delivery.route.is_none(), ErrorCode::InvalidParameter);
+
+        delivery.milestone_count = milestone_count;
+        Ok(())
+    }
+
+    // delivery_id: Number, Underway order the vehicle reports progress on, 12345
+    // position: Object, Waypoint reached in millionths of a degree, { latE6: 40730600, lngE6: -73986600 }
+    pub fn release_milestone(
+        ctx: Context<ReleaseMilestone>,
+        delivery_id: u64,
+        position: GeoPoint,
+    ) -> Result<()> {
+        position.validate()?;
+
+        let config = &ctx.accounts.config;
+        require!(config.is_active, ErrorCode::ConfigInactive);
+
+        let delivery = &ctx.accounts.delivery;
+        require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.milestones_released.saturating_add(1) < delivery.milestone_count,
+            ErrorCode::NoMilestoneLeft
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.vehicle.check_delegate(
+            ctx.accounts.signer.key(),
+            ctx.accounts.dispatcher_key.as_deref(),
+            PERM_COMPLETE,
+            now,
+        )?;
+
+        let fee_bps = config.fee_bps_for(ctx.accounts.vehicle.vehicle_class);
+        let slice = delivery.milestone_slice(fee_bps, config.fee_rounding)?;
+        ctx.accounts.config.record_payout(slice, now)?;
+
+        let escrow_info = ctx.accounts.escrow.to_account_info();
+        ctx.accounts.escrow.withdraw(slice)?;
+        move_lamports(&escrow_info, &ctx.accounts.earnings.to_account_info(), slice)?;
+        let earnings = &mut ctx.accounts.earnings;
+        earnings.open(ctx.bumps.earnings, ctx.accounts.vehicle.operator);
+        earnings.accrue(slice)?;
+
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.milestones_released += 1;
+        delivery.milestone_released = delivery.milestone_released
+            .checked_add(slice)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.move_to(position, now);
+        vehicle.revenue = vehicle.revenue
+            .checked_add(slice)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // delivery_id: Number, Order to split into stops, 12345
+    // stops: Array, Pickup first, then each drop-off with its share of the operator payment in basis points, [{ point: { latE6: 40712800, lngE6: -74006000 }, paymentBps: 0 }, { point: { latE6: 40730600, lngE6: -73986600 }, paymentBps: 10000 }]
+    pub fn set_route(
+        ctx: Context<SetRoute>,
+        delivery_id: u64,
+        stops: Vec<RouteStopDeclaration>,
+    ) -> Result<()> {
+        require!(stops.len() >= 2 && stops.len() <= MAX_ROUTE_STOPS, ErrorCode::InvalidParameter);
+        let mut total_bps = 0u32;
+        for stop in &stops {
+            stop.point.validate()?;
+            total_bps += stop.payment_bps as u32;
+        }
+        require!(total_bps == 10000, ErrorCode::InvalidParameter);
+
+        let route_key = ctx.accounts.route.key();
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        require!(
+            delivery.status == DeliveryStatus::Pending || delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
+        );
+        require!(delivery.payment_mint == Pubkey::default(), ErrorCode::InvalidMint);
+        require!(delivery.milestone_count == 0, ErrorCode::InvalidParameter);
+        delivery.route = Some(route_key);
+
+        let route = &mut ctx.accounts.route;
+        route.bump = ctx.bumps.route;
+        route.delivery = delivery_key;
+        route.stop_count = stops.len() as u8;
+        route.stops_completed = 0;
+        for (slot, declared) in route.stops.iter_mut().zip(stops) {
+            *slot = RouteStop {
+                point: declared.point,
+                payment_bps: declared.payment_bps,
+                completed_at: None,
+            };
+        }
+        Ok(())
+    }
+
+    // delivery_id: Number, Underway order whose next stop the vehicle reached, 12345
+    pub fn complete_stop(ctx: Context<CompleteStop>, delivery_id: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active, ErrorCode::ConfigInactive);
+
+        let delivery = &ctx.accounts.delivery;
+        require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.vehicle.check_delegate(
+            ctx.accounts.signer.key(),
+            ctx.accounts.dispatcher_key.as_deref(),
+            PERM_COMPLETE,
+            now,
+        )?;
+
+        let route = &ctx.accounts.route;
+        let index = route.stops_completed as usize;
+        require!(index < route.stop_count as usize, ErrorCode::NoStopLeft);
+        let stop = route.stops[index];
+
+        // Slices are shares of the operator payment; rounding up never lets
+        // them add up to more than it. The last stop's share is held back and
+        // settles with the order once the drop-off is confirmed, so the
+        // vehicle cannot collect the whole route on its own word
+        let fee_bps = config.fee_bps_for(ctx.accounts.vehicle.vehicle_class);
+        let operator_share = delivery.operator_share(fee_bps, config.fee_rounding)?;
+        let unreleased = operator_share
+            .checked_sub(delivery.milestone_released)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let slice = if index + 1 == route.stop_count as usize {
+            0
+        } else {
+            apply_bps(operator_share, stop.payment_bps as u64, config.fee_rounding)?.min(unreleased)
+        };
+        ctx.accounts.config.record_payout(slice, now)?;
+
+        let escrow_info = ctx.accounts.escrow.to_account_info();
+        ctx.accounts.escrow.withdraw(slice)?;
+        move_lamports(&escrow_info, &ctx.accounts.earnings.to_account_info(), slice)?;
+        let earnings = &mut ctx.accounts.earnings;
+        earnings.open(ctx.bumps.earnings, ctx.accounts.vehicle.operator);
+        earnings.accrue(slice)?;
+
+        let route = &mut ctx.accounts.route;
+        route.stops[index].completed_at = Some(now);
+        route.stops_completed += 1;
+
+        // Released stop slices come off the final payment like milestones do
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.milestone_released = delivery.milestone_released
+            .checked_add(slice)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.move_to(stop.point, now);
+        vehicle.revenue = vehicle.revenue
+            .checked_add(slice)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // delivery_id: Number, Order the vehicle has collected from the sender, 12345
+    // proof_hash: Bytes, Hash of the pickup scan or photo, [0; 32] if none
+    pub fn confirm_pickup(ctx: Context<ConfirmPickup>, delivery_id: u64, proof_hash: [u8; 32]) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.vehicle.check_delegate(
+            ctx.accounts.signer.key(),
+            ctx.accounts.dispatcher_key.as_deref(),
+            PERM_COMPLETE,
+            now,
+        )?;
+
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::InProgress, ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.assigned_vehicle == Some(ctx.accounts.vehicle.key()),
+            ErrorCode::Unauthorized
+        );
+
+        delivery.status = DeliveryStatus::PickedUp;
+        delivery.picked_up_at = Some(now);
+        delivery.pickup_proof_hash = proof_hash;
+        emit!(PickupConfirmed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            vehicle: ctx.accounts.vehicle.key(),
+            picked_up_at: ctx.accounts.delivery.picked_up_at.unwrap_or_default(),
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Order the vehicle has dropped off, 12345
+    pub fn mark_delivered(ctx: Context<MarkDelivered>, delivery_id: u64) -> Result<()> {
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::PickedUp, ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.assigned_vehicle == Some(ctx.accounts.vehicle.key()),
+            ErrorCode::Unauthorized
+        );
+        require!(delivery.delivered_at.is_none(), ErrorCode::InvalidDeliveryStatus);
+
+        delivery.delivered_at = Some(Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    // delivery_id: Number, Order the customer confirms as received, 12345
+    pub fn confirm_delivery(ctx: Context<ConfirmDelivery>, delivery_id: u64) -> Result<()> {
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.confirmation_mode == ConfirmationMode::Customer,
+            ErrorCode::InvalidConfirmationMode
+        );
+        require!(delivery.delivered_at.is_some(), ErrorCode::NotYetDelivered);
+
+        delivery.confirmed_at = Some(Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    // delivery_id: Number, Order handed over at the door, 12345
+    // pin: Bytes, PIN revealed by the recipient, "4821"
+    // salt: Bytes, Secret salt handed to the recipient with the PIN, [32 random bytes]
+    pub fn confirm_with_pin(
+        ctx: Context<ConfirmWithPin>,
+        delivery_id: u64,
+        pin: Vec<u8>,
+        salt: [u8; 32],
+    ) -> Result<()> {
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.confirmation_mode == ConfirmationMode::Pin,
+            ErrorCode::InvalidConfirmationMode
+        );
+        require!(
+            delivery.assigned_vehicle == Some(ctx.accounts.vehicle.key()),
+            ErrorCode::Unauthorized
+        );
+
+        require!(delivery.pin_attempts < MAX_PIN_ATTEMPTS, ErrorCode::PinAttemptsExceeded);
+
+        // The salt never goes on chain until the hand-over, so the stored hash
+        // cannot be brute-forced over the short PIN space. A wrong guess is
+        // recorded rather than reverted so it counts against the limit; once
+        // the limit is hit only the customer can confirm the order.
+        let revealed = anchor_lang::solana_program::hash::hashv(&[delivery_key.as_ref(), &salt, &pin]);
+        if revealed.to_bytes() != delivery.pin_hash {
+            delivery.pin_attempts += 1;
+            if delivery.pin_attempts >= MAX_PIN_ATTEMPTS {
+                delivery.confirmation_mode = ConfirmationMode::Customer;
+            }
+            emit!(PinRejected {
+                schema_version: EVENT_SCHEMA_VERSION,
+                delivery: delivery_key,
+                attempts: delivery.pin_attempts,
+            });
+            return Ok(());
+        }
+
+        delivery.confirmed_at = Some(Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    // delivery_id: Number, Order the packages belong to, 12345
+    // packages: Array, Weight, declared value and optional recipient per package, [{ weightGrams: 1200, declaredValue: 25000000, recipient: null }]
+    pub fn declare_packages(
+        ctx: Context<DeclarePackages>,
+        delivery_id: u64,
+        packages: Vec<PackageDeclaration>,
+    ) -> Result<()> {
+        require!(!packages.is_empty() && packages.len() <= MAX_PACKAGES, ErrorCode::InvalidParameter);
+
+        let delivery_key = ctx.accounts.delivery.key();
+        let delivery = &mut ctx.accounts.delivery;
+        require!(
+            delivery.status == DeliveryStatus::Pending || delivery.status == DeliveryStatus::Queued,
+            ErrorCode::InvalidDeliveryStatus
+        );
+        delivery.package_count = packages.len() as u8;
+
+        let manifest = &mut ctx.accounts.manifest;
+        manifest.bump = ctx.bumps.manifest;
+        manifest.delivery = delivery_key;
+        manifest.packages = packages
+            .into_iter()
+            .map(|declared| Package {
+                weight_grams: declared.weight_grams,
+                declared_value: declared.declared_value,
+                recipient: declared.recipient,
+                status: PackageStatus::Pending,
+            })
+            .collect();
+        Ok(())
+    }
+
+    // delivery_id: Number, Order the package belongs to, 12345
+    // index: Number, Position of the package in the manifest, 0
+    // delivered: Boolean, Handed over (true) or failed (false), true
+    pub fn settle_package(
+        ctx: Context<SettlePackage>,
+        delivery_id: u64,
+        index: u8,
+        delivered: bool,
+    ) -> Result<()> {
+        let delivery = &ctx.accounts.delivery;
+        require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.assigned_vehicle == Some(ctx.accounts.vehicle.key()),
+            ErrorCode::Unauthorized
+        );
+
+        let signer = ctx.accounts.signer.key();
+        let package = ctx.accounts.manifest.packages
+            .get_mut(index as usize)
+            .ok_or(ErrorCode::InvalidParameter)?;
+        require!(package.status == PackageStatus::Pending, ErrorCode::PackageAlreadySettled);
+
+        // The operator settles either way; a package's own recipient may
+        // only confirm receipt
+        let is_operator = signer == ctx.accounts.vehicle.operator;
+        let is_recipient = delivered && package.recipient == Some(signer);
+        require!(is_operator || is_recipient, ErrorCode::Unauthorized);
+
+        package.status = if delivered { PackageStatus::Delivered } else { PackageStatus::Failed };
+        Ok(())
+    }
+
+    // oracle: Address, Delivery-proof attestation signer, 3NB7Q...9999
+    pub fn register_proof_oracle(ctx: Context<RegisterProofOracle>, oracle: Pubkey) -> Result<()> {
+        let proof_oracle = &mut ctx.accounts.proof_oracle;
+        proof_oracle.bump = ctx.bumps.proof_oracle;
+        proof_oracle.oracle = oracle;
+        proof_oracle.registered_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // oracle: Address, Attestation signer being removed, 3NB7Q...9999
+    pub fn remove_proof_oracle(_ctx: Context<RemoveProofOracle>, _oracle: Pubkey) -> Result<()> {
+        Ok(())
+    }
+
+    // delivery_id: Number, Order the oracle attests as delivered, 12345
+    // proof_hash: Bytes, Hash of the geofence fix and drop-off photo, [0; 32] if none
+    pub fn attest_delivery(
+        ctx: Context<AttestDelivery>,
+        delivery_id: u64,
+        proof_hash: [u8; 32],
+    ) -> Result<()> {
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.confirmation_mode == ConfirmationMode::Oracle,
+            ErrorCode::InvalidConfirmationMode
+        );
+        require!(
+            delivery.delivery_mode != DeliveryMode::Contactless || proof_hash != [0u8; 32],
+            ErrorCode::InvalidParameter
+        );
+
+        delivery.proof_hash = proof_hash;
+        delivery.confirmed_at = Some(Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    // delivery_id: Number, Order whose vehicle has reached the pickup point, 12345
+    // proof_hash: Bytes, Hash of the geofence fix at the pickup point, [32 bytes]
+    pub fn attest_arrival(
+        ctx: Context<AttestArrival>,
+        delivery_id: u64,
+        proof_hash: [u8; 32],
+    ) -> Result<()> {
+        let delivery = &mut ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::InProgress, ErrorCode::InvalidDeliveryStatus);
+        require!(delivery.arrived_at.is_none(), ErrorCode::InvalidParameter);
+        require!(delivery.picked_up_at.is_none(), ErrorCode::InvalidDeliveryStatus);
+        // A no-show claim later rests on this, so the oracle must show its fix
+        require!(proof_hash != [0u8; 32], ErrorCode::InvalidParameter);
+
+        delivery.arrival_proof_hash = proof_hash;
+        delivery.arrived_at = Some(Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    // delivery_id: Number, Order whose sender never handed over the package, 12345
+    pub fn claim_no_show(ctx: Context<ClaimNoShow>, delivery_id: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active, ErrorCode::ConfigInactive);
+
+        let delivery = &ctx.accounts.delivery;
+        require!(delivery.status == DeliveryStatus::InProgress, ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.assigned_vehicle == Some(ctx.accounts.vehicle.key()),
+            ErrorCode::Unauthorized
+        );
+        require!(
+            delivery.confirmed_at.is_none()
+                && delivery.delivered_at.is_none()
+                && delivery.picked_up_at.is_none(),
+            ErrorCode::InvalidDeliveryStatus
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.vehicle.check_delegate(
+            ctx.accounts.signer.key(),
+            ctx.accounts.dispatcher_key.as_deref(),
+            PERM_CANCEL,
+            now,
+        )?;
+        let deposit = delivery.no_show_deposit;
+        let arrived_at = delivery.arrived_at.ok_or(ErrorCode::NoShowNotClaimable)?;
+        require!(
+            deposit > 0 && now >= arrived_at.saturating_add(NO_SHOW_WAIT_SECONDS),
+            ErrorCode::NoShowNotClaimable
+        );
+        ctx.accounts.config.record_payout(deposit, now)?;
+
+        // The deposit compensates the wasted trip; closing the escrow sends
+        // everything else, rent included, back to the sender
+        let escrow_info = ctx.accounts.escrow.to_account_info();
+        ctx.accounts.escrow.withdraw(deposit)?;
+        move_lamports(&escrow_info, &ctx.accounts.vehicle_operator.to_account_info(), deposit)?;
+
+        ctx.accounts.delivery.status = DeliveryStatus::Cancelled;
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_cancelled()?;
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.mark_idle(now)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            status: ctx.accounts.delivery.status.clone(),
+            cancelled_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Order under dispute, 12345
+    // reason_hash: Bytes, Hash of the off-chain complaint, [32 bytes]
+    pub fn open_dispute(
+        ctx: Context<OpenDispute>,
+        delivery_id: u64,
+        reason_hash: [u8; 32],
+    ) -> Result<()> {
+        let delivery = &ctx.accounts.delivery;
+        require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+        require!(
+            delivery.assigned_vehicle == Some(ctx.accounts.vehicle.key()),
+            ErrorCode::InvalidParameter
+        );
+
+        let party = ctx.accounts.party.key();
+        let is_customer = party == delivery.customer;
+        require!(
+            is_customer || party == ctx.accounts.vehicle.operator,
+            ErrorCode::NotDisputeParty
+        );
+        // Once a signed hand-over is on record the customer cannot claim
+        // non-receipt; contactless drop-offs stay open to it
+        if is_customer && delivery.confirmed_at.is_some() {
+            require!(
+                delivery.delivery_mode.allows_non_receipt_claims(),
+                ErrorCode::DisputeNotAllowed
+            );
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+        let dispute = &mut ctx.accounts.dispute;
+        dispute.bump = ctx.bumps.dispute;
+        dispute.delivery = delivery.key();
+        dispute.opened_by = party;
+        dispute.reason_hash = reason_hash;
+        dispute.status = DisputeStatus::Open;
+        dispute.evidence = Vec::new();
+        dispute.opened_at = now;
+        dispute.resolved_at = None;
+        dispute.customer_share_bps = 0;
+
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.disputes = profile.disputes
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Freezes the escrow: settlement, confirmation and claims all
+        // require an underway order
+        ctx.accounts.delivery.status = DeliveryStatus::Disputed;
+
+        emit!(DisputeOpened {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: dispute.delivery,
+            opened_by: party,
+            reason_hash,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Order under dispute, 12345
+    // evidence_hash: Bytes, Hash of a photo, log or statement stored off-chain, [32 bytes]
+    pub fn submit_evidence(
+        ctx: Context<SubmitEvidence>,
+        delivery_id: u64,
+        evidence_hash: [u8; 32],
+    ) -> Result<()> {
+        let party = ctx.accounts.party.key();
+        require!(
+            party == ctx.accounts.delivery.customer || party == ctx.accounts.vehicle.operator,
+            ErrorCode::NotDisputeParty
+        );
+
+        let dispute = &mut ctx.accounts.dispute;
+        require!(dispute.status == DisputeStatus::Open, ErrorCode::DisputeNotOpen);
+        require!(dispute.evidence.len() < MAX_DISPUTE_EVIDENCE, ErrorCode::EvidenceFull);
+
+        dispute.evidence.push(Evidence {
+            submitted_by: party,
+            hash: evidence_hash,
+            submitted_at: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Order under dispute, 12345
+    // customer_share_bps: Number, Share of the order returned to the customer, ignored when the dispute timed out, 5000 = half
+    pub fn resolve_dispute(
+        ctx: Context<ResolveDispute>,
+        delivery_id: u64,
+        customer_share_bps: u16,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let config = &ctx.accounts.config;
+        let is_arbiter = config.has_role(ctx.accounts.resolver.key(), ctx.accounts.role.as_deref(), RoleKind::Arbiter);
+        let customer_share_bps = ctx.accounts.dispute.ruling(is_arbiter, customer_share_bps, &ctx.accounts.delivery, now)?;
+
+        // The operator's awarded part settles through the same split as a
+        // completion; the escrow closes the customer's part back to them.
+        // The award adds any late penalty back, so congestion is left out
+        let fleet_share_bps = ctx.accounts.vehicle.fleet_share_bps(ctx.accounts.fleet.as_deref());
+        let full = Settlement::for_delivery(
+            &ctx.accounts.delivery,
+            config,
+            ctx.accounts.vehicle.vehicle_class,
+            0,
+            fleet_share_bps,
+            now,
+        )?;
+        let settlement = full.awarded(&ctx.accounts.delivery, config, fleet_share_bps, customer_share_bps)?;
+        let Settlement { fee, tax, vehicle_payment, operator_payment, released, .. } = settlement;
+        let forfeited = full.vehicle_payment
+            .checked_add(full.penalty)
+            .ok_or(ErrorCode::MathOverflow)?
+            .saturating_sub(vehicle_payment);
+        ctx.accounts.config.record_payout(released, now)?;
+
+        let operator_referred = ctx.accounts.operator_profile.as_ref().is_some_and(|profile| profile.referred);
+        let treasury_fee = settlement.pay_out(
+            &mut ctx.accounts.escrow,
+            &ctx.accounts.delivery,
+            &ctx.accounts.config,
+            ctx.accounts.fleet.as_deref(),
+            PayoutAccounts {
+                customer: ctx.accounts.customer.to_account_info(),
+                earnings: ctx.accounts.earnings.to_account_info(),
+                fleet_payout: ctx.accounts.fleet_payout.as_ref().map(|payout| payout.to_account_info()),
+                treasury: ctx.accounts.treasury.to_account_info(),
+                tax_vault: ctx.accounts.tax_vault.to_account_info(),
+                charity: ctx.accounts.charity.as_ref().map(|charity| charity.to_account_info()),
+                insurance_pool: ctx.accounts.insurance_pool.as_mut(),
+                referrals: [
+                    (ctx.accounts.customer_profile.referred, ctx.accounts.customer_referral.as_mut()),
+                    (operator_referred, ctx.accounts.operator_referral.as_mut()),
+                ],
+            },
+        )?;
+        ctx.accounts.treasury.credit_fee(&ctx.accounts.config.fee_recipients, treasury_fee)?;
+        let earnings = &mut ctx.accounts.earnings;
+        earnings.open(ctx.bumps.earnings, ctx.accounts.vehicle.operator);
+        earnings.accrue(operator_payment)?;
+
+        let tax_vault = &mut ctx.accounts.tax_vault;
+        tax_vault.total_collected = tax_vault.total_collected
+            .checked_add(tax)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let config_mut = &mut ctx.accounts.config;
+        config_mut.total_fees_collected = config_mut.total_fees_collected
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Operators without a profile still get their share; only the
+        // statistics are skipped
+        if let Some(profile) = ctx.accounts.operator_profile.as_mut() {
+            profile.gross_earnings = profile.gross_earnings
+                .checked_add(operator_payment)
+                .ok_or(ErrorCode::MathOverflow)?;
+            profile.fees_paid = profile.fees_paid
+                .checked_add(fee)
+                .ok_or(ErrorCode::MathOverflow)?;
+            profile.taxes_withheld = profile.taxes_withheld
+                .checked_add(tax)
+                .ok_or(ErrorCode::MathOverflow)?;
+            profile.dispute_losses = profile.dispute_losses
+                .checked_add(forfeited)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        if ctx.accounts.vehicle.fleet.is_some() {
+            let fleet = ctx.accounts.fleet.as_mut().ok_or(ErrorCode::FleetAccountRequired)?;
+            fleet.record_job(vehicle_payment)?;
+        }
+
+        // Closing the escrow pays the customer's share back with its rent
+        let escrow_info = ctx.accounts.escrow.to_account_info();
+        let rent_floor = Rent::get()?.minimum_balance(escrow_info.data_len());
+        let refund = escrow_info.lamports().saturating_sub(rent_floor);
+
+        let dispute = &mut ctx.accounts.dispute;
+        dispute.status = DisputeStatus::Resolved;
+        dispute.resolved_at = Some(now);
+        dispute.customer_share_bps = customer_share_bps;
+
+        // The customer loses the dispute when the arbiter awards them less than half
+        if customer_share_bps < 5000 {
+            let profile = &mut ctx.accounts.customer_profile;
+            profile.disputes_lost = profile.disputes_lost
+                .checked_add(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.status = DeliveryStatus::Completed;
+        delivery.completed_at = Some(now);
+        delivery.dispute_refund_bps = customer_share_bps;
+        // Volume counts the order value as at settlement, with the fee the
+        // awarded part paid
+        let payment_amount = delivery.payment_amount;
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_completed = daily_stats.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_completed(payment_amount, fee)?;
+
+        // Losing the dispute outright costs the operator part of its stake
+        if customer_share_bps > 5000 && ctx.accounts.vehicle.staked > 0 {
+            let stake = ctx.accounts.stake.as_mut().ok_or(ErrorCode::StakeAccountRequired)?;
+            let slashed = slash_stake(
+                stake,
+                &ctx.accounts.config,
+                &ctx.accounts.customer.to_account_info(),
+                &mut ctx.accounts.treasury,
+            )?;
+            ctx.accounts.vehicle.sync_stake(stake);
+            if let Some(profile) = ctx.accounts.operator_profile.as_mut() {
+                profile.dispute_losses = profile.dispute_losses
+                    .checked_add(slashed)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+        }
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.mark_idle(now)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+
+        emit!(DisputeResolved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            arbiter: ctx.accounts.resolver.key(),
+            customer_share: refund,
+            operator_share: operator_payment,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Token-paid order under dispute, 12345
+    // customer_share_bps: Number, Share of the order returned to the customer, ignored when the dispute timed out, 5000 = half
+    pub fn resolve_dispute_spl(
+        ctx: Context<ResolveDisputeSpl>,
+        delivery_id: u64,
+        customer_share_bps: u16,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let config = &ctx.accounts.config;
+        let is_arbiter = config.has_role(ctx.accounts.resolver.key(), ctx.accounts.role.as_deref(), RoleKind::Arbiter);
+        let customer_share_bps = ctx.accounts.dispute.ruling(is_arbiter, customer_share_bps, &ctx.accounts.delivery, now)?;
+        require!(!ctx.accounts.delivery.confidential, ErrorCode::ConfidentialOrder);
+
+        // Same split as a SOL dispute, in the token's base units
+        let fleet_share_bps = ctx.accounts.vehicle.fleet_share_bps(ctx.accounts.fleet.as_deref());
+        let full = Settlement::for_delivery(
+            &ctx.accounts.delivery,
+            config,
+            ctx.accounts.vehicle.vehicle_class,
+            0,
+            fleet_share_bps,
+            now,
+        )?;
+        let settlement = full.awarded(&ctx.accounts.delivery, config, fleet_share_bps, customer_share_bps)?;
+        let escrowed = ctx.accounts.escrow_token.amount;
+
+        let customer_key = ctx.accounts.customer.key();
+        let config_key = ctx.accounts.config.key();
+        let id_bytes = delivery_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow_token",
+            customer_key.as_ref(),
+            &id_bytes,
+            &[ctx.bumps.escrow_token],
+        ]];
+        settlement.pay_out_token(
+            &ctx.accounts.token_program,
+            &mut ctx.accounts.escrow_token,
+            &ctx.accounts.mint,
+            ctx.accounts.fleet.as_deref(),
+            TokenPayoutAccounts {
+                operator: &ctx.accounts.operator_token,
+                treasury: &ctx.accounts.treasury_token,
+                customer: &ctx.accounts.customer_token,
+                fleet: ctx.accounts.fleet_token.as_ref(),
+                insurance: ctx.accounts.insurance_token.as_ref(),
+                insurance_pool: Pubkey::find_program_address(&[b"insurance_pool", config_key.as_ref()], &crate::ID).0,
+                rent_to: ctx.accounts.customer.to_account_info(),
+            },
+            signer_seeds,
+        )?;
+
+        let dispute = &mut ctx.accounts.dispute;
+        dispute.status = DisputeStatus::Resolved;
+        dispute.resolved_at = Some(now);
+        dispute.customer_share_bps = customer_share_bps;
+
+        if customer_share_bps < 5000 {
+            let profile = &mut ctx.accounts.customer_profile;
+            profile.disputes_lost = profile.disputes_lost
+                .checked_add(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.status = DeliveryStatus::Completed;
+        delivery.completed_at = Some(now);
+        delivery.dispute_refund_bps = customer_share_bps;
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_completed = daily_stats.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_token_completed()?;
+
+        // Profile amounts are kept in lamports, so only a slashed stake is
+        // booked as a loss
+        if customer_share_bps > 5000 && ctx.accounts.vehicle.staked > 0 {
+            let stake = ctx.accounts.stake.as_mut().ok_or(ErrorCode::StakeAccountRequired)?;
+            let slashed = slash_stake(
+                stake,
+                &ctx.accounts.config,
+                &ctx.accounts.customer.to_account_info(),
+                &mut ctx.accounts.treasury,
+            )?;
+            ctx.accounts.vehicle.sync_stake(stake);
+            if let Some(profile) = ctx.accounts.operator_profile.as_mut() {
+                profile.dispute_losses = profile.dispute_losses
+                    .checked_add(slashed)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+        }
+        if ctx.accounts.vehicle.fleet.is_some() {
+            let fleet = ctx.accounts.fleet.as_mut().ok_or(ErrorCode::FleetAccountRequired)?;
+            fleet.record_job(settlement.vehicle_payment)?;
+        }
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.mark_idle(now)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+
+        emit!(DisputeResolved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            arbiter: ctx.accounts.resolver.key(),
+            customer_share: escrowed.saturating_sub(settlement.released),
+            operator_share: settlement.operator_payment,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Confidential order under dispute, 12345
+    // customer_share_bps: Number, Share of the escrow returned to the customer, ignored when the dispute timed out, 5000 = half
+    // legs: Array, Operator and customer payouts with the escrow's decryptable balance after each, ending empty, [{ payee: { operator: {} }, escrowAfter }]
+    pub fn resolve_dispute_confidential<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ResolveDisputeConfidential<'info>>,
+        delivery_id: u64,
+        customer_share_bps: u16,
+        legs: Vec<ConfidentialLeg>,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let config = &ctx.accounts.config;
+        let is_arbiter = config.has_role(ctx.accounts.resolver.key(), ctx.accounts.role.as_deref(), RoleKind::Arbiter);
+        let customer_share_bps = ctx.accounts.dispute.ruling(is_arbiter, customer_share_bps, &ctx.accounts.delivery, now)?;
+        require!(ctx.accounts.delivery.confidential, ErrorCode::NotConfidential);
+        require_keys_eq!(ctx.accounts.mint.key(), ctx.accounts.delivery.payment_mint, ErrorCode::InvalidMint);
+
+        require!(
+            ctx.accounts.vehicle.fleet.is_none() || ctx.accounts.fleet.is_some(),
+            ErrorCode::FleetAccountRequired
+        );
+
+        // The prover splits the hidden escrow at the awarded share; a party
+        // with a share must get a leg and a party without one must not. The
+        // operator's part settles like a completion, so it carries the fleet
+        // share, the insurance slice and the treasury's fee, paid last
+        let operator_awarded = customer_share_bps < 10000;
+        let fleet_takes_share = ctx.accounts.vehicle.fleet_share_bps(ctx.accounts.fleet.as_deref()) > 0;
+        let required = [
+            (ConfidentialPayee::Operator, operator_awarded),
+            (ConfidentialPayee::Fleet, operator_awarded && fleet_takes_share),
+            (ConfidentialPayee::InsurancePool, operator_awarded && config.insurance_bps > 0),
+            (ConfidentialPayee::Customer, customer_share_bps > 0),
+            (ConfidentialPayee::Treasury, operator_awarded),
+        ];
+        for (payee, needed) in required {
+            require!(
+                legs.iter().any(|leg| leg.payee == payee) == needed,
+                ErrorCode::InvalidConfidentialLeg
+            );
+        }
+        if operator_awarded {
+            require!(
+                legs.last().map(|leg| leg.payee) == Some(ConfidentialPayee::Treasury),
+                ErrorCode::InvalidConfidentialLeg
+            );
+        }
+
+        let customer_key = ctx.accounts.customer.key();
+        let operator = ctx.accounts.vehicle.operator;
+        let fleet_payout = ctx.accounts.fleet.as_ref().map(|fleet| fleet.payout_destination);
+        let config_key = ctx.accounts.config.key();
+        let (insurance_pool, _) = Pubkey::find_program_address(&[b"insurance_pool", config_key.as_ref()], &crate::ID);
+        let treasury = config.treasury;
+        let id_bytes = delivery_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow_token",
+            customer_key.as_ref(),
+            &id_bytes,
+            &[ctx.bumps.escrow_token],
+        ]];
+        let escrow_info = ctx.accounts.escrow_token.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        pay_confidential_legs(
+            &token_program_info,
+            &escrow_info,
+            &ctx.accounts.mint.to_account_info(),
+            &legs,
+            ctx.remaining_accounts,
+            |payee| match payee {
+                ConfidentialPayee::Operator => Some(operator),
+                ConfidentialPayee::Fleet => fleet_payout,
+                ConfidentialPayee::InsurancePool => Some(insurance_pool),
+                ConfidentialPayee::Customer => Some(customer_key),
+                ConfidentialPayee::Treasury => Some(treasury),
+            },
+            signer_seeds,
+        )?;
+        close_confidential_escrow(
+            &token_program_info,
+            &escrow_info,
+            &ctx.accounts.zero_balance_proof.to_account_info(),
+            &ctx.accounts.customer.to_account_info(),
+            signer_seeds,
+        )?;
+
+        let dispute = &mut ctx.accounts.dispute;
+        dispute.status = DisputeStatus::Resolved;
+        dispute.resolved_at = Some(now);
+        dispute.customer_share_bps = customer_share_bps;
+
+        if customer_share_bps < 5000 {
+            let profile = &mut ctx.accounts.customer_profile;
+            profile.disputes_lost = profile.disputes_lost
+                .checked_add(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        let delivery = &mut ctx.accounts.delivery;
+        delivery.status = DeliveryStatus::Completed;
+        delivery.completed_at = Some(now);
+        delivery.dispute_refund_bps = customer_share_bps;
+        let daily_stats = &mut ctx.accounts.daily_stats;
+        daily_stats.open(ctx.bumps.daily_stats, now);
+        daily_stats.orders_completed = daily_stats.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_token_completed()?;
+
+        // The stake is in lamports, so losing outright is slashed as usual
+        let mut slashed = 0;
+        if customer_share_bps > 5000 && ctx.accounts.vehicle.staked > 0 {
+            let stake = ctx.accounts.stake.as_mut().ok_or(ErrorCode::StakeAccountRequired)?;
+            slashed = slash_stake(
+                stake,
+                &ctx.accounts.config,
+                &ctx.accounts.customer.to_account_info(),
+                &mut ctx.accounts.treasury,
+            )?;
+            ctx.accounts.vehicle.sync_stake(stake);
+        }
+        if let Some(profile) = ctx.accounts.operator_profile.as_mut() {
+            profile.dispute_losses = profile.dispute_losses
+                .checked_add(slashed)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        if operator_awarded {
+            if let Some(fleet) = ctx.accounts.fleet.as_mut() {
+                fleet.record_job(0)?;
+            }
+        }
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.mark_idle(now)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+
+        // The amounts are encrypted, so the event carries the awarded split
+        emit!(ConfidentialDisputeResolved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            arbiter: ctx.accounts.resolver.key(),
+            customer_share_bps,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Settled order whose package was lost or damaged, 12345
+    // amount: Number, Compensation asked for in lamports, at most the order value less any dispute refund, 5000000
+    // reason_hash: Bytes, sha256 of the off-chain claim and photos, [0; 32]
+    pub fn file_claim(
+        ctx: Context<FileClaim>,
+        delivery_id: u64,
+        amount: u64,
+        reason_hash: [u8; 32],
+    ) -> Result<()> {
+        // Claims wait for any dispute to be ruled on, so the pool never pays
+        // for what the ruling already refunded
+        let delivery = &ctx.accounts.delivery;
+        require!(
+            delivery.assigned_vehicle.is_some() && delivery.status == DeliveryStatus::Completed,
+            ErrorCode::InvalidDeliveryStatus
+        );
+        require!(amount > 0 && amount <= delivery.claimable()?, ErrorCode::InvalidAmount);
+
+        let claim = &mut ctx.accounts.claim;
+        claim.bump = ctx.bumps.claim;
+        claim.delivery = ctx.accounts.delivery.key();
+        claim.customer = ctx.accounts.customer.key();
+        claim.amount = amount;
+        claim.reason_hash = reason_hash;
+        claim.status = ClaimStatus::Filed;
+        claim.paid_amount = 0;
+        claim.filed_at = Clock::get()?.unix_timestamp;
+        claim.resolved_at = None;
+        Ok(())
+    }
+
+    // payout: Number, Lamports paid from the insurance pool, at most the claimed amount, 0 = reject
+    pub fn pay_claim(ctx: Context<PayClaim>, payout: u64) -> Result<()> {
+        let claim = &ctx.accounts.claim;
+        require!(claim.status == ClaimStatus::Filed, ErrorCode::ClaimNotOpen);
+        require!(payout <= claim.amount, ErrorCode::InvalidAmount);
+
+        let pool = &mut ctx.accounts.insurance_pool;
+        require!(payout <= pool.available()?, ErrorCode::InsufficientBalance);
+        pool.total_paid = pool.total_paid
+            .checked_add(payout)
+            .ok_or(ErrorCode::MathOverflow)?;
+        move_lamports(
+            &ctx.accounts.insurance_pool.to_account_info(),
+            &ctx.accounts.customer.to_account_info(),
+            payout,
+        )?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let claim = &mut ctx.accounts.claim;
+        claim.status = if payout > 0 { ClaimStatus::Paid } else { ClaimStatus::Rejected };
+        claim.paid_amount = payout;
+        claim.resolved_at = Some(now);
+        emit!(ClaimResolved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: claim.delivery,
+            customer: claim.customer,
+            payout,
+            resolved_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Closed order the resolved claim was filed on, 12345
+    pub fn close_claim(ctx: Context<CloseClaim>, delivery_id: u64) -> Result<()> {
+        // The claim's address is what stops a second claim on the order, so
+        // it only closes once closeDelivery has shrunk the order to a marker
+        // that can no longer take claims
+        let info = ctx.accounts.delivery.to_account_info();
+        ClosedDelivery::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        Ok(())
+    }
+
+    // nonce: Number, Strictly increasing per-vehicle counter, 43
+    // position: Object, Current position in millionths of a degree, { latE6: 40730600, lngE6: -73986600 }
+    pub fn report_checkpoint(
+        ctx: Context<ReportCheckpoint>,
+        nonce: u64,
+        position: GeoPoint,
+    ) -> Result<()> {
+        position.validate()?;
+
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.consume_nonce(nonce)?;
+        vehicle.last_checkpoint_at = Clock::get()?.unix_timestamp;
+        vehicle.move_to(position, vehicle.last_checkpoint_at);
+        Ok(())
+    }
+
+    // position: Object, Current position in millionths of a degree, { latE6: 40730600, lngE6: -73986600 }
+    pub fn update_vehicle_location(ctx: Context<UpdateVehicleLocation>, position: GeoPoint) -> Result<()> {
+        position.validate()?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let vehicle_key = ctx.accounts.vehicle.key();
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.check_delegate(
+            ctx.accounts.signer.key(),
+            ctx.accounts.dispatcher_key.as_deref(),
+            PERM_UPDATE_LOCATION,
+            now,
+        )?;
+        vehicle.move_to(position, now);
+
+        // A checked-in vehicle that crosses into another cell moves buckets
+        // with it, or drops out of the index when that cell has no bucket yet
+        let cell = position.geohash();
+        if vehicle.geohash != [0u8; GEOHASH_LEN] && vehicle.geohash != cell {
+            let old_bucket = ctx.accounts.old_bucket.as_mut().ok_or(ErrorCode::GeoBucketRequired)?;
+            old_bucket.remove(vehicle_key);
+
+            let new_bucket = ctx.accounts.new_bucket.as_deref_mut();
+            vehicle.geohash = if new_bucket.is_some() { cell } else { [0u8; GEOHASH_LEN] };
+            vehicle.sync_geo_bucket(vehicle_key, new_bucket)?;
+        }
+        Ok(())
+    }
+
+    // Creates the treasury for a config initialized before fees settled into
+    // a program-owned account; anyone may pay the rent
+    pub fn initialize_treasury(ctx: Context<InitializeTreasury>) -> Result<()> {
+        let treasury = &mut ctx.accounts.treasury;
+        treasury.bump = ctx.bumps.treasury;
+        treasury.config = ctx.accounts.config.key();
+        treasury.total_collected = 0;
+        treasury.total_withdrawn = 0;
+        treasury.recipient_owed = [0; MAX_FEE_RECIPIENTS];
+        Ok(())
+    }
+
+    // vehicle_id: String, Vehicle registered before fixed-point positions, "AV-001"
+    pub fn migrate_vehicle_location(
+        ctx: Context<MigrateVehicleLocation>,
+        _vehicle_id: String,
+    ) -> Result<()> {
+        let info = ctx.accounts.vehicle.to_account_info();
+        grow_account(
+            &info,
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            8 + Vehicle::LEN,
+        )?;
+
+        let mut vehicle = Vehicle::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        vehicle.position = GeoPoint::parse(&vehicle.location)?;
+        vehicle.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        Ok(())
+    }
+
+    // operator: Address, Operator whose profile predates dispute_losses, 7GH8J...2222
+    pub fn migrate_operator_profile(ctx: Context<MigrateOperatorProfile>, _operator: Pubkey) -> Result<()> {
+        grow_account(
+            &ctx.accounts.operator_profile.to_account_info(),
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            8 + OperatorProfile::LEN,
+        )
+    }
+
+    // customer: Address, Customer who booked the order, 8ABC...3333
+    // delivery_id: Number, Order booked before fixed-point points, 12345
+    pub fn migrate_delivery_locations(
+        ctx: Context<MigrateDeliveryLocations>,
+        _customer: Pubkey,
+        _delivery_id: u64,
+    ) -> Result<()> {
+        let info = ctx.accounts.delivery.to_account_info();
+        grow_account(
+            &info,
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            8 + Delivery::LEN,
+        )?;
+
+        let mut delivery = Delivery::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        delivery.derive_points();
+        delivery.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        Ok(())
+    }
+
+    // capacity_grams: Number, Cargo the vehicle can carry right now, 2500
+    // battery_pct: Number, Remaining charge from 0 to 100, 87
+    pub fn update_vehicle_telemetry(
+        ctx: Context<VehicleSession>,
+        capacity_grams: u32,
+        battery_pct: u8,
+    ) -> Result<()> {
+        require!(battery_pct <= 100, ErrorCode::InvalidParameter);
+
+        let now = Clock::get()?.unix_timestamp;
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.check_delegate(
+            ctx.accounts.signer.key(),
+            ctx.accounts.dispatcher_key.as_deref(),
+            PERM_UPDATE_LOCATION,
+            now,
+        )?;
+        vehicle.capacity_grams = capacity_grams;
+        vehicle.battery_pct = battery_pct;
+        vehicle.telemetry_updated_at = now;
+        Ok(())
+    }
+
+    // session_key: Pubkey, Hot key kept on the vehicle, "7xKXtg2CW87d97TXJSDpbD5jBkheTqA83TZRuJosgAsU"
+    // expires_at: Number, Unix time the key stops being accepted, 1700086400
+    // permissions: Number, Bitmask of PERM_* handlers the key may call, 10 = complete and update location
+    pub fn register_session_key(
+        ctx: Context<RegisterSessionKey>,
+        session_key: Pubkey,
+        expires_at: i64,
+        permissions: u8,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            expires_at > now && expires_at - now <= MAX_SESSION_SECONDS,
+            ErrorCode::InvalidParameter
+        );
+        require!(session_key != Pubkey::default(), ErrorCode::InvalidParameter);
+        require!(
+            permissions != 0 && permissions & !PERM_ALL == 0,
+            ErrorCode::InvalidParameter
+        );
+
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.session_key = session_key;
+        vehicle.session_expires_at = expires_at;
+        vehicle.session_permissions = permissions;
+        Ok(())
+    }
+
+    pub fn revoke_session_key(ctx: Context<RevokeSessionKey>) -> Result<()> {
+        let vehicle = &mut ctx.accounts.vehicle;
+        vehicle.session_key = Pubkey::default();
+        vehicle.session_expires_at = 0;
+        vehicle.session_permissions = 0;
+        Ok(())
+    }
+
+    // is_active: Boolean, Whether the vehicle takes new work, false
+    pub fn set_vehicle_active(ctx: Context<SetVehicleActive>, is_active: bool) -> Result<()> {
+        let signer = ctx.accounts.signer.key();
+        let vehicle_key = ctx.accounts.vehicle.key();
+        let vehicle = &mut ctx.accounts.vehicle;
+
+        // The config authority can take a vehicle offline and only it can
+        // bring a vehicle it suspended back; operators toggle freely otherwise
+        if signer == ctx.accounts.config.authority {
+            vehicle.suspended = !is_active;
+        } else {
+            require!(signer == vehicle.operator, ErrorCode::Unauthorized);
+            require!(!(is_active && vehicle.suspended), ErrorCode::VehicleSuspended);
+        }
+        vehicle.is_active = is_active;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+        Ok(())
+    }
+
+    // new_operator: Address, Wallet taking over the vehicle, 7xKXt...9Wq2
+    pub fn transfer_vehicle_operator(ctx: Context<TransferVehicleOperator>, new_operator: Pubkey) -> Result<()> {
+        require!(new_operator != Pubkey::default(), ErrorCode::InvalidParameter);
+
+        // Payouts for an order in flight go to whoever operates the vehicle at
+        // settlement, so ownership only changes hands between jobs
+        let vehicle = &mut ctx.accounts.vehicle;
+        require!(vehicle.ownership_mint.is_none(), ErrorCode::VehicleNftBound);
+        require!(!vehicle.is_busy, ErrorCode::VehicleNotAvailable);
+        let previous_operator = vehicle.operator;
+        vehicle.operator = new_operator;
+        // A session key handed out by the old operator must not outlive them
+        vehicle.session_key = Pubkey::default();
+        vehicle.session_expires_at = 0;
+        vehicle.session_permissions = 0;
+
+        emit!(VehicleOperatorTransferred {
+            schema_version: EVENT_SCHEMA_VERSION,
+            vehicle: ctx.accounts.vehicle.key(),
+            previous_operator,
+            new_operator,
+        });
+        Ok(())
+    }
+
+    // Hands an NFT-bound vehicle to the wallet now holding its ownership NFT
+    pub fn sync_operator_from_nft(ctx: Context<SyncOperatorFromNft>) -> Result<()> {
+        let new_operator = ctx.accounts.ownership_token.owner;
+        let vehicle = &mut ctx.accounts.vehicle;
+        require!(new_operator != vehicle.operator, ErrorCode::InvalidParameter);
+        // Same rule as a direct transfer: the payout for an order in flight
+        // stays with the wallet that accepted it
+        require!(!vehicle.is_busy, ErrorCode::VehicleNotAvailable);
+        let previous_operator = vehicle.operator;
+        vehicle.operator = new_operator;
+        vehicle.session_key = Pubkey::default();
+        vehicle.session_expires_at = 0;
+        vehicle.session_permissions = 0;
+
+        emit!(VehicleOperatorTransferred {
+            schema_version: EVENT_SCHEMA_VERSION,
+            vehicle: ctx.accounts.vehicle.key(),
+            previous_operator,
+            new_operator,
+        });
+        Ok(())
+    }
+
+    // fleet_id: Number, Manager-chosen fleet number, 1
+    // payout_destination: Address, Wallet that receives the fleet's share of earnings, 9WzDX...AWWM
+    pub fn create_fleet(
+        ctx: Context<CreateFleet>,
+        fleet_id: u32,
+        payout_destination: Pubkey,
+    ) -> Result<()> {
+        require!(payout_destination != Pubkey::default(), ErrorCode::InvalidParameter);
+
+        let fleet = &mut ctx.accounts.fleet;
+        fleet.bump = ctx.bumps.fleet;
+        fleet.manager = ctx.accounts.manager.key();
+        fleet.fleet_id = fleet_id;
+        fleet.payout_destination = payout_destination;
+        fleet.is_paused = false;
+        fleet.vehicle_count = 0;
+        fleet.completed_jobs = 0;
+        fleet.gross_earnings = 0;
+        fleet.created_at = Clock::get()?.unix_timestamp;
+        fleet.fleet_share_bps = 0;
+        Ok(())
+    }
+
+    pub fn register_vehicle_to_fleet(ctx: Context<RegisterVehicleToFleet>) -> Result<()> {
+        let vehicle = &mut ctx.accounts.vehicle;
+        require!(vehicle.fleet.is_none(), ErrorCode::VehicleInFleet);
+        vehicle.fleet = Some(ctx.accounts.fleet.key());
+        // Both parties sign, so the operator agrees to the share in force
+        vehicle.agreed_fleet_share_bps = ctx.accounts.fleet.fleet_share_bps;
+
+        let fleet = &mut ctx.accounts.fleet;
+        fleet.vehicle_count = fleet.vehicle_count
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn remove_vehicle_from_fleet(ctx: Context<RemoveVehicleFromFleet>) -> Result<()> {
+        let signer = ctx.accounts.signer.key();
+        require!(
+            signer == ctx.accounts.fleet.manager || signer == ctx.accounts.vehicle.operator,
+            ErrorCode::Unauthorized
+        );
+
+        // Settlement reads the fleet of the vehicle, so members leave between jobs
+        let vehicle = &mut ctx.accounts.vehicle;
+        require!(!vehicle.is_busy, ErrorCode::VehicleNotAvailable);
+        vehicle.fleet = None;
+        vehicle.agreed_fleet_share_bps = 0;
+
+        let fleet = &mut ctx.accounts.fleet;
+        fleet.vehicle_count = fleet.vehicle_count
+            .checked_sub(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // is_paused: Boolean, Stops every vehicle in the fleet from taking new work, true
+    pub fn set_fleet_paused(ctx: Context<UpdateFleet>, is_paused: bool) -> Result<()> {
+        ctx.accounts.fleet.is_paused = is_paused;
+        Ok(())
+    }
+
+    // payout_destination: Address, Wallet that receives the fleet's share of earnings, 9WzDX...AWWM
+    pub fn set_fleet_payout(ctx: Context<UpdateFleet>, payout_destination: Pubkey) -> Result<()> {
+        require!(payout_destination != Pubkey::default(), ErrorCode::InvalidParameter);
+        ctx.accounts.fleet.payout_destination = payout_destination;
+        Ok(())
+    }
+
+    // fleet_share_bps: Number, Share of each operator payment routed to the fleet payout wallet, 2000 = 20%
+    pub fn set_fleet_share(ctx: Context<UpdateFleet>, fleet_share_bps: u16) -> Result<()> {
+        require!(fleet_share_bps <= MAX_FLEET_SHARE_BPS, ErrorCode::InvalidFleetShare);
+        ctx.accounts.fleet.fleet_share_bps = fleet_share_bps;
+        emit!(FleetShareChanged {
+            schema_version: EVENT_SCHEMA_VERSION,
+            fleet: ctx.accounts.fleet.key(),
+            fleet_share_bps,
+        });
+        Ok(())
+    }
+
+    // Operator agrees to the fleet's current share, e.g. after the manager
+    // raised it
+    pub fn accept_fleet_share(ctx: Context<AcceptFleetShare>) -> Result<()> {
+        ctx.accounts.vehicle.agreed_fleet_share_bps = ctx.accounts.fleet.fleet_share_bps;
+        Ok(())
+    }
+
+    // amount: Number, Lamports added to the vehicle's stake, 1000000000 = 1 SOL
+    pub fn stake_vehicle(ctx: Context<StakeVehicle>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.operator.to_account_info(),
+                    to: ctx.accounts.stake.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let stake = &mut ctx.accounts.stake;
+        stake.bump = ctx.bumps.stake;
+        stake.vehicle = ctx.accounts.vehicle.key();
+        stake.amount = stake.amount
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        ctx.accounts.vehicle.sync_stake(stake);
+        Ok(())
+    }
+
+    // amount: Number, Lamports of stake to withdraw once the cooldown ends, 500000000 = 0.5 SOL
+    pub fn request_unstake(ctx: Context<RequestUnstake>, amount: u64) -> Result<()> {
+        let stake = &mut ctx.accounts.stake;
+        require!(amount > 0 && amount <= stake.amount, ErrorCode::InvalidAmount);
+
+        // A new request replaces the previous one and restarts the cooldown
+        stake.pending_unstake = amount;
+        stake.unstake_requested_at = Clock::get()?.unix_timestamp;
+        ctx.accounts.vehicle.sync_stake(stake);
+        Ok(())
+    }
+
+    // Pays the requested stake out once the cooldown has passed; a slash in
+    // the meantime shrinks what is paid
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>) -> Result<()> {
+        require!(!ctx.accounts.vehicle.is_busy, ErrorCode::VehicleNotAvailable);
+        let stake = &mut ctx.accounts.stake;
+        require!(stake.pending_unstake > 0, ErrorCode::NoUnstakeRequested);
+        let unlocks_at = stake.unstake_requested_at
+            .checked_add(UNSTAKE_COOLDOWN_SECONDS)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(Clock::get()?.unix_timestamp >= unlocks_at, ErrorCode::UnstakeCooldownActive);
+
+        let amount = stake.pending_unstake.min(stake.amount);
+        stake.amount = stake.amount
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stake.pending_unstake = 0;
+        move_lamports(&stake.to_account_info(), &ctx.accounts.operator.to_account_info(), amount)?;
+        ctx.accounts.vehicle.sync_stake(stake);
+        Ok(())
+    }
+
+    // Closes the vehicle and hands what is left of its stake back to the operator
+    pub fn deregister_vehicle(ctx: Context<DeregisterVehicle>) -> Result<()> {
+        let vehicle = &ctx.accounts.vehicle;
+        require!(!vehicle.is_busy, ErrorCode::VehicleNotAvailable);
+        require!(vehicle.fleet.is_none(), ErrorCode::VehicleInFleet);
+        require!(
+            vehicle.staked == 0 || ctx.accounts.stake.is_some(),
+            ErrorCode::StakeAccountRequired
+        );
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        if vehicle.geohash != [0u8; GEOHASH_LEN] {
+            let bucket = ctx.accounts.geo_bucket.as_mut().ok_or(ErrorCode::GeoBucketRequired)?;
+            bucket.remove(vehicle_key);
+        }
+        if let Some(stake) = &ctx.accounts.stake {
+            stake.close(ctx.accounts.operator.to_account_info())?;
+        }
+        Ok(())
+    }
+
+    // deposit: Number, Lamports locked for the vehicle to draw on, 100000000 = 0.1 SOL
+    // duration_seconds: Number, How long the vehicle may settle vouchers before the customer can reclaim, 3600
+    pub fn open_channel(
+        ctx: Context<OpenChannel>,
+        deposit: u64,
+        duration_seconds: i64,
+    ) -> Result<()> {
+        require!(deposit > 0, ErrorCode::InvalidAmount);
+        require!(duration_seconds > 0, ErrorCode::InvalidParameter);
+
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.customer.to_account_info(),
+                    to: ctx.accounts.channel.to_account_info(),
+                },
+            ),
+            deposit,
+        )?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let nonce = ctx.accounts.channel_nonce.next(ctx.bumps.channel_nonce)?;
+        let channel = &mut ctx.accounts.channel;
+        channel.bump = ctx.bumps.channel;
+        channel.customer = ctx.accounts.customer.key();
+        channel.vehicle = ctx.accounts.vehicle.key();
+        channel.deposit = deposit;
+        channel.settled = 0;
+        channel.opened_at = now;
+        channel.expires_at = now.checked_add(duration_seconds).ok_or(ErrorCode::MathOverflow)?;
+        channel.nonce = nonce;
+        Ok(())
+    }
+
+    // cumulative_amount: Number, Total the customer's latest voucher authorizes, 2500000
+    // The transaction must carry an Ed25519 program instruction, immediately
+    // before this one, verifying the customer's signature over the voucher
+    pub fn settle_channel(ctx: Context<SettleChannel>, cumulative_amount: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active, ErrorCode::ConfigInactive);
+
+        let channel = &ctx.accounts.channel;
+        require!(cumulative_amount > channel.settled, ErrorCode::InvalidVoucher);
+        require!(cumulative_amount <= channel.deposit, ErrorCode::InvalidVoucher);
+
+        let message = channel_voucher(&ctx.accounts.channel.key(), channel.nonce, cumulative_amount);
+        verify_ed25519_signature(&ctx.accounts.instructions, &channel.customer, &message)?;
+
+        let owed = cumulative_amount
+            .checked_sub(channel.settled)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let fee_bps = config.fee_bps_for(ctx.accounts.vehicle.vehicle_class);
+        let fee = apply_bps(owed, fee_bps as u64, config.fee_rounding)?;
+        let operator_payment = owed.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+
+        let channel_info = ctx.accounts.channel.to_account_info();
+        move_lamports(&channel_info, &ctx.accounts.earnings.to_account_info(), operator_payment)?;
+        move_lamports(&channel_info, &ctx.accounts.treasury.to_account_info(), fee)?;
+        ctx.accounts.treasury.credit_fee(&config.fee_recipients, fee)?;
+
+        let earnings = &mut ctx.accounts.earnings;
+        earnings.open(ctx.bumps.earnings, ctx.accounts.vehicle.operator);
+        earnings.accrue(operator_payment)?;
+
+        let config = &mut ctx.accounts.config;
+        config.total_fees_collected = config.total_fees_collected
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        ctx.accounts.channel.settled = cumulative_amount;
+        Ok(())
+    }
+
+    // Returns the unspent deposit to the customer; the vehicle may close early,
+    // the customer only once the channel has expired
+    pub fn close_channel(ctx: Context<CloseChannel>) -> Result<()> {
+        let signer = ctx.accounts.signer.key();
+        let channel = &ctx.accounts.channel;
+        if signer == channel.customer {
+            require!(
+                Clock::get()?.unix_timestamp >= channel.expires_at,
+                ErrorCode::ChannelNotExpired
+            );
+        } else {
+            require!(signer == ctx.accounts.vehicle.operator, ErrorCode::Unauthorized);
+        }
+        Ok(())
+    }
+
+    // deposit: Number, Accepted-mint base units locked for the vehicle to draw on, 10000000 = 10 USDC
+    // duration_seconds: Number, How long the vehicle may settle vouchers before the customer can reclaim, 3600
+    pub fn open_channel_spl(
+        ctx: Context<OpenChannelSpl>,
+        deposit: u64,
+        duration_seconds: i64,
+    ) -> Result<()> {
+        require!(deposit > 0, ErrorCode::InvalidAmount);
+        require!(duration_seconds > 0, ErrorCode::InvalidParameter);
+
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        let mint_info = ctx.accounts.mint.to_account_info();
+        check_mint_extensions(&mint_info, config.allowed_token_extensions)?;
+
+        // Gross up for any transfer fee so the vault holds the full deposit
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.customer_token.to_account_info(),
+                    mint: mint_info.clone(),
+                    to: ctx.accounts.channel_token.to_account_info(),
+                    authority: ctx.accounts.customer.to_account_info(),
+                },
+            ),
+            gross_for_net(&mint_info, deposit)?,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let nonce = ctx.accounts.channel_nonce.next(ctx.bumps.channel_nonce)?;
+        let channel = &mut ctx.accounts.channel;
+        channel.bump = ctx.bumps.channel;
+        channel.customer = ctx.accounts.customer.key();
+        channel.vehicle = ctx.accounts.vehicle.key();
+        channel.mint = ctx.accounts.mint.key();
+        channel.deposit = deposit;
+        channel.settled = 0;
+        channel.opened_at = now;
+        channel.expires_at = now.checked_add(duration_seconds).ok_or(ErrorCode::MathOverflow)?;
+        channel.nonce = nonce;
+        Ok(())
+    }
+
+    // cumulative_amount: Number, Total the customer's latest voucher authorizes, 2500000
+    // Same voucher scheme as settle_channel, signed over the token channel's
+    // address. The platform fee absorbs the transfer fee on the operator's leg
+    pub fn settle_channel_spl(ctx: Context<SettleChannelSpl>, cumulative_amount: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active, ErrorCode::ConfigInactive);
+
+        let channel = &ctx.accounts.channel;
+        require!(cumulative_amount > channel.settled, ErrorCode::InvalidVoucher);
+        require!(cumulative_amount <= channel.deposit, ErrorCode::InvalidVoucher);
+
+        let channel_key = ctx.accounts.channel.key();
+        let message = channel_voucher(&channel_key, channel.nonce, cumulative_amount);
+        verify_ed25519_signature(&ctx.accounts.instructions, &channel.customer, &message)?;
+
+        let owed = cumulative_amount
+            .checked_sub(channel.settled)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let fee_bps = config.fee_bps_for(ctx.accounts.vehicle.vehicle_class);
+        let fee = apply_bps(owed, fee_bps as u64, config.fee_rounding)?;
+        let operator_payment = owed.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let vault_info = ctx.accounts.channel_token.to_account_info();
+        let mint_info = ctx.accounts.mint.to_account_info();
+        let decimals = ctx.accounts.mint.decimals;
+        let operator_gross = gross_for_net(&mint_info, operator_payment)?;
+        let treasury_fee = fee
+            .checked_sub(operator_gross.checked_sub(operator_payment).ok_or(ErrorCode::MathOverflow)?)
+            .ok_or(ErrorCode::TransferFeeUncovered)?;
+
+        let signer_seeds: &[&[&[u8]]] = &[&[b"channel_token", channel_key.as_ref(), &[ctx.bumps.channel_token]]];
+        transfer_from_token_escrow(
+            &token_program_info,
+            &vault_info,
+            &mint_info,
+            &ctx.accounts.operator_token.to_account_info(),
+            operator_gross,
+            decimals,
+            signer_seeds,
+        )?;
+        transfer_from_token_escrow(
+            &token_program_info,
+            &vault_info,
+            &mint_info,
+            &ctx.accounts.treasury_token.to_account_info(),
+            treasury_fee,
+            decimals,
+            signer_seeds,
+        )?;
+
+        ctx.accounts.channel.settled = cumulative_amount;
+        Ok(())
+    }
+
+    // Returns the unspent tokens to the customer and closes the vault; the
+    // vehicle may close early, the customer only once the channel has expired
+    pub fn close_channel_spl(ctx: Context<CloseChannelSpl>) -> Result<()> {
+        let signer = ctx.accounts.signer.key();
+        let channel = &ctx.accounts.channel;
+        if signer == channel.customer {
+            require!(
+                Clock::get()?.unix_timestamp >= channel.expires_at,
+                ErrorCode::ChannelNotExpired
+            );
+        } else {
+            require!(signer == ctx.accounts.vehicle.operator, ErrorCode::Unauthorized);
+        }
+
+        let channel_key = ctx.accounts.channel.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[b"channel_token", channel_key.as_ref(), &[ctx.bumps.channel_token]]];
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let vault_info = ctx.accounts.channel_token.to_account_info();
+        let mint_info = ctx.accounts.mint.to_account_info();
+        transfer_from_token_escrow(
+            &token_program_info,
+            &vault_info,
+            &mint_info,
+            &ctx.accounts.customer_token.to_account_info(),
+            ctx.accounts.channel_token.amount,
+            ctx.accounts.mint.decimals,
+            signer_seeds,
+        )?;
+        close_token_escrow(
+            &token_program_info,
+            &vault_info,
+            &mint_info,
+            &ctx.accounts.customer.to_account_info(),
+            signer_seeds,
+        )
+    }
+
+    // delivery_id: Number, Accepted order the vehicle never delivered, 12345
+    pub fn abandon_delivery(ctx: Context<AbandonDelivery>, delivery_id: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require_abandoned(&ctx.accounts.delivery, &ctx.accounts.config, now)?;
+
+        // Closing the escrow refunds the whole fare; the stake pays for the
+        // customer's lost time
+        if ctx.accounts.vehicle.staked > 0 {
+            let stake = ctx.accounts.stake.as_mut().ok_or(ErrorCode::StakeAccountRequired)?;
+            slash_stake(
+                stake,
+                &ctx.accounts.config,
+                &ctx.accounts.customer.to_account_info(),
+                &mut ctx.accounts.treasury,
+            )?;
+            ctx.accounts.vehicle.sync_stake(stake);
+        }
+
+        ctx.accounts.delivery.status = DeliveryStatus::Cancelled;
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_cancelled()?;
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.mark_idle(now)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            status: ctx.accounts.delivery.status.clone(),
+            cancelled_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Accepted token-paid order the vehicle never delivered, 12345
+    pub fn abandon_delivery_spl(ctx: Context<AbandonDeliverySpl>, delivery_id: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require_abandoned(&ctx.accounts.delivery, &ctx.accounts.config, now)?;
+        require!(!ctx.accounts.delivery.confidential, ErrorCode::ConfidentialOrder);
+
+        refund_token_escrow(
+            &ctx.accounts.token_program,
+            &ctx.accounts.escrow_token,
+            &ctx.accounts.mint,
+            &ctx.accounts.customer_token.to_account_info(),
+            &ctx.accounts.customer.to_account_info(),
+            delivery_id,
+            ctx.bumps.escrow_token,
+        )?;
+        // The stake is in lamports, so it is slashed as for a SOL order
+        if ctx.accounts.vehicle.staked > 0 {
+            let stake = ctx.accounts.stake.as_mut().ok_or(ErrorCode::StakeAccountRequired)?;
+            slash_stake(
+                stake,
+                &ctx.accounts.config,
+                &ctx.accounts.customer.to_account_info(),
+                &mut ctx.accounts.treasury,
+            )?;
+            ctx.accounts.vehicle.sync_stake(stake);
+        }
+
+        ctx.accounts.delivery.status = DeliveryStatus::Cancelled;
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_cancelled()?;
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.mark_idle(now)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            status: ctx.accounts.delivery.status.clone(),
+            cancelled_at: now,
+        });
+        Ok(())
+    }
+
+    // delivery_id: Number, Accepted confidential order the vehicle never delivered, 12345
+    // legs: Array, The single refund leg back to the customer, emptying the escrow, [{ payee: { customer: {} }, escrowAfter }]
+    pub fn abandon_delivery_confidential<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AbandonDeliveryConfidential<'info>>,
+        delivery_id: u64,
+        legs: Vec<ConfidentialLeg>,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require_abandoned(&ctx.accounts.delivery, &ctx.accounts.config, now)?;
+        require!(ctx.accounts.delivery.confidential, ErrorCode::NotConfidential);
+        require_keys_eq!(ctx.accounts.mint.key(), ctx.accounts.delivery.payment_mint, ErrorCode::InvalidMint);
+
+        // The whole escrow goes back to the customer in a single leg
+        require!(legs.len() == 1, ErrorCode::InvalidConfidentialLeg);
+        let customer_key = ctx.accounts.customer.key();
+        let id_bytes = delivery_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"escrow_token",
+            customer_key.as_ref(),
+            &id_bytes,
+            &[ctx.bumps.escrow_token],
+        ]];
+        let escrow_info = ctx.accounts.escrow_token.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        pay_confidential_legs(
+            &token_program_info,
+            &escrow_info,
+            &ctx.accounts.mint.to_account_info(),
+            &legs,
+            ctx.remaining_accounts,
+            |payee| (payee == ConfidentialPayee::Customer).then_some(customer_key),
+            signer_seeds,
+        )?;
+        close_confidential_escrow(
+            &token_program_info,
+            &escrow_info,
+            &ctx.accounts.zero_balance_proof.to_account_info(),
+            &ctx.accounts.customer.to_account_info(),
+            signer_seeds,
+        )?;
+        if ctx.accounts.vehicle.staked > 0 {
+            let stake = ctx.accounts.stake.as_mut().ok_or(ErrorCode::StakeAccountRequired)?;
+            slash_stake(
+                stake,
+                &ctx.accounts.config,
+                &ctx.accounts.customer.to_account_info(),
+                &mut ctx.accounts.treasury,
+            )?;
+            ctx.accounts.vehicle.sync_stake(stake);
+        }
+
+        ctx.accounts.delivery.status = DeliveryStatus::Cancelled;
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+        global_stats.record_cancelled()?;
+
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.mark_idle(now)?;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())?;
+        emit!(OrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            delivery: ctx.accounts.delivery.key(),
+            status: ctx.accounts.delivery.status.clone(),
+            cancelled_at: now,
+        });
+        Ok(())
+    }
+
+    // away_until: Number, Unix time the vehicle returns, 1700003600, 0 = back now
+    pub fn set_away(ctx: Context<SetAway>, away_until: i64) -> Result<()> {
+        ctx.accounts.vehicle.away_until = away_until;
+        Ok(())
+    }
+
+    // min_score: Number, Lowest customer score in basis points the vehicle accepts, 8000, 0 = anyone
+    pub fn set_min_customer_score(ctx: Context<SetMinCustomerScore>, min_score: u16) -> Result<()> {
+        require!(min_score <= 10000, ErrorCode::InvalidParameter);
+        ctx.accounts.vehicle.min_customer_score = min_score;
+        Ok(())
+    }
+
+    // max_active_orders: Number, Orders the vehicle may carry at once, settled together with complete_deliveries_batch, 4
+    pub fn set_max_active_orders(ctx: Context<SetMaxActiveOrders>, max_active_orders: u8) -> Result<()> {
+        require!(
+            max_active_orders >= 1 && max_active_orders as usize <= MAX_BATCH_SETTLEMENTS,
+            ErrorCode::InvalidParameter
+        );
+        // Lowering the limit below the orders in hand only holds off new ones
+        let vehicle_key = ctx.accounts.vehicle.key();
+        ctx.accounts.vehicle.max_active_orders = max_active_orders;
+        ctx.accounts.vehicle.sync_geo_bucket(vehicle_key, ctx.accounts.geo_bucket.as_deref_mut())
+    }
+
+    // geohash: Bytes, Geohash cell of the vehicle's current position, "dr5ru7"
+    pub fn update_geo_bucket(ctx: Context<UpdateGeoBucket>, geohash: [u8; GEOHASH_LEN]) -> Result<()> {
+        let vehicle_key = ctx.accounts.vehicle.key();
+        let vehicle = &ctx.accounts.vehicle;
+        require!(geohash == vehicle.position.geohash(), ErrorCode::GeohashMismatch);
+
+        if vehicle.geohash != [0u8; GEOHASH_LEN] && vehicle.geohash != geohash {
+            let old_bucket = ctx.accounts.old_bucket.as_mut().ok_or(ErrorCode::GeoBucketRequired)?;
+            old_bucket.remove(vehicle_key);
+        }
+
+        let new_bucket = &mut ctx.accounts.new_bucket;
+        new_bucket.bump = ctx.bumps.new_bucket;
+        new_bucket.geohash = geohash;
+        if vehicle.is_active && vehicle.has_capacity() {
+            new_bucket.insert(vehicle_key)?;
+        }
+
+        ctx.accounts.vehicle.geohash = geohash;
+        Ok(())
+    }
+
+    // dispatcher: Address, Fleet NOC key allowed to accept jobs for the operator's vehicles, 5RT2W...7777
+    // name: String, Label for the key, "noc-primary"
+    // expires_at: Number, Unix timestamp after which the key stops working, 1735689600
+    // permissions: Number, Bitmask of PERM_* handlers the key may call, 1 = accept only
+    pub fn authorize_dispatcher_key(
+        ctx: Context<AuthorizeDispatcherKey>,
+        dispatcher: Pubkey,
+        name: String,
+        expires_at: i64,
+        permissions: u8,
+    ) -> Result<()> {
+        require!(name.len() <= 32, ErrorCode::InvalidParameter);
+        require!(
+            permissions != 0 && permissions & !PERM_ALL == 0,
+            ErrorCode::InvalidParameter
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(expires_at > now, ErrorCode::InvalidParameter);
+
+        let dispatcher_key = &mut ctx.accounts.dispatcher_key;
+        dispatcher_key.bump = ctx.bumps.dispatcher_key;
+        dispatcher_key.operator = ctx.accounts.operator.key();
+        dispatcher_key.dispatcher = dispatcher;
+        dispatcher_key.name = name;
+        dispatcher_key.expires_at = expires_at;
+        dispatcher_key.created_at = now;
+        dispatcher_key.permissions = permissions;
+        Ok(())
+    }
+
+    // dispatcher: Address, Dispatcher key being revoked, 5RT2W...7777
+    pub fn revoke_dispatcher_key(_ctx: Context<RevokeDispatcherKey>, _dispatcher: Pubkey) -> Result<()> {
+        Ok(())
+    }
+
+    // target: Address, Stranded escrow or token vault to sweep, 7TQ1E...2222
+    // recovery_address: Address, Wallet receiving the swept lamports, 4WX8R...6666
+    pub fn initiate_recovery(
+        ctx: Context<InitiateRecovery>,
+        target: Pubkey,
+        recovery_address: Pubkey,
+    ) -> Result<()> {
+        require!(target != ctx.accounts.config.key(), ErrorCode::InvalidParameter);
+        ctx.accounts.config.check_approved(
+            ctx.accounts.proposal.as_deref_mut(),
+            &AdminAction::InitiateRecovery { target, recovery_address },
+        )?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let executable_at = now
+            .checked_add(RECOVERY_TIMELOCK_SECONDS)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let recovery = &mut ctx.accounts.recovery;
+        recovery.bump = ctx.bumps.recovery;
+        recovery.config = ctx.accounts.config.key();
+        recovery.target = target;
+        recovery.recovery_address = recovery_address;
+        recovery.initiated_at = now;
+        recovery.executable_at = executable_at;
+
+        emit!(RecoveryInitiated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            target,
+            recovery_address,
+            initiated_by: ctx.accounts.authority.key(),
+            executable_at,
+        });
+        Ok(())
+    }
+
+    // target: Address, Account whose pending sweep is executed, 7TQ1E...2222
+    // vault: Object, Seeds of the token vault being swept, or null for a lamport escrow, { orderEscrow: { customer, deliveryId } } / { channel: { channel } }
+    pub fn execute_recovery(ctx: Context<ExecuteRecovery>, target: Pubkey, vault: Option<RecoveryVault>) -> Result<()> {
+        let recovery = &ctx.accounts.recovery;
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= recovery.executable_at, ErrorCode::RecoveryTimelockActive);
+
+        let target_info = ctx.accounts.target.to_account_info();
+        let recovery_address = ctx.accounts.recovery_address.to_account_info();
+        let amount = target_info.lamports();
+        match vault {
+            // A lamport escrow, swept only once its order can no longer settle
+            None => {
+                require!(target_info.owner == ctx.program_id, ErrorCode::RecoveryTargetProtected);
+                let escrow = {
+                    let data = target_info.try_borrow_data()?;
+                    require!(data.starts_with(Escrow::DISCRIMINATOR), ErrorCode::RecoveryTargetProtected);
+                    Escrow::try_deserialize(&mut &data[..])?
+                };
+                let delivery = ctx.accounts.delivery.as_ref().ok_or(ErrorCode::RecoveryTargetProtected)?;
+                require_keys_eq!(delivery.key(), escrow.delivery, ErrorCode::RecoveryTargetProtected);
+                require!(order_released(delivery)?, ErrorCode::RecoveryTargetProtected);
+
+                // Sweep everything, rent included; the emptied account is reclaimed by the runtime
+                move_lamports(&target_info, &recovery_address, amount)?;
+            }
+            // A token vault, drained and closed through the token program
+            // with the vault's own PDA seeds
+            Some(vault) => {
+                let token_program = ctx.accounts.token_program.as_ref().ok_or(ErrorCode::RecoveryTargetProtected)?;
+                let mint = ctx.accounts.mint.as_ref().ok_or(ErrorCode::RecoveryTargetProtected)?;
+                let recovery_token = ctx.accounts.recovery_token.as_ref().ok_or(ErrorCode::RecoveryTargetProtected)?;
+                require_keys_eq!(*target_info.owner, token_program.key(), ErrorCode::RecoveryTargetProtected);
+                require_keys_eq!(recovery_token.mint, mint.key(), ErrorCode::InvalidMint);
+                require_keys_eq!(recovery_token.owner, recovery.recovery_address, ErrorCode::Unauthorized);
+                let balance = {
+                    let data = target_info.try_borrow_data()?;
+                    let account = TokenAccount::try_deserialize(&mut &data[..])?;
+                    require_keys_eq!(account.mint, mint.key(), ErrorCode::InvalidMint);
+                    account.amount
+                };
+
+                let (seeds, holder): (Vec<Vec<u8>>, Pubkey) = match vault {
+                    RecoveryVault::OrderEscrow { customer, delivery_id } => (
+                        vec![b"escrow_token".to_vec(), customer.to_bytes().to_vec(), delivery_id.to_le_bytes().to_vec()],
+                        Pubkey::find_program_address(
+                            &[b"delivery", customer.as_ref(), &delivery_id.to_le_bytes()],
+                            ctx.program_id,
+                        )
+                        .0,
+                    ),
+                    RecoveryVault::Channel { channel } => {
+                        (vec![b"channel_token".to_vec(), channel.to_bytes().to_vec()], channel)
+                    }
+                };
+                let seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+                let (address, bump) = Pubkey::find_program_address(&seeds, ctx.program_id);
+                require_keys_eq!(address, target, ErrorCode::RecoveryTargetProtected);
+
+                // The order must be finished or gone; a channel vault is
+                // orphaned only once its channel account is closed
+                let holder_info = ctx.accounts.delivery.as_ref().ok_or(ErrorCode::RecoveryTargetProtected)?;
+                require_keys_eq!(holder_info.key(), holder, ErrorCode::RecoveryTargetProtected);
+                let released = match vault {
+                    RecoveryVault::OrderEscrow { .. } => order_released(holder_info)?,
+                    RecoveryVault::Channel { .. } => holder_info.owner != ctx.program_id || holder_info.data_is_empty(),
+                };
+                require!(released, ErrorCode::RecoveryTargetProtected);
+
+                let bump = [bump];
+                let mut signer = seeds.clone();
+                signer.push(&bump);
+                let signer_seeds: &[&[&[u8]]] = &[&signer];
+                let token_program_info = token_program.to_account_info();
+                let mint_info = mint.to_account_info();
+                transfer_from_token_escrow(
+                    &token_program_info,
+                    &target_info,
+                    &mint_info,
+                    &recovery_token.to_account_info(),
+                    balance,
+                    mint.decimals,
+                    signer_seeds,
+                )?;
+                close_token_escrow(&token_program_info, &target_info, &mint_info, &recovery_address, signer_seeds)?;
+                emit!(RecoveryTokensSwept {
+                    schema_version: EVENT_SCHEMA_VERSION,
+                    target,
+                    mint: mint.key(),
+                    recovery_token: recovery_token.key(),
+                    amount: balance,
+                    executed_at: now,
+                });
+            }
+        }
+
+        emit!(RecoveryExecuted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            target,
+            recovery_address: recovery.recovery_address,
+            amount,
+            executed_at: now,
+        });
+        Ok(())
+    }
+
+    // target: Address, Account whose pending sweep is abandoned, 7TQ1E...2222
+    pub fn cancel_recovery(ctx: Context<CancelRecovery>, target: Pubkey) -> Result<()> {
+        emit!(RecoveryCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            target,
+            cancelled_by: ctx.accounts.authority.key(),
+        });
+        Ok(())
+    }
+
+    // provider: Address, Map-data vendor payout wallet, 2HD4M...8888
+    // price_per_tile: Number, Lamports charged per tile, 1000000 = 0.001 SOL
+    pub fn register_map_provider(
+        ctx: Context<RegisterMapProvider>,
+        provider: Pubkey,
+        price_per_tile: u64,
+    ) -> Result<()> {
+        require!(price_per_tile > 0, ErrorCode::InvalidAmount);
+
+        let map_provider = &mut ctx.accounts.map_provider;
+        map_provider.bump = ctx.bumps.map_provider;
+        map_provider.provider = provider;
+        map_provider.price_per_tile = price_per_tile;
+        map_provider.tiles_sold = 0;
+        map_provider.is_active = true;
+        map_provider.registered_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // price_per_tile: Number, New per-tile price in lamports, 2000000 = 0.002 SOL
+    pub fn set_map_tile_price(ctx: Context<SetMapTilePrice>, price_per_tile: u64) -> Result<()> {
+        require!(price_per_tile > 0, ErrorCode::InvalidAmount);
+        ctx.accounts.map_provider.price_per_tile = price_per_tile;
+        Ok(())
+    }
+
+    // provider: Address, Map-data vendor being suspended or reinstated, 2HD4M...8888
+    // is_active: Boolean, Whether the provider's tiles can be bought, false
+    pub fn set_map_provider_active(
+        ctx: Context<SetMapProviderActive>,
+        provider: Pubkey,
+        is_active: bool,
+    ) -> Result<()> {
+        ctx.accounts.map_provider.is_active = is_active;
+        Ok(())
+    }
+
+    // provider: Address, Map-data vendor selling the tile, 2HD4M...8888
+    // tile_id: Number, Provider's tile identifier, 4294967296
+    // version_hash: Bytes, Hash of the tile version bought, [0; 32]
+    // max_price: Number, Most the buyer will pay per tile in lamports, 1000000 = 0.001 SOL
+    pub fn purchase_map_tile(
+        ctx: Context<PurchaseMapTile>,
+        provider: Pubkey,
+        tile_id: u64,
+        version_hash: [u8; 32],
+        max_price: u64,
+    ) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+
+        let map_provider = &ctx.accounts.map_provider;
+        require!(map_provider.is_active, ErrorCode::ProviderInactive);
+        let price = map_provider.price_per_tile;
+        // The provider can reprice between the buyer's quote and this purchase
+        require!(price <= max_price, ErrorCode::PriceAboveLimit);
+
+        // Paid from the operator's prepaid wallet
+        let wallet = &mut ctx.accounts.wallet;
+        wallet.balance = wallet.balance
+            .checked_sub(price)
+            .ok_or(ErrorCode::InsufficientBalance)?;
+        move_lamports(
+            &ctx.accounts.wallet.to_account_info(),
+            &ctx.accounts.provider_wallet.to_account_info(),
+            price,
+        )?;
+
+        let map_provider = &mut ctx.accounts.map_provider;
+        map_provider.tiles_sold = map_provider.tiles_sold
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let purchase = &mut ctx.accounts.tile_purchase;
+        purchase.bump = ctx.bumps.tile_purchase;
+        purchase.vehicle = ctx.accounts.vehicle.key();
+        purchase.provider = provider;
+        purchase.tile_id = tile_id;
+        purchase.version_hash = version_hash;
+        purchase.price = price;
+        purchase.purchased_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // feature_id: Number, Identifier of the unlockable feature, 7 = highway autonomy
+    // provider: Address, Manufacturer or vendor paid for licenses, 6MF3K...1111
+    // price_per_day: Number, License price in lamports per day, 10000000 = 0.01 SOL
+    pub fn register_feature(
+        ctx: Context<RegisterFeature>,
+        feature_id: u64,
+        provider: Pubkey,
+        price_per_day: u64,
+    ) -> Result<()> {
+        require!(price_per_day > 0, ErrorCode::InvalidAmount);
+
+        let feature = &mut ctx.accounts.feature;
+        feature.bump = ctx.bumps.feature;
+        feature.feature_id = feature_id;
+        feature.provider = provider;
+        feature.price_per_day = price_per_day;
+        feature.is_active = true;
+        Ok(())
+    }
+
+    // feature_id: Number, Feature being withdrawn or reinstated, 7
+    // is_active: Boolean, Whether new licenses can be bought, false
+    pub fn set_feature_active(ctx: Context<SetFeatureActive>, feature_id: u64, is_active: bool) -> Result<()> {
+        ctx.accounts.feature.is_active = is_active;
+        Ok(())
+    }
+
+    // feature_id: Number, Feature being licensed, 7
+    // duration_days: Number, Days of entitlement bought, 30
+    pub fn purchase_feature_license(
+        ctx: Context<PurchaseFeatureLicense>,
+        feature_id: u64,
+        duration_days: u32,
+    ) -> Result<()> {
+        require!(duration_days > 0, ErrorCode::InvalidParameter);
+
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+
+        let feature = &ctx.accounts.feature;
+        require!(feature.is_active, ErrorCode::ProviderInactive);
+        let price = feature.price_per_day
+            .checked_mul(duration_days as u64)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.operator.to_account_info(),
+                    to: ctx.accounts.provider.to_account_info(),
+                },
+            ),
+            price,
+        )?;
+
+        // Renewals extend from the current expiry while the license is still live
+        let now = Clock::get()?.unix_timestamp;
+        let license = &mut ctx.accounts.license;
+        let starts_at = license.expires_at.max(now);
+        license.bump = ctx.bumps.license;
+        license.vehicle = ctx.accounts.vehicle.key();
+        license.feature_id = feature_id;
+        license.expires_at = starts_at
+            .checked_add(duration_days as i64 * SECONDS_PER_DAY)
+            .ok_or(ErrorCode::MathOverflow)?;
+        license.total_paid = license.total_paid
+            .checked_add(price)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // corridor_id: Number, Air corridor identifier, 12
+    // fee_recipient: Address, Airspace authority collecting transit fees, 9AC2R...3333
+    // fee_per_transit: Number, Lamports charged per transit, 5000000 = 0.005 SOL
+    pub fn register_air_corridor(
+        ctx: Context<RegisterAirCorridor>,
+        corridor_id: u32,
+        fee_recipient: Pubkey,
+        fee_per_transit: u64,
+    ) -> Result<()> {
+        let corridor = &mut ctx.accounts.corridor;
+        corridor.bump = ctx.bumps.corridor;
+        corridor.corridor_id = corridor_id;
+        corridor.fee_recipient = fee_recipient;
+        corridor.fee_per_transit = fee_per_transit;
+        corridor.transits = 0;
+        corridor.is_active = true;
+        Ok(())
+    }
+
+    // corridor_id: Number, Corridor being closed or reopened, 12
+    // is_active: Boolean, Whether drones can pay to transit it, false
+    pub fn set_air_corridor_active(
+        ctx: Context<SetAirCorridorActive>,
+        corridor_id: u32,
+        is_active: bool,
+    ) -> Result<()> {
+        ctx.accounts.corridor.is_active = is_active;
+        Ok(())
+    }
+
+    // corridor_id: Number, Corridor the drone is transiting, 12
+    pub fn pay_corridor_fee(ctx: Context<PayCorridorFee>, corridor_id: u32) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+        require!(
+            ctx.accounts.vehicle.vehicle_class == VehicleClass::Drone,
+            ErrorCode::VehicleClassMismatch
+        );
+
+        let corridor = &ctx.accounts.corridor;
+        require!(corridor.is_active, ErrorCode::ProviderInactive);
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.operator.to_account_info(),
+                    to: ctx.accounts.fee_recipient.to_account_info(),
+                },
+            ),
+            corridor.fee_per_transit,
+        )?;
+
+        let corridor = &mut ctx.accounts.corridor;
+        corridor.transits = corridor.transits
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // oracle: Address, Weather feed signer, 8WX3H...5555
+    pub fn register_weather_oracle(ctx: Context<RegisterWeatherOracle>, oracle: Pubkey) -> Result<()> {
+        let weather_oracle = &mut ctx.accounts.weather_oracle;
+        weather_oracle.bump = ctx.bumps.weather_oracle;
+        weather_oracle.oracle = oracle;
+        weather_oracle.registered_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // oracle: Address, Weather feed signer being removed, 8WX3H...5555
+    pub fn remove_weather_oracle(_ctx: Context<RemoveWeatherOracle>, _oracle: Pubkey) -> Result<()> {
+        Ok(())
+    }
+
+    // region_id: Number, Region the reading applies to, 1
+    // hazard_level: Number, 0 = clear up to MAX_HAZARD_LEVEL = severe, 2
+    pub fn set_hazard_level(
+        ctx: Context<SetHazardLevel>,
+        region_id: u16,
+        hazard_level: u8,
+    ) -> Result<()> {
+        require!(hazard_level <= MAX_HAZARD_LEVEL, ErrorCode::InvalidParameter);
+
+        let region = &mut ctx.accounts.region;
+        region.hazard_level = hazard_level;
+        region.hazard_updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // oracle: Address, Traffic feed signer, 4TF7C...6666
+    pub fn register_traffic_oracle(ctx: Context<RegisterTrafficOracle>, oracle: Pubkey) -> Result<()> {
+        let traffic_oracle = &mut ctx.accounts.traffic_oracle;
+        traffic_oracle.bump = ctx.bumps.traffic_oracle;
+        traffic_oracle.oracle = oracle;
+        traffic_oracle.registered_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // oracle: Address, Traffic feed signer being removed, 4TF7C...6666
+    pub fn remove_traffic_oracle(_ctx: Context<RemoveTrafficOracle>, _oracle: Pubkey) -> Result<()> {
+        Ok(())
+    }
+
+    // region_id: Number, Region the reading applies to, 1
+    // congestion_index: Number, Percent by which SLA deadlines stretch, 40
+    pub fn set_congestion(
+        ctx: Context<SetCongestion>,
+        region_id: u16,
+        congestion_index: u8,
+    ) -> Result<()> {
+        require!(congestion_index <= MAX_CONGESTION_INDEX, ErrorCode::InvalidParameter);
+
+        let region = &mut ctx.accounts.region;
+        region.congestion_index = congestion_index;
+        region.congestion_updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // currency: Bytes, ISO 4217 code of the feed, "USD"
+    // oracle: Address, Signer allowed to publish prices, 1PX9Q...7777
+    pub fn initialize_price_feed(
+        ctx: Context<InitializePriceFeed>,
+        currency: [u8; 3],
+        oracle: Pubkey,
+    ) -> Result<()> {
+        let feed = &mut ctx.accounts.price_feed;
+        feed.bump = ctx.bumps.price_feed;
+        feed.currency = currency;
+        feed.oracle = oracle;
+        feed.micros_per_sol = 0;
+        feed.updated_at = 0;
+        Ok(())
+    }
+
+    // currency: Bytes, Feed being updated, "USD"
+    // micros_per_sol: Number, Price of 1 SOL in millionths of the currency, 150000000 = $150
+    pub fn update_price_feed(
+        ctx: Context<UpdatePriceFeed>,
+        currency: [u8; 3],
+        micros_per_sol: u64,
+    ) -> Result<()> {
+        require!(micros_per_sol > 0, ErrorCode::InvalidAmount);
+
+        let feed = &mut ctx.accounts.price_feed;
+        feed.micros_per_sol = micros_per_sol;
+        feed.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // currency: Bytes, Feed whose oracle has stalled, "USD"
+    // micros_per_sol: Number, Price of 1 SOL in millionths of the currency, 150000000 = $150
+    pub fn override_price_feed(
+        ctx: Context<OverridePriceFeed>,
+        currency: [u8; 3],
+        micros_per_sol: u64,
+    ) -> Result<()> {
+        require!(micros_per_sol > 0, ErrorCode::InvalidAmount);
+
+        // The authority only steps in once the oracle has let the feed go
+        // stale, so it can never overrule a live price
+        let now = Clock::get()?.unix_timestamp;
+        let feed = &mut ctx.accounts.price_feed;
+        require!(!feed.is_fresh(now), ErrorCode::PriceFeedLive);
+        feed.micros_per_sol = micros_per_sol;
+        feed.updated_at = now;
+        emit!(PriceFeedOverridden {
+            schema_version: EVENT_SCHEMA_VERSION,
+            currency,
+            micros_per_sol,
+            authority: ctx.accounts.authority.key(),
+        });
+        Ok(())
+    }
+
+    // preferred_vehicles: Array, Vehicles offered the customer's orders first, [7XY2A...1111]
+    // exclusive_window_seconds: Number, How long only preferred vehicles may accept, 120
+    pub fn set_preferred_vehicles(
+        ctx: Context<SetPreferredVehicles>,
+        preferred_vehicles: Vec<Pubkey>,
+        exclusive_window_seconds: u32,
+    ) -> Result<()> {
+        require!(preferred_vehicles.len() <= MAX_PREFERRED_VEHICLES, ErrorCode::InvalidParameter);
+        require!(
+            exclusive_window_seconds <= MAX_EXCLUSIVE_WINDOW_SECONDS,
+            ErrorCode::InvalidParameter
+        );
+
+        let customer_key = ctx.accounts.customer.key();
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.open(ctx.bumps.customer_profile, customer_key, Clock::get()?.unix_timestamp);
+        profile.preferred_vehicles = preferred_vehicles;
+        profile.exclusive_window_seconds = exclusive_window_seconds;
+        Ok(())
+    }
+
+    // round_up_unit: Number, Fares are rounded up to a multiple of this and the difference donated, 10000000 = 0.01 SOL, 0 = off
+    pub fn set_round_up(ctx: Context<SetRoundUp>, round_up_unit: u64) -> Result<()> {
+        let customer_key = ctx.accounts.customer.key();
+        let profile = &mut ctx.accounts.customer_profile;
+        profile.open(ctx.bumps.customer_profile, customer_key, Clock::get()?.unix_timestamp);
+        profile.round_up_unit = round_up_unit;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
 pub struct InitializeConfig<'info> {
     #[account(
         init,
-        seeds = [b"config", authority.key().as_ref()],
+        seeds = [b"config"],
+        bump,
+        payer = authority,
+        space = 8 + Config::LEN
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        init,
+        seeds = [b"fee_history", config.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + FeeHistory::LEN
+    )]
+    pub fee_history: Account<'info, FeeHistory>,
+    #[account(
+        init,
+        seeds = [b"tax_vault", config.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + TaxVault::LEN
+    )]
+    pub tax_vault: Account<'info, TaxVault>,
+    #[account(
+        init,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + Treasury::LEN
+    )]
+    pub treasury: Account<'info, Treasury>,
+    // Only the program's upgrade authority can claim the config, so nobody
+    // can front-run the deployment and install themselves as the authority
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @ ErrorCode::Unauthorized)]
+    pub program: Program<'info, crate::program::AutonomousVehiclePayments>,
+    #[account(constraint = program_data.upgrade_authority_address == Some(authority.key()) @ ErrorCode::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"fee_history", config.key().as_ref()],
+        bump = fee_history.bump,
+    )]
+    pub fee_history: Account<'info, FeeHistory>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(code_hash: [u8; 32])]
+pub struct CreateVoucher<'info> {
+    #[account(
+        init,
+        seeds = [b"voucher", code_hash.as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + Voucher::LEN
+    )]
+    pub voucher: Account<'info, Voucher>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeInsurancePool<'info> {
+    #[account(
+        init,
+        seeds = [b"insurance_pool", config.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + InsurancePool::LEN
+    )]
+    pub insurance_pool: Account<'info, InsurancePool>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApprovedConfigUpdate<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"fee_history", config.key().as_ref()],
+        bump = fee_history.bump,
+    )]
+    pub fee_history: Account<'info, FeeHistory>,
+    #[account(
+        mut,
+        constraint = proposal.config == config.key() @ ErrorCode::ProposalMismatch,
+    )]
+    pub proposal: Option<Account<'info, AdminProposal>>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteConfigChange<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"fee_history", config.key().as_ref()],
+        bump = fee_history.bump,
+    )]
+    pub fee_history: Account<'info, FeeHistory>,
+    #[account(
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+}
+
+#[derive(Accounts)]
+#[instruction(proposal_id: u64)]
+pub struct ProposeAdminAction<'info> {
+    #[account(
+        init,
+        seeds = [b"admin_action", config.key().as_ref(), &proposal_id.to_le_bytes()],
+        bump,
+        payer = approver,
+        space = 8 + AdminProposal::LEN
+    )]
+    pub proposal: Account<'info, AdminProposal>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub approver: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(proposal_id: u64)]
+pub struct ApproveAdminAction<'info> {
+    #[account(
+        mut,
+        seeds = [b"admin_action", config.key().as_ref(), &proposal_id.to_le_bytes()],
+        bump = proposal.bump,
+    )]
+    pub proposal: Account<'info, AdminProposal>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    pub approver: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(proposal_id: u64)]
+pub struct CloseAdminAction<'info> {
+    #[account(
+        mut,
+        close = proposer,
+        seeds = [b"admin_action", config.key().as_ref(), &proposal_id.to_le_bytes()],
+        bump = proposal.bump,
+        has_one = proposer,
+    )]
+    pub proposal: Account<'info, AdminProposal>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AdminUpdate<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(authority.key(), role.as_deref(), RoleKind::Admin) @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Admin as u8], authority.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    // Authority or an Admin role holder
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetSurge<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(signer.key(), role.as_deref(), RoleKind::Dispatcher) @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Dispatcher as u8], signer.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    // Authority, the configured surge dispatcher or a Dispatcher role holder
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(kind: RoleKind, holder: Pubkey)]
+pub struct GrantRole<'info> {
+    #[account(
+        init,
+        seeds = [b"role", &[kind as u8], holder.as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + Role::LEN
+    )]
+    pub role: Account<'info, Role>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(kind: RoleKind, holder: Pubkey)]
+pub struct RevokeRole<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"role", &[kind as u8], holder.as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Account<'info, Role>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.pending_authority == new_authority.key() @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub new_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(vehicle_id: String)]
+pub struct RegisterVehicle<'info> {
+    #[account(
+        init,
+        seeds = [b"vehicle", vehicle_id.as_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + Vehicle::LEN
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    pub ownership_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub ownership_token: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// CHECK: The vehicle's stake PDA, created here when the config asks for a stake
+    #[account(mut)]
+    pub stake: Option<UncheckedAccount<'info>>,
+    // The operator, funding the minimum stake
+    #[account(mut)]
+    pub staker: Option<Signer<'info>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(region_id: u16)]
+pub struct InitializeRegion<'info> {
+    #[account(
+        init,
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + Region::LEN
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(authority.key(), role.as_deref(), RoleKind::Admin) @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Admin as u8], authority.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    // Authority or an Admin role holder
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(region_id: u16, page: u16)]
+pub struct InitializeOpenOrders<'info> {
+    #[account(
+        init,
+        seeds = [b"open_orders", &region_id.to_le_bytes(), &page.to_le_bytes()],
+        bump,
+        payer = payer,
+        space = 8 + OpenOrders::LEN
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+    // Page before the new one; required for every page after the first
+    #[account(
+        seeds = [b"open_orders", &region_id.to_le_bytes(), &previous.page.to_le_bytes()],
+        bump = previous.bump,
+    )]
+    pub previous: Option<Account<'info, OpenOrders>>,
+    #[account(
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = page > 0 || config.has_role(payer.key(), role.as_deref(), RoleKind::Admin) @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Admin as u8], payer.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    // Authority or an Admin role holder for the first page; anyone for the
+    // next page once the previous one is full
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(region_id: u16)]
+pub struct SetRegionTax<'info> {
+    #[account(
+        mut,
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(region_id: u16)]
+pub struct SetRegionRobotAccess<'info> {
+    #[account(
+        mut,
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeOperatorProfile<'info> {
+    #[account(
+        init,
+        seeds = [b"operator", operator.key().as_ref()],
+        bump,
+        payer = operator,
+        space = 8 + OperatorProfile::LEN
+    )]
+    pub operator_profile: Account<'info, OperatorProfile>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimEarnings<'info> {
+    #[account(
+        mut,
+        seeds = [b"earnings", operator.key().as_ref()],
+        bump = earnings.bump,
+        has_one = operator @ ErrorCode::Unauthorized,
+    )]
+    pub earnings: Account<'info, Earnings>,
+    /// CHECK: Operator the earnings are paid to; bound by the earnings seeds
+    #[account(mut)]
+    pub operator: UncheckedAccount<'info>,
+    // The operator, or one of its dispatcher keys allowed to claim
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [b"dispatcher_key", operator.key().as_ref(), signer.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+}
+
+#[derive(Accounts)]
+#[instruction(referrer: Pubkey)]
+pub struct RegisterReferral<'info> {
+    #[account(
+        init,
+        seeds = [b"referral", referee.key().as_ref()],
+        bump,
+        payer = referee,
+        space = 8 + Referral::LEN
+    )]
+    pub referral: Account<'info, Referral>,
+    /// CHECK: The referrer's own referral if it has one, read to refuse circular referrals
+    #[account(
+        seeds = [b"referral", referrer.as_ref()],
+        bump,
+    )]
+    pub referrer_referral: UncheckedAccount<'info>,
+    /// CHECK: The referrer's customer or operator profile; checked in the handler
+    pub referrer_profile: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [b"customer", referee.key().as_ref()],
+        bump,
+        payer = referee,
+        space = 8 + CustomerProfile::LEN
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    // Passed when the referee also operates vehicles
+    #[account(
+        mut,
+        seeds = [b"operator", referee.key().as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Option<Account<'info, OperatorProfile>>,
+    // Customer or operator being referred
+    #[account(mut)]
+    pub referee: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"referral", referral.referee.as_ref()],
+        bump = referral.bump,
+        has_one = referrer @ ErrorCode::Unauthorized,
+    )]
+    pub referral: Account<'info, Referral>,
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    delivery_id: u64,
+    payment_amount: u64,
+    pickup_location: String,
+    delivery_location: String,
+    region_id: u16,
+)]
+pub struct CreateDeliveryOrder<'info> {
+    #[account(
+        init,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + Delivery::LEN
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        init,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + Escrow::LEN
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        init_if_needed,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + CustomerProfile::LEN
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    #[account(
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &region_id.to_le_bytes(), &open_orders.page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    #[account(
+        mut,
+        seeds = [b"voucher", voucher.code_hash.as_ref()],
+        bump = voucher.bump,
+    )]
+    pub voucher: Option<Account<'info, Voucher>>,
+    /// CHECK: Customer's use of the voucher at ["voucher_use", voucher, customer], created by the handler
+    #[account(mut)]
+    pub voucher_use: Option<UncheckedAccount<'info>>,
+    #[account(
+        seeds = [b"price_feed", b"USD"],
+        bump = usd_feed.bump,
+    )]
+    pub usd_feed: Account<'info, PriceFeed>,
+    #[account(
+        seeds = [b"price_feed", b"EUR"],
+        bump = eur_feed.bump,
+    )]
+    pub eur_feed: Option<Account<'info, PriceFeed>>,
+    // Pyth SOL/USD price update, required by createDeliveryOrderUsd
+    pub pyth_price: Option<Account<'info, PriceUpdateV2>>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(template_id: u32)]
+pub struct CreateTemplate<'info> {
+    #[account(
+        init,
+        seeds = [b"template", owner.key().as_ref(), &template_id.to_le_bytes()],
+        bump,
+        payer = owner,
+        space = 8 + DeliveryTemplate::LEN
+    )]
+    pub template: Account<'info, DeliveryTemplate>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(template_id: u32)]
+pub struct DeleteTemplate<'info> {
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"template", owner.key().as_ref(), &template_id.to_le_bytes()],
+        bump = template.bump,
+    )]
+    pub template: Account<'info, DeliveryTemplate>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(template_id: u32, delivery_id: u64)]
+pub struct CreateFromTemplate<'info> {
+    #[account(
+        seeds = [b"template", customer.key().as_ref(), &template_id.to_le_bytes()],
+        bump = template.bump,
+    )]
+    pub template: Account<'info, DeliveryTemplate>,
+    #[account(
+        init,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + Delivery::LEN
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        init,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + Escrow::LEN
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        init_if_needed,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + CustomerProfile::LEN
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    #[account(
+        seeds = [b"region", &template.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"price_feed", b"USD"],
+        bump = usd_feed.bump,
+    )]
+    pub usd_feed: Account<'info, PriceFeed>,
+    #[account(
+        seeds = [b"price_feed", b"EUR"],
+        bump = eur_feed.bump,
+    )]
+    pub eur_feed: Option<Account<'info, PriceFeed>>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64, other_delivery_id: u64)]
+pub struct MergeDeliveries<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    /// CHECK: Pending order being absorbed, read in the handler and shrunk to a ClosedDelivery marker
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [b"delivery", customer.key().as_ref(), &other_delivery_id.to_le_bytes()],
+        bump,
+    )]
+    pub other_delivery: UncheckedAccount<'info>,
+    // Board the absorbed order is listed on; the handler checks it is that
+    // order's page
+    #[account(
+        mut,
+        seeds = [b"open_orders", &open_orders.region_id.to_le_bytes(), &open_orders.page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    // Voucher the absorbed order redeemed, handed back since its discount
+    // does not carry over
+    #[account(
+        mut,
+        seeds = [b"voucher", voucher.code_hash.as_ref()],
+        bump = voucher.bump,
+    )]
+    pub voucher: Option<Account<'info, Voucher>>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"voucher_use", voucher_use.voucher.as_ref(), customer.key().as_ref()],
+        bump = voucher_use.bump,
+        constraint = voucher_use.delivery == other_delivery.key() @ ErrorCode::VoucherAccountsRequired,
+    )]
+    pub voucher_use: Option<Account<'info, VoucherUse>>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"escrow", customer.key().as_ref(), &other_delivery_id.to_le_bytes()],
+        bump = other_escrow.bump,
+        constraint = other_escrow.delivery == other_delivery.key() @ ErrorCode::InvalidEscrow
+    )]
+    pub other_escrow: Account<'info, Escrow>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(other_delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct BoostPriority<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Option<Account<'info, Region>>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct TopUpEscrow<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CancelDelivery<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    #[account(
+        mut,
+        seeds = [b"voucher", voucher.code_hash.as_ref()],
+        bump = voucher.bump,
+    )]
+    pub voucher: Option<Account<'info, Voucher>>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"voucher_use", voucher_use.voucher.as_ref(), customer.key().as_ref()],
+        bump = voucher_use.bump,
+        constraint = voucher_use.delivery == delivery.key() @ ErrorCode::VoucherAccountsRequired,
+    )]
+    pub voucher_use: Option<Account<'info, VoucherUse>>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ExpireDelivery<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    #[account(
+        mut,
+        seeds = [b"voucher", voucher.code_hash.as_ref()],
+        bump = voucher.bump,
+    )]
+    pub voucher: Option<Account<'info, Voucher>>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"voucher_use", voucher_use.voucher.as_ref(), customer.key().as_ref()],
+        bump = voucher_use.bump,
+        constraint = voucher_use.delivery == delivery.key() @ ErrorCode::VoucherAccountsRequired,
+    )]
+    pub voucher_use: Option<Account<'info, VoucherUse>>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: Customer receiving the refund; bound by the delivery and escrow seeds
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = caller,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CloseDelivery<'info> {
+    /// CHECK: Finished order, read in the handler and shrunk to a ClosedDelivery marker
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+    )]
+    pub delivery: UncheckedAccount<'info>,
+    // Route of an order that never settled; a settled order's route is
+    // already closed
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"route", delivery.key().as_ref()],
+        bump = route.bump,
+    )]
+    pub route: Option<Account<'info, Route>>,
+    /// CHECK: Customer who paid the rent; bound by the delivery seeds
+    #[account(mut)]
+    pub customer: UncheckedAccount<'info>,
+    // Customer, or any keeper once the grace period has passed
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct QueueDelivery<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    #[account(
+        mut,
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct AcceptDelivery<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    #[account(
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"customer", delivery.customer.as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"dispatcher_key", vehicle.operator.as_ref(), operator.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct AssignDelivery<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    #[account(
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"customer", delivery.customer.as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(signer.key(), role.as_deref(), RoleKind::Dispatcher) @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Dispatcher as u8], signer.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    // Authority, the configured surge dispatcher or a Dispatcher role holder
+    pub signer: Signer<'info>,
+    // The vehicle operator's consent to be dispatched by the signer
+    #[account(
+        seeds = [b"dispatcher_key", vehicle.operator.as_ref(), signer.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct StartAuction<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct PlaceBid<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        init_if_needed,
+        seeds = [b"bid", delivery.key().as_ref(), vehicle.key().as_ref()],
+        bump,
+        payer = operator,
+        space = 8 + Bid::LEN
+    )]
+    pub bid: Account<'info, Bid>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::NotVehicleOperator
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct FinalizeAuction<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    #[account(
+        mut,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    // Lowest bidder; omitted when nobody bid, or to reopen the order once the
+    // award window has passed
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Option<Account<'info, Vehicle>>,
+    // Matched against the winner's geohash in the handler
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", geo_bucket.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    /// CHECK: Customer account for seed derivation and the price refund
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CloseBid<'info> {
+    #[account(
+        mut,
+        close = operator,
+        seeds = [b"bid", delivery.key().as_ref(), bid.vehicle.as_ref()],
+        bump = bid.bump,
+        has_one = operator,
+    )]
+    pub bid: Account<'info, Bid>,
+    /// CHECK: Order or its closed marker; the bid's seeds bind it to this address
+    #[account(owner = crate::ID)]
+    pub delivery: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct AcceptNext<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::NotVehicleOperator
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    #[account(
+        mut,
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"customer", delivery.customer.as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CompleteDelivery<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        mut,
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    /// CHECK: Verified against fleet.payout_destination in the handler
+    #[account(mut)]
+    pub fleet_payout: Option<UncheckedAccount<'info>>,
+    /// CHECK: Vehicle operator the settlement pays, through its earnings
+    #[account(constraint = vehicle_operator.key() == vehicle.operator @ ErrorCode::Unauthorized)]
+    pub vehicle_operator: AccountInfo<'info>,
+    // Operator, its session key or a dispatcher key allowed to complete;
+    // pays the rent of any account the settlement opens
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [b"dispatcher_key", vehicle.operator.as_ref(), signer.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+    #[account(
+        init_if_needed,
+        seeds = [b"earnings", vehicle.operator.as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + Earnings::LEN
+    )]
+    pub earnings: Account<'info, Earnings>,
+    #[account(
+        init_if_needed,
+        seeds = [b"vehicle_history", vehicle.key().as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + VehicleHistory::LEN
+    )]
+    pub vehicle_history: Account<'info, VehicleHistory>,
+    #[account(
+        init,
+        seeds = [b"receipt", delivery.key().as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + Receipt::LEN
+    )]
+    pub receipt: Account<'info, Receipt>,
+    #[account(
+        mut,
+        seeds = [b"operator", vehicle.operator.as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Account<'info, OperatorProfile>,
+    #[account(
+        mut,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = signer,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = signer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        mut,
+        seeds = [b"tax_vault", config.key().as_ref()],
+        bump = tax_vault.bump,
+    )]
+    pub tax_vault: Account<'info, TaxVault>,
+    /// CHECK: Checked against the charity each order recorded at booking
+    #[account(mut)]
+    pub charity: Option<UncheckedAccount<'info>>,
+    #[account(
+        mut,
+        seeds = [b"insurance_pool", config.key().as_ref()],
+        bump = insurance_pool.bump,
+    )]
+    pub insurance_pool: Option<Account<'info, InsurancePool>>,
+    #[account(
+        mut,
+        seeds = [b"referral", customer.key().as_ref()],
+        bump = customer_referral.bump,
+    )]
+    pub customer_referral: Option<Account<'info, Referral>>,
+    #[account(
+        mut,
+        seeds = [b"referral", vehicle.operator.as_ref()],
+        bump = operator_referral.bump,
+    )]
+    pub operator_referral: Option<Account<'info, Referral>>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"manifest", delivery.key().as_ref()],
+        bump = manifest.bump,
+    )]
+    pub manifest: Option<Account<'info, PackageManifest>>,
+    // Closed with the settlement, returning its rent to the customer
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"route", delivery.key().as_ref()],
+        bump = route.bump,
+    )]
+    pub route: Option<Account<'info, Route>>,
+    // Pyth SOL/USD price update, required to settle a USD-priced order
+    pub pyth_price: Option<Account<'info, PriceUpdateV2>>,
+    /// CHECK: Receipt NFT mint at ["receipt_nft", delivery], created in the handler
+    #[account(mut)]
+    pub receipt_mint: Option<UncheckedAccount<'info>>,
+    /// CHECK: Customer's associated token account for the receipt NFT, created by the associated token program
+    #[account(mut)]
+    pub receipt_token: Option<UncheckedAccount<'info>>,
+    /// CHECK: Metaplex metadata account, derived and checked by the token metadata program
+    #[account(mut)]
+    pub receipt_metadata: Option<UncheckedAccount<'info>>,
+    /// CHECK: Metaplex master edition account, derived and checked by the token metadata program
+    #[account(mut)]
+    pub receipt_edition: Option<UncheckedAccount<'info>>,
+    pub token_metadata_program: Option<Program<'info, Metadata>>,
+    pub token_program: Option<Program<'info, Token>>,
+    pub associated_token_program: Option<Program<'info, AssociatedToken>>,
+    pub rent: Option<Sysvar<'info, Rent>>,
+    /// CHECK: Platform receipt tree, pinned to config.receipt_tree and checked by Bubblegum
+    #[account(
+        mut,
+        constraint = receipt_tree.key() == config.receipt_tree @ ErrorCode::InvalidReceiptTree
+    )]
+    pub receipt_tree: Option<UncheckedAccount<'info>>,
+    /// CHECK: Bubblegum tree config PDA, derived and checked by Bubblegum
+    #[account(mut)]
+    pub receipt_tree_config: Option<UncheckedAccount<'info>>,
+    /// CHECK: Bubblegum program
+    #[account(address = mpl_bubblegum::ID)]
+    pub bubblegum_program: Option<UncheckedAccount<'info>>,
+    /// CHECK: SPL account compression program
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: Option<UncheckedAccount<'info>>,
+    /// CHECK: SPL noop program Bubblegum logs leaves through
+    #[account(address = SPL_NOOP_ID)]
+    pub log_wrapper: Option<UncheckedAccount<'info>>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    /// CHECK: Customer account for seed derivation and late-penalty refunds
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(region_id: u16)]
+pub struct CompleteDeliveriesBatch<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        mut,
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    /// CHECK: Verified against fleet.payout_destination in the handler
+    #[account(mut)]
+    pub fleet_payout: Option<UncheckedAccount<'info>>,
+    // Operator, its session key or a dispatcher key allowed to complete;
+    // payouts still go to the operator's earnings
+    #[account(mut)]
+    pub vehicle_operator: Signer<'info>,
+    #[account(
+        seeds = [b"dispatcher_key", vehicle.operator.as_ref(), vehicle_operator.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+    #[account(
+        init_if_needed,
+        seeds = [b"earnings", vehicle.operator.as_ref()],
+        bump,
+        payer = vehicle_operator,
+        space = 8 + Earnings::LEN
+    )]
+    pub earnings: Account<'info, Earnings>,
+    #[account(
+        init_if_needed,
+        seeds = [b"vehicle_history", vehicle.key().as_ref()],
+        bump,
+        payer = vehicle_operator,
+        space = 8 + VehicleHistory::LEN
+    )]
+    pub vehicle_history: Account<'info, VehicleHistory>,
+    #[account(
+        mut,
+        seeds = [b"operator", vehicle.operator.as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Account<'info, OperatorProfile>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = vehicle_operator,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(region_id as u64).to_le_bytes()],
+        bump,
+        payer = vehicle_operator,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        mut,
+        seeds = [b"tax_vault", config.key().as_ref()],
+        bump = tax_vault.bump,
+    )]
+    pub tax_vault: Account<'info, TaxVault>,
+    /// CHECK: Checked against the charity each order recorded at booking
+    #[account(mut)]
+    pub charity: Option<UncheckedAccount<'info>>,
+    #[account(
+        mut,
+        seeds = [b"insurance_pool", config.key().as_ref()],
+        bump = insurance_pool.bump,
+    )]
+    pub insurance_pool: Option<Account<'info, InsurancePool>>,
+    #[account(
+        mut,
+        seeds = [b"referral", vehicle.operator.as_ref()],
+        bump = operator_referral.bump,
+    )]
+    pub operator_referral: Option<Account<'info, Referral>>,
+    // Pyth SOL/USD price update, required when the batch holds a USD-priced order
+    pub pyth_price: Option<Account<'info, PriceUpdateV2>>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct RateDelivery<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::InvalidParameter
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct TipOperator<'info> {
+    #[account(
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::InvalidParameter
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"operator", vehicle.operator.as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Account<'info, OperatorProfile>,
+    /// CHECK: Verified against vehicle.operator
+    #[account(
+        mut,
+        constraint = vehicle_operator.key() == vehicle.operator @ ErrorCode::Unauthorized
+    )]
+    pub vehicle_operator: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct TipOperatorSpl<'info> {
+    #[account(
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+        constraint = delivery.payment_mint != Pubkey::default() @ ErrorCode::InvalidMint
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::InvalidParameter
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"operator", vehicle.operator.as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Account<'info, OperatorProfile>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        token::authority = customer,
+    )]
+    pub customer_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        constraint = operator_token.owner == vehicle.operator @ ErrorCode::Unauthorized
+    )]
+    pub operator_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        constraint = treasury_token.owner == config.treasury @ ErrorCode::InvalidTreasury
+    )]
+    pub treasury_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = delivery.payment_mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    pub customer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CreateDeliveryOrderSpl<'info> {
+    #[account(
+        init,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + Delivery::LEN
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        init,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = customer,
+        token::mint = mint,
+        token::authority = escrow_token,
+        token::token_program = token_program,
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = customer,
+    )]
+    pub customer_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = mint.key() == config.accepted_mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + CustomerProfile::LEN
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    #[account(
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CompleteDeliverySpl<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        token::mint = delivery.payment_mint,
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        mut,
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"manifest", delivery.key().as_ref()],
+        bump = manifest.bump,
+    )]
+    pub manifest: Option<Account<'info, PackageManifest>>,
+    #[account(
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        init,
+        seeds = [b"receipt", delivery.key().as_ref()],
+        bump,
+        payer = vehicle_operator,
+        space = 8 + Receipt::LEN
+    )]
+    pub receipt: Account<'info, Receipt>,
+    #[account(
+        mut,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        mut,
+        seeds = [b"operator", vehicle.operator.as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Account<'info, OperatorProfile>,
+    #[account(mut, constraint = vehicle_operator.key() == vehicle.operator @ ErrorCode::Unauthorized)]
+    pub vehicle_operator: Signer<'info>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        token::authority = vehicle_operator,
+    )]
+    pub operator_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        constraint = treasury_token.owner == config.treasury @ ErrorCode::InvalidTreasury
+    )]
+    pub treasury_token: InterfaceAccount<'info, TokenAccount>,
+    // Receives the late penalty, voucher discount and leftover dust
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        token::authority = customer,
+    )]
+    pub customer_token: InterfaceAccount<'info, TokenAccount>,
+    // Owned by the fleet's payout destination; needed when the fleet takes a share
+    #[account(mut, token::mint = delivery.payment_mint)]
+    pub fleet_token: Option<InterfaceAccount<'info, TokenAccount>>,
+    // Owned by the insurance pool PDA; needed when the pool takes a slice
+    #[account(mut, token::mint = delivery.payment_mint)]
+    pub insurance_token: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = delivery.payment_mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: Customer account for seed derivation and escrow rent refund
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = vehicle_operator,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = vehicle_operator,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CancelDeliverySpl<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        token::mint = delivery.payment_mint,
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        token::authority = customer,
+    )]
+    pub customer_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = delivery.payment_mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ExpireDeliverySpl<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        token::mint = delivery.payment_mint,
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        token::authority = customer,
+    )]
+    pub customer_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = delivery.payment_mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: Customer receiving the escrow rent; bound by the delivery and escrow seeds
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = caller,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CreateDeliveryOrderConfidential<'info> {
+    #[account(
+        init,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + Delivery::LEN
+    )]
+    pub delivery: Account<'info, Delivery>,
+    /// CHECK: Created, initialized and configured for confidential transfers in the handler
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+    )]
+    pub escrow_token: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = customer,
+    )]
+    pub customer_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = mint.key() == config.accepted_mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Context account with the verified validity proof of the escrow's ElGamal key, checked by Token-2022
+    pub pubkey_validity_proof: UncheckedAccount<'info>,
+    /// CHECK: Context account with the customer's verified equality proof, checked by Token-2022
+    pub equality_proof: UncheckedAccount<'info>,
+    /// CHECK: Context account with the customer's verified ciphertext validity proof, checked by Token-2022
+    pub ciphertext_validity_proof: UncheckedAccount<'info>,
+    /// CHECK: Context account with the customer's verified range proof, checked by Token-2022
+    pub range_proof: UncheckedAccount<'info>,
+    /// CHECK: Context account with the prover's ciphertext-commitment equality proof, checked in the handler
+    pub commitment_proof: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + CustomerProfile::LEN
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    #[account(
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CompleteDeliveryConfidential<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    /// CHECK: Confidential escrow token account, validated by Token-2022 on every call
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+    )]
+    pub escrow_token: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        mut,
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"manifest", delivery.key().as_ref()],
+        bump = manifest.bump,
+    )]
+    pub manifest: Option<Account<'info, PackageManifest>>,
+    #[account(
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        init,
+        seeds = [b"receipt", delivery.key().as_ref()],
+        bump,
+        payer = prover,
+        space = 8 + Receipt::LEN
+    )]
+    pub receipt: Account<'info, Receipt>,
+    #[account(
+        mut,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        mut,
+        seeds = [b"operator", vehicle.operator.as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Account<'info, OperatorProfile>,
+    #[account(address = delivery.payment_mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Context account with the verified zero-balance proof for the emptied escrow
+    pub zero_balance_proof: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut, constraint = prover.key() == config.confidential_prover @ ErrorCode::Unauthorized)]
+    pub prover: Signer<'info>,
+    /// CHECK: Customer account for seed derivation and escrow rent refund
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    pub token_program: Program<'info, Token2022>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = prover,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = prover,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CancelDeliveryConfidential<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    /// CHECK: Confidential escrow token account, validated by Token-2022 on every call
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+    )]
+    pub escrow_token: UncheckedAccount<'info>,
+    // Checked against the order's mint in the handler
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Context account with the verified zero-balance proof for the emptied escrow
+    pub zero_balance_proof: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub token_program: Program<'info, Token2022>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ExpireDeliveryConfidential<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"open_orders", &delivery.region_id.to_le_bytes(), &delivery.listed_page.to_le_bytes()],
+        bump = open_orders.bump,
+    )]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    /// CHECK: Confidential escrow token account, validated by Token-2022 on every call
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+    )]
+    pub escrow_token: UncheckedAccount<'info>,
+    // Checked against the order's mint in the handler
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Context account with the verified zero-balance proof for the emptied escrow
+    pub zero_balance_proof: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"region", &delivery.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: Customer receiving the refund and escrow rent; bound by the delivery and escrow seeds
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    pub token_program: Program<'info, Token2022>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = caller,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositWallet<'info> {
+    #[account(
+        init_if_needed,
+        seeds = [b"wallet", owner.key().as_ref()],
+        bump,
+        payer = owner,
+        space = 8 + PrepaidWallet::LEN
+    )]
+    pub wallet: Account<'info, PrepaidWallet>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawWallet<'info> {
+    #[account(
+        mut,
+        seeds = [b"wallet", owner.key().as_ref()],
+        bump = wallet.bump,
+        has_one = owner,
+    )]
+    pub wallet: Account<'info, PrepaidWallet>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(standing_order_id: u64, pickup_location: String, delivery_location: String, region_id: u16)]
+pub struct CreateStandingOrder<'info> {
+    #[account(
+        init,
+        seeds = [b"standing_order", customer.key().as_ref(), &standing_order_id.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + StandingOrder::LEN
+    )]
+    pub standing_order: Account<'info, StandingOrder>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    // Matches are booked in this region, so it must exist up front
+    #[account(
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(standing_order_id: u64)]
+pub struct CancelStandingOrder<'info> {
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"standing_order", customer.key().as_ref(), &standing_order_id.to_le_bytes()],
+        bump = standing_order.bump,
+    )]
+    pub standing_order: Account<'info, StandingOrder>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(standing_order_id: u64, delivery_id: u64)]
+pub struct AutoMatch<'info> {
+    #[account(
+        mut,
+        seeds = [b"standing_order", customer.key().as_ref(), &standing_order_id.to_le_bytes()],
+        bump = standing_order.bump,
+    )]
+    pub standing_order: Account<'info, StandingOrder>,
+    #[account(
+        mut,
+        seeds = [b"wallet", customer.key().as_ref()],
+        bump = wallet.bump,
+    )]
+    pub wallet: Account<'info, PrepaidWallet>,
+    #[account(
+        init,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = operator,
+        space = 8 + Delivery::LEN
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        init,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = operator,
+        space = 8 + Escrow::LEN
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        init_if_needed,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump,
+        payer = operator,
+        space = 8 + CustomerProfile::LEN
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = operator,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = operator,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"region", &standing_order.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"price_feed", b"USD"],
+        bump = usd_feed.bump,
+    )]
+    pub usd_feed: Account<'info, PriceFeed>,
+    #[account(
+        seeds = [b"price_feed", b"EUR"],
+        bump = eur_feed.bump,
+    )]
+    pub eur_feed: Option<Account<'info, PriceFeed>>,
+    /// CHECK: Customer account for seed derivation
+    pub customer: AccountInfo<'info>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(subscription_id: u64)]
+pub struct CreateSubscription<'info> {
+    #[account(
+        init,
+        seeds = [b"subscription", customer.key().as_ref(), &subscription_id.to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + Subscription::LEN
+    )]
+    pub subscription: Account<'info, Subscription>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(subscription_id: u64)]
+pub struct FundSubscription<'info> {
+    #[account(
+        mut,
+        seeds = [b"subscription", customer.key().as_ref(), &subscription_id.to_le_bytes()],
+        bump = subscription.bump,
+    )]
+    pub subscription: Account<'info, Subscription>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(subscription_id: u64)]
+pub struct CancelSubscription<'info> {
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"subscription", customer.key().as_ref(), &subscription_id.to_le_bytes()],
+        bump = subscription.bump,
+    )]
+    pub subscription: Account<'info, Subscription>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(subscription_id: u64, delivery_id: u64)]
+pub struct SpawnNextOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"subscription", customer.key().as_ref(), &subscription_id.to_le_bytes()],
+        bump = subscription.bump,
+    )]
+    pub subscription: Account<'info, Subscription>,
+    #[account(
+        init,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = cranker,
+        space = 8 + Delivery::LEN
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        init,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        payer = cranker,
+        space = 8 + Escrow::LEN
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        init_if_needed,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump,
+        payer = cranker,
+        space = 8 + CustomerProfile::LEN
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = cranker,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = cranker,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"region", &subscription.region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"price_feed", b"USD"],
+        bump = usd_feed.bump,
+    )]
+    pub usd_feed: Account<'info, PriceFeed>,
+    #[account(
+        seeds = [b"price_feed", b"EUR"],
+        bump = eur_feed.bump,
+    )]
+    pub eur_feed: Option<Account<'info, PriceFeed>>,
+    /// CHECK: Customer account for seed derivation
+    pub customer: AccountInfo<'info>,
+    // Anyone may crank a due subscription
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct FinalizeStatement<'info> {
+    #[account(
+        init,
+        seeds = [b"statement", operator_profile.operator.as_ref(), &epoch.to_le_bytes()],
+        bump,
+        payer = payer,
+        space = 8 + Statement::LEN
+    )]
+    pub statement: Account<'info, Statement>,
+    #[account(
+        mut,
+        seeds = [b"operator", operator_profile.operator.as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Account<'info, OperatorProfile>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RecordDistance<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct RollRevenueEpoch<'info> {
+    #[account(
+        init,
+        seeds = [b"revenue_epoch", config.key().as_ref(), &epoch.to_le_bytes()],
+        bump,
+        payer = payer,
+        space = 8 + RevenueEpoch::LEN
+    )]
+    pub revenue_epoch: Account<'info, RevenueEpoch>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTax<'info> {
+    #[account(
+        mut,
+        seeds = [b"tax_vault", config.key().as_ref()],
+        bump = tax_vault.bump,
+    )]
+    pub tax_vault: Account<'info, TaxVault>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(authority.key(), role.as_deref(), RoleKind::Treasurer) @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Treasurer as u8], authority.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    /// CHECK: Tax authority wallet receiving the remittance
+    #[account(mut)]
+    pub destination: AccountInfo<'info>,
+    // Authority or a Treasurer role holder
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(authority.key(), role.as_deref(), RoleKind::Treasurer) @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Treasurer as u8], authority.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    /// CHECK: Any wallet the treasurer pays revenue out to
+    #[account(mut)]
+    pub destination: AccountInfo<'info>,
+    // Authority or a Treasurer role holder
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DistributeFeeShare<'info> {
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: Must be the wallet configured for the slot; checked in the handler
+    #[account(mut)]
+    pub recipient: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct SetConfirmationMode<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct SetDeliveryMode<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct SetDeliverySla<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct SetMilestones<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ReleaseMilestone<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"escrow", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::Unauthorized,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        init_if_needed,
+        seeds = [b"earnings", vehicle.operator.as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + Earnings::LEN
+    )]
+    pub earnings: Account<'info, Earnings>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    // Dispatcher key of the signer, when it is neither the operator nor its
+    // session key
+    #[account(
+        seeds = [b"dispatcher_key", vehicle.operator.as_ref(), signer.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+    // Operator, its session key or a dispatcher key with the permission
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct SetRoute<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        init,
+        seeds = [b"route", delivery.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + Route::LEN
+    )]
+    pub route: Account<'info, Route>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CompleteStop<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"route", delivery.key().as_ref()],
+        bump = route.bump,
+    )]
+    pub route: Account<'info, Route>,
+    #[account(
+        mut,
+        seeds = [b"escrow", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::Unauthorized,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        init_if_needed,
+        seeds = [b"earnings", vehicle.operator.as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + Earnings::LEN
+    )]
+    pub earnings: Account<'info, Earnings>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    // Dispatcher key of the signer, when it is neither the operator nor its
+    // session key
+    #[account(
+        seeds = [b"dispatcher_key", vehicle.operator.as_ref(), signer.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+    // Operator, its session key or a dispatcher key with the permission
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ConfirmDelivery<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ConfirmWithPin<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct MarkDelivered<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ConfirmPickup<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    // Dispatcher key of the signer, when it is neither the operator nor its
+    // session key
+    #[account(
+        seeds = [b"dispatcher_key", vehicle.operator.as_ref(), signer.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+    // Operator, its session key or a dispatcher key with the permission
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct DeclarePackages<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        init,
+        seeds = [b"manifest", delivery.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + PackageManifest::LEN
+    )]
+    pub manifest: Account<'info, PackageManifest>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct SettlePackage<'info> {
+    #[account(
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"manifest", delivery.key().as_ref()],
+        bump = manifest.bump,
+    )]
+    pub manifest: Account<'info, PackageManifest>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(oracle: Pubkey)]
+pub struct RegisterProofOracle<'info> {
+    #[account(
+        init,
+        seeds = [b"proof_oracle", oracle.as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + ProofOracle::LEN
+    )]
+    pub proof_oracle: Account<'info, ProofOracle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(oracle: Pubkey)]
+pub struct RemoveProofOracle<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"proof_oracle", oracle.as_ref()],
+        bump = proof_oracle.bump,
+    )]
+    pub proof_oracle: Account<'info, ProofOracle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct AttestDelivery<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        seeds = [b"proof_oracle", oracle.key().as_ref()],
+        bump = proof_oracle.bump,
+    )]
+    pub proof_oracle: Account<'info, ProofOracle>,
+    pub oracle: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct AttestArrival<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        seeds = [b"proof_oracle", oracle.key().as_ref()],
+        bump = proof_oracle.bump,
+    )]
+    pub proof_oracle: Account<'info, ProofOracle>,
+    pub oracle: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ClaimNoShow<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    /// CHECK: Operator receiving the deposit; bound to the vehicle
+    #[account(mut, address = vehicle.operator @ ErrorCode::Unauthorized)]
+    pub vehicle_operator: UncheckedAccount<'info>,
+    // Operator, its session key or a dispatcher key allowed to cancel
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [b"dispatcher_key", vehicle.operator.as_ref(), signer.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: Sender receiving the escrow refund
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = signer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct OpenDispute<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        init,
+        seeds = [b"dispute", delivery.key().as_ref()],
+        bump,
+        payer = party,
+        space = 8 + Dispute::LEN
+    )]
+    pub dispute: Account<'info, Dispute>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"customer", delivery.customer.as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(mut)]
+    pub party: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct SubmitEvidence<'info> {
+    #[account(
+        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"dispute", delivery.key().as_ref()],
+        bump = dispute.bump,
+    )]
+    pub dispute: Account<'info, Dispute>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::InvalidParameter
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub party: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ResolveDispute<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        close = dispute_opener,
+        seeds = [b"dispute", delivery.key().as_ref()],
+        bump = dispute.bump,
+    )]
+    pub dispute: Account<'info, Dispute>,
+    /// CHECK: Party that opened the dispute and gets its rent back; bound by the dispute
+    #[account(mut, address = dispute.opened_by @ ErrorCode::InvalidParameter)]
+    pub dispute_opener: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::InvalidParameter
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"operator", vehicle.operator.as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Option<Account<'info, OperatorProfile>>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        mut,
+        seeds = [b"stake", vehicle.key().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Option<Account<'info, VehicleStake>>,
+    #[account(
+        mut,
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    /// CHECK: Verified against fleet.payout_destination in the handler
+    #[account(mut)]
+    pub fleet_payout: Option<UncheckedAccount<'info>>,
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        mut,
+        seeds = [b"tax_vault", config.key().as_ref()],
+        bump = tax_vault.bump,
+    )]
+    pub tax_vault: Account<'info, TaxVault>,
+    /// CHECK: Checked against the charity each order recorded at booking
+    #[account(mut)]
+    pub charity: Option<UncheckedAccount<'info>>,
+    #[account(
+        mut,
+        seeds = [b"insurance_pool", config.key().as_ref()],
+        bump = insurance_pool.bump,
+    )]
+    pub insurance_pool: Option<Account<'info, InsurancePool>>,
+    #[account(
+        mut,
+        seeds = [b"referral", customer.key().as_ref()],
+        bump = customer_referral.bump,
+    )]
+    pub customer_referral: Option<Account<'info, Referral>>,
+    #[account(
+        mut,
+        seeds = [b"referral", vehicle.operator.as_ref()],
+        bump = operator_referral.bump,
+    )]
+    pub operator_referral: Option<Account<'info, Referral>>,
+    #[account(
+        init_if_needed,
+        seeds = [b"earnings", vehicle.operator.as_ref()],
+        bump,
+        payer = resolver,
+        space = 8 + Earnings::LEN
+    )]
+    pub earnings: Account<'info, Earnings>,
+    /// CHECK: Customer receiving their share; bound by the delivery and escrow seeds
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Arbiter as u8], resolver.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    // Configured arbiter or an Arbiter role holder; anyone once the dispute
+    // has timed out
+    #[account(mut)]
+    pub resolver: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = resolver,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = resolver,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ResolveDisputeSpl<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        token::mint = delivery.payment_mint,
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        close = dispute_opener,
+        seeds = [b"dispute", delivery.key().as_ref()],
+        bump = dispute.bump,
+    )]
+    pub dispute: Account<'info, Dispute>,
+    /// CHECK: Party that opened the dispute and gets its rent back; bound by the dispute
+    #[account(mut, address = dispute.opened_by @ ErrorCode::InvalidParameter)]
+    pub dispute_opener: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::InvalidParameter
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"operator", vehicle.operator.as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Option<Account<'info, OperatorProfile>>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        mut,
+        seeds = [b"stake", vehicle.key().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Option<Account<'info, VehicleStake>>,
+    #[account(
+        mut,
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        constraint = operator_token.owner == vehicle.operator @ ErrorCode::Unauthorized
+    )]
+    pub operator_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        constraint = treasury_token.owner == config.treasury @ ErrorCode::InvalidTreasury
+    )]
+    pub treasury_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        token::authority = customer,
+    )]
+    pub customer_token: InterfaceAccount<'info, TokenAccount>,
+    // Owned by the fleet's payout destination; needed when the fleet takes a share
+    #[account(mut, token::mint = delivery.payment_mint)]
+    pub fleet_token: Option<InterfaceAccount<'info, TokenAccount>>,
+    // Owned by the insurance pool PDA; needed when the pool takes a slice
+    #[account(mut, token::mint = delivery.payment_mint)]
+    pub insurance_token: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = delivery.payment_mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Customer receiving their share and the escrow rent; bound by the delivery and escrow seeds
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Arbiter as u8], resolver.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    // Configured arbiter or an Arbiter role holder; anyone once the dispute
+    // has timed out
+    #[account(mut)]
+    pub resolver: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = resolver,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = resolver,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct ResolveDisputeConfidential<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    /// CHECK: Confidential escrow token account, validated by Token-2022 on every call
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+    )]
+    pub escrow_token: UncheckedAccount<'info>,
+    // Checked against the order's mint in the handler
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Context account with the verified zero-balance proof for the emptied escrow
+    pub zero_balance_proof: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        close = dispute_opener,
+        seeds = [b"dispute", delivery.key().as_ref()],
+        bump = dispute.bump,
+    )]
+    pub dispute: Account<'info, Dispute>,
+    /// CHECK: Party that opened the dispute and gets its rent back; bound by the dispute
+    #[account(mut, address = dispute.opened_by @ ErrorCode::InvalidParameter)]
+    pub dispute_opener: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump = customer_profile.bump,
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::InvalidParameter
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"operator", vehicle.operator.as_ref()],
+        bump = operator_profile.bump,
+    )]
+    pub operator_profile: Option<Account<'info, OperatorProfile>>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        mut,
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub fleet: Option<Account<'info, Fleet>>,
+    #[account(
+        mut,
+        seeds = [b"stake", vehicle.key().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Option<Account<'info, VehicleStake>>,
+    /// CHECK: Customer receiving their share and the escrow rent; bound by the delivery and escrow seeds
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Arbiter as u8], resolver.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    // Configured arbiter or an Arbiter role holder; anyone once the dispute
+    // has timed out
+    #[account(mut)]
+    pub resolver: Signer<'info>,
+    pub token_program: Program<'info, Token2022>,
+    #[account(
+        init_if_needed,
+        seeds = [b"daily", &DailyStats::current_day()?.to_le_bytes()],
+        bump,
+        payer = resolver,
+        space = 8 + DailyStats::LEN
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = resolver,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct FileClaim<'info> {
+    #[account(
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        init,
+        seeds = [b"claim", delivery.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + InsuranceClaim::LEN
+    )]
+    pub claim: Account<'info, InsuranceClaim>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PayClaim<'info> {
+    #[account(
+        mut,
+        seeds = [b"claim", claim.delivery.as_ref()],
+        bump = claim.bump,
+        has_one = customer,
+    )]
+    pub claim: Account<'info, InsuranceClaim>,
+    #[account(
+        mut,
+        seeds = [b"insurance_pool", config.key().as_ref()],
+        bump = insurance_pool.bump,
+    )]
+    pub insurance_pool: Account<'info, InsurancePool>,
+    /// CHECK: Customer who filed the claim; bound by has_one on the claim
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(arbiter.key(), role.as_deref(), RoleKind::Arbiter) @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"role", &[RoleKind::Arbiter as u8], arbiter.key().as_ref()],
+        bump = role.bump,
+    )]
+    pub role: Option<Account<'info, Role>>,
+    // Configured arbiter or an Arbiter role holder
+    pub arbiter: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct CloseClaim<'info> {
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"claim", delivery.key().as_ref()],
+        bump = claim.bump,
+        has_one = customer,
+        constraint = claim.status != ClaimStatus::Filed @ ErrorCode::ClaimStillOpen,
+    )]
+    pub claim: Account<'info, InsuranceClaim>,
+    /// CHECK: Order the claim was filed on, checked in the handler to be a ClosedDelivery marker
+    #[account(
+        owner = crate::ID,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+    )]
+    pub delivery: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReportCheckpoint<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetAway<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TransferVehicleOperator<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeSessionKey<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterSessionKey<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMinCustomerScore<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxActiveOrders<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SyncOperatorFromNft<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        constraint = Some(ownership_token.mint) == vehicle.ownership_mint @ ErrorCode::InvalidOwnershipNft,
+        constraint = ownership_token.amount == 1 @ ErrorCode::InvalidOwnershipNft,
+    )]
+    pub ownership_token: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct VehicleSession<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    // Dispatcher key of the signer, when it is neither the operator nor its
+    // session key
+    #[account(
+        seeds = [b"dispatcher_key", vehicle.operator.as_ref(), signer.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+    // Operator, its session key or a dispatcher key with the permission
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(position: GeoPoint)]
+pub struct UpdateVehicleLocation<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = old_bucket.bump,
+    )]
+    pub old_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", position.geohash().as_ref()],
+        bump = new_bucket.bump,
+    )]
+    pub new_bucket: Option<Account<'info, GeoBucket>>,
+    // Dispatcher key of the signer, when it is neither the operator nor its
+    // session key
+    #[account(
+        seeds = [b"dispatcher_key", vehicle.operator.as_ref(), signer.key().as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Option<Account<'info, DispatcherKey>>,
+    // Operator, its session key or a dispatcher key with the permission
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeTreasury<'info> {
+    #[account(
+        init,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump,
+        payer = payer,
+        space = 8 + Treasury::LEN
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(vehicle_id: String)]
+pub struct MigrateVehicleLocation<'info> {
+    /// CHECK: May still have an older, shorter layout; owner and discriminator
+    /// are checked when the handler deserializes it
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle_id.as_bytes()],
+        bump,
+    )]
+    pub vehicle: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(operator: Pubkey)]
+pub struct MigrateOperatorProfile<'info> {
+    /// CHECK: May still have an older, shorter layout; grow_account checks
+    /// the owner and the zeroed tail reads back as no dispute losses
+    #[account(
+        mut,
+        seeds = [b"operator", operator.as_ref()],
+        bump,
+    )]
+    pub operator_profile: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(customer: Pubkey, delivery_id: u64)]
+pub struct MigrateDeliveryLocations<'info> {
+    /// CHECK: May still have an older, shorter layout; owner and discriminator
+    /// are checked when the handler deserializes it
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+    )]
+    pub delivery: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetVehicleActive<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(fleet_id: u32)]
+pub struct CreateFleet<'info> {
+    #[account(
+        init,
+        seeds = [b"fleet", manager.key().as_ref(), &fleet_id.to_le_bytes()],
+        bump,
+        payer = manager,
+        space = 8 + Fleet::LEN
+    )]
+    pub fleet: Account<'info, Fleet>,
+    #[account(mut)]
+    pub manager: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterVehicleToFleet<'info> {
+    #[account(
+        mut,
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        has_one = manager @ ErrorCode::Unauthorized,
+    )]
+    pub fleet: Account<'info, Fleet>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::NotVehicleOperator
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub manager: Signer<'info>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveVehicleFromFleet<'info> {
+    #[account(
+        mut,
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+    )]
+    pub fleet: Account<'info, Fleet>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFleet<'info> {
+    #[account(
+        mut,
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+        has_one = manager @ ErrorCode::Unauthorized,
+    )]
+    pub fleet: Account<'info, Fleet>,
+    pub manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptFleetShare<'info> {
+    #[account(
+        seeds = [b"fleet", fleet.manager.as_ref(), &fleet.fleet_id.to_le_bytes()],
+        bump = fleet.bump,
+    )]
+    pub fleet: Account<'info, Fleet>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.fleet == Some(fleet.key()) @ ErrorCode::InvalidFleet,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::NotVehicleOperator
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct StakeVehicle<'info> {
+    #[account(
+        init_if_needed,
+        seeds = [b"stake", vehicle.key().as_ref()],
+        bump,
+        payer = operator,
+        space = 8 + VehicleStake::LEN
+    )]
+    pub stake: Account<'info, VehicleStake>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::NotVehicleOperator
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RequestUnstake<'info> {
+    #[account(
+        mut,
+        seeds = [b"stake", vehicle.key().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Account<'info, VehicleStake>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::NotVehicleOperator
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawStake<'info> {
+    #[account(
+        mut,
+        seeds = [b"stake", vehicle.key().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Account<'info, VehicleStake>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::NotVehicleOperator
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DeregisterVehicle<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::NotVehicleOperator
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"stake", vehicle.key().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Option<Account<'info, VehicleStake>>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: Receives the vehicle account's rent, which the authority paid at registration
+    #[account(
+        mut,
+        constraint = authority.key() == config.authority @ ErrorCode::Unauthorized
+    )]
+    pub authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct AbandonDelivery<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = escrow.bump,
+        has_one = delivery,
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::InvalidParameter
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"stake", vehicle.key().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Option<Account<'info, VehicleStake>>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct AbandonDeliverySpl<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+        token::mint = delivery.payment_mint,
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = delivery.payment_mint,
+        token::authority = customer,
+    )]
+    pub customer_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = delivery.payment_mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::InvalidParameter
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"stake", vehicle.key().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Option<Account<'info, VehicleStake>>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delivery_id: u64)]
+pub struct AbandonDeliveryConfidential<'info> {
+    #[account(
+        mut,
+        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump = delivery.bump,
+    )]
+    pub delivery: Account<'info, Delivery>,
+    /// CHECK: Confidential escrow token account, validated by Token-2022 on every call
+    #[account(
+        mut,
+        seeds = [b"escrow_token", customer.key().as_ref(), &delivery_id.to_le_bytes()],
+        bump,
+    )]
+    pub escrow_token: UncheckedAccount<'info>,
+    // Checked against the order's mint in the handler
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Context account with the verified zero-balance proof for the emptied escrow
+    pub zero_balance_proof: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = delivery.assigned_vehicle == Some(vehicle.key()) @ ErrorCode::InvalidParameter
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"stake", vehicle.key().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Option<Account<'info, VehicleStake>>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = geo_bucket.bump,
+    )]
+    pub geo_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub token_program: Program<'info, Token2022>,
+    #[account(
+        init_if_needed,
+        seeds = [b"global_stats", &GlobalStats::shard_of(delivery_id).to_le_bytes()],
+        bump,
+        payer = customer,
+        space = 8 + GlobalStats::LEN
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenChannel<'info> {
+    #[account(
+        init,
+        seeds = [b"channel", customer.key().as_ref(), vehicle.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + PaymentChannel::LEN
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    #[account(
+        init_if_needed,
+        seeds = [b"channel_nonce", customer.key().as_ref(), vehicle.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + ChannelNonce::LEN
+    )]
+    pub channel_nonce: Account<'info, ChannelNonce>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleChannel<'info> {
+    #[account(
+        mut,
+        seeds = [b"channel", channel.customer.as_ref(), vehicle.key().as_ref()],
+        bump = channel.bump,
+        has_one = vehicle,
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::NotVehicleOperator
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        init_if_needed,
+        seeds = [b"earnings", vehicle.operator.as_ref()],
+        bump,
+        payer = operator,
+        space = 8 + Earnings::LEN
+    )]
+    pub earnings: Account<'info, Earnings>,
+    #[account(
+        mut,
+        seeds = [b"treasury", config.key().as_ref()],
+        bump = treasury.bump,
+    )]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: Instructions sysvar, read for the Ed25519 voucher check
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: AccountInfo<'info>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseChannel<'info> {
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"channel", customer.key().as_ref(), vehicle.key().as_ref()],
+        bump = channel.bump,
+        has_one = customer,
+        has_one = vehicle,
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    /// CHECK: Receives the unspent deposit; bound by the channel seeds
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct OpenChannelSpl<'info> {
+    #[account(
+        init,
+        seeds = [b"token_channel", customer.key().as_ref(), vehicle.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + TokenChannel::LEN
+    )]
+    pub channel: Account<'info, TokenChannel>,
+    #[account(
+        init_if_needed,
+        seeds = [b"channel_nonce", customer.key().as_ref(), vehicle.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + ChannelNonce::LEN
+    )]
+    pub channel_nonce: Account<'info, ChannelNonce>,
+    #[account(
+        init,
+        seeds = [b"channel_token", channel.key().as_ref()],
+        bump,
+        payer = customer,
+        token::mint = mint,
+        token::authority = channel_token,
+        token::token_program = token_program,
+    )]
+    pub channel_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = customer,
+    )]
+    pub customer_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = mint.key() == config.accepted_mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleChannelSpl<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_channel", channel.customer.as_ref(), vehicle.key().as_ref()],
+        bump = channel.bump,
+        has_one = vehicle,
+        has_one = mint,
+    )]
+    pub channel: Account<'info, TokenChannel>,
+    #[account(
+        mut,
+        seeds = [b"channel_token", channel.key().as_ref()],
+        bump,
+        token::mint = mint,
+    )]
+    pub channel_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::NotVehicleOperator
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        token::mint = mint,
+        constraint = operator_token.owner == vehicle.operator @ ErrorCode::Unauthorized
+    )]
+    pub operator_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        constraint = treasury_token.owner == config.treasury @ ErrorCode::InvalidTreasury
+    )]
+    pub treasury_token: InterfaceAccount<'info, TokenAccount>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    /// CHECK: Instructions sysvar, read for the Ed25519 voucher check
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: AccountInfo<'info>,
+    pub operator: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CloseChannelSpl<'info> {
+    #[account(
+        mut,
+        close = customer,
+        seeds = [b"token_channel", customer.key().as_ref(), vehicle.key().as_ref()],
+        bump = channel.bump,
+        has_one = customer,
+        has_one = vehicle,
+        has_one = mint,
+    )]
+    pub channel: Account<'info, TokenChannel>,
+    #[account(
+        mut,
+        seeds = [b"channel_token", channel.key().as_ref()],
+        bump,
+        token::mint = mint,
+    )]
+    pub channel_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = customer,
+    )]
+    pub customer_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    /// CHECK: Receives the vault and channel rent; bound by the channel seeds
+    #[account(mut)]
+    pub customer: AccountInfo<'info>,
+    pub signer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(geohash: [u8; GEOHASH_LEN])]
+pub struct UpdateGeoBucket<'info> {
+    #[account(
+        mut,
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"geo_bucket", vehicle.geohash.as_ref()],
+        bump = old_bucket.bump,
+    )]
+    pub old_bucket: Option<Account<'info, GeoBucket>>,
+    #[account(
+        init_if_needed,
+        seeds = [b"geo_bucket", geohash.as_ref()],
+        bump,
+        payer = operator,
+        space = 8 + GeoBucket::LEN
+    )]
+    pub new_bucket: Account<'info, GeoBucket>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(dispatcher: Pubkey)]
+pub struct AuthorizeDispatcherKey<'info> {
+    #[account(
+        init,
+        seeds = [b"dispatcher_key", operator.key().as_ref(), dispatcher.as_ref()],
+        bump,
+        payer = operator,
+        space = 8 + DispatcherKey::LEN
+    )]
+    pub dispatcher_key: Account<'info, DispatcherKey>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(dispatcher: Pubkey)]
+pub struct RevokeDispatcherKey<'info> {
+    #[account(
+        mut,
+        close = operator,
+        seeds = [b"dispatcher_key", operator.key().as_ref(), dispatcher.as_ref()],
+        bump = dispatcher_key.bump,
+    )]
+    pub dispatcher_key: Account<'info, DispatcherKey>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(target: Pubkey)]
+pub struct InitiateRecovery<'info> {
+    #[account(
+        init,
+        seeds = [b"recovery", config.key().as_ref(), target.as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + RecoveryRequest::LEN
+    )]
+    pub recovery: Account<'info, RecoveryRequest>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        constraint = proposal.config == config.key() @ ErrorCode::ProposalMismatch,
+    )]
+    pub proposal: Option<Account<'info, AdminProposal>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(target: Pubkey)]
+pub struct ExecuteRecovery<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"recovery", config.key().as_ref(), target.as_ref()],
+        bump = recovery.bump,
+        has_one = recovery_address,
+    )]
+    pub recovery: Account<'info, RecoveryRequest>,
+    /// CHECK: Escrow or token vault being swept; what it is and whose it is are checked in the handler
+    #[account(mut, address = target)]
+    pub target: AccountInfo<'info>,
+    /// CHECK: Destination fixed when the recovery was initiated
+    #[account(mut)]
+    pub recovery_address: AccountInfo<'info>,
+    /// CHECK: Order the escrow belongs to, or the channel a channel vault
+    /// belongs to; it must be finished or gone, as checked in the handler
+    pub delivery: Option<AccountInfo<'info>>,
+    pub mint: Option<InterfaceAccount<'info, Mint>>,
+    /// Token account of the recovery address in the vault's mint
+    #[account(mut)]
+    pub recovery_token: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(target: Pubkey)]
+pub struct CancelRecovery<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"recovery", config.key().as_ref(), target.as_ref()],
+        bump = recovery.bump,
+    )]
+    pub recovery: Account<'info, RecoveryRequest>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(provider: Pubkey)]
+pub struct RegisterMapProvider<'info> {
+    #[account(
+        init,
+        seeds = [b"map_provider", provider.as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + MapProvider::LEN
+    )]
+    pub map_provider: Account<'info, MapProvider>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetMapTilePrice<'info> {
+    #[account(
+        mut,
+        seeds = [b"map_provider", provider.key().as_ref()],
+        bump = map_provider.bump,
+        has_one = provider,
+    )]
+    pub map_provider: Account<'info, MapProvider>,
+    pub provider: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(provider: Pubkey)]
+pub struct SetMapProviderActive<'info> {
+    #[account(
+        mut,
+        seeds = [b"map_provider", provider.as_ref()],
+        bump = map_provider.bump,
+    )]
+    pub map_provider: Account<'info, MapProvider>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(provider: Pubkey, tile_id: u64, version_hash: [u8; 32])]
+pub struct PurchaseMapTile<'info> {
+    // One purchase per tile version, so an updated tile can be bought again
+    #[account(
+        init,
+        seeds = [
+            b"map_tile",
+            vehicle.key().as_ref(),
+            provider.as_ref(),
+            &tile_id.to_le_bytes(),
+            version_hash.as_ref()
+        ],
+        bump,
+        payer = operator,
+        space = 8 + MapTilePurchase::LEN
+    )]
+    pub tile_purchase: Account<'info, MapTilePurchase>,
+    #[account(
+        mut,
+        seeds = [b"map_provider", provider.as_ref()],
+        bump = map_provider.bump,
+    )]
+    pub map_provider: Account<'info, MapProvider>,
+    /// CHECK: Provider payout wallet recorded on the registry entry
+    #[account(mut, address = map_provider.provider)]
+    pub provider_wallet: AccountInfo<'info>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        mut,
+        seeds = [b"wallet", operator.key().as_ref()],
+        bump = wallet.bump,
+    )]
+    pub wallet: Account<'info, PrepaidWallet>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(feature_id: u64)]
+pub struct RegisterFeature<'info> {
+    #[account(
+        init,
+        seeds = [b"feature", &feature_id.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + Feature::LEN
+    )]
+    pub feature: Account<'info, Feature>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(feature_id: u64)]
+pub struct SetFeatureActive<'info> {
+    #[account(
+        mut,
+        seeds = [b"feature", &feature_id.to_le_bytes()],
+        bump = feature.bump,
+    )]
+    pub feature: Account<'info, Feature>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(feature_id: u64)]
+pub struct PurchaseFeatureLicense<'info> {
+    #[account(
+        init_if_needed,
+        seeds = [b"license", vehicle.key().as_ref(), &feature_id.to_le_bytes()],
+        bump,
+        payer = operator,
+        space = 8 + FeatureLicense::LEN
+    )]
+    pub license: Account<'info, FeatureLicense>,
+    #[account(
+        seeds = [b"feature", &feature_id.to_le_bytes()],
+        bump = feature.bump,
+    )]
+    pub feature: Account<'info, Feature>,
+    /// CHECK: Provider wallet recorded on the feature entry
+    #[account(mut, address = feature.provider)]
+    pub provider: AccountInfo<'info>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(corridor_id: u32)]
+pub struct RegisterAirCorridor<'info> {
+    #[account(
+        init,
+        seeds = [b"corridor", &corridor_id.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + AirCorridor::LEN
+    )]
+    pub corridor: Account<'info, AirCorridor>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(corridor_id: u32)]
+pub struct SetAirCorridorActive<'info> {
+    #[account(
+        mut,
+        seeds = [b"corridor", &corridor_id.to_le_bytes()],
+        bump = corridor.bump,
+    )]
+    pub corridor: Account<'info, AirCorridor>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(corridor_id: u32)]
+pub struct PayCorridorFee<'info> {
+    #[account(
+        mut,
+        seeds = [b"corridor", &corridor_id.to_le_bytes()],
+        bump = corridor.bump,
+    )]
+    pub corridor: Account<'info, AirCorridor>,
+    /// CHECK: Fee recipient recorded on the corridor
+    #[account(mut, address = corridor.fee_recipient)]
+    pub fee_recipient: AccountInfo<'info>,
+    #[account(
+        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
+        bump = vehicle.bump,
+        constraint = vehicle.operator == operator.key() @ ErrorCode::Unauthorized
+    )]
+    pub vehicle: Account<'info, Vehicle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub operator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(oracle: Pubkey)]
+pub struct RegisterWeatherOracle<'info> {
+    #[account(
+        init,
+        seeds = [b"weather_oracle", oracle.as_ref()],
         bump,
         payer = authority,
-        space = 8 + Config::LEN
+        space = 8 + WeatherOracle::LEN
+    )]
+    pub weather_oracle: Account<'info, WeatherOracle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(oracle: Pubkey)]
+pub struct RemoveWeatherOracle<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"weather_oracle", oracle.as_ref()],
+        bump = weather_oracle.bump,
+    )]
+    pub weather_oracle: Account<'info, WeatherOracle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(region_id: u16)]
+pub struct SetHazardLevel<'info> {
+    #[account(
+        mut,
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"weather_oracle", oracle.key().as_ref()],
+        bump = weather_oracle.bump,
+    )]
+    pub weather_oracle: Account<'info, WeatherOracle>,
+    pub oracle: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(oracle: Pubkey)]
+pub struct RegisterTrafficOracle<'info> {
+    #[account(
+        init,
+        seeds = [b"traffic_oracle", oracle.as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + TrafficOracle::LEN
+    )]
+    pub traffic_oracle: Account<'info, TrafficOracle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(oracle: Pubkey)]
+pub struct RemoveTrafficOracle<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"traffic_oracle", oracle.as_ref()],
+        bump = traffic_oracle.bump,
+    )]
+    pub traffic_oracle: Account<'info, TrafficOracle>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(region_id: u16)]
+pub struct SetCongestion<'info> {
+    #[account(
+        mut,
+        seeds = [b"region", &region_id.to_le_bytes()],
+        bump = region.bump,
+    )]
+    pub region: Account<'info, Region>,
+    #[account(
+        seeds = [b"traffic_oracle", oracle.key().as_ref()],
+        bump = traffic_oracle.bump,
+    )]
+    pub traffic_oracle: Account<'info, TrafficOracle>,
+    pub oracle: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(currency: [u8; 3])]
+pub struct InitializePriceFeed<'info> {
+    #[account(
+        init,
+        seeds = [b"price_feed", currency.as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + PriceFeed::LEN
+    )]
+    pub price_feed: Account<'info, PriceFeed>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
     )]
     pub config: Account<'info, Config>,
     #[account(mut)]
@@ -190,158 +11738,3568 @@ pub struct InitializeConfig<'info> {
     pub system_program: Program<'info, System>,
 }
 
-#[derive(Accounts)]
-#[instruction(vehicle_id: String)]
-pub struct RegisterVehicle<'info> {
-    #[account(
-        init,
-        seeds = [b"vehicle", vehicle_id.as_bytes()],
-        bump,
-        payer = authority,
-        space = 8 + Vehicle::LEN
-    )]
-    pub vehicle: Account<'info, Vehicle>,
-    #[account(
-        seeds = [b"config", authority.key().as_ref()],
-        bump = config.bump,
-    )]
-    pub config: Account<'info, Config>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
+#[derive(Accounts)]
+#[instruction(currency: [u8; 3])]
+pub struct UpdatePriceFeed<'info> {
+    #[account(
+        mut,
+        seeds = [b"price_feed", currency.as_ref()],
+        bump = price_feed.bump,
+        has_one = oracle,
+    )]
+    pub price_feed: Account<'info, PriceFeed>,
+    pub oracle: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(currency: [u8; 3])]
+pub struct OverridePriceFeed<'info> {
+    #[account(
+        mut,
+        seeds = [b"price_feed", currency.as_ref()],
+        bump = price_feed.bump,
+    )]
+    pub price_feed: Account<'info, PriceFeed>,
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPreferredVehicles<'info> {
+    #[account(
+        init_if_needed,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + CustomerProfile::LEN
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetRoundUp<'info> {
+    #[account(
+        init_if_needed,
+        seeds = [b"customer", customer.key().as_ref()],
+        bump,
+        payer = customer,
+        space = 8 + CustomerProfile::LEN
+    )]
+    pub customer_profile: Account<'info, CustomerProfile>,
+    #[account(mut)]
+    pub customer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[account]
+pub struct Config {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub is_active: bool,
+    pub is_paused: bool,
+    pub fee_bps: u16,
+    pub treasury: Pubkey,
+    pub version: u8,
+    pub total_fees_collected: u64,
+    pub last_revenue_epoch: u64,
+    pub fees_at_last_rollover: u64,
+    pub next_invoice_number: u64,
+    pub daily_escrow_cap: u64,
+    pub daily_payout_cap: u64,
+    pub volume_day: u32,
+    pub escrowed_today: u64,
+    pub paid_out_today: u64,
+    pub max_withdrawal_per_epoch: u64,
+    pub withdrawal_epoch: u64,
+    pub withdrawn_this_epoch: u64,
+    pub pending_withdrawal_limit: u64,
+    pub pending_limit_epoch: u64,
+    pub settlement_only: bool,
+    pub max_fee_step_bps: u16,
+    pub fee_step_period: i64,
+    pub fee_window_start: i64,
+    pub fee_at_window_start: u16,
+    pub robot_max_order_value: u64,
+    pub hazard_surcharge_bps: u16,
+    pub fee_rounding: FeeRounding,
+    pub charity: Pubkey,
+    pub no_show_deposit: u64,
+    pub accepted_mint: Pubkey,
+    pub expiry_seconds: i64,
+    pub pending_authority: Pubkey,
+    pub auto_release_seconds: i64,
+    pub arbiter: Pubkey,
+    pub tips_fee_exempt: bool,
+    pub min_stake: u64,
+    pub slash_bps: u16,
+    pub abandon_seconds: i64,
+    pub schedule_window_seconds: i64,
+    pub surge_bps: u16,
+    pub surge_dispatcher: Pubkey,
+    pub class_fee_bps: [Option<u16>; VehicleClass::COUNT],
+    pub approvers: [Pubkey; MAX_APPROVERS],
+    pub approval_threshold: u8,
+    pub config_change_delay_seconds: i64,
+    pub pending_config_change: Option<PendingConfigChange>,
+    pub insurance_bps: u16,
+    pub allowed_token_extensions: u16,
+    pub confidential_prover: Pubkey,
+    pub receipt_nft_uri: String,
+    pub receipt_tree: Pubkey,
+    pub fee_recipients: [FeeRecipient; MAX_FEE_RECIPIENTS],
+    pub referral_bps: u16,
+    // Part of every slash paid to the treasury rather than the customer
+    pub slash_treasury_bps: u16,
+    // Pyth SOL/USD price update account USD orders are priced and settled
+    // against, and the feed id it must carry; default = USD orders off
+    pub pyth_price_update: Pubkey,
+    pub pyth_feed_id: [u8; 32],
+}
+impl Config {
+    pub const LEN: usize = 1 + 32 + 1 + 1 + 2 + 32 + 1 + 8 + 8 + 8 + 8 + 8 + 8 + 4 + 8 + 8
+        + 8 + 8 + 8 + 8 + 8 + 1 + 2 + 8 + 8 + 2 + 8 + 2 + 1 + 32 + 8 + 32 + 8 + 32 + 8 + 32 + 1
+        + 8 + 2 + 8 + 8 + 2 + 32 + VehicleClass::COUNT * (1 + 2)
+        + MAX_APPROVERS * 32 + 1 + 8 + (1 + PendingConfigChange::LEN) + 2 + 2 + 32
+        + (4 + MAX_RECEIPT_URI_LEN) + 32 + MAX_FEE_RECIPIENTS * FeeRecipient::LEN + 2 + 2 + 32 + 32;
+
+    pub fn is_approver(&self, key: Pubkey) -> bool {
+        key != Pubkey::default() && self.approvers.contains(&key)
+    }
+
+    // Once an approver set is configured, fee and payout changes, treasury
+    // rotation and recovery sweeps also need a proposal for exactly this action that
+    // enough current approvers signed; the proposal is spent here
+    pub fn check_approved(&self, proposal: Option<&mut AdminProposal>, action: &AdminAction) -> Result<()> {
+        if self.approval_threshold == 0 {
+            return Ok(());
+        }
+        let proposal = proposal.ok_or(ErrorCode::ApprovalRequired)?;
+        require!(!proposal.executed, ErrorCode::ProposalExecuted);
+        require!(proposal.action == *action, ErrorCode::ProposalMismatch);
+        let approvals = proposal.approved_by[..proposal.approval_count as usize]
+            .iter()
+            .filter(|key| self.is_approver(**key))
+            .count();
+        require!(approvals >= self.approval_threshold as usize, ErrorCode::ApprovalRequired);
+        proposal.executed = true;
+        Ok(())
+    }
+
+    // The key named in config for a role, or a holder of its Role account.
+    // The authority carries every role except Arbiter, so disputes stay
+    // with a party other than the platform
+    pub fn has_role(&self, signer: Pubkey, role: Option<&Role>, kind: RoleKind) -> bool {
+        let named = match kind {
+            RoleKind::Admin | RoleKind::Treasurer => signer == self.authority,
+            RoleKind::Dispatcher => signer == self.authority || signer == self.surge_dispatcher,
+            RoleKind::Arbiter => signer == self.arbiter,
+        };
+        named || role.map_or(false, |role| role.kind == kind && role.holder == signer)
+    }
+
+    // Fare with the current surge premium added; 0 bps leaves it unchanged
+    pub fn apply_surge(&self, amount: u64) -> Result<u64> {
+        let premium = apply_bps(amount, self.surge_bps as u64, self.fee_rounding)?;
+        Ok(amount.checked_add(premium).ok_or(ErrorCode::MathOverflow)?)
+    }
+
+    // A class's own fee tier wins; without one the class pays the global fee
+    pub fn fee_bps_for(&self, class: VehicleClass) -> u16 {
+        self.class_fee_bps[class.index()].unwrap_or(self.fee_bps)
+    }
+
+    // Platform cut of a tip; nothing while tips are fee exempt
+    pub fn tip_fee(&self, amount: u64, class: VehicleClass) -> Result<u64> {
+        if self.tips_fee_exempt {
+            return Ok(0);
+        }
+        apply_bps(amount, self.fee_bps_for(class) as u64, self.fee_rounding)
+    }
+
+    pub fn check_stake(&self, vehicle: &Vehicle) -> Result<()> {
+        require!(
+            vehicle.staked.saturating_sub(vehicle.pending_unstake) >= self.min_stake,
+            ErrorCode::InsufficientStake
+        );
+        Ok(())
+    }
+
+    // Sidewalk robots only run in zones that admit them and only carry
+    // orders up to the configured value
+    pub fn check_vehicle_class(&self, vehicle: &Vehicle, region: &Region, payment_amount: u64) -> Result<()> {
+        if vehicle.vehicle_class == VehicleClass::SidewalkRobot {
+            require!(region.robots_allowed, ErrorCode::VehicleClassMismatch);
+            require!(
+                self.robot_max_order_value == 0 || payment_amount <= self.robot_max_order_value,
+                ErrorCode::OrderValueTooHigh
+            );
+        }
+        Ok(())
+    }
+
+    // The fee may drift at most max_fee_step_bps away from where it stood when
+    // the current window opened, so in-flight orders never see a sudden jump
+    pub fn check_fee_step(&mut self, new_bps: u16, now: i64) -> Result<()> {
+        let window_end = self.fee_window_start
+            .checked_add(self.fee_step_period)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if now >= window_end {
+            self.fee_window_start = now;
+            self.fee_at_window_start = self.fee_bps;
+        }
+        require!(
+            new_bps.abs_diff(self.fee_at_window_start) <= self.max_fee_step_bps,
+            ErrorCode::FeeChangeTooLarge
+        );
+        Ok(())
+    }
+
+    // A class tier is held to the same step limit, measured from the fee the
+    // class pays today; dropping the tier moves it back to the global fee
+    pub fn check_class_fee_step(&self, class: VehicleClass, fee_bps: Option<u16>) -> Result<()> {
+        let new_bps = fee_bps.unwrap_or(self.fee_bps);
+        require!(
+            new_bps.abs_diff(self.fee_bps_for(class)) <= self.max_fee_step_bps,
+            ErrorCode::FeeChangeTooLarge
+        );
+        Ok(())
+    }
+
+    // Tightening applies at once; loosening (or lifting) the limit only takes
+    // effect from the next epoch, so a stolen authority key cannot raise the
+    // limit and drain in the same epoch.
+    pub fn set_withdrawal_limit(&mut self, limit: u64, epoch: u64) {
+        self.apply_pending_limit(epoch);
+        let tightens = limit != 0
+            && (self.max_withdrawal_per_epoch == 0 || limit <= self.max_withdrawal_per_epoch);
+        if tightens {
+            self.max_withdrawal_per_epoch = limit;
+            self.pending_limit_epoch = 0;
+        } else {
+            self.pending_withdrawal_limit = limit;
+            self.pending_limit_epoch = epoch.saturating_add(1);
+        }
+    }
+
+    fn apply_pending_limit(&mut self, epoch: u64) {
+        if self.pending_limit_epoch != 0 && epoch >= self.pending_limit_epoch {
+            self.max_withdrawal_per_epoch = self.pending_withdrawal_limit;
+            self.pending_limit_epoch = 0;
+        }
+    }
+
+    pub fn record_withdrawal(&mut self, amount: u64, epoch: u64) -> Result<()> {
+        self.apply_pending_limit(epoch);
+        if epoch != self.withdrawal_epoch {
+            self.withdrawal_epoch = epoch;
+            self.withdrawn_this_epoch = 0;
+        }
+        self.withdrawn_this_epoch = self.withdrawn_this_epoch
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            self.max_withdrawal_per_epoch == 0
+                || self.withdrawn_this_epoch <= self.max_withdrawal_per_epoch,
+            ErrorCode::WithdrawalLimitExceeded
+        );
+        Ok(())
+    }
+
+    fn roll_volume_day(&mut self, now: i64) {
+        let today = DailyStats::day_of(now);
+        if today != self.volume_day {
+            self.volume_day = today;
+            self.escrowed_today = 0;
+            self.paid_out_today = 0;
+        }
+    }
+
+    // Circuit breaker: new escrow is refused once the day's cap is reached
+    pub fn record_escrow(&mut self, amount: u64, now: i64) -> Result<()> {
+        self.roll_volume_day(now);
+        self.escrowed_today = self.escrowed_today
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            self.daily_escrow_cap == 0 || self.escrowed_today <= self.daily_escrow_cap,
+            ErrorCode::DailyVolumeCapReached
+        );
+        Ok(())
+    }
+
+    pub fn record_payout(&mut self, amount: u64, now: i64) -> Result<()> {
+        self.roll_volume_day(now);
+        self.paid_out_today = self.paid_out_today
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            self.daily_payout_cap == 0 || self.paid_out_today <= self.daily_payout_cap,
+            ErrorCode::DailyVolumeCapReached
+        );
+        Ok(())
+    }
+}
+
+#[account]
+pub struct FeeHistory {
+    pub bump: u8,
+    pub config: Pubkey,
+    pub entries: Vec<FeeChange>,
+}
+impl FeeHistory {
+    pub const LEN: usize = 1 + 32 + (4 + MAX_FEE_HISTORY * FeeChange::LEN);
+
+    // Oldest entries are dropped once the ring is full
+    pub fn push(&mut self, change: FeeChange) {
+        if self.entries.len() >= MAX_FEE_HISTORY {
+            self.entries.remove(0);
+        }
+        self.entries.push(change);
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct FeeChange {
+    pub old_bps: u16,
+    pub new_bps: u16,
+    pub changed_at: i64,
+    pub proposer: Pubkey,
+    // None for the global fee, otherwise the class whose tier changed
+    pub class: Option<VehicleClass>,
+}
+impl FeeChange { pub const LEN: usize = 2 + 2 + 8 + 32 + (1 + 1); }
+
+// An empty slot has the default recipient
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq)]
+pub struct FeeRecipient {
+    pub recipient: Pubkey,
+    pub bps: u16,
+}
+impl FeeRecipient { pub const LEN: usize = 32 + 2; }
+
+#[account]
+pub struct Vehicle {
+    pub bump: u8,
+    pub vehicle_id: String,
+    pub operator: Pubkey,
+    // Deprecated: mirrors `position` for clients that still read the string
+    pub location: String,
+    pub is_active: bool,
+    pub is_busy: bool,
+    pub total_deliveries: u64,
+    pub registered_at: i64,
+    pub busy_seconds: u64,
+    pub idle_seconds: u64,
+    pub distance_claimed_m: u64,
+    pub revenue: u64,
+    pub last_status_change_at: i64,
+    pub last_nonce: u64,
+    pub last_checkpoint_at: i64,
+    pub vehicle_class: VehicleClass,
+    pub geohash: [u8; GEOHASH_LEN],
+    pub away_until: i64,
+    pub rating_count: u32,
+    pub rating_sum: u64,
+    pub min_customer_score: u16,
+    pub total_tips: u64,
+    pub location_updated_at: i64,
+    pub suspended: bool,
+    pub capacity_grams: u32,
+    pub battery_pct: u8,
+    pub telemetry_updated_at: i64,
+    pub fleet: Option<Pubkey>,
+    pub staked: u64,
+    pub session_key: Pubkey,
+    pub session_expires_at: i64,
+    pub position: GeoPoint,
+    pub ownership_mint: Option<Pubkey>,
+    // PERM_* bits the session key may use
+    pub session_permissions: u8,
+    // Highest fleet share the operator has agreed to pay its fleet
+    pub agreed_fleet_share_bps: u16,
+    // Part of `staked` asked back; it no longer counts toward the minimum
+    pub pending_unstake: u64,
+    // Orders accepted and not yet settled; `is_busy` while any are
+    pub active_orders: u8,
+    // Orders the operator lets the vehicle carry at once, 0 read as 1
+    pub max_active_orders: u8,
+}
+impl Vehicle {
+    pub const LEN: usize = 1 + (4 + 32) + 32 + (4 + 64) + 1 + 1 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8
+        + 1 + GEOHASH_LEN + 8 + 4 + 8 + 2 + 8 + 8 + 1 + 4 + 1 + 8 + (1 + 32) + 8
+        + 32 + 8 + GeoPoint::LEN + (1 + 32) + 1 + 2 + 8 + 1 + 1;
+
+    // Room for another order; vehicles from before multi-order carrying
+    // read a zero limit as one
+    pub fn has_capacity(&self) -> bool {
+        self.active_orders < self.max_active_orders.max(1)
+    }
+
+    // Mirrors the stake account after it changes
+    pub fn sync_stake(&mut self, stake: &VehicleStake) {
+        self.staked = stake.amount;
+        self.pending_unstake = stake.pending_unstake;
+    }
+
+    // Share of the operator payment owed to the vehicle's fleet. A manager
+    // can lower it at any time but a raise only applies once the operator
+    // accepts it
+    pub fn fleet_share_bps(&self, fleet: Option<&Fleet>) -> u16 {
+        fleet.map_or(0, |fleet| fleet.fleet_share_bps.min(self.agreed_fleet_share_bps))
+    }
+
+    // Average star rating in hundredths, 450 = 4.5 stars
+    pub fn average_rating(&self) -> u16 {
+        if self.rating_count == 0 {
+            return 0;
+        }
+        (self.rating_sum * 100 / self.rating_count as u64) as u16
+    }
+
+    // Fleet vehicles must present their fleet, whose pause stops new work
+    pub fn check_fleet(&self, fleet: Option<&Fleet>) -> Result<()> {
+        if self.fleet.is_some() {
+            let fleet = fleet.ok_or(ErrorCode::FleetAccountRequired)?;
+            require!(!fleet.is_paused, ErrorCode::FleetPaused);
+        }
+        Ok(())
+    }
+
+    pub fn check_required_class(&self, required: Option<VehicleClass>) -> Result<()> {
+        if let Some(class) = required {
+            require!(self.vehicle_class == class, ErrorCode::VehicleClassMismatch);
+        }
+        Ok(())
+    }
+
+    // Records a new position, keeping the deprecated location string in step
+    pub fn move_to(&mut self, position: GeoPoint, now: i64) {
+        self.position = position;
+        self.location = position.to_string();
+        self.location_updated_at = now;
+    }
+
+    // The operator; the session key it registered while that is unexpired;
+    // or one of its dispatcher keys. Delegated keys only pass for the
+    // handlers their permission bits allow
+    pub fn check_delegate(
+        &self,
+        signer: Pubkey,
+        dispatcher_key: Option<&DispatcherKey>,
+        permission: u8,
+        now: i64,
+    ) -> Result<()> {
+        if signer == self.operator {
+            return Ok(());
+        }
+        if signer == self.session_key {
+            require!(now < self.session_expires_at, ErrorCode::SessionKeyExpired);
+            require!(self.session_permissions & permission != 0, ErrorCode::MissingPermission);
+            return Ok(());
+        }
+        let dispatcher_key = dispatcher_key.ok_or(ErrorCode::Unauthorized)?;
+        require!(
+            dispatcher_key.operator == self.operator && dispatcher_key.dispatcher == signer,
+            ErrorCode::Unauthorized
+        );
+        dispatcher_key.check(permission, now)
+    }
+
+    // Unrated vehicles are let through so new vehicles can earn a rating
+    pub fn check_rating(&self, min_rating: u16) -> Result<()> {
+        require!(
+            self.rating_count == 0 || self.average_rating() >= min_rating,
+            ErrorCode::RatingTooLow
+        );
+        Ok(())
+    }
+
+    // Away vehicles stay registered and visible but are skipped by automatic
+    // matching until they return
+    pub fn is_away(&self, now: i64) -> bool {
+        now < self.away_until
+    }
+
+    // Rejects any signed submission whose nonce is not above the last one seen,
+    // so a captured message cannot be replayed
+    pub fn consume_nonce(&mut self, nonce: u64) -> Result<()> {
+        require!(nonce > self.last_nonce, ErrorCode::StaleNonce);
+        self.last_nonce = nonce;
+        Ok(())
+    }
+
+    // Takes on an order; idle time stops accruing with the first one
+    pub fn mark_busy(&mut self, now: i64) -> Result<()> {
+        if !self.is_busy {
+            self.idle_seconds = self.idle_seconds
+                .checked_add(self.elapsed_since_status_change(now)?)
+                .ok_or(ErrorCode::MathOverflow)?;
+            self.last_status_change_at = now;
+            self.is_busy = true;
+        }
+        self.active_orders = self.active_orders
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // Drops an order that settled or fell through; the vehicle is idle once
+    // none are left. A vehicle accepted before the count existed holds one
+    // uncounted order, hence the saturation
+    pub fn mark_idle(&mut self, now: i64) -> Result<()> {
+        self.active_orders = self.active_orders.saturating_sub(1);
+        if self.active_orders == 0 {
+            self.busy_seconds = self.busy_seconds
+                .checked_add(self.elapsed_since_status_change(now)?)
+                .ok_or(ErrorCode::MathOverflow)?;
+            self.last_status_change_at = now;
+            self.is_busy = false;
+        }
+        Ok(())
+    }
+
+    // Keeps the vehicle's GeoBucket in step with its idle/busy state; vehicles
+    // that never checked into a bucket are not indexed
+    pub fn sync_geo_bucket(&self, vehicle_key: Pubkey, bucket: Option<&mut GeoBucket>) -> Result<()> {
+        if self.geohash == [0u8; GEOHASH_LEN] {
+            return Ok(());
+        }
+        let bucket = bucket.ok_or(ErrorCode::GeoBucketRequired)?;
+        if self.is_active && self.has_capacity() {
+            // A full cell must not hold up settlement or acceptance; the
+            // vehicle is simply not listed until the operator moves it
+            if bucket.is_full() && !bucket.vehicles.contains(&vehicle_key) {
+                return Ok(());
+            }
+            bucket.insert(vehicle_key)
+        } else {
+            bucket.remove(vehicle_key);
+            Ok(())
+        }
+    }
+
+    fn elapsed_since_status_change(&self, now: i64) -> Result<u64> {
+        let elapsed = now
+            .checked_sub(self.last_status_change_at)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(elapsed.max(0) as u64)
+    }
+}
+
+#[account]
+pub struct OperatorProfile {
+    pub bump: u8,
+    pub operator: Pubkey,
+    pub gross_earnings: u64,
+    pub fees_paid: u64,
+    pub tips: u64,
+    pub penalties: u64,
+    pub completed_jobs: u64,
+    pub taxes_withheld: u64,
+    pub created_at: i64,
+    pub last_statement_epoch: u64,
+    pub last_statement_at: i64,
+    pub last_statement_totals: EarningsTotals,
+    // Escrow awarded back to customers plus stake slashed in lost disputes
+    pub dispute_losses: u64,
+    // Set by register_referral; settlements must then pay the referrer
+    pub referred: bool,
+}
+impl OperatorProfile {
+    pub const LEN: usize = 1 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + EarningsTotals::LEN + 8 + 1;
+
+    pub fn totals(&self) -> EarningsTotals {
+        EarningsTotals {
+            gross_earnings: self.gross_earnings,
+            fees_paid: self.fees_paid,
+            tips: self.tips,
+            penalties: self.penalties,
+            completed_jobs: self.completed_jobs,
+            taxes_withheld: self.taxes_withheld,
+            dispute_losses: self.dispute_losses,
+        }
+    }
+}
+
+// Settled operator payments waiting to be claimed; the lamports sit on this
+// account above its rent
+#[account]
+pub struct Earnings {
+    pub bump: u8,
+    pub operator: Pubkey,
+    pub accrued: u64,
+    pub total_claimed: u64,
+    pub last_claimed_at: i64,
+}
+impl Earnings {
+    pub const LEN: usize = 1 + 32 + 8 + 8 + 8;
+
+    pub fn open(&mut self, bump: u8, operator: Pubkey) {
+        if self.operator == Pubkey::default() {
+            self.bump = bump;
+            self.operator = operator;
+        }
+    }
+
+    pub fn accrue(&mut self, amount: u64) -> Result<()> {
+        self.accrued = self.accrued
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+}
+
+// Links a customer or operator to the wallet that referred them; the
+// referrer's share of fees on their orders sits on this account above its
+// rent until claimed
+#[account]
+pub struct Referral {
+    pub bump: u8,
+    pub referee: Pubkey,
+    pub referrer: Pubkey,
+    pub created_at: i64,
+    pub accrued: u64,
+    pub total_earned: u64,
+    pub total_claimed: u64,
+}
+impl Referral {
+    pub const LEN: usize = 1 + 32 + 32 + 8 + 8 + 8 + 8;
+
+    pub fn accrue(&mut self, amount: u64) -> Result<()> {
+        self.accrued = self.accrued
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.total_earned = self.total_earned
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+}
+
+// Promo code created by the authority; customers name it with the code
+// itself, which only the hash is stored for
+#[account]
+pub struct Voucher {
+    pub bump: u8,
+    pub code_hash: [u8; 32],
+    pub discount_bps: u16,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub expires_at: i64,
+    pub created_at: i64,
+}
+impl Voucher {
+    pub const LEN: usize = 1 + 32 + 2 + 4 + 4 + 8 + 8;
+
+    pub fn redeem(&mut self, code: &str, now: i64) -> Result<()> {
+        let hash = anchor_lang::solana_program::hash::hash(code.as_bytes());
+        require!(hash.to_bytes() == self.code_hash, ErrorCode::InvalidPromoCode);
+        require!(now < self.expires_at, ErrorCode::VoucherExpired);
+        require!(self.uses < self.max_uses, ErrorCode::VoucherExhausted);
+        self.uses += 1;
+        Ok(())
+    }
+
+    // Gives a use back when the order it was redeemed on is refunded
+    pub fn release(&mut self) {
+        self.uses = self.uses.saturating_sub(1);
+    }
+}
+
+// A customer's redemption of a voucher, at ["voucher_use", voucher, customer].
+// Its existence blocks a second redemption; it closes if the order is refunded
+#[account]
+pub struct VoucherUse {
+    pub bump: u8,
+    pub voucher: Pubkey,
+    pub customer: Pubkey,
+    pub delivery: Pubkey,
+}
+impl VoucherUse {
+    pub const LEN: usize = 1 + 32 + 32 + 32;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, PartialEq)]
+pub struct EarningsTotals {
+    pub gross_earnings: u64,
+    pub fees_paid: u64,
+    pub tips: u64,
+    pub penalties: u64,
+    pub completed_jobs: u64,
+    pub taxes_withheld: u64,
+    pub dispute_losses: u64,
+}
+impl EarningsTotals { pub const LEN: usize = 8 * 7; }
+
+#[account]
+pub struct Statement {
+    pub bump: u8,
+    pub operator: Pubkey,
+    pub epoch: u64,
+    pub period_start: i64,
+    pub period_end: i64,
+    pub completed_jobs: u64,
+    pub gross_earnings: u64,
+    pub fees_paid: u64,
+    pub tips: u64,
+    pub penalties: u64,
+    pub taxes_withheld: u64,
+    pub dispute_losses: u64,
+}
+impl Statement { pub const LEN: usize = 1 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8; }
+
+#[account]
+pub struct CustomerProfile {
+    pub bump: u8,
+    pub customer: Pubkey,
+    pub total_spend: u64,
+    pub orders_created: u64,
+    pub orders_completed: u64,
+    pub disputes: u64,
+    pub loyalty_tier: u8,
+    pub created_at: i64,
+    pub preferred_vehicles: Vec<Pubkey>,
+    pub exclusive_window_seconds: u32,
+    pub round_up_unit: u64,
+    pub cancellations: u64,
+    pub disputes_lost: u64,
+    // Set by register_referral; settlements must then pay the referrer
+    pub referred: bool,
+}
+impl CustomerProfile {
+    pub const LEN: usize = 1 + 32 + 8 + 8 + 8 + 8 + 1 + 8 + (4 + MAX_PREFERRED_VEHICLES * 32) + 4 + 8 + 8 + 8 + 1;
+
+    // Share of the customer's settled orders that completed cleanly, in
+    // basis points; customers with no history start at a perfect score
+    pub fn score(&self) -> u16 {
+        let settled = self.orders_completed
+            .saturating_add(self.cancellations)
+            .saturating_add(self.disputes_lost);
+        if settled == 0 {
+            return 10000;
+        }
+        (self.orders_completed.saturating_mul(10000) / settled) as u16
+    }
+
+    pub fn check_score(&self, min_score: u16) -> Result<()> {
+        require!(self.score() >= min_score, ErrorCode::CustomerScoreTooLow);
+        Ok(())
+    }
+
+    // Amount that lifts the fare to the next multiple of the customer's
+    // round-up unit; zero when round-ups are off or no charity is configured
+    pub fn round_up(&self, amount: u64, config: &Config) -> Result<u64> {
+        if self.round_up_unit == 0 || config.charity == Pubkey::default() {
+            return Ok(0);
+        }
+        let remainder = amount % self.round_up_unit;
+        if remainder == 0 {
+            return Ok(0);
+        }
+        Ok(self.round_up_unit.checked_sub(remainder).ok_or(ErrorCode::MathOverflow)?)
+    }
+
+    // During the exclusive window after an order is placed only the
+    // customer's preferred vehicles may take it
+    pub fn check_preferred(&self, vehicle: Pubkey, created_at: i64, now: i64) -> Result<()> {
+        let window_end = created_at.saturating_add(self.exclusive_window_seconds as i64);
+        if now < window_end && !self.preferred_vehicles.is_empty() {
+            require!(
+                self.preferred_vehicles.contains(&vehicle),
+                ErrorCode::ExclusiveWindowActive
+            );
+        }
+        Ok(())
+    }
+
+    // Fills in identity fields the first time an init_if_needed profile is seen
+    pub fn open(&mut self, bump: u8, customer: Pubkey, now: i64) {
+        if self.customer == Pubkey::default() {
+            self.bump = bump;
+            self.customer = customer;
+            self.created_at = now;
+        }
+    }
+
+    pub fn tier_for(orders_completed: u64) -> u8 {
+        LOYALTY_TIER_THRESHOLDS
+            .iter()
+            .filter(|threshold| orders_completed >= **threshold)
+            .count() as u8
+    }
+}
+
+#[account]
+pub struct Delivery {
+    pub bump: u8,
+    pub delivery_id: u64,
+    pub customer: Pubkey,
+    pub payment_amount: u64,
+    // Deprecated: free-form text kept for older clients; see pickup_point and dropoff_point
+    pub pickup_location: String,
+    pub delivery_location: String,
+    pub status: DeliveryStatus,
+    pub assigned_vehicle: Option<Pubkey>,
+    pub created_at: i64,
+    pub accepted_at: Option<i64>,
+    pub completed_at: Option<i64>,
+    pub priority: u8,
+    pub region_id: u16,
+    pub tax_bps: u16,
+    pub tax_inclusive: bool,
+    pub tax_amount: u64,
+    pub confirmation_mode: ConfirmationMode,
+    pub pin_hash: [u8; 32],
+    pub confirmed_at: Option<i64>,
+    pub hazard_surcharge: u64,
+    pub sla_seconds: u32,
+    pub late_penalty_bps: u16,
+    pub quote_usd_micros: u64,
+    pub quote_eur_micros: u64,
+    pub category: u8,
+    pub requirements: u16,
+    pub round_up: u64,
+    pub package_count: u8,
+    pub delivery_mode: DeliveryMode,
+    pub proof_hash: [u8; 32],
+    pub no_show_deposit: u64,
+    pub arrived_at: Option<i64>,
+    pub payment_mint: Pubkey,
+    pub delivered_at: Option<i64>,
+    pub picked_up_at: Option<i64>,
+    pub min_rating: u16,
+    pub rated: bool,
+    pub required_class: Option<VehicleClass>,
+    pub milestone_count: u8,
+    pub milestones_released: u8,
+    pub milestone_released: u64,
+    pub usd_cents: u64,
+    pub pickup_point: Option<GeoPoint>,
+    pub dropoff_point: Option<GeoPoint>,
+    pub route: Option<Pubkey>,
+    pub scheduled_at: Option<i64>,
+    pub surge_bps: u16,
+    pub auction_ends_at: Option<i64>,
+    pub lowest_bid: u64,
+    pub lowest_bidder: Option<Pubkey>,
+    pub listed: bool,
+    // Page of the region's board the order is listed on
+    pub listed_page: u16,
+    pub confidential: bool,
+    pub pickup_proof_hash: [u8; 32],
+    pub voucher_discount: u64,
+    // Voucher redeemed at booking, released again if the order is refunded
+    pub voucher: Option<Pubkey>,
+    // Pedersen commitment to a confidential order's escrowed amount
+    pub amount_commitment: [u8; 32],
+    // Wrong PINs submitted so far for this order
+    pub pin_attempts: u8,
+    // Charity the round-up goes to, fixed at booking
+    pub charity: Pubkey,
+    // Proof oracle's geofence fix at the pickup point, backing no-show claims
+    pub arrival_proof_hash: [u8; 32],
+    // Share of the order a dispute ruling returned to the customer
+    pub dispute_refund_bps: u16,
+}
+impl Delivery {
+    pub const LEN: usize = 1 + 8 + 32 + 8 + (4 + 64) + (4 + 64) + 1 + (1 + 32) + 8 + (1 + 8) + (1 + 8) + 1 + 2 + 2 + 1 + 8
+        + 1 + 32 + (1 + 8) + 8 + 4 + 2 + 8 + 8 + 1 + 2 + 8 + 1 + 1 + 32 + 8 + (1 + 8) + 32 + (1 + 8) + (1 + 8) + 2 + 1 + (1 + 1) + 1 + 1 + 8
+        + 8 + (1 + GeoPoint::LEN) + (1 + GeoPoint::LEN) + (1 + 32) + (1 + 8) + 2
+        + (1 + 8) + 8 + (1 + 32) + 1 + 2 + 1 + 32 + 8 + (1 + 32) + 32 + 1 + 32 + 32 + 2;
+
+    // Fills in a freshly created order as Pending with the region's pricing;
+    // callers set the bump, locations and quote snapshot themselves
+    pub fn book(
+        &mut self,
+        delivery_id: u64,
+        customer: Pubkey,
+        payment_amount: u64,
+        region: &Region,
+        pricing: &OrderPricing,
+        now: i64,
+    ) {
+        self.delivery_id = delivery_id;
+        self.customer = customer;
+        self.payment_amount = payment_amount;
+        self.region_id = region.region_id;
+        self.tax_bps = region.tax_bps;
+        self.tax_inclusive = region.tax_inclusive;
+        self.tax_amount = pricing.tax_amount;
+        self.hazard_surcharge = pricing.hazard_surcharge;
+        self.round_up = pricing.round_up;
+        self.charity = pricing.charity;
+        self.no_show_deposit = pricing.no_show_deposit;
+        self.arrived_at = None;
+        self.arrival_proof_hash = [0u8; 32];
+        self.payment_mint = Pubkey::default();
+        self.delivered_at = None;
+        self.picked_up_at = None;
+        self.min_rating = 0;
+        self.rated = false;
+        self.required_class = None;
+        self.milestone_count = 0;
+        self.milestones_released = 0;
+        self.milestone_released = 0;
+        self.usd_cents = 0;
+        self.pickup_point = None;
+        self.dropoff_point = None;
+        self.route = None;
+        self.scheduled_at = None;
+        self.surge_bps = 0;
+        self.auction_ends_at = None;
+        self.lowest_bid = 0;
+        self.lowest_bidder = None;
+        self.listed = false;
+        self.listed_page = 0;
+        self.confidential = false;
+        self.pickup_proof_hash = [0u8; 32];
+        self.voucher_discount = 0;
+        self.voucher = None;
+        self.amount_commitment = [0u8; 32];
+        self.pin_attempts = 0;
+        self.dispute_refund_bps = 0;
+        self.sla_seconds = 0;
+        self.late_penalty_bps = 0;
+        self.confirmation_mode = ConfirmationMode::Customer;
+        self.delivery_mode = DeliveryMode::SignatureRequired;
+        self.status = DeliveryStatus::Pending;
+        self.assigned_vehicle = None;
+        self.created_at = now;
+        self.priority = 0;
+        self.category = 0;
+        self.requirements = 0;
+        self.package_count = 0;
+    }
+
+    // Most an insurance claim can ask for: the order value less whatever a
+    // dispute ruling already refunded
+    pub fn claimable(&self) -> Result<u64> {
+        let refunded = apply_bps(self.payment_amount, self.dispute_refund_bps as u64, FeeRounding::Ceiling)?;
+        Ok(self.payment_amount.checked_sub(refunded).ok_or(ErrorCode::MathOverflow)?)
+    }
+
+    // Ends any auction on the order and forgets its bids, so bidders can
+    // close their Bid accounts
+    pub fn reopen_auction(&mut self) {
+        self.auction_ends_at = None;
+        self.lowest_bid = 0;
+        self.lowest_bidder = None;
+    }
+
+    // Takes a listed order off its region's board once it stops being open
+    pub fn unlist(&mut self, delivery_key: Pubkey, board: Option<&mut OpenOrders>) -> Result<()> {
+        if !self.listed {
+            return Ok(());
+        }
+        let board = board.ok_or(ErrorCode::OpenOrdersRequired)?;
+        board.remove(delivery_key);
+        self.listed = false;
+        Ok(())
+    }
+
+    // Sets the deprecated free-form locations and derives fixed-point points
+    // from whichever of them are "lat,lng" coordinates
+    pub fn set_locations(&mut self, pickup_location: String, delivery_location: String) {
+        self.pickup_location = pickup_location;
+        self.delivery_location = delivery_location;
+        self.derive_points();
+    }
+
+    pub fn derive_points(&mut self) {
+        self.pickup_point = GeoPoint::parse(&self.pickup_location).ok();
+        self.dropoff_point = GeoPoint::parse(&self.delivery_location).ok();
+    }
+
+    // Accepted by a vehicle and not yet settled, before or after pickup
+    pub fn is_underway(&self) -> bool {
+        self.status == DeliveryStatus::InProgress || self.status == DeliveryStatus::PickedUp
+    }
+
+    // Nothing left to settle, so the account can be closed
+    pub fn is_finished(&self) -> bool {
+        self.status == DeliveryStatus::Completed
+            || self.status == DeliveryStatus::Cancelled
+            || self.status == DeliveryStatus::Expired
+    }
+
+    // Escrow is released on confirmation, or for customer-confirmed orders
+    // once the customer has let the auto-release window lapse after drop-off
+    pub fn settlement_unlocked(&self, auto_release_seconds: i64, now: i64) -> bool {
+        if self.confirmed_at.is_some() {
+            return true;
+        }
+        match self.delivered_at {
+            Some(delivered_at) if auto_release_seconds > 0
+                && self.confirmation_mode == ConfirmationMode::Customer =>
+            {
+                now >= delivered_at.saturating_add(auto_release_seconds)
+            }
+            _ => false,
+        }
+    }
+
+    // Namespace of an order, read straight off its id
+    pub fn namespace_of(delivery_id: u64) -> u8 {
+        (delivery_id >> DELIVERY_NAMESPACE_SHIFT) as u8
+    }
+
+    // Whether the delivery landed after its SLA; congestion_index stretches
+    // the allowance by that percentage. Lateness runs to the drop-off, so a
+    // slow customer confirmation or settlement crank does not count against
+    // the operator
+    pub fn is_late(&self, congestion_index: u8, now: i64) -> bool {
+        let accepted_at = match self.accepted_at {
+            Some(accepted_at) if self.sla_seconds > 0 => accepted_at,
+            _ => return false,
+        };
+        let allowed = self.sla_seconds as i64 * (100 + congestion_index as i64) / 100;
+        let arrived_at = self.delivered_at.unwrap_or(now);
+        arrived_at.saturating_sub(accepted_at) > allowed
+    }
+
+    // Share of the operator payment forfeited when the delivery is late
+    pub fn late_penalty(
+        &self,
+        operator_payment: u64,
+        congestion_index: u8,
+        now: i64,
+        rounding: FeeRounding,
+    ) -> Result<u64> {
+        if !self.is_late(congestion_index, now) {
+            return Ok(0);
+        }
+        apply_bps(operator_payment, self.late_penalty_bps as u64, rounding)
+    }
+
+    // Lamports the escrow holds for this order: tax is added on top unless the
+    // fare includes it, plus any hazard surcharge, round-up and no-show deposit
+    pub fn escrowed_total(&self) -> Result<u64> {
+        let fare = if self.tax_inclusive {
+            self.payment_amount
+        } else {
+            self.payment_amount.checked_add(self.tax_amount).ok_or(ErrorCode::MathOverflow)?
+        };
+        Ok(fare
+            .checked_add(self.hazard_surcharge)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(self.round_up)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(self.no_show_deposit)
+            .ok_or(ErrorCode::MathOverflow)?)
+    }
+
+    // Scheduled orders open for acceptance `window_seconds` before their
+    // pickup time; others as soon as they are placed
+    pub fn check_schedule(&self, window_seconds: i64, now: i64) -> Result<()> {
+        if let Some(scheduled_at) = self.scheduled_at {
+            require!(
+                now >= scheduled_at.saturating_sub(window_seconds),
+                ErrorCode::ScheduleWindowNotOpen
+            );
+        }
+        Ok(())
+    }
+
+    // When the unaccepted-order expiry countdown starts
+    pub fn expiry_starts_at(&self) -> i64 {
+        self.scheduled_at.unwrap_or(self.created_at)
+    }
+
+    // Operator's cut of one route segment: the fare net of tax and platform
+    // fee, split evenly; the last segment settles with the order itself
+    pub fn milestone_slice(&self, fee_bps: u16, rounding: FeeRounding) -> Result<u64> {
+        Ok(self.operator_share(fee_bps, rounding)? / self.milestone_count as u64)
+    }
+
+    // Fare net of tax and platform fee, before any surcharge or penalty
+    pub fn operator_share(&self, fee_bps: u16, rounding: FeeRounding) -> Result<u64> {
+        let net_fare = self.net_fare()?;
+        let fee = apply_bps(net_fare, fee_bps as u64, rounding)?;
+        Ok(net_fare.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?)
+    }
+
+    // Fare the platform fee and operator payment are computed from
+    pub fn net_fare(&self) -> Result<u64> {
+        if self.tax_inclusive {
+            Ok(self.payment_amount.checked_sub(self.tax_amount).ok_or(ErrorCode::MathOverflow)?)
+        } else {
+            Ok(self.payment_amount)
+        }
+    }
+}
+
+// Amounts fixed when an order is booked in a region
+pub struct OrderPricing {
+    pub tax_amount: u64,
+    pub hazard_surcharge: u64,
+    pub round_up: u64,
+    // Charity receiving the round-up, default when there is none
+    pub charity: Pubkey,
+    pub no_show_deposit: u64,
+    pub escrow_amount: u64,
+}
+impl OrderPricing {
+    pub fn for_region(
+        payment_amount: u64,
+        region: &Region,
+        config: &Config,
+        profile: &CustomerProfile,
+        now: i64,
+    ) -> Result<Self> {
+        let tax_amount = compute_tax(payment_amount, region.tax_bps, region.tax_inclusive)?;
+        let hazard_surcharge = region.hazard_surcharge(payment_amount, config.hazard_surcharge_bps, now)?;
+        let fare = if region.tax_inclusive {
+            payment_amount
+        } else {
+            payment_amount.checked_add(tax_amount).ok_or(ErrorCode::MathOverflow)?
+        };
+        let round_up = profile.round_up(fare, config)?;
+        let escrow_amount = fare
+            .checked_add(hazard_surcharge)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(round_up)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(config.no_show_deposit)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(Self {
+            tax_amount,
+            hazard_surcharge,
+            round_up,
+            charity: if round_up > 0 { config.charity } else { Pubkey::default() },
+            no_show_deposit: config.no_show_deposit,
+            escrow_amount,
+        })
+    }
+}
+
+// Amounts one completed order pays out of its escrow
+pub struct Settlement {
+    pub fee: u64,
+    pub discount: u64,
+    pub insurance: u64,
+    pub tax: u64,
+    pub penalty: u64,
+    pub vehicle_payment: u64,
+    pub fleet_share: u64,
+    pub operator_payment: u64,
+    pub released: u64,
+}
+impl Settlement {
+    pub fn for_delivery(
+        delivery: &Delivery,
+        config: &Config,
+        vehicle_class: VehicleClass,
+        congestion_index: u8,
+        fleet_share_bps: u16,
+        now: i64,
+    ) -> Result<Self> {
+        let tax = delivery.tax_amount;
+        let net_fare = delivery.net_fare()?;
+        let fee_bps = config.fee_bps_for(vehicle_class);
+        let fee = apply_bps(net_fare, fee_bps as u64, config.fee_rounding)?;
+        let vehicle_payment = net_fare
+            .checked_sub(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Hazard surcharge goes mostly to the operator
+        let surcharge = delivery.hazard_surcharge;
+        let surcharge_fee = apply_bps(surcharge, HAZARD_PLATFORM_SHARE_BPS, config.fee_rounding)?;
+        let fee = fee
+            .checked_add(surcharge_fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let vehicle_payment = vehicle_payment
+            .checked_add(surcharge.checked_sub(surcharge_fee).ok_or(ErrorCode::MathOverflow)?)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // A voucher discount comes back to the customer out of the fee, so the
+        // operator is paid in full even if the fee has since dropped below it
+        let discount = delivery.voucher_discount.min(fee);
+        let fee = fee
+            .checked_sub(discount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // A missed SLA refunds part of the operator payment to the customer,
+        // with the deadline stretched by the region's current congestion.
+        // Penalty and fleet share are taken on the whole payment, so paying
+        // it out in milestones does not shrink them
+        let penalty = delivery.late_penalty(
+            vehicle_payment,
+            congestion_index,
+            now,
+            config.fee_rounding,
+        )?;
+        let fleet_share = apply_bps(
+            vehicle_payment.checked_sub(penalty).ok_or(ErrorCode::MathOverflow)?,
+            fleet_share_bps as u64,
+            config.fee_rounding,
+        )?;
+
+        // Milestones already paid out of the escrow come off the final
+        // payment; the penalty and fleet share can only take what is left
+        let vehicle_payment = vehicle_payment
+            .checked_sub(delivery.milestone_released)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let penalty = penalty.min(vehicle_payment);
+        let vehicle_payment = vehicle_payment
+            .checked_sub(penalty)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let fleet_share = fleet_share.min(vehicle_payment);
+
+        let released = vehicle_payment
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(tax)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(penalty)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(discount)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(delivery.round_up)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Part of the platform fee funds the insurance pool
+        let insurance = apply_bps(fee, config.insurance_bps as u64, config.fee_rounding)?;
+
+        // Fleet vehicles pay the fleet its share of the operator payment
+        let operator_payment = vehicle_payment
+            .checked_sub(fleet_share)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(Self {
+            fee,
+            discount,
+            insurance,
+            tax,
+            penalty,
+            vehicle_payment,
+            fleet_share,
+            operator_payment,
+            released,
+        })
+    }
+
+    // Scales the operator's, fleet's, platform's and tax shares down by the
+    // failed part of a manifest. What they no longer take stays in the
+    // escrow and goes back to the customer when it closes
+    pub fn less_failed(self, config: &Config, failed_bps: u16) -> Result<Self> {
+        if failed_bps == 0 {
+            return Ok(self);
+        }
+        let kept_bps = 10000u64
+            .checked_sub(failed_bps as u64)
+            .ok_or(ErrorCode::InvalidParameter)?;
+        let rounding = config.fee_rounding;
+        let vehicle_payment = apply_bps(self.vehicle_payment, kept_bps, rounding)?;
+        let fleet_share = apply_bps(self.fleet_share, kept_bps, rounding)?.min(vehicle_payment);
+        let fee = apply_bps(self.fee, kept_bps, rounding)?;
+        let tax = apply_bps(self.tax, kept_bps, rounding)?;
+        let insurance = apply_bps(fee, config.insurance_bps as u64, rounding)?;
+        let operator_payment = vehicle_payment
+            .checked_sub(fleet_share)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let refunded = self.vehicle_payment
+            .checked_sub(vehicle_payment)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(self.fee.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(self.tax.checked_sub(tax).ok_or(ErrorCode::MathOverflow)?)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let released = self.released
+            .checked_sub(refunded)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(Self {
+            fee,
+            insurance,
+            tax,
+            vehicle_payment,
+            fleet_share,
+            operator_payment,
+            released,
+            ..self
+        })
+    }
+
+    // Part of a disputed order the arbiter awards the operator, settled like
+    // a completion with its platform fee and tax. Milestones already released
+    // count toward the award. The late penalty and voucher discount are left
+    // to the arbiter's split, and the rest of the escrow goes back to the
+    // customer when it closes
+    pub fn awarded(
+        &self,
+        delivery: &Delivery,
+        config: &Config,
+        fleet_share_bps: u16,
+        customer_share_bps: u16,
+    ) -> Result<Self> {
+        let operator_bps = 10000u64
+            .checked_sub(customer_share_bps as u64)
+            .ok_or(ErrorCode::InvalidParameter)?;
+        let rounding = config.fee_rounding;
+        let earned = self.vehicle_payment
+            .checked_add(self.penalty)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(delivery.milestone_released)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let award = apply_bps(earned, operator_bps, rounding)?;
+        let vehicle_payment = award.saturating_sub(delivery.milestone_released);
+        let fleet_share = apply_bps(award, fleet_share_bps as u64, rounding)?.min(vehicle_payment);
+        let fee = apply_bps(self.fee, operator_bps, rounding)?;
+        let tax = apply_bps(self.tax, operator_bps, rounding)?;
+        let insurance = apply_bps(fee, config.insurance_bps as u64, rounding)?;
+        let operator_payment = vehicle_payment
+            .checked_sub(fleet_share)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let released = vehicle_payment
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(tax)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(delivery.round_up)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(Self {
+            fee,
+            discount: 0,
+            insurance,
+            tax,
+            penalty: 0,
+            vehicle_payment,
+            fleet_share,
+            operator_payment,
+            released,
+        })
+    }
+}
+
+// Where a settled order's escrow goes. Single and batch settlement fill it
+// from their own accounts and share Settlement::pay_out, so both route
+// every lamport the same way
+pub struct PayoutAccounts<'a, 'info> {
+    pub customer: AccountInfo<'info>,
+    pub earnings: AccountInfo<'info>,
+    pub fleet_payout: Option<AccountInfo<'info>>,
+    pub treasury: AccountInfo<'info>,
+    pub tax_vault: AccountInfo<'info>,
+    pub charity: Option<AccountInfo<'info>>,
+    pub insurance_pool: Option<&'a mut Account<'info, InsurancePool>>,
+    // Customer's and operator's referral, each with whether their profile
+    // says they were referred
+    pub referrals: [(bool, Option<&'a mut Account<'info, Referral>>); 2],
+}
+
+impl Settlement {
+    // Moves every part of the settlement out of the escrow. Returns the
+    // treasury's part of the fee, already moved, for the caller to book
+    // with Treasury::credit_fee; the operator payment lands on `earnings`
+    // for the caller to accrue
+    pub fn pay_out<'info>(
+        &self,
+        escrow: &mut Account<'info, Escrow>,
+        delivery: &Delivery,
+        config: &Config,
+        fleet: Option<&Fleet>,
+        to: PayoutAccounts<'_, 'info>,
+    ) -> Result<u64> {
+        let escrow_info = escrow.to_account_info();
+
+        escrow.withdraw(self.operator_payment)?;
+        move_lamports(&escrow_info, &to.earnings, self.operator_payment)?;
+
+        if self.fleet_share > 0 {
+            let fleet_payout = to.fleet_payout.ok_or(ErrorCode::FleetPayoutRequired)?;
+            let payout_destination = fleet.map(|fleet| fleet.payout_destination);
+            require!(payout_destination == Some(fleet_payout.key()), ErrorCode::FleetPayoutRequired);
+            escrow.withdraw(self.fleet_share)?;
+            move_lamports(&escrow_info, &fleet_payout, self.fleet_share)?;
+        }
+
+        // The platform fee, less the insurance pool's slice
+        escrow.withdraw(self.fee)?;
+        let mut treasury_fee = self.fee
+            .checked_sub(self.insurance)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if self.insurance > 0 {
+            let pool = to.insurance_pool.ok_or(ErrorCode::InsurancePoolRequired)?;
+            move_lamports(&escrow_info, &pool.to_account_info(), self.insurance)?;
+            pool.contribute(self.insurance)?;
+        }
+
+        // Whoever referred the customer or the operator earns a share of
+        // the rest; a referred party's referral account has to be passed
+        let referral_base = treasury_fee;
+        for (referred, referral) in to.referrals {
+            if referred {
+                let referral = referral.ok_or(ErrorCode::ReferralRequired)?;
+                let share = apply_bps(referral_base, config.referral_bps as u64, FeeRounding::Floor)?;
+                move_lamports(&escrow_info, &referral.to_account_info(), share)?;
+                referral.accrue(share)?;
+                treasury_fee = treasury_fee
+                    .checked_sub(share)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+        }
+        move_lamports(&escrow_info, &to.treasury, treasury_fee)?;
+
+        // VAT/GST goes to the tax vault
+        escrow.withdraw(self.tax)?;
+        move_lamports(&escrow_info, &to.tax_vault, self.tax)?;
+
+        // The late penalty, voucher discount and no-show deposit go back to
+        // the customer now that the package was tendered
+        let refund = self.penalty
+            .checked_add(self.discount)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(delivery.no_show_deposit)
+            .ok_or(ErrorCode::MathOverflow)?;
+        escrow.withdraw(refund)?;
+        move_lamports(&escrow_info, &to.customer, refund)?;
+
+        // Donate the customer's round-up
+        if delivery.round_up > 0 {
+            let charity = to.charity.ok_or(ErrorCode::CharityAccountRequired)?;
+            require_keys_eq!(charity.key(), delivery.charity, ErrorCode::InvalidCharity);
+            escrow.withdraw(delivery.round_up)?;
+            move_lamports(&escrow_info, &charity, delivery.round_up)?;
+        }
+        Ok(treasury_fee)
+    }
+}
+
+// Where a settled token order's escrow goes; the token counterpart of
+// PayoutAccounts
+pub struct TokenPayoutAccounts<'a, 'info> {
+    pub operator: &'a InterfaceAccount<'info, TokenAccount>,
+    pub treasury: &'a InterfaceAccount<'info, TokenAccount>,
+    // Receives the late penalty, voucher discount and leftover dust
+    pub customer: &'a InterfaceAccount<'info, TokenAccount>,
+    pub fleet: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    pub insurance: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    // Insurance pool PDA that must own the insurance token account
+    pub insurance_pool: Pubkey,
+    // Receives the escrow's rent once it is closed
+    pub rent_to: AccountInfo<'info>,
+}
+
+impl Settlement {
+    // Moves a token order's settlement out of its escrow and closes it. The
+    // platform fee absorbs the mint's transfer fee on the operator's and the
+    // fleet's legs so both net exactly their share. Returns the platform fee
+    // left after that, insurance slice included
+    pub fn pay_out_token<'info>(
+        &self,
+        token_program: &Interface<'info, TokenInterface>,
+        escrow: &mut InterfaceAccount<'info, TokenAccount>,
+        mint: &InterfaceAccount<'info, Mint>,
+        fleet: Option<&Fleet>,
+        to: TokenPayoutAccounts<'_, 'info>,
+        signer_seeds: &[&[&[u8]]],
+    ) -> Result<u64> {
+        let token_program_info = token_program.to_account_info();
+        let escrow_info = escrow.to_account_info();
+        let mint_info = mint.to_account_info();
+        let decimals = mint.decimals;
+
+        let operator_gross = gross_for_net(&mint_info, self.operator_payment)?;
+        let fleet_gross = gross_for_net(&mint_info, self.fleet_share)?;
+        let transfer_fees = operator_gross
+            .checked_sub(self.operator_payment)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(fleet_gross.checked_sub(self.fleet_share).ok_or(ErrorCode::MathOverflow)?)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let fee = self.fee
+            .checked_sub(transfer_fees)
+            .ok_or(ErrorCode::TransferFeeUncovered)?;
+        let insurance = self.insurance.min(fee);
+        let treasury_fee = fee
+            .checked_sub(insurance)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        transfer_from_token_escrow(
+            &token_program_info,
+            &escrow_info,
+            &mint_info,
+            &to.operator.to_account_info(),
+            operator_gross,
+            decimals,
+            signer_seeds,
+        )?;
+        if self.fleet_share > 0 {
+            let fleet_token = to.fleet.ok_or(ErrorCode::FleetPayoutRequired)?;
+            let payout_destination = fleet.map(|fleet| fleet.payout_destination);
+            require!(payout_destination == Some(fleet_token.owner), ErrorCode::FleetPayoutRequired);
+            transfer_from_token_escrow(
+                &token_program_info,
+                &escrow_info,
+                &mint_info,
+                &fleet_token.to_account_info(),
+                fleet_gross,
+                decimals,
+                signer_seeds,
+            )?;
+        }
+        // The insurance pool's slice is held in a token account the pool owns
+        if insurance > 0 {
+            let insurance_token = to.insurance.ok_or(ErrorCode::InsurancePoolRequired)?;
+            require!(insurance_token.owner == to.insurance_pool, ErrorCode::InsurancePoolRequired);
+            transfer_from_token_escrow(
+                &token_program_info,
+                &escrow_info,
+                &mint_info,
+                &insurance_token.to_account_info(),
+                insurance,
+                decimals,
+                signer_seeds,
+            )?;
+        }
+        transfer_from_token_escrow(
+            &token_program_info,
+            &escrow_info,
+            &mint_info,
+            &to.treasury.to_account_info(),
+            treasury_fee,
+            decimals,
+            signer_seeds,
+        )?;
+
+        // The late penalty, voucher discount and any rounding dust go back
+        // to the customer with whatever else is left in the escrow
+        escrow.reload()?;
+        transfer_from_token_escrow(
+            &token_program_info,
+            &escrow_info,
+            &mint_info,
+            &to.customer.to_account_info(),
+            escrow.amount,
+            decimals,
+            signer_seeds,
+        )?;
+        close_token_escrow(&token_program_info, &escrow_info, &mint_info, &to.rent_to, signer_seeds)?;
+        Ok(fee)
+    }
+}
+
+// Tax owed on a fare; inclusive rates carve the tax out of the amount instead of adding to it
+pub fn compute_tax(amount: u64, tax_bps: u16, inclusive: bool) -> Result<u64> {
+    let divisor = if inclusive { 10000 + tax_bps as u128 } else { 10000 };
+    let tax = (amount as u128)
+        .checked_mul(tax_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(divisor)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(tax as u64)
+}
+
+// Voucher bytes for one opening of a channel. The same PDA can be closed and
+// opened again with `settled` back at 0, so the opening's nonce keeps
+// vouchers signed for an earlier opening from being replayed
+pub fn channel_voucher(channel: &Pubkey, nonce: u64, cumulative_amount: u64) -> [u8; VOUCHER_LEN] {
+    let mut message = [0u8; VOUCHER_LEN];
+    message[..32].copy_from_slice(channel.as_ref());
+    message[32..40].copy_from_slice(&nonce.to_le_bytes());
+    message[40..].copy_from_slice(&cumulative_amount.to_le_bytes());
+    message
+}
+
+// Checks that the instruction just before the current one is an Ed25519
+// program verification of `message` signed by `signer`, with the key,
+// signature and message all carried inline in that instruction
+pub fn verify_ed25519_signature(instructions: &AccountInfo, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    let current = load_current_index_checked(instructions)?;
+    require!(current > 0, ErrorCode::InvalidVoucher);
+    let ix = load_instruction_at_checked(current as usize - 1, instructions)?;
+    require!(ix.program_id == ed25519_program::ID, ErrorCode::InvalidVoucher);
+
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, ErrorCode::InvalidVoucher);
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    require!(
+        read_u16(4) == u16::MAX && read_u16(8) == u16::MAX && read_u16(14) == u16::MAX,
+        ErrorCode::InvalidVoucher
+    );
+
+    let key_offset = read_u16(6) as usize;
+    let message_offset = read_u16(10) as usize;
+    let message_len = read_u16(12) as usize;
+    let key = data.get(key_offset..key_offset + 32).ok_or(ErrorCode::InvalidVoucher)?;
+    let signed = data
+        .get(message_offset..message_offset + message_len)
+        .ok_or(ErrorCode::InvalidVoucher)?;
+    require!(key == signer.as_ref() && signed == message, ErrorCode::InvalidVoucher);
+    Ok(())
+}
+
+// Accepts one "lat,lng" component such as "-74.0060" as millionths of a
+// degree; digits past the sixth decimal place are dropped
+fn parse_degrees_e6(value: &str) -> Result<i32> {
+    let value = value.trim();
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    require!(
+        !whole.is_empty()
+            && whole.len() <= 3
+            && whole.bytes().all(|b| b.is_ascii_digit())
+            && fraction.bytes().all(|b| b.is_ascii_digit()),
+        ErrorCode::InvalidLocation
+    );
+    let mut e6 = whole.parse::<i32>().map_err(|_| ErrorCode::InvalidLocation)? * 1_000_000;
+    for (place, digit) in fraction.bytes().take(6).enumerate() {
+        e6 += (digit - b'0') as i32 * 10i32.pow(5 - place as u32);
+    }
+    Ok(if negative { -e6 } else { e6 })
+}
+
+fn fmt_degrees_e6(f: &mut std::fmt::Formatter, e6: i32) -> std::fmt::Result {
+    let sign = if e6 < 0 { "-" } else { "" };
+    let abs = e6.unsigned_abs();
+    write!(f, "{}{}.{:06}", sign, abs / 1_000_000, abs % 1_000_000)
+}
+
+// Grows an account created under an older, shorter layout to `space` bytes,
+// topping up rent from `payer`. The new tail is zeroed, so fields appended
+// since then read back as zero, false or None
+pub fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+) -> Result<()> {
+    require!(account.owner == &crate::ID, ErrorCode::Unauthorized);
+    if account.data_len() >= space {
+        return Ok(());
+    }
+    let shortfall = Rent::get()?.minimum_balance(space).saturating_sub(account.lamports());
+    if shortfall > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+    account.realloc(space, true)?;
+    Ok(())
+}
+
+// Shrinks a program account to `space` bytes and hands the rent it no longer
+// needs to `refund_to`
+pub fn shrink_account<'info>(account: &AccountInfo<'info>, refund_to: &AccountInfo<'info>, space: usize) -> Result<()> {
+    require!(account.owner == &crate::ID, ErrorCode::Unauthorized);
+    account.realloc(space, false)?;
+    let excess = account.lamports().saturating_sub(Rent::get()?.minimum_balance(space));
+    **account.try_borrow_mut_lamports()? -= excess;
+    **refund_to.try_borrow_mut_lamports()? = refund_to
+        .lamports()
+        .checked_add(excess)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(())
+}
+
+// Creates a PDA owned by `owner` whose address or size is only known at run
+// time, the way `init` does for declared accounts. Lamports already sent to
+// the address are kept and `payer` only covers the rent shortfall
+pub fn create_pda_account<'info>(
+    payer: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+    seeds: &[&[u8]],
+    owner: &Pubkey,
+) -> Result<()> {
+    let rent = Rent::get()?.minimum_balance(space);
+    if account.lamports() == 0 {
+        anchor_lang::system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                anchor_lang::system_program::CreateAccount {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+                &[seeds],
+            ),
+            rent,
+            space as u64,
+            owner,
+        )?;
+        return Ok(());
+    }
+    let shortfall = rent.saturating_sub(account.lamports());
+    if shortfall > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+    anchor_lang::system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Allocate {
+                account_to_allocate: account.clone(),
+            },
+            &[seeds],
+        ),
+        space as u64,
+    )?;
+    anchor_lang::system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Assign {
+                account_to_assign: account.clone(),
+            },
+            &[seeds],
+        ),
+        owner,
+    )?;
+    Ok(())
+}
+
+// Contexts that change the config, so every setter can announce the change
+// the same way
+pub trait ConfigChangeContext {
+    fn config_key(&self) -> Pubkey;
+    fn changed_by(&self) -> Pubkey;
+}
+impl ConfigChangeContext for UpdateConfig<'_> {
+    fn config_key(&self) -> Pubkey { self.config.key() }
+    fn changed_by(&self) -> Pubkey { self.authority.key() }
+}
+impl ConfigChangeContext for ApprovedConfigUpdate<'_> {
+    fn config_key(&self) -> Pubkey { self.config.key() }
+    fn changed_by(&self) -> Pubkey { self.authority.key() }
+}
+impl ConfigChangeContext for AdminUpdate<'_> {
+    fn config_key(&self) -> Pubkey { self.config.key() }
+    fn changed_by(&self) -> Pubkey { self.authority.key() }
+}
+impl ConfigChangeContext for AcceptAuthority<'_> {
+    fn config_key(&self) -> Pubkey { self.config.key() }
+    fn changed_by(&self) -> Pubkey { self.new_authority.key() }
+}
+impl ConfigChangeContext for SetSurge<'_> {
+    fn config_key(&self) -> Pubkey { self.config.key() }
+    fn changed_by(&self) -> Pubkey { self.signer.key() }
+}
+
+pub fn emit_config_updated<T: ConfigChangeContext + Bumps>(ctx: &Context<T>) {
+    emit!(ConfigUpdated {
+        schema_version: EVENT_SCHEMA_VERSION,
+        config: ctx.accounts.config_key(),
+        authority: ctx.accounts.changed_by(),
+    });
+}
+
+// Fields of a plain order, whichever currency its fare was given in
+pub struct OrderRequest {
+    pub delivery_id: u64,
+    pub payment_amount: u64,
+    pub pickup_location: String,
+    pub delivery_location: String,
+    pub min_rating: u16,
+    pub required_class: Option<VehicleClass>,
+    pub scheduled_at: Option<i64>,
+    pub voucher_code: Option<String>,
+    // Fare in US cents the lamports were converted from, 0 for SOL fares
+    pub usd_cents: u64,
+}
+
+// Books an order and escrows its fare; shared by the SOL and USD entry points
+pub fn book_delivery_order(ctx: Context<CreateDeliveryOrder>, order: OrderRequest) -> Result<()> {
+    let OrderRequest {
+        delivery_id,
+        payment_amount,
+        pickup_location,
+        delivery_location,
+        min_rating,
+        required_class,
+        scheduled_at,
+        voucher_code,
+        usd_cents,
+    } = order;
+    require!(pickup_location.len() <= 64, ErrorCode::InvalidParameter);
+    require!(delivery_location.len() <= 64, ErrorCode::InvalidParameter);
+    require!(payment_amount > 0, ErrorCode::InvalidAmount);
+    require!(min_rating <= MAX_RATING as u16 * 100, ErrorCode::InvalidParameter);
+
+    let now = Clock::get()?.unix_timestamp;
+    require!(scheduled_at.map_or(true, |at| at > now), ErrorCode::InvalidParameter);
+
+    let config = &ctx.accounts.config;
+    require!(config.is_active && !config.is_paused, ErrorCode::ConfigInactive);
+    require!(!config.settlement_only, ErrorCode::SettlementOnly);
+
+    let customer_key = ctx.accounts.customer.key();
+
+    // Surge is locked in at booking so settlement never depends on the
+    // premium in force later
+    let surge_bps = config.surge_bps;
+    let payment_amount = config.apply_surge(payment_amount)?;
+
+    let region = &ctx.accounts.region;
+    let pricing = OrderPricing::for_region(payment_amount, region, config, &ctx.accounts.customer_profile, now)?;
+    let escrow_amount = pricing.escrow_amount;
+    ctx.accounts.config.record_escrow(escrow_amount, now)?;
+
+    // Snapshot the fiat value at booking time
+    let quote_usd = ctx.accounts.usd_feed.quote(payment_amount, now)?;
+    let quote_eur = match &ctx.accounts.eur_feed {
+        Some(feed) => feed.quote(payment_amount, now)?,
+        None => 0,
+    };
+
+    // Escrow payment from customer
+    anchor_lang::system_program::transfer(
+        CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            anchor_lang::system_program::Transfer {
+                from: ctx.accounts.customer.to_account_info(),
+                to: ctx.accounts.escrow.to_account_info(),
+            },
+        ),
+        escrow_amount,
+    )?;
+
+    let delivery_key = ctx.accounts.delivery.key();
+    let escrow = &mut ctx.accounts.escrow;
+    escrow.open(ctx.bumps.escrow, delivery_key, customer_key, escrow_amount);
+
+    let delivery = &mut ctx.accounts.delivery;
+    delivery.bump = ctx.bumps.delivery;
+    delivery.book(delivery_id, customer_key, payment_amount, region, &pricing, now);
+    delivery.surge_bps = surge_bps;
+    delivery.quote_usd_micros = quote_usd;
+    delivery.quote_eur_micros = quote_eur;
+    delivery.set_locations(pickup_location, delivery_location);
+    delivery.min_rating = min_rating;
+    delivery.required_class = required_class;
+    delivery.scheduled_at = scheduled_at;
+    delivery.usd_cents = usd_cents;
+    if let Some(board) = ctx.accounts.open_orders.as_deref_mut() {
+        board.insert(delivery_key)?;
+        delivery.listed = true;
+        delivery.listed_page = board.page;
+    }
+
+    // The discount is fixed now and refunded out of the platform fee at
+    // settlement
+    match (ctx.accounts.voucher.as_mut(), ctx.accounts.voucher_use.as_ref(), voucher_code) {
+        (Some(voucher), Some(voucher_use), Some(code)) => {
+            voucher.redeem(&code, now)?;
+            delivery.voucher_discount =
+                apply_bps(payment_amount, voucher.discount_bps as u64, FeeRounding::Floor)?;
+            let voucher_key = voucher.key();
+            delivery.voucher = Some(voucher_key);
+
+            // One redemption per customer: the use PDA can only be
+            // created once while it is open
+            let (use_key, use_bump) = Pubkey::find_program_address(
+                &[b"voucher_use", voucher_key.as_ref(), customer_key.as_ref()],
+                &crate::ID,
+            );
+            require!(voucher_use.key() == use_key, ErrorCode::InvalidPromoCode);
+            let use_info = voucher_use.to_account_info();
+            require!(use_info.data_is_empty(), ErrorCode::VoucherAlreadyUsed);
+            create_pda_account(
+                &ctx.accounts.customer.to_account_info(),
+                &use_info,
+                &ctx.accounts.system_program.to_account_info(),
+                8 + VoucherUse::LEN,
+                &[b"voucher_use", voucher_key.as_ref(), customer_key.as_ref(), &[use_bump]],
+                &crate::ID,
+            )?;
+            let record = VoucherUse {
+                bump: use_bump,
+                voucher: voucher_key,
+                customer: customer_key,
+                delivery: delivery_key,
+            };
+            record.try_serialize(&mut &mut use_info.try_borrow_mut_data()?[..])?;
+        }
+        (None, None, None) => {}
+        _ => return err!(ErrorCode::InvalidPromoCode),
+    }
+
+    let profile = &mut ctx.accounts.customer_profile;
+    profile.open(ctx.bumps.customer_profile, customer_key, delivery.created_at);
+    profile.orders_created = profile.orders_created
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let daily_stats = &mut ctx.accounts.daily_stats;
+    daily_stats.open(ctx.bumps.daily_stats, delivery.created_at);
+    daily_stats.orders_created = daily_stats.orders_created
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let global_stats = &mut ctx.accounts.global_stats;
+    global_stats.open(ctx.bumps.global_stats, GlobalStats::shard_of(delivery_id));
+    global_stats.record_created()?;
+    emit!(OrderCreated {
+        schema_version: EVENT_SCHEMA_VERSION,
+        delivery: ctx.accounts.delivery.key(),
+        customer: ctx.accounts.delivery.customer,
+        payment_amount: ctx.accounts.delivery.payment_amount,
+        region_id: ctx.accounts.delivery.region_id,
+    });
+    Ok(())
+}
+
+// Whether an order can no longer settle, so whatever its escrow still
+// holds is stranded: the order account is gone, closed down to a
+// `ClosedDelivery`, or finished. An order in flight or in dispute is not
+pub fn order_released(delivery: &AccountInfo) -> Result<bool> {
+    if delivery.owner != &crate::ID || delivery.data_is_empty() {
+        return Ok(true);
+    }
+    let data = delivery.try_borrow_data()?;
+    if data.starts_with(ClosedDelivery::DISCRIMINATOR) {
+        return Ok(true);
+    }
+    if !data.starts_with(Delivery::DISCRIMINATOR) {
+        return Ok(false);
+    }
+    Ok(Delivery::try_deserialize(&mut &data[..])?.is_finished())
+}
+
+// Fails unless the order was accepted, is still undelivered and the
+// config's abandonment window has passed since acceptance
+pub fn require_abandoned(delivery: &Delivery, config: &Config, now: i64) -> Result<()> {
+    require!(config.is_active, ErrorCode::ConfigInactive);
+    require!(config.abandon_seconds > 0, ErrorCode::DeliveryNotAbandoned);
+    require!(delivery.is_underway(), ErrorCode::InvalidDeliveryStatus);
+    require!(delivery.delivered_at.is_none(), ErrorCode::InvalidDeliveryStatus);
+    let accepted_at = delivery.accepted_at.ok_or(ErrorCode::InvalidDeliveryStatus)?;
+    require!(
+        now >= accepted_at.saturating_add(config.abandon_seconds),
+        ErrorCode::DeliveryNotAbandoned
+    );
+    Ok(())
+}
+
+// Slashes the configured share of a vehicle's stake. The treasury takes its
+// configured part and the customer gets the rest. Returns the total taken
+pub fn slash_stake<'info>(
+    stake: &mut Account<'info, VehicleStake>,
+    config: &Config,
+    customer: &AccountInfo<'info>,
+    treasury: &mut Account<'info, Treasury>,
+) -> Result<u64> {
+    let slashed = stake.slash(config.slash_bps, config.fee_rounding)?;
+    let to_treasury = apply_bps(slashed, config.slash_treasury_bps as u64, config.fee_rounding)?;
+    let to_customer = slashed
+        .checked_sub(to_treasury)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let stake_info = stake.to_account_info();
+    move_lamports(&stake_info, customer, to_customer)?;
+    move_lamports(&stake_info, &treasury.to_account_info(), to_treasury)?;
+    treasury.accrue(to_treasury)?;
+    Ok(slashed)
+}
+
+// Hands a refunded order's voucher use back: the voucher gets the use again
+// and the caller's context closes the customer's use PDA
+pub fn release_voucher(
+    delivery: &Delivery,
+    voucher: Option<&mut Account<Voucher>>,
+    voucher_use: Option<&VoucherUse>,
+) -> Result<()> {
+    let Some(voucher_key) = delivery.voucher else {
+        return Ok(());
+    };
+    let (Some(voucher), Some(voucher_use)) = (voucher, voucher_use) else {
+        return err!(ErrorCode::VoucherAccountsRequired);
+    };
+    require!(
+        voucher.key() == voucher_key && voucher_use.voucher == voucher_key,
+        ErrorCode::VoucherAccountsRequired
+    );
+    voucher.release();
+    Ok(())
+}
+
+// Basis-point share of an amount, rounded per the configured policy so the
+// deployment decides who absorbs the rounding dust
+pub fn apply_bps(amount: u64, bps: u64, rounding: FeeRounding) -> Result<u64> {
+    let product = (amount as u128)
+        .checked_mul(bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let quotient = product / 10000;
+    let remainder = product % 10000;
+    let round_up = match rounding {
+        FeeRounding::Floor => false,
+        FeeRounding::Ceiling => remainder > 0,
+        FeeRounding::RoundHalfEven => remainder > 5000 || (remainder == 5000 && quotient % 2 == 1),
+    };
+    let rounded = if round_up { quotient + 1 } else { quotient };
+    Ok(u64::try_from(rounded).map_err(|_| ErrorCode::MathOverflow)?)
+}
+
+fn token_extension_bit(extension: ExtensionType) -> Option<u16> {
+    match extension {
+        ExtensionType::TransferFeeConfig => Some(TOKEN_EXT_TRANSFER_FEE),
+        ExtensionType::InterestBearingConfig => Some(TOKEN_EXT_INTEREST_BEARING),
+        ExtensionType::MetadataPointer | ExtensionType::TokenMetadata => Some(TOKEN_EXT_METADATA),
+        ExtensionType::MintCloseAuthority => Some(TOKEN_EXT_MINT_CLOSE_AUTHORITY),
+        ExtensionType::DefaultAccountState => Some(TOKEN_EXT_DEFAULT_ACCOUNT_STATE),
+        ExtensionType::ConfidentialTransferMint => Some(TOKEN_EXT_CONFIDENTIAL_TRANSFER),
+        _ => None,
+    }
+}
+
+// Refuses Token-2022 mints carrying an extension outside the allowed mask;
+// classic SPL mints have none and always pass
+pub fn check_mint_extensions(mint: &AccountInfo, allowed: u16) -> Result<()> {
+    if *mint.owner != anchor_spl::token_2022::ID {
+        return Ok(());
+    }
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<anchor_spl::token_2022::spl_token_2022::state::Mint>::unpack(&data)?;
+    for extension in state.get_extension_types()? {
+        let bit = token_extension_bit(extension).ok_or(ErrorCode::TokenExtensionNotAllowed)?;
+        require!(allowed & bit == bit, ErrorCode::TokenExtensionNotAllowed);
+    }
+    Ok(())
+}
+
+// The mint's transfer fee for the current epoch, if it charges one
+pub fn transfer_fee_for(mint: &AccountInfo) -> Result<Option<TransferFee>> {
+    if *mint.owner != anchor_spl::token_2022::ID {
+        return Ok(None);
+    }
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<anchor_spl::token_2022::spl_token_2022::state::Mint>::unpack(&data)?;
+    let Ok(fee_config) = state.get_extension::<TransferFeeConfig>() else {
+        return Ok(None);
+    };
+    Ok(Some(*fee_config.get_epoch_fee(Clock::get()?.epoch)))
+}
+
+// Decryptable balances arrive as the 36-byte authenticated-encryption
+// ciphertext the key holder computed off-chain
+pub fn decryptable_balance(bytes: [u8; 36]) -> DecryptableBalance {
+    bytemuck::cast(bytes)
+}
+
+// Runs a Token-2022 instruction sequence, signing for any program PDAs
+pub fn invoke_token_2022(
+    instructions: Vec<Instruction>,
+    accounts: &[AccountInfo],
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    for instruction in instructions {
+        invoke_signed(&instruction, accounts, signer_seeds)?;
+    }
+    Ok(())
+}
+
+// Applies the funding credited to a confidential escrow so its whole balance
+// can be proven against and spent
+pub fn apply_confidential_pending<'info>(
+    token_program: &AccountInfo<'info>,
+    escrow: &AccountInfo<'info>,
+    available_balance: [u8; 36],
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    invoke_token_2022(
+        vec![confidential_ix::apply_pending_balance(
+            token_program.key,
+            escrow.key,
+            CONFIDENTIAL_ESCROW_MAX_CREDITS,
+            &decryptable_balance(available_balance),
+            escrow.key,
+            &[],
+        )?],
+        &[escrow.clone(), token_program.clone()],
+        signer_seeds,
+    )
+}
+
+// Checks a verified ciphertext-commitment equality proof showing that the
+// escrow's available balance holds exactly the committed amount
+pub fn check_escrow_commitment(escrow: &AccountInfo, proof: &AccountInfo, commitment: &[u8; 32]) -> Result<()> {
+    require_keys_eq!(*proof.owner, zk_elgamal_proof_program::id(), ErrorCode::CommitmentMismatch);
+    let proof_data = proof.try_borrow_data()?;
+    let state = bytemuck::try_from_bytes::<ProofContextState<CiphertextCommitmentEqualityProofContext>>(&proof_data)
+        .map_err(|_| ErrorCode::CommitmentMismatch)?;
+    require!(
+        state.proof_type == ProofType::CiphertextCommitmentEquality.into(),
+        ErrorCode::CommitmentMismatch
+    );
+
+    let escrow_data = escrow.try_borrow_data()?;
+    let account = StateWithExtensions::<anchor_spl::token_2022::spl_token_2022::state::Account>::unpack(&escrow_data)?;
+    let confidential = account.get_extension::<ConfidentialTransferAccount>()?;
+    let context = &state.proof_context;
+    require!(
+        bytemuck::bytes_of(&context.pubkey) == bytemuck::bytes_of(&confidential.elgamal_pubkey)
+            && bytemuck::bytes_of(&context.ciphertext) == bytemuck::bytes_of(&confidential.available_balance)
+            && bytemuck::bytes_of(&context.commitment) == commitment,
+        ErrorCode::CommitmentMismatch
+    );
+    Ok(())
+}
+
+// Pays each leg out of a confidential escrow. `owner_for` names the wallet
+// that must own a payee's token account, or None where the payee has no part
+// in this payout; no payee is paid twice
+pub fn pay_confidential_legs<'info>(
+    token_program: &AccountInfo<'info>,
+    escrow: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    legs: &[ConfidentialLeg],
+    accounts: &[AccountInfo<'info>],
+    owner_for: impl Fn(ConfidentialPayee) -> Option<Pubkey>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    require!(
+        accounts.len() == legs.len() * CONFIDENTIAL_LEG_ACCOUNTS,
+        ErrorCode::InvalidConfidentialLeg
+    );
+    for (index, (leg, leg_accounts)) in legs.iter().zip(accounts.chunks(CONFIDENTIAL_LEG_ACCOUNTS)).enumerate() {
+        require!(
+            !legs[..index].iter().any(|earlier| earlier.payee == leg.payee),
+            ErrorCode::InvalidConfidentialLeg
+        );
+        let owner = owner_for(leg.payee).ok_or(ErrorCode::InvalidConfidentialLeg)?;
+        let [destination, equality_proof, validity_proof, range_proof] = leg_accounts else {
+            return err!(ErrorCode::InvalidConfidentialLeg);
+        };
+        require_keys_eq!(*destination.owner, token_program.key(), ErrorCode::InvalidConfidentialLeg);
+        {
+            let data = destination.try_borrow_data()?;
+            let account = StateWithExtensions::<anchor_spl::token_2022::spl_token_2022::state::Account>::unpack(&data)?;
+            require!(
+                account.base.owner == owner && account.base.mint == mint.key(),
+                ErrorCode::InvalidConfidentialLeg
+            );
+        }
+        invoke_token_2022(
+            confidential_ix::transfer(
+                token_program.key,
+                escrow.key,
+                mint.key,
+                destination.key,
+                &decryptable_balance(leg.escrow_after),
+                escrow.key,
+                &[],
+                ProofLocation::ContextStateAccount(equality_proof.key),
+                ProofLocation::ContextStateAccount(validity_proof.key),
+                ProofLocation::ContextStateAccount(range_proof.key),
+            )?,
+            &[
+                escrow.clone(),
+                mint.clone(),
+                destination.clone(),
+                equality_proof.clone(),
+                validity_proof.clone(),
+                range_proof.clone(),
+                token_program.clone(),
+            ],
+            signer_seeds,
+        )?;
+    }
+    Ok(())
+}
+
+// A confidential account closes only once its encrypted balance is proven
+// zero; the rent then goes to `rent_to`
+pub fn close_confidential_escrow<'info>(
+    token_program: &AccountInfo<'info>,
+    escrow: &AccountInfo<'info>,
+    zero_balance_proof: &AccountInfo<'info>,
+    rent_to: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    invoke_token_2022(
+        confidential_ix::empty_account(
+            token_program.key,
+            escrow.key,
+            escrow.key,
+            &[],
+            ProofLocation::ContextStateAccount(zero_balance_proof.key),
+        )?,
+        &[escrow.clone(), zero_balance_proof.clone(), token_program.clone()],
+        signer_seeds,
+    )?;
+    token_interface::close_account(CpiContext::new_with_signer(
+        token_program.clone(),
+        CloseAccount {
+            account: escrow.clone(),
+            destination: rent_to.clone(),
+            authority: escrow.clone(),
+        },
+        signer_seeds,
+    ))
+}
+
+// Amount to send so the recipient is credited `net` after the transfer fee
+pub fn gross_for_net(mint: &AccountInfo, net: u64) -> Result<u64> {
+    let Some(transfer_fee) = transfer_fee_for(mint)? else {
+        return Ok(net);
+    };
+    let fee = transfer_fee.calculate_inverse_fee(net).ok_or(ErrorCode::MathOverflow)?;
+    Ok(net.checked_add(fee).ok_or(ErrorCode::MathOverflow)?)
+}
+
+// Moves tokens out of an order's token escrow, which is its own authority
+pub fn transfer_from_token_escrow<'info>(
+    token_program: &AccountInfo<'info>,
+    escrow: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    amount: u64,
+    decimals: u8,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            token_program.clone(),
+            TransferChecked {
+                from: escrow.clone(),
+                mint: mint.clone(),
+                to: to.clone(),
+                authority: escrow.clone(),
+            },
+            signer_seeds,
+        ),
+        amount,
+        decimals,
+    )
+}
+
+// Closes an emptied token escrow, its rent going to `rent_to`. Fees withheld
+// on the escrow when it was funded block the close, so they are swept to the
+// mint first
+pub fn close_token_escrow<'info>(
+    token_program: &AccountInfo<'info>,
+    escrow: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    rent_to: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    if transfer_fee_for(mint)?.is_some() {
+        harvest_withheld_tokens_to_mint(
+            CpiContext::new(
+                token_program.clone(),
+                HarvestWithheldTokensToMint {
+                    token_program_id: token_program.clone(),
+                    mint: mint.clone(),
+                },
+            ),
+            vec![escrow.clone()],
+        )?;
+    }
+    token_interface::close_account(CpiContext::new_with_signer(
+        token_program.clone(),
+        CloseAccount {
+            account: escrow.clone(),
+            destination: rent_to.clone(),
+            authority: escrow.clone(),
+        },
+        signer_seeds,
+    ))
+}
+
+// Returns everything a cancelled or expired token order's escrow holds to
+// the customer and closes it. A transfer-fee mint charges the refund leg
+pub fn refund_token_escrow<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    escrow: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    customer_token: &AccountInfo<'info>,
+    customer: &AccountInfo<'info>,
+    delivery_id: u64,
+    escrow_bump: u8,
+) -> Result<()> {
+    let customer_key = customer.key();
+    let id_bytes = delivery_id.to_le_bytes();
+    let signer_seeds: &[&[&[u8]]] = &[&[b"escrow_token", customer_key.as_ref(), &id_bytes, &[escrow_bump]]];
+    let token_program = token_program.to_account_info();
+    let escrow_info = escrow.to_account_info();
+    let mint_info = mint.to_account_info();
+    transfer_from_token_escrow(
+        &token_program,
+        &escrow_info,
+        &mint_info,
+        customer_token,
+        escrow.amount,
+        mint.decimals,
+        signer_seeds,
+    )?;
+    close_token_escrow(&token_program, &escrow_info, &mint_info, customer, signer_seeds)
+}
+
+#[account]
+pub struct Region {
+    pub bump: u8,
+    pub region_id: u16,
+    pub total_queued: u64,
+    pub queue: Vec<QueueEntry>,
+    pub tax_bps: u16,
+    pub tax_inclusive: bool,
+    pub robots_allowed: bool,
+    pub hazard_level: u8,
+    pub hazard_updated_at: i64,
+    pub congestion_index: u8,
+    pub congestion_updated_at: i64,
+}
+impl Region {
+    pub const LEN: usize = 1 + 2 + 8 + (4 + MAX_QUEUE_LEN * QueueEntry::LEN) + 2 + 1 + 1 + 1 + 8 + 1 + 8;
+
+    // Surcharge scales with the reported hazard level, zero in clear weather
+    // or once the reading has gone stale
+    pub fn hazard_surcharge(&self, amount: u64, surcharge_bps: u16, now: i64) -> Result<u64> {
+        let hazard_level = if Self::is_fresh(self.hazard_updated_at, now) { self.hazard_level } else { 0 };
+        Ok(amount
+            .checked_mul(surcharge_bps as u64 * hazard_level as u64)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(10000)
+            .ok_or(ErrorCode::MathOverflow)?)
+    }
+
+    // Congestion index to stretch SLA deadlines by, zero once the reading has
+    // gone stale
+    pub fn congestion_at(&self, now: i64) -> u8 {
+        if Self::is_fresh(self.congestion_updated_at, now) { self.congestion_index } else { 0 }
+    }
+
+    fn is_fresh(updated_at: i64, now: i64) -> bool {
+        now.saturating_sub(updated_at) <= MAX_REGION_READING_AGE_SECONDS
+    }
+
+    pub fn next_index(&self) -> Option<usize> {
+        self.queue
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, entry)| (std::cmp::Reverse(entry.priority), entry.queued_at, *i))
+            .map(|(i, _)| i)
+    }
+
+    // Takes an order off the waitlist; total_queued counts what is waiting
+    pub fn dequeue(&mut self, delivery: Pubkey) -> Result<()> {
+        let before = self.queue.len();
+        self.queue.retain(|entry| entry.delivery != delivery);
+        let removed = (before - self.queue.len()) as u64;
+        self.total_queued = self.total_queued
+            .checked_sub(removed)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct QueueEntry {
+    pub delivery: Pubkey,
+    pub priority: u8,
+    pub queued_at: i64,
+}
+impl QueueEntry { pub const LEN: usize = 32 + 1 + 8; }
+
+#[account]
+pub struct PrepaidWallet {
+    pub bump: u8,
+    pub owner: Pubkey,
+    pub balance: u64,
+}
+impl PrepaidWallet { pub const LEN: usize = 1 + 32 + 8; }
+
+#[account]
+pub struct StandingOrder {
+    pub bump: u8,
+    pub standing_order_id: u64,
+    pub customer: Pubkey,
+    pub pickup_location: String,
+    pub delivery_location: String,
+    pub region_id: u16,
+    pub max_price: u64,
+    pub interval_seconds: i64,
+    pub remaining_runs: u32,
+    pub matched_count: u32,
+    pub next_eligible_at: i64,
+}
+impl StandingOrder {
+    pub const LEN: usize = 1 + 8 + 32 + (4 + 64) + (4 + 64) + 2 + 8 + 8 + 4 + 4 + 8;
+
+    // Materialized deliveries carry the standing order id in bits 32-54.
+    // One-off ids are picked by the customer, so one booked by hand at or
+    // above 1 << 32 can still take a match's id; that match then fails
+    // until the one-off order is closed
+    pub fn next_delivery_id(&self) -> u64 {
+        (self.standing_order_id << 32) | self.matched_count as u64
+    }
+}
+
+#[account]
+pub struct Subscription {
+    pub bump: u8,
+    pub subscription_id: u64,
+    pub customer: Pubkey,
+    pub pickup_location: String,
+    pub delivery_location: String,
+    pub region_id: u16,
+    pub payment_amount: u64,
+    pub period_seconds: i64,
+    pub remaining_runs: u32,
+    pub spawned_count: u32,
+    pub next_spawn_at: i64,
+    pub balance: u64,
+}
+impl Subscription {
+    pub const LEN: usize = 1 + 8 + 32 + (4 + 64) + (4 + 64) + 2 + 8 + 8 + 4 + 4 + 8 + 8;
+
+    // Spawned deliveries set bit 55 so they never collide with standing-order
+    // matches, which share namespace 0
+    pub fn next_delivery_id(&self) -> u64 {
+        1 << 55 | (self.subscription_id << 32) | self.spawned_count as u64
+    }
+}
+
+#[account]
+pub struct RevenueEpoch {
+    pub bump: u8,
+    pub config: Pubkey,
+    pub epoch: u64,
+    pub fees_earned: u64,
+    pub cumulative_fees: u64,
+    pub closed_at: i64,
+}
+impl RevenueEpoch { pub const LEN: usize = 1 + 32 + 8 + 8 + 8 + 8; }
+
+// One shard of the protocol-wide lifetime counters; dashboards sum the
+// GLOBAL_STATS_SHARDS accounts. Volume and fees only count SOL settlements,
+// token and confidential completions are counted apart since their amounts
+// are in other units or hidden
+#[account]
+pub struct GlobalStats {
+    pub bump: u8,
+    pub shard: u8,
+    pub orders_created: u64,
+    pub orders_completed: u64,
+    pub orders_cancelled: u64,
+    pub token_orders_completed: u64,
+    pub total_volume: u128,
+    pub total_fees: u128,
+}
+impl GlobalStats {
+    pub const LEN: usize = 1 + 1 + 8 + 8 + 8 + 8 + 16 + 16;
+
+    // Orders spread over the shards by id; any shard may count any order
+    pub fn shard_of(delivery_id: u64) -> u8 {
+        (delivery_id % GLOBAL_STATS_SHARDS) as u8
+    }
+
+    // Fills in identity fields the first time an init_if_needed shard is seen
+    pub fn open(&mut self, bump: u8, shard: u8) {
+        if self.bump == 0 {
+            self.bump = bump;
+            self.shard = shard;
+        }
+    }
+
+    pub fn record_created(&mut self) -> Result<()> {
+        self.orders_created = self.orders_created
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_completed(&mut self, volume: u64, fee: u64) -> Result<()> {
+        self.orders_completed = self.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.total_volume = self.total_volume
+            .checked_add(volume as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.total_fees = self.total_fees
+            .checked_add(fee as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_token_completed(&mut self) -> Result<()> {
+        self.orders_completed = self.orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.token_orders_completed = self.token_orders_completed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_cancelled(&mut self) -> Result<()> {
+        self.orders_cancelled = self.orders_cancelled
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+}
+
+#[account]
+pub struct DailyStats {
+    pub bump: u8,
+    pub day: u32,
+    pub orders_created: u64,
+    pub orders_completed: u64,
+    pub initialized: bool,
+}
+impl DailyStats {
+    pub const LEN: usize = 1 + 4 + 8 + 8 + 1;
+
+    pub fn day_of(unix_timestamp: i64) -> u32 {
+        (unix_timestamp / SECONDS_PER_DAY) as u32
+    }
+
+    pub fn current_day() -> Result<u32> {
+        Ok(Self::day_of(Clock::get()?.unix_timestamp))
+    }
+
+    // Fills in identity fields the first time an init_if_needed bucket is seen
+    pub fn open(&mut self, bump: u8, now: i64) {
+        if !self.initialized {
+            self.initialized = true;
+            self.bump = bump;
+            self.day = Self::day_of(now);
+        }
+    }
+}
+
+// Last MAX_VEHICLE_HISTORY settlements of one vehicle, oldest first, so
+// reputation and analytics need no off-chain indexer
+#[account]
+pub struct VehicleHistory {
+    pub bump: u8,
+    pub vehicle: Pubkey,
+    pub entries: Vec<HistoryEntry>,
+}
+impl VehicleHistory {
+    pub const LEN: usize = 1 + 32 + (4 + MAX_VEHICLE_HISTORY * HistoryEntry::LEN);
+
+    // Fills in identity fields the first time an init_if_needed ring is seen
+    pub fn open(&mut self, bump: u8, vehicle: Pubkey) {
+        if self.bump == 0 {
+            self.bump = bump;
+            self.vehicle = vehicle;
+        }
+    }
+
+    // Oldest entries are dropped once the ring is full
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.entries.len() >= MAX_VEHICLE_HISTORY {
+            self.entries.remove(0);
+        }
+        self.entries.push(entry);
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub delivery_id: u64,
+    pub payment_amount: u64,
+    pub completed_at: i64,
+}
+impl HistoryEntry { pub const LEN: usize = 8 + 8 + 8; }
+
+// What is left of an order after closeDelivery: enough to keep its address
+// taken so the id cannot be booked again
+#[account]
+pub struct ClosedDelivery {
+    pub customer: Pubkey,
+    pub delivery_id: u64,
+    pub closed_at: i64,
+}
+impl ClosedDelivery {
+    pub const LEN: usize = 32 + 8 + 8;
+}
+
+#[account]
+pub struct Receipt {
+    pub bump: u8,
+    pub delivery: Pubkey,
+    pub invoice_number: u64,
+    pub customer: Pubkey,
+    pub vehicle: Pubkey,
+    pub operator: Pubkey,
+    pub gross_amount: u64,
+    pub platform_fee: u64,
+    pub operator_payment: u64,
+    pub issued_at: i64,
+    pub tax_bps: u16,
+    pub tax_inclusive: bool,
+    pub tax_amount: u64,
+    pub quote_usd_micros: u64,
+    pub quote_eur_micros: u64,
+    pub charity_amount: u64,
+    pub fleet_share: u64,
+    pub pickup_proof_hash: [u8; 32],
+    pub dropoff_proof_hash: [u8; 32],
+    pub picked_up_at: Option<i64>,
+    pub delivered_at: Option<i64>,
+    pub nft_mint: Option<Pubkey>,
+    pub cnft_tree: Option<Pubkey>,
+    pub cnft_leaf_index: u64,
+    // Mint the amounts are in; the default key for lamports
+    pub payment_mint: Pubkey,
+}
+impl Receipt {
+    pub const LEN: usize = 1 + 32 + 8 + 32 + 32 + 32 + 8 + 8 + 8 + 8 + 2 + 1 + 8 + 8 + 8 + 8 + 8
+        + 32 + 32 + (1 + 8) + (1 + 8) + (1 + 32) + (1 + 32) + 8 + 32;
+}
+
+#[account]
+pub struct TaxVault {
+    pub bump: u8,
+    pub config: Pubkey,
+    pub total_collected: u64,
+    pub total_withdrawn: u64,
+}
+impl TaxVault { pub const LEN: usize = 1 + 32 + 8 + 8; }
+
+// Platform fees from lamport settlements; the lamports sit on this account
+// above its rent until a treasurer withdraws them
+#[account]
+pub struct Treasury {
+    pub bump: u8,
+    pub config: Pubkey,
+    pub total_collected: u64,
+    pub total_withdrawn: u64,
+    // Lamports held for each fee recipient slot until distributed; they are
+    // never part of what the treasurer can withdraw
+    pub recipient_owed: [u64; MAX_FEE_RECIPIENTS],
+}
+impl Treasury {
+    pub const LEN: usize = 1 + 32 + 8 + 8 + MAX_FEE_RECIPIENTS * 8;
+
+    // Books a platform fee already moved into the treasury. Each configured
+    // recipient's share, rounded down, is set aside for them and the
+    // platform keeps the rest, rounding dust included
+    pub fn credit_fee(&mut self, recipients: &[FeeRecipient; MAX_FEE_RECIPIENTS], amount: u64) -> Result<()> {
+        let mut kept = amount;
+        for (slot, owed) in recipients.iter().zip(self.recipient_owed.iter_mut()) {
+            if slot.recipient == Pubkey::default() {
+                continue;
+            }
+            let share = apply_bps(amount, slot.bps as u64, FeeRounding::Floor)?;
+            *owed = owed.checked_add(share).ok_or(ErrorCode::MathOverflow)?;
+            kept = kept.checked_sub(share).ok_or(ErrorCode::MathOverflow)?;
+        }
+        self.accrue(kept)
+    }
+
+    pub fn accrue(&mut self, amount: u64) -> Result<()> {
+        self.total_collected = self.total_collected
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn available(&self) -> Result<u64> {
+        Ok(self.total_collected
+            .checked_sub(self.total_withdrawn)
+            .ok_or(ErrorCode::MathOverflow)?)
+    }
+}
+
+#[account]
+pub struct ProofOracle {
+    pub bump: u8,
+    pub oracle: Pubkey,
+    pub registered_at: i64,
+}
+impl ProofOracle { pub const LEN: usize = 1 + 32 + 8; }
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum ConfirmationMode {
+    Customer,
+    Pin,
+    Oracle,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum DeliveryMode {
+    SignatureRequired,
+    Contactless,
+}
+impl DeliveryMode {
+    pub fn for_confirmation(mode: ConfirmationMode) -> Self {
+        match mode {
+            ConfirmationMode::Oracle => DeliveryMode::Contactless,
+            ConfirmationMode::Customer | ConfirmationMode::Pin => DeliveryMode::SignatureRequired,
+        }
+    }
+
+    // A signed hand-over already proves receipt, so "not received" claims
+    // are only open to contactless drop-offs
+    pub fn allows_non_receipt_claims(&self) -> bool {
+        *self == DeliveryMode::Contactless
+    }
+}
+
+#[account]
+pub struct Escrow {
+    pub bump: u8,
+    pub delivery: Pubkey,
+    pub funder: Pubkey,
+    pub amount: u64,
+    pub state: EscrowState,
+}
+impl Escrow {
+    pub const LEN: usize = 1 + 32 + 32 + 8 + 1;
+
+    pub fn open(&mut self, bump: u8, delivery: Pubkey, funder: Pubkey, amount: u64) {
+        self.bump = bump;
+        self.delivery = delivery;
+        self.funder = funder;
+        self.amount = amount;
+        self.state = EscrowState::Funded;
+    }
+
+    // Records lamports already moved into the escrow account
+    pub fn deposit(&mut self, amount: u64) -> Result<()> {
+        require!(self.state == EscrowState::Funded, ErrorCode::InvalidEscrow);
+        self.amount = self.amount.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    // Reserves lamports about to leave the escrow; never dips into rent
+    pub fn withdraw(&mut self, amount: u64) -> Result<()> {
+        require!(self.state == EscrowState::Funded, ErrorCode::InvalidEscrow);
+        self.amount = self.amount.checked_sub(amount).ok_or(ErrorCode::InsufficientEscrow)?;
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum EscrowState {
+    Funded,
+    Released,
+    Refunded,
+}
+
+// Moves lamports out of a program-owned account with checked balances on both sides
+pub fn move_lamports<'info>(
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let from_balance = from.lamports()
+        .checked_sub(amount)
+        .ok_or(ErrorCode::InsufficientBalance)?;
+    let to_balance = to.lamports()
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    **from.try_borrow_mut_lamports()? = from_balance;
+    **to.try_borrow_mut_lamports()? = to_balance;
+    Ok(())
+}
+
+// A privilege the config authority delegates to another wallet, one
+// account per (kind, holder) pair
+#[account]
+pub struct Role {
+    pub bump: u8,
+    pub kind: RoleKind,
+    pub holder: Pubkey,
+    pub granted_at: i64,
 }
+impl Role { pub const LEN: usize = 1 + 1 + 32 + 8; }
 
-#[derive(Accounts)]
-#[instruction(delivery_id: u64)]
-pub struct CreateDeliveryOrder<'info> {
-    #[account(
-        init,
-        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
-        bump,
-        payer = customer,
-        space = 8 + Delivery::LEN
-    )]
-    pub delivery: Account<'info, Delivery>,
-    #[account(
-        init,
-        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
-        bump,
-        payer = customer,
-        space = 0
-    )]
-    /// CHECK: PDA for holding escrowed payment
-    pub escrow: AccountInfo<'info>,
-    #[account(
-        seeds = [b"config", config.authority.as_ref()],
-        bump = config.bump,
-    )]
-    pub config: Account<'info, Config>,
-    #[account(mut)]
-    pub customer: Signer<'info>,
-    pub system_program: Program<'info, System>,
+#[account]
+pub struct DispatcherKey {
+    pub bump: u8,
+    pub operator: Pubkey,
+    pub dispatcher: Pubkey,
+    pub name: String,
+    pub expires_at: i64,
+    pub created_at: i64,
+    pub permissions: u8,
 }
+impl DispatcherKey {
+    pub const LEN: usize = 1 + 32 + 32 + (4 + 32) + 8 + 8 + 1;
 
-#[derive(Accounts)]
-#[instruction(delivery_id: u64)]
-pub struct AcceptDelivery<'info> {
-    #[account(
-        mut,
-        seeds = [b"delivery", delivery.customer.as_ref(), &delivery_id.to_le_bytes()],
-        bump = delivery.bump,
-    )]
-    pub delivery: Account<'info, Delivery>,
-    #[account(
-        mut,
-        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
-        bump = vehicle.bump,
-    )]
-    pub vehicle: Account<'info, Vehicle>,
-    #[account(
-        seeds = [b"config", config.authority.as_ref()],
-        bump = config.bump,
-    )]
-    pub config: Account<'info, Config>,
-    pub operator: Signer<'info>,
+    pub fn check(&self, permission: u8, now: i64) -> Result<()> {
+        require!(now < self.expires_at, ErrorCode::DispatcherKeyExpired);
+        require!(self.permissions & permission != 0, ErrorCode::MissingPermission);
+        Ok(())
+    }
+}
+
+#[account]
+pub struct RecoveryRequest {
+    pub bump: u8,
+    pub config: Pubkey,
+    pub target: Pubkey,
+    pub recovery_address: Pubkey,
+    pub initiated_at: i64,
+    pub executable_at: i64,
+}
+impl RecoveryRequest { pub const LEN: usize = 1 + 32 + 32 + 32 + 8 + 8; }
+
+// Seeds of a token vault a recovery sweeps, so the program can sign for it
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RecoveryVault {
+    // An order's token escrow, `["escrow_token", customer, delivery_id]`
+    OrderEscrow { customer: Pubkey, delivery_id: u64 },
+    // A token channel's vault, `["channel_token", channel]`
+    Channel { channel: Pubkey },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub enum AdminAction {
+    ConfigChange { change: ConfigChange },
+    InitiateRecovery { target: Pubkey, recovery_address: Pubkey },
+    SetApprovers { approvers: [Pubkey; MAX_APPROVERS], threshold: u8 },
+}
+impl AdminAction {
+    // Tag plus the largest variant, ConfigChange
+    pub const LEN: usize = 1 + ConfigChange::LEN;
+}
+
+#[account]
+pub struct AdminProposal {
+    pub bump: u8,
+    pub config: Pubkey,
+    pub proposal_id: u64,
+    pub proposer: Pubkey,
+    pub action: AdminAction,
+    pub approved_by: [Pubkey; MAX_APPROVERS],
+    pub approval_count: u8,
+    pub created_at: i64,
+    pub executed: bool,
+}
+impl AdminProposal {
+    pub const LEN: usize = 1 + 32 + 8 + 32 + AdminAction::LEN + MAX_APPROVERS * 32 + 1 + 8 + 1;
+}
+
+#[account]
+pub struct MapProvider {
+    pub bump: u8,
+    pub provider: Pubkey,
+    pub price_per_tile: u64,
+    pub tiles_sold: u64,
+    pub is_active: bool,
+    pub registered_at: i64,
+}
+impl MapProvider { pub const LEN: usize = 1 + 32 + 8 + 8 + 1 + 8; }
+
+#[account]
+pub struct MapTilePurchase {
+    pub bump: u8,
+    pub vehicle: Pubkey,
+    pub provider: Pubkey,
+    pub tile_id: u64,
+    pub version_hash: [u8; 32],
+    pub price: u64,
+    pub purchased_at: i64,
+}
+impl MapTilePurchase { pub const LEN: usize = 1 + 32 + 32 + 8 + 32 + 8 + 8; }
+
+#[account]
+pub struct Feature {
+    pub bump: u8,
+    pub feature_id: u64,
+    pub provider: Pubkey,
+    pub price_per_day: u64,
+    pub is_active: bool,
+}
+impl Feature { pub const LEN: usize = 1 + 8 + 32 + 8 + 1; }
+
+#[account]
+pub struct FeatureLicense {
+    pub bump: u8,
+    pub vehicle: Pubkey,
+    pub feature_id: u64,
+    pub expires_at: i64,
+    pub total_paid: u64,
+}
+impl FeatureLicense {
+    pub const LEN: usize = 1 + 32 + 8 + 8 + 8;
+
+    pub fn is_valid(&self, now: i64) -> bool {
+        now < self.expires_at
+    }
+}
+
+#[account]
+pub struct AirCorridor {
+    pub bump: u8,
+    pub corridor_id: u32,
+    pub fee_recipient: Pubkey,
+    pub fee_per_transit: u64,
+    pub transits: u64,
+    pub is_active: bool,
+}
+impl AirCorridor { pub const LEN: usize = 1 + 4 + 32 + 8 + 8 + 1; }
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum VehicleClass {
+    Road,
+    Drone,
+    SidewalkRobot,
+}
+impl VehicleClass {
+    pub const COUNT: usize = 3;
+
+    // Slot of the class in per-class tables such as Config::class_fee_bps
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[account]
+pub struct WeatherOracle {
+    pub bump: u8,
+    pub oracle: Pubkey,
+    pub registered_at: i64,
+}
+impl WeatherOracle { pub const LEN: usize = 1 + 32 + 8; }
+
+#[account]
+pub struct TrafficOracle {
+    pub bump: u8,
+    pub oracle: Pubkey,
+    pub registered_at: i64,
+}
+impl TrafficOracle { pub const LEN: usize = 1 + 32 + 8; }
+
+#[account]
+pub struct PriceFeed {
+    pub bump: u8,
+    pub currency: [u8; 3],
+    pub oracle: Pubkey,
+    pub micros_per_sol: u64,
+    pub updated_at: i64,
+}
+impl PriceFeed {
+    pub const LEN: usize = 1 + 3 + 32 + 8 + 8;
+
+    // Whether the feed was published within MAX_QUOTE_AGE_SECONDS
+    pub fn is_fresh(&self, now: i64) -> bool {
+        self.updated_at > 0 && now - self.updated_at <= MAX_QUOTE_AGE_SECONDS
+    }
+
+    // Value of `lamports` in millionths of the feed currency. The snapshot is
+    // only for display, so a stalled feed gives 0 instead of blocking bookings
+    pub fn quote(&self, lamports: u64, now: i64) -> Result<u64> {
+        if !self.is_fresh(now) {
+            return Ok(0);
+        }
+        let value = (lamports as u128)
+            .checked_mul(self.micros_per_sol as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            / 1_000_000_000;
+        Ok(u64::try_from(value).map_err(|_| ErrorCode::MathOverflow)?)
+    }
+
+    // Lamports worth `micros` millionths of the feed currency
+    pub fn lamports_for(&self, micros: u64, now: i64) -> Result<u64> {
+        require!(self.is_fresh(now), ErrorCode::StalePrice);
+        require!(self.micros_per_sol > 0, ErrorCode::StalePrice);
+        let lamports = (micros as u128)
+            .checked_mul(1_000_000_000)
+            .ok_or(ErrorCode::MathOverflow)?
+            / self.micros_per_sol as u128;
+        Ok(u64::try_from(lamports).map_err(|_| ErrorCode::MathOverflow)?)
+    }
+}
+
+// SOL/USD in millionths of a dollar from the configured Pyth price update.
+// The account must be the one in the config, carry its feed id, be fully
+// verified, no older than PYTH_MAX_AGE_SECONDS, and have a confidence
+// interval within PYTH_MAX_CONF_BPS of the price
+pub fn pyth_micros_per_sol(config: &Config, pyth_price: Option<&Account<PriceUpdateV2>>) -> Result<u64> {
+    require!(config.pyth_price_update != Pubkey::default(), ErrorCode::PythFeedNotConfigured);
+    let pyth_price = pyth_price.ok_or(ErrorCode::PythFeedRequired)?;
+    require_keys_eq!(pyth_price.key(), config.pyth_price_update, ErrorCode::PythFeedMismatch);
+    let price = pyth_price
+        .get_price_no_older_than(&Clock::get()?, PYTH_MAX_AGE_SECONDS, &config.pyth_feed_id)
+        .map_err(|_| error!(ErrorCode::PythPriceStale))?;
+    require!(price.price > 0, ErrorCode::PythPriceStale);
+    let units = price.price as u128;
+    require!(
+        (price.conf as u128) * 10_000 <= units * PYTH_MAX_CONF_BPS as u128,
+        ErrorCode::PythConfidenceTooWide
+    );
+
+    // The price is `price × 10^exponent` dollars
+    let scale = price.exponent.checked_add(6).ok_or(ErrorCode::MathOverflow)?;
+    let micros = if scale >= 0 {
+        units.checked_mul(10u128.checked_pow(scale as u32).ok_or(ErrorCode::MathOverflow)?)
+    } else {
+        10u128.checked_pow(scale.unsigned_abs()).map(|divisor| units / divisor)
+    }
+    .ok_or(ErrorCode::MathOverflow)?;
+    require!(micros > 0, ErrorCode::PythPriceStale);
+    Ok(u64::try_from(micros).map_err(|_| ErrorCode::MathOverflow)?)
+}
+
+// A fee or payout update waiting out the config change timelock
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PendingConfigChange {
+    pub change: ConfigChange,
+    pub queued_by: Pubkey,
+    pub executable_at: i64,
+}
+impl PendingConfigChange { pub const LEN: usize = ConfigChange::LEN + 32 + 8; }
+
+// Every setting that moves money between the platform, its recipients and
+// users. They all wait out the timelock and, with approvers configured,
+// need an approved proposal
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum ConfigChange {
+    FeeAndTreasury { fee_bps: u16, treasury: Pubkey, delay_hours: u16 },
+    ClassFee { class: VehicleClass, fee_bps: Option<u16> },
+    FeeRecipients { recipients: [FeeRecipient; MAX_FEE_RECIPIENTS] },
+    InsuranceShare { insurance_bps: u16 },
+    ReferralShare { referral_bps: u16 },
+}
+impl ConfigChange {
+    // Tag plus the largest variant, FeeRecipients
+    pub const LEN: usize = 1 + MAX_FEE_RECIPIENTS * FeeRecipient::LEN;
+
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            ConfigChange::FeeAndTreasury { fee_bps, treasury, delay_hours } => {
+                require!(fee_bps <= MAX_FEE_BPS, ErrorCode::FeeTooHigh);
+                require!(treasury != Pubkey::default(), ErrorCode::InvalidTreasury);
+                require!(
+                    (MIN_CONFIG_CHANGE_DELAY_HOURS..=MAX_CONFIG_CHANGE_DELAY_HOURS).contains(&delay_hours),
+                    ErrorCode::InvalidParameter
+                );
+            }
+            ConfigChange::ClassFee { fee_bps, .. } => {
+                require!(fee_bps.map_or(true, |bps| bps <= MAX_FEE_BPS), ErrorCode::FeeTooHigh);
+            }
+            ConfigChange::FeeRecipients { recipients } => {
+                // Filled slots come first; the rest keep the default recipient
+                let filled = recipients
+                    .iter()
+                    .take_while(|slot| slot.recipient != Pubkey::default())
+                    .count();
+                let mut total_bps = 0u16;
+                for (i, entry) in recipients.iter().enumerate() {
+                    if i >= filled {
+                        require!(*entry == FeeRecipient::default(), ErrorCode::InvalidParameter);
+                        continue;
+                    }
+                    require!(
+                        entry.bps > 0
+                            && !recipients[..i].iter().any(|other| other.recipient == entry.recipient),
+                        ErrorCode::InvalidParameter
+                    );
+                    total_bps = total_bps
+                        .checked_add(entry.bps)
+                        .ok_or(ErrorCode::MathOverflow)?;
+                }
+                require!(total_bps <= 10000, ErrorCode::InvalidParameter);
+            }
+            ConfigChange::InsuranceShare { insurance_bps } => {
+                require!(insurance_bps <= 10000, ErrorCode::InvalidParameter);
+            }
+            ConfigChange::ReferralShare { referral_bps } => {
+                require!(referral_bps <= MAX_REFERRAL_BPS, ErrorCode::InvalidParameter);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Party a confidential escrow pays out to
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum ConfidentialPayee {
+    Operator,
+    Fleet,
+    InsurancePool,
+    Customer,
+    Treasury,
+}
+
+// One confidential transfer out of an order's escrow, with the escrow's
+// decryptable balance once it is paid
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct ConfidentialLeg {
+    pub payee: ConfidentialPayee,
+    pub escrow_after: [u8; 36],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum RoleKind {
+    // Pause, activation and region setup
+    Admin,
+    // Surge pricing and direct order assignment
+    Dispatcher,
+    // Dispute resolution
+    Arbiter,
+    // Tax vault remittance
+    Treasurer,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum FeeRounding {
+    Floor,
+    Ceiling,
+    RoundHalfEven,
+}
+
+#[account]
+pub struct GeoBucket {
+    pub bump: u8,
+    pub geohash: [u8; GEOHASH_LEN],
+    pub vehicles: Vec<Pubkey>,
+}
+impl GeoBucket {
+    pub const LEN: usize = 1 + GEOHASH_LEN + (4 + MAX_BUCKET_VEHICLES * 32);
+
+    pub fn is_full(&self) -> bool {
+        self.vehicles.len() >= MAX_BUCKET_VEHICLES
+    }
+
+    pub fn insert(&mut self, vehicle: Pubkey) -> Result<()> {
+        if !self.vehicles.contains(&vehicle) {
+            require!(!self.is_full(), ErrorCode::GeoBucketFull);
+            self.vehicles.push(vehicle);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, vehicle: Pubkey) {
+        self.vehicles.retain(|key| *key != vehicle);
+    }
+}
+
+// Pending orders in one region, so vehicles can find work without scanning
+// every delivery account
+#[account]
+pub struct OpenOrders {
+    pub bump: u8,
+    pub region_id: u16,
+    pub page: u16,
+    pub orders: Vec<Pubkey>,
+}
+impl OpenOrders {
+    pub const LEN: usize = 1 + 2 + 2 + (4 + MAX_OPEN_ORDERS * 32);
+
+    pub fn is_full(&self) -> bool {
+        self.orders.len() >= MAX_OPEN_ORDERS
+    }
+
+    pub fn insert(&mut self, delivery: Pubkey) -> Result<()> {
+        if !self.orders.contains(&delivery) {
+            require!(!self.is_full(), ErrorCode::OpenOrdersFull);
+            self.orders.push(delivery);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, delivery: Pubkey) {
+        self.orders.retain(|key| *key != delivery);
+    }
+}
+
+#[account]
+pub struct DeliveryTemplate {
+    pub bump: u8,
+    pub owner: Pubkey,
+    pub template_id: u32,
+    pub pickup_location: String,
+    pub delivery_location: String,
+    pub region_id: u16,
+    pub category: u8,
+    pub requirements: u16,
+    pub default_amount: u64,
+}
+impl DeliveryTemplate { pub const LEN: usize = 1 + 32 + 4 + (4 + 64) + (4 + 64) + 2 + 1 + 2 + 8; }
+
+#[account]
+pub struct PackageManifest {
+    pub bump: u8,
+    pub delivery: Pubkey,
+    pub packages: Vec<Package>,
+}
+impl PackageManifest {
+    pub const LEN: usize = 1 + 32 + (4 + MAX_PACKAGES * Package::LEN);
+
+    pub fn all_settled(&self) -> bool {
+        self.packages.iter().all(|package| package.status != PackageStatus::Pending)
+    }
+
+    // Share of the order, in basis points, carried by packages that failed.
+    // Declared values weigh the packages; without any, each counts the same
+    pub fn failed_bps(&self) -> Result<u16> {
+        let value_of = |failed_only: bool| {
+            self.packages
+                .iter()
+                .filter(|package| !failed_only || package.status == PackageStatus::Failed)
+                .try_fold(0u64, |total, package| total.checked_add(package.declared_value))
+                .ok_or(ErrorCode::MathOverflow)
+        };
+        let (failed, total) = match value_of(false)? {
+            0 => (
+                self.packages.iter().filter(|package| package.status == PackageStatus::Failed).count() as u64,
+                self.packages.len() as u64,
+            ),
+            total => (value_of(true)?, total),
+        };
+        if total == 0 {
+            return Ok(0);
+        }
+        Ok(((failed as u128) * 10000 / (total as u128)) as u16)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct Package {
+    pub weight_grams: u32,
+    pub declared_value: u64,
+    pub recipient: Option<Pubkey>,
+    pub status: PackageStatus,
+}
+impl Package { pub const LEN: usize = 4 + 8 + (1 + 32) + 1; }
+
+// Ordered stops of a multi-stop order; stop 0 is the pickup
+#[account]
+pub struct Route {
+    pub bump: u8,
+    pub delivery: Pubkey,
+    pub stop_count: u8,
+    pub stops_completed: u8,
+    pub stops: [RouteStop; MAX_ROUTE_STOPS],
+}
+impl Route {
+    pub const LEN: usize = 1 + 32 + 1 + 1 + MAX_ROUTE_STOPS * RouteStop::LEN;
+
+    pub fn all_completed(&self) -> bool {
+        self.stops_completed == self.stop_count
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Default)]
+pub struct RouteStop {
+    pub point: GeoPoint,
+    pub payment_bps: u16,
+    pub completed_at: Option<i64>,
+}
+impl RouteStop { pub const LEN: usize = GeoPoint::LEN + 2 + (1 + 8); }
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct RouteStopDeclaration {
+    pub point: GeoPoint,
+    pub payment_bps: u16,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct PackageDeclaration {
+    pub weight_grams: u32,
+    pub declared_value: u64,
+    pub recipient: Option<Pubkey>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum PackageStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[account]
+pub struct Bid {
+    pub bump: u8,
+    pub delivery: Pubkey,
+    pub vehicle: Pubkey,
+    pub operator: Pubkey,
+    pub amount: u64,
+    pub placed_at: i64,
+}
+impl Bid { pub const LEN: usize = 1 + 32 + 32 + 32 + 8 + 8; }
+
+#[account]
+pub struct Dispute {
+    pub bump: u8,
+    pub delivery: Pubkey,
+    pub opened_by: Pubkey,
+    pub reason_hash: [u8; 32],
+    pub status: DisputeStatus,
+    pub evidence: Vec<Evidence>,
+    pub opened_at: i64,
+    pub resolved_at: Option<i64>,
+    pub customer_share_bps: u16,
+}
+impl Dispute {
+    pub const LEN: usize = 1 + 32 + 32 + 32 + 1 + (4 + MAX_DISPUTE_EVIDENCE * Evidence::LEN) + 8 + (1 + 8) + 2;
+
+    // Customer share a resolution applies. The arbiter rules any split; once
+    // the arbiter has let the dispute run past DISPUTE_TIMEOUT_SECONDS anyone
+    // may settle it by default, paying the operator when a drop-off is on
+    // record and refunding the customer otherwise
+    pub fn ruling(&self, is_arbiter: bool, customer_share_bps: u16, delivery: &Delivery, now: i64) -> Result<u16> {
+        require!(self.status == DisputeStatus::Open, ErrorCode::DisputeNotOpen);
+        if is_arbiter {
+            require!(customer_share_bps <= 10000, ErrorCode::InvalidParameter);
+            return Ok(customer_share_bps);
+        }
+        require!(
+            now >= self.opened_at.saturating_add(DISPUTE_TIMEOUT_SECONDS),
+            ErrorCode::ArbiterTimeoutPending
+        );
+        Ok(if delivery.delivered_at.is_some() { 0 } else { 10000 })
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct Evidence {
+    pub submitted_by: Pubkey,
+    pub hash: [u8; 32],
+    pub submitted_at: i64,
+}
+impl Evidence { pub const LEN: usize = 32 + 32 + 8; }
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum DisputeStatus {
+    Open,
+    Resolved,
+}
+
+// Fee-funded reserve that compensates customers for lost or damaged packages
+#[account]
+pub struct InsurancePool {
+    pub bump: u8,
+    pub config: Pubkey,
+    pub total_contributed: u64,
+    pub total_paid: u64,
+}
+impl InsurancePool {
+    pub const LEN: usize = 1 + 32 + 8 + 8;
+
+    pub fn contribute(&mut self, amount: u64) -> Result<()> {
+        self.total_contributed = self.total_contributed
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn available(&self) -> Result<u64> {
+        Ok(self.total_contributed
+            .checked_sub(self.total_paid)
+            .ok_or(ErrorCode::MathOverflow)?)
+    }
+}
+
+#[account]
+pub struct InsuranceClaim {
+    pub bump: u8,
+    pub delivery: Pubkey,
+    pub customer: Pubkey,
+    pub amount: u64,
+    pub reason_hash: [u8; 32],
+    pub status: ClaimStatus,
+    pub paid_amount: u64,
+    pub filed_at: i64,
+    pub resolved_at: Option<i64>,
 }
+impl InsuranceClaim { pub const LEN: usize = 1 + 32 + 32 + 8 + 32 + 1 + 8 + 8 + (1 + 8); }
 
-#[derive(Accounts)]
-#[instruction(delivery_id: u64)]
-pub struct CompleteDelivery<'info> {
-    #[account(
-        mut,
-        seeds = [b"delivery", customer.key().as_ref(), &delivery_id.to_le_bytes()],
-        bump = delivery.bump,
-    )]
-    pub delivery: Account<'info, Delivery>,
-    #[account(
-        mut,
-        seeds = [b"escrow", customer.key().as_ref(), &delivery_id.to_le_bytes()],
-        bump,
-    )]
-    /// CHECK: PDA holding escrowed payment
-    pub escrow: AccountInfo<'info>,
-    #[account(
-        mut,
-        seeds = [b"vehicle", vehicle.vehicle_id.as_bytes()],
-        bump = vehicle.bump,
-    )]
-    pub vehicle: Account<'info, Vehicle>,
-    /// CHECK: Vehicle operator receiving payment
-    #[account(mut)]
-    pub vehicle_operator: AccountInfo<'info>,
-    /// CHECK: Verified through config.treasury constraint
-    #[account(mut)]
-    pub treasury: AccountInfo<'info>,
-    #[account(
-        seeds = [b"config", config.authority.as_ref()],
-        bump = config.bump,
-        constraint = treasury.key() == config.treasury @ ErrorCode::InvalidTreasury
-    )]
-    pub config: Account<'info, Config>,
-    /// CHECK: Customer account for seed derivation
-    pub customer: AccountInfo<'info>,
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum ClaimStatus {
+    Filed,
+    Paid,
+    Rejected,
 }
 
 #[account]
-pub struct Config {
+pub struct Fleet {
     pub bump: u8,
-    pub authority: Pubkey,
-    pub is_active: bool,
+    pub manager: Pubkey,
+    pub fleet_id: u32,
+    pub payout_destination: Pubkey,
     pub is_paused: bool,
-    pub fee_bps: u16,
-    pub treasury: Pubkey,
-    pub version: u8,
+    pub vehicle_count: u32,
+    pub completed_jobs: u64,
+    pub gross_earnings: u64,
+    pub created_at: i64,
+    pub fleet_share_bps: u16,
+}
+impl Fleet {
+    pub const LEN: usize = 1 + 32 + 4 + 32 + 1 + 4 + 8 + 8 + 8 + 2;
+
+    pub fn record_job(&mut self, earnings: u64) -> Result<()> {
+        self.completed_jobs = self.completed_jobs
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.gross_earnings = self.gross_earnings
+            .checked_add(earnings)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
 }
-impl Config { pub const LEN: usize = 1 + 32 + 1 + 1 + 2 + 32 + 1; }
 
 #[account]
-pub struct Vehicle {
+pub struct VehicleStake {
     pub bump: u8,
-    pub vehicle_id: String,
-    pub operator: Pubkey,
-    pub location: String,
-    pub is_active: bool,
-    pub is_busy: bool,
-    pub total_deliveries: u64,
-    pub registered_at: i64,
+    pub vehicle: Pubkey,
+    pub amount: u64,
+    pub slashed_total: u64,
+    // Stake asked back, still slashable until the cooldown ends
+    pub pending_unstake: u64,
+    pub unstake_requested_at: i64,
+}
+impl VehicleStake {
+    pub const LEN: usize = 1 + 32 + 8 + 8 + 8 + 8;
+
+    // Takes the configured share of the stake and returns the lamports the
+    // caller must move out of the account
+    pub fn slash(&mut self, slash_bps: u16, rounding: FeeRounding) -> Result<u64> {
+        let slashed = apply_bps(self.amount, slash_bps as u64, rounding)?;
+        self.amount = self.amount
+            .checked_sub(slashed)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.slashed_total = self.slashed_total
+            .checked_add(slashed)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.pending_unstake = self.pending_unstake.min(self.amount);
+        Ok(slashed)
+    }
 }
-impl Vehicle { pub const LEN: usize = 1 + (4 + 32) + 32 + (4 + 64) + 1 + 1 + 8 + 8; }
 
+// Unidirectional SOL channel from a customer to one vehicle; `settled` is
+// the highest cumulative voucher paid out so far
 #[account]
-pub struct Delivery {
+pub struct PaymentChannel {
     pub bump: u8,
-    pub delivery_id: u64,
     pub customer: Pubkey,
-    pub payment_amount: u64,
-    pub pickup_location: String,
-    pub delivery_location: String,
-    pub status: DeliveryStatus,
-    pub assigned_vehicle: Option<Pubkey>,
-    pub created_at: i64,
-    pub accepted_at: Option<i64>,
-    pub completed_at: Option<i64>,
+    pub vehicle: Pubkey,
+    pub deposit: u64,
+    pub settled: u64,
+    pub opened_at: i64,
+    pub expires_at: i64,
+    // Which opening of a channel between this customer and vehicle this is,
+    // from their ChannelNonce; vouchers are signed over it
+    pub nonce: u64,
+}
+impl PaymentChannel { pub const LEN: usize = 1 + 32 + 32 + 8 + 8 + 8 + 8 + 8; }
+
+// A PaymentChannel funded in the accepted mint; the deposit sits in the
+// `channel_token` vault PDA
+#[account]
+pub struct TokenChannel {
+    pub bump: u8,
+    pub customer: Pubkey,
+    pub vehicle: Pubkey,
+    pub mint: Pubkey,
+    pub deposit: u64,
+    pub settled: u64,
+    pub opened_at: i64,
+    pub expires_at: i64,
+    pub nonce: u64,
+}
+impl TokenChannel { pub const LEN: usize = 1 + 32 + 32 + 32 + 8 + 8 + 8 + 8 + 8; }
+
+// Counts the channels a customer has opened to one vehicle, SOL and token
+// alike. It is never closed, so every opening gets a number no earlier
+// voucher was signed for, even when a channel is closed and reopened within
+// the same second
+#[account]
+pub struct ChannelNonce {
+    pub bump: u8,
+    pub openings: u64,
+}
+impl ChannelNonce {
+    pub const LEN: usize = 1 + 8;
+
+    pub fn next(&mut self, bump: u8) -> Result<u64> {
+        self.bump = bump;
+        self.openings = self.openings.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        Ok(self.openings)
+    }
+}
+
+// Fixed-point coordinate in millionths of a degree (about 11 cm at the
+// equator), so positions compare exactly instead of as free-form text
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct GeoPoint {
+    pub lat_e6: i32,
+    pub lng_e6: i32,
+}
+impl GeoPoint {
+    pub const LEN: usize = 4 + 4;
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            (-90_000_000..=90_000_000).contains(&self.lat_e6)
+                && (-180_000_000..=180_000_000).contains(&self.lng_e6),
+            ErrorCode::InvalidLocation
+        );
+        Ok(())
+    }
+
+    // Geohash cell of GEOHASH_LEN characters containing the point, found by
+    // bisecting the longitude and latitude ranges in alternation
+    pub fn geohash(&self) -> [u8; GEOHASH_LEN] {
+        let mut lat = (-90_000_000i64, 90_000_000i64);
+        let mut lng = (-180_000_000i64, 180_000_000i64);
+        let mut cell = [0u8; GEOHASH_LEN];
+        let mut even = true;
+        for ch in cell.iter_mut() {
+            let mut index = 0usize;
+            for _ in 0..5 {
+                let (range, value) = if even {
+                    (&mut lng, self.lng_e6 as i64)
+                } else {
+                    (&mut lat, self.lat_e6 as i64)
+                };
+                let mid = (range.0 + range.1) / 2;
+                index <<= 1;
+                if value >= mid {
+                    index |= 1;
+                    range.0 = mid;
+                } else {
+                    range.1 = mid;
+                }
+                even = !even;
+            }
+            *ch = GEOHASH_ALPHABET[index];
+        }
+        cell
+    }
+
+    // Deprecated "lat,lng" decimal-degree strings, e.g. "40.7128,-74.0060"
+    pub fn parse(location: &str) -> Result<Self> {
+        require!(!location.is_empty() && location.len() <= 64, ErrorCode::InvalidLocation);
+        let (lat, lng) = location.split_once(',').ok_or(ErrorCode::InvalidLocation)?;
+        let point = GeoPoint {
+            lat_e6: parse_degrees_e6(lat)?,
+            lng_e6: parse_degrees_e6(lng)?,
+        };
+        point.validate()?;
+        Ok(point)
+    }
+}
+impl std::fmt::Display for GeoPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt_degrees_e6(f, self.lat_e6)?;
+        f.write_str(",")?;
+        fmt_degrees_e6(f, self.lng_e6)
+    }
 }
-impl Delivery { pub const LEN: usize = 1 + 8 + 32 + 8 + (4 + 64) + (4 + 64) + 1 + (1 + 32) + 8 + (1 + 8) + (1 + 8); }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
 pub enum DeliveryStatus {
@@ -349,8 +15307,232 @@ pub enum DeliveryStatus {
     InProgress,
     Completed,
     Cancelled,
+    Queued,
+    Expired,
+    PickedUp,
+    Disputed,
+}
+
+#[event]
+pub struct RecoveryInitiated {
+    pub schema_version: u8,
+    pub target: Pubkey,
+    pub recovery_address: Pubkey,
+    pub initiated_by: Pubkey,
+    pub executable_at: i64,
+}
+
+#[event]
+pub struct RecoveryExecuted {
+    pub schema_version: u8,
+    pub target: Pubkey,
+    pub recovery_address: Pubkey,
+    pub amount: u64,
+    pub executed_at: i64,
+}
+
+#[event]
+pub struct RecoveryTokensSwept {
+    pub schema_version: u8,
+    pub target: Pubkey,
+    pub mint: Pubkey,
+    pub recovery_token: Pubkey,
+    pub amount: u64,
+    pub executed_at: i64,
+}
+
+#[event]
+pub struct RecoveryCancelled {
+    pub schema_version: u8,
+    pub target: Pubkey,
+    pub cancelled_by: Pubkey,
+}
+
+#[event]
+pub struct DisputeOpened {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub opened_by: Pubkey,
+    pub reason_hash: [u8; 32],
+}
+
+#[event]
+pub struct DisputeResolved {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub arbiter: Pubkey,
+    pub customer_share: u64,
+    pub operator_share: u64,
+}
+
+#[event]
+pub struct ConfidentialDisputeResolved {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub arbiter: Pubkey,
+    pub customer_share_bps: u16,
+}
+
+#[event]
+pub struct VehicleOperatorTransferred {
+    pub schema_version: u8,
+    pub vehicle: Pubkey,
+    pub previous_operator: Pubkey,
+    pub new_operator: Pubkey,
+}
+
+#[event]
+pub struct OrderCreated {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub customer: Pubkey,
+    pub payment_amount: u64,
+    pub region_id: u16,
+}
+
+#[event]
+pub struct OrderAccepted {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub vehicle: Pubkey,
+    pub accepted_at: i64,
+}
+
+#[event]
+pub struct PickupConfirmed {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub vehicle: Pubkey,
+    pub picked_up_at: i64,
+}
+
+#[event]
+pub struct OrderCompleted {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub vehicle: Pubkey,
+    pub operator_payment: u64,
+    pub platform_fee: u64,
+    pub completed_at: i64,
+}
+
+#[event]
+pub struct OrderCancelled {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub status: DeliveryStatus,
+    pub cancelled_at: i64,
+}
+
+#[event]
+pub struct VehicleRegistered {
+    pub schema_version: u8,
+    pub vehicle: Pubkey,
+    pub vehicle_id: String,
+    pub operator: Pubkey,
+    pub vehicle_class: VehicleClass,
+}
+
+#[event]
+pub struct ConfigUpdated {
+    pub schema_version: u8,
+    pub config: Pubkey,
+    pub authority: Pubkey,
+}
+
+#[event]
+pub struct RoleChanged {
+    pub schema_version: u8,
+    pub kind: RoleKind,
+    pub holder: Pubkey,
+    pub granted: bool,
+}
+
+#[event]
+pub struct AdminActionApproved {
+    pub schema_version: u8,
+    pub proposal: Pubkey,
+    pub approver: Pubkey,
+    pub approval_count: u8,
+}
+
+#[event]
+pub struct ConfigChangeQueued {
+    pub schema_version: u8,
+    pub config: Pubkey,
+    pub change: ConfigChange,
+    // Timelock the change is held under
+    pub delay_seconds: i64,
+    pub executable_at: i64,
+}
+
+#[event]
+pub struct ConfigChangeExecuted {
+    pub schema_version: u8,
+    pub config: Pubkey,
+    pub change: ConfigChange,
+    pub executed_at: i64,
+}
+
+#[event]
+pub struct ClaimResolved {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub customer: Pubkey,
+    pub payout: u64,
+    pub resolved_at: i64,
+}
+
+#[event]
+pub struct TreasuryWithdrawn {
+    pub schema_version: u8,
+    pub treasury: Pubkey,
+    pub destination: Pubkey,
+    pub authority: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct ReferralRegistered {
+    pub schema_version: u8,
+    pub referee: Pubkey,
+    pub referrer: Pubkey,
+}
+
+#[event]
+pub struct FeeShareDistributed {
+    pub schema_version: u8,
+    pub treasury: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
 }
 
+#[event]
+pub struct AuctionReopened {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub skipped_bidder: Pubkey,
+}
+
+#[event]
+pub struct PinRejected {
+    pub schema_version: u8,
+    pub delivery: Pubkey,
+    pub attempts: u8,
+}
+#[event]
+pub struct FleetShareChanged {
+    pub schema_version: u8,
+    pub fleet: Pubkey,
+    pub fleet_share_bps: u16,
+}
+#[event]
+pub struct PriceFeedOverridden {
+    pub schema_version: u8,
+    pub currency: [u8; 3],
+    pub micros_per_sol: u64,
+    pub authority: Pubkey,
+}
 #[error_code]
 pub enum ErrorCode {
     #[msg("Math overflow occurred")]
@@ -369,4 +15551,834 @@ pub enum ErrorCode {
     Unauthorized,
     #[msg("Invalid treasury")]
     InvalidTreasury,
+    #[msg("Deliveries cannot be merged")]
+    DeliveriesNotMergeable,
+    #[msg("Region waitlist is full")]
+    QueueFull,
+    #[msg("Region waitlist is empty")]
+    QueueEmpty,
+    #[msg("Delivery is not next in the waitlist")]
+    NotNextInQueue,
+    #[msg("Insufficient prepaid balance")]
+    InsufficientBalance,
+    #[msg("Standing order has no runs left")]
+    StandingOrderExhausted,
+    #[msg("Standing order is not due yet")]
+    StandingOrderNotDue,
+    #[msg("Epoch has not finished yet")]
+    EpochNotFinished,
+    #[msg("Period already finalized")]
+    PeriodAlreadyFinalized,
+    #[msg("Delivery has not been confirmed")]
+    DeliveryNotConfirmed,
+    #[msg("Instruction does not match the order's confirmation mode")]
+    InvalidConfirmationMode,
+    #[msg("Invalid delivery PIN")]
+    InvalidPin,
+    #[msg("Escrow does not match the order or is no longer funded")]
+    InvalidEscrow,
+    #[msg("Insufficient escrowed funds")]
+    InsufficientEscrow,
+    #[msg("Nonce has already been used")]
+    StaleNonce,
+    #[msg("Daily volume cap reached")]
+    DailyVolumeCapReached,
+    #[msg("Withdrawal exceeds the per-epoch limit")]
+    WithdrawalLimitExceeded,
+    #[msg("Dispatcher key expired")]
+    DispatcherKeyExpired,
+    #[msg("Key lacks permission for this instruction")]
+    MissingPermission,
+    #[msg("Recovery timelock has not elapsed")]
+    RecoveryTimelockActive,
+    #[msg("Platform is in settlement-only mode")]
+    SettlementOnly,
+    #[msg("Fee change exceeds the allowed step for this period")]
+    FeeChangeTooLarge,
+    #[msg("Provider is not active")]
+    ProviderInactive,
+    #[msg("Vehicle class not allowed for this operation")]
+    VehicleClassMismatch,
+    #[msg("Order value exceeds the vehicle class limit")]
+    OrderValueTooHigh,
+    #[msg("Price feed is stale")]
+    StalePrice,
+    #[msg("Vehicle's geo bucket account is required")]
+    GeoBucketRequired,
+    #[msg("Geo bucket is full")]
+    GeoBucketFull,
+    #[msg("Order is reserved for the customer's preferred vehicles")]
+    ExclusiveWindowActive,
+    #[msg("Charity account does not match config")]
+    InvalidCharity,
+    #[msg("Order has a round-up but no charity account was supplied")]
+    CharityAccountRequired,
+    #[msg("Package has already been settled")]
+    PackageAlreadySettled,
+    #[msg("Every declared package must be delivered or failed before settlement")]
+    PackagesUnsettled,
+    #[msg("Arrival not attested, wait not over, or no deposit posted")]
+    NoShowNotClaimable,
+    #[msg("Vehicle is away")]
+    VehicleAway,
+    #[msg("Mint is not accepted for payment")]
+    InvalidMint,
+    #[msg("Token-paid orders are only accepted in untaxed regions")]
+    TokenTaxUnsupported,
+    #[msg("Order has not reached its expiry yet")]
+    DeliveryNotExpired,
+    #[msg("Fee exceeds the platform maximum")]
+    FeeTooHigh,
+    #[msg("Signer is not the vehicle's operator")]
+    NotVehicleOperator,
+    #[msg("Vehicle has not marked the order delivered yet")]
+    NotYetDelivered,
+    #[msg("Only the customer or the assigned vehicle's operator may do this")]
+    NotDisputeParty,
+    #[msg("Dispute is not open")]
+    DisputeNotOpen,
+    #[msg("Dispute evidence list is full")]
+    EvidenceFull,
+    #[msg("A signed hand-over cannot be disputed as not received")]
+    DisputeNotAllowed,
+    #[msg("Vehicle's average rating is below the order's minimum")]
+    RatingTooLow,
+    #[msg("Delivery has already been rated")]
+    AlreadyRated,
+    #[msg("Customer's score is below the vehicle's minimum")]
+    CustomerScoreTooLow,
+    #[msg("Location must be \"lat,lng\" in decimal degrees")]
+    InvalidLocation,
+    #[msg("Vehicle was suspended by the platform authority")]
+    VehicleSuspended,
+    #[msg("Fleet account must be supplied for fleet vehicles")]
+    FleetAccountRequired,
+    #[msg("Fleet is paused")]
+    FleetPaused,
+    #[msg("Fleet does not match the vehicle")]
+    InvalidFleet,
+    #[msg("Vehicle already belongs to a fleet")]
+    VehicleInFleet,
+    #[msg("Fleet share cannot exceed 100%")]
+    InvalidFleetShare,
+    #[msg("Fleet payout account must be supplied and match the fleet's payout destination")]
+    FleetPayoutRequired,
+    #[msg("Vehicle has not locked the required stake")]
+    InsufficientStake,
+    #[msg("Stake account must be supplied for staked vehicles")]
+    StakeAccountRequired,
+    #[msg("Order has not been outstanding long enough to count as abandoned")]
+    DeliveryNotAbandoned,
+    #[msg("Every milestone before the final drop-off has been released")]
+    NoMilestoneLeft,
+    #[msg("Voucher signature or amount is invalid")]
+    InvalidVoucher,
+    #[msg("Channel has not expired yet")]
+    ChannelNotExpired,
+    #[msg("Session key has expired")]
+    SessionKeyExpired,
+    #[msg("Geohash does not match the vehicle's position")]
+    GeohashMismatch,
+    #[msg("Order cannot be closed by a keeper until its grace period has passed")]
+    CloseGraceNotElapsed,
+    #[msg("Batch accounts are malformed or include an order that must settle on its own")]
+    InvalidBatch,
+    #[msg("Every stop on the route has been completed")]
+    NoStopLeft,
+    #[msg("Every route stop must be completed before settlement")]
+    StopsUnfinished,
+    #[msg("Scheduled order is not yet open for acceptance")]
+    ScheduleWindowNotOpen,
+    #[msg("Subscription has no deliveries left")]
+    SubscriptionExhausted,
+    #[msg("Subscription's next delivery is not due yet")]
+    SubscriptionNotDue,
+    #[msg("Order is out for bids")]
+    AuctionInProgress,
+    #[msg("Order is not taking bids")]
+    AuctionNotOpen,
+    #[msg("Bid must be positive, within the max price and below the vehicle's previous bid")]
+    BidTooHigh,
+    #[msg("Region's open-orders board is full")]
+    OpenOrdersFull,
+    #[msg("Order is listed; its region's open-orders board is required")]
+    OpenOrdersRequired,
+    #[msg("Action needs an admin proposal approved by the approver threshold")]
+    ApprovalRequired,
+    #[msg("Admin proposal does not match this action")]
+    ProposalMismatch,
+    #[msg("Admin proposal was already executed")]
+    ProposalExecuted,
+    #[msg("Signer is not in the config's approver set")]
+    NotApprover,
+    #[msg("Approver already approved this proposal")]
+    AlreadyApproved,
+    #[msg("No config change is queued")]
+    NoPendingConfigChange,
+    #[msg("Queued config change is still timelocked")]
+    ConfigChangeTimelockActive,
+    #[msg("Order pays into the insurance pool; its account is required")]
+    InsurancePoolRequired,
+    #[msg("Insurance claim was already resolved")]
+    ClaimNotOpen,
+    #[msg("Mint carries a Token-2022 extension the config does not allow")]
+    TokenExtensionNotAllowed,
+    #[msg("Platform fee is too small to cover the mint's transfer fee")]
+    TransferFeeUncovered,
+    #[msg("Confidential orders are not enabled")]
+    ConfidentialDisabled,
+    #[msg("Mint does not support fee-free confidential transfers")]
+    ConfidentialUnsupported,
+    #[msg("Confidential orders settle through completeDeliveryConfidential")]
+    ConfidentialOrder,
+    #[msg("Order is not a confidential order")]
+    NotConfidential,
+    #[msg("Receipt NFTs are not enabled")]
+    ReceiptNftDisabled,
+    #[msg("Receipt NFT needs its token, metadata, edition, program and rent accounts")]
+    ReceiptNftAccountsRequired,
+    #[msg("Receipt mint is not the delivery's receipt NFT address")]
+    InvalidReceiptMint,
+    #[msg("Ownership NFT must be a fixed-supply one-of-one token held by the operator")]
+    InvalidOwnershipNft,
+    #[msg("Vehicle is controlled by its ownership NFT")]
+    VehicleNftBound,
+    #[msg("Receipt tree is not the configured tree")]
+    InvalidReceiptTree,
+    #[msg("Recipient is not the wallet configured for this fee slot")]
+    FeeRecipientsRequired,
+    #[msg("A wallet cannot refer itself or the wallet that referred it")]
+    SelfReferral,
+    #[msg("Promo code does not match the voucher account")]
+    InvalidPromoCode,
+    #[msg("Voucher has expired")]
+    VoucherExpired,
+    #[msg("Voucher has no uses left")]
+    VoucherExhausted,
+    #[msg("Payout would leave the recipient below the rent-exempt minimum")]
+    RecipientBelowRent,
+    #[msg("Distribute the slot's fee shares before replacing its recipient")]
+    FeeSharesUndistributed,
+    #[msg("Referrer has no customer or operator profile")]
+    ReferrerNotFound,
+    #[msg("Referrals can only be registered before the first order")]
+    ReferralTooLate,
+    #[msg("Referred party's referral account is missing")]
+    ReferralRequired,
+    #[msg("The platform authority cannot act as arbiter")]
+    ArbiterNotIndependent,
+    #[msg("Confidential payout legs do not match the parties owed a share")]
+    InvalidConfidentialLeg,
+    #[msg("Escrow balance does not match the amount commitment")]
+    CommitmentMismatch,
+    #[msg("The order's route account must be closed with it")]
+    RouteAccountRequired,
+    #[msg("Only the arbiter can resolve a dispute before it times out")]
+    ArbiterTimeoutPending,
+    #[msg("Voucher discount cannot exceed the platform fee")]
+    VoucherExceedsFee,
+    #[msg("Customer has already redeemed this voucher")]
+    VoucherAlreadyUsed,
+    #[msg("Order redeemed a voucher; pass the voucher and its use account")]
+    VoucherAccountsRequired,
+    #[msg("Open the next board page only once the previous one is full")]
+    OpenOrdersNotFull,
+    #[msg("Too many wrong PINs; the customer must confirm the order")]
+    PinAttemptsExceeded,
+    #[msg("No unstake request is pending")]
+    NoUnstakeRequested,
+    #[msg("Stake cooldown has not ended yet")]
+    UnstakeCooldownActive,
+    #[msg("Only escrows and vaults of finished or closed orders can be recovered")]
+    RecoveryTargetProtected,
+    #[msg("Insurance claim is still waiting for the arbiter")]
+    ClaimStillOpen,
+    #[msg("Price rose above the buyer's limit")]
+    PriceAboveLimit,
+    #[msg("Price feed is still live; only a stale feed can be overridden")]
+    PriceFeedLive,
+    #[msg("Only the epoch that just ended can be closed out")]
+    EpochNotPrevious,
+    #[msg("No Pyth price feed is configured for USD orders")]
+    PythFeedNotConfigured,
+    #[msg("The configured Pyth price update account must be passed")]
+    PythFeedRequired,
+    #[msg("Pyth price update account is not the configured one")]
+    PythFeedMismatch,
+    #[msg("Pyth price is stale, unverified, non-positive or for another feed")]
+    PythPriceStale,
+    #[msg("Pyth price confidence interval is too wide")]
+    PythConfidenceTooWide,
+}
+
+// Fixtures for downstream integration tests (solana-program-test / bankrun).
+// Builds the instructions needed to stand up a working deployment so tests
+// only have to sign and send them
+#[cfg(feature = "test-utils")]
+pub mod test_utils {
+    use super::*;
+    use anchor_lang::solana_program::instruction::Instruction;
+    use anchor_lang::solana_program::system_instruction;
+    use anchor_lang::{AccountDeserialize, AccountSerialize, InstructionData, ToAccountMetas};
+    use solana_program_test::{BanksClientError, ProgramTest, ProgramTestContext};
+    use solana_sdk::instruction::InstructionError;
+    use solana_sdk::signature::{Keypair, Signer as _};
+    use solana_sdk::transaction::{Transaction, TransactionError};
+
+    pub fn config_pda() -> Pubkey {
+        Pubkey::find_program_address(&[b"config"], &crate::ID).0
+    }
+
+    pub fn fee_history_pda(config: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"fee_history", config.as_ref()], &crate::ID).0
+    }
+
+    pub fn tax_vault_pda(config: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"tax_vault", config.as_ref()], &crate::ID).0
+    }
+
+    pub fn treasury_pda(config: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"treasury", config.as_ref()], &crate::ID).0
+    }
+
+    pub fn region_pda(region_id: u16) -> Pubkey {
+        Pubkey::find_program_address(&[b"region", &region_id.to_le_bytes()], &crate::ID).0
+    }
+
+    pub fn vehicle_pda(vehicle_id: &str) -> Pubkey {
+        Pubkey::find_program_address(&[b"vehicle", vehicle_id.as_bytes()], &crate::ID).0
+    }
+
+    pub fn price_feed_pda(currency: &[u8; 3]) -> Pubkey {
+        Pubkey::find_program_address(&[b"price_feed", currency.as_ref()], &crate::ID).0
+    }
+
+    pub fn proof_oracle_pda(oracle: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"proof_oracle", oracle.as_ref()], &crate::ID).0
+    }
+
+    pub fn delivery_pda(customer: &Pubkey, delivery_id: u64) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"delivery", customer.as_ref(), &delivery_id.to_le_bytes()],
+            &crate::ID,
+        )
+        .0
+    }
+
+    pub fn escrow_pda(customer: &Pubkey, delivery_id: u64) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"escrow", customer.as_ref(), &delivery_id.to_le_bytes()],
+            &crate::ID,
+        )
+        .0
+    }
+
+    pub fn escrow_token_pda(customer: &Pubkey, delivery_id: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"escrow_token", customer.as_ref(), &delivery_id.to_le_bytes()], &crate::ID).0
+    }
+
+    pub fn dispute_pda(delivery: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"dispute", delivery.as_ref()], &crate::ID).0
+    }
+
+    pub fn fleet_pda(manager: &Pubkey, fleet_id: u32) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"fleet", manager.as_ref(), &fleet_id.to_le_bytes()],
+            &crate::ID,
+        )
+        .0
+    }
+
+    pub fn customer_profile_pda(customer: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"customer", customer.as_ref()], &crate::ID).0
+    }
+
+    pub fn daily_stats_pda(unix_timestamp: i64) -> Pubkey {
+        let day = DailyStats::day_of(unix_timestamp);
+        Pubkey::find_program_address(&[b"daily", &day.to_le_bytes()], &crate::ID).0
+    }
+
+    pub fn program_data_pda() -> Pubkey {
+        Pubkey::find_program_address(
+            &[crate::ID.as_ref()],
+            &anchor_lang::solana_program::bpf_loader_upgradeable::ID,
+        )
+        .0
+    }
+
+    pub fn global_stats_pda(delivery_id: u64) -> Pubkey {
+        let shard = GlobalStats::shard_of(delivery_id);
+        Pubkey::find_program_address(&[b"global_stats", &shard.to_le_bytes()], &crate::ID).0
+    }
+
+    pub fn initialize_config_ix(authority: Pubkey, fee_bps: u16, treasury: Pubkey) -> Instruction {
+        let config = config_pda();
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitializeConfig {
+                config,
+                fee_history: fee_history_pda(&config),
+                tax_vault: tax_vault_pda(&config),
+                treasury: treasury_pda(&config),
+                authority,
+                program: crate::ID,
+                program_data: program_data_pda(),
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitializeConfig {
+                fee_bps,
+                treasury,
+                max_fee_step_bps: 10000,
+                fee_step_period_hours: 1,
+            }
+            .data(),
+        }
+    }
+
+    pub fn initialize_region_ix(authority: Pubkey, region_id: u16) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitializeRegion {
+                region: region_pda(region_id),
+                config: config_pda(),
+                role: None,
+                authority,
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitializeRegion { region_id }.data(),
+        }
+    }
+
+    pub fn register_vehicle_ix(
+        authority: Pubkey,
+        vehicle_id: &str,
+        operator: Pubkey,
+        vehicle_class: VehicleClass,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::RegisterVehicle {
+                vehicle: vehicle_pda(vehicle_id),
+                config: config_pda(),
+                ownership_mint: None,
+                ownership_token: None,
+                stake: None,
+                staker: None,
+                authority,
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::RegisterVehicle {
+                vehicle_id: vehicle_id.to_string(),
+                operator,
+                position: GeoPoint::default(),
+                vehicle_class,
+            }
+            .data(),
+        }
+    }
+
+    // Mock price oracle: creates the feed with `oracle` as its publisher
+    pub fn initialize_price_feed_ix(authority: Pubkey, currency: [u8; 3], oracle: Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitializePriceFeed {
+                price_feed: price_feed_pda(&currency),
+                config: config_pda(),
+                authority,
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitializePriceFeed { currency, oracle }.data(),
+        }
+    }
+
+    pub fn update_price_feed_ix(oracle: Pubkey, currency: [u8; 3], micros_per_sol: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::UpdatePriceFeed {
+                price_feed: price_feed_pda(&currency),
+                oracle,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::UpdatePriceFeed { currency, micros_per_sol }.data(),
+        }
+    }
+
+    // Mock GPS/proof oracle: registers `oracle` so it can attest arrivals and drop-offs
+    pub fn register_proof_oracle_ix(authority: Pubkey, oracle: Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::RegisterProofOracle {
+                proof_oracle: proof_oracle_pda(&oracle),
+                config: config_pda(),
+                authority,
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::RegisterProofOracle { oracle }.data(),
+        }
+    }
+
+    pub fn create_delivery_order_ix(
+        authority: Pubkey,
+        customer: Pubkey,
+        delivery_id: u64,
+        payment_amount: u64,
+        region_id: u16,
+        now: i64,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::CreateDeliveryOrder {
+                delivery: delivery_pda(&customer, delivery_id),
+                escrow: escrow_pda(&customer, delivery_id),
+                customer_profile: customer_profile_pda(&customer),
+                daily_stats: daily_stats_pda(now),
+                global_stats: global_stats_pda(delivery_id),
+                region: region_pda(region_id),
+                open_orders: None,
+                voucher: None,
+                voucher_use: None,
+                usd_feed: price_feed_pda(b"USD"),
+                eur_feed: None,
+                pyth_price: None,
+                config: config_pda(),
+                customer,
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::CreateDeliveryOrder {
+                delivery_id,
+                payment_amount,
+                pickup_location: "40.7128,-74.0060".to_string(),
+                delivery_location: "40.7589,-73.9851".to_string(),
+                region_id,
+                min_rating: 0,
+                required_class: None,
+                scheduled_at: None,
+                voucher_code: None,
+            }
+            .data(),
+        }
+    }
+
+    pub fn set_pyth_feed_ix(authority: Pubkey, price_update: Pubkey, feed_id: [u8; 32]) -> Instruction {
+        let config = config_pda();
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::UpdateConfig { config, fee_history: fee_history_pda(&config), authority }
+                .to_account_metas(None),
+            data: crate::instruction::SetPythFeed { price_update, feed_id }.data(),
+        }
+    }
+
+    pub fn create_delivery_order_usd_ix(
+        customer: Pubkey,
+        delivery_id: u64,
+        usd_cents: u64,
+        region_id: u16,
+        now: i64,
+        pyth_price: Option<Pubkey>,
+    ) -> Instruction {
+        let accounts = crate::accounts::CreateDeliveryOrder {
+            delivery: delivery_pda(&customer, delivery_id),
+            escrow: escrow_pda(&customer, delivery_id),
+            customer_profile: customer_profile_pda(&customer),
+            daily_stats: daily_stats_pda(now),
+            global_stats: global_stats_pda(delivery_id),
+            region: region_pda(region_id),
+            open_orders: None,
+            voucher: None,
+            voucher_use: None,
+            usd_feed: price_feed_pda(b"USD"),
+            eur_feed: None,
+            pyth_price,
+            config: config_pda(),
+            customer,
+            system_program: anchor_lang::system_program::ID,
+        };
+        Instruction {
+            program_id: crate::ID,
+            accounts: accounts.to_account_metas(None),
+            data: crate::instruction::CreateDeliveryOrderUsd {
+                delivery_id,
+                usd_cents,
+                pickup_location: "40.7128,-74.0060".to_string(),
+                delivery_location: "40.7589,-73.9851".to_string(),
+                region_id,
+                min_rating: 0,
+                required_class: None,
+                scheduled_at: None,
+            }
+            .data(),
+        }
+    }
+
+    pub fn initialize_operator_profile_ix(operator: Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitializeOperatorProfile {
+                operator_profile: Pubkey::find_program_address(&[b"operator", operator.as_ref()], &crate::ID).0,
+                operator,
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitializeOperatorProfile {}.data(),
+        }
+    }
+
+    // Settles a confirmed SOL order, signed by the vehicle's operator
+    pub fn complete_delivery_ix(
+        operator: Pubkey,
+        customer: Pubkey,
+        delivery_id: u64,
+        vehicle_id: &str,
+        region_id: u16,
+        now: i64,
+        pyth_price: Option<Pubkey>,
+    ) -> Instruction {
+        let config = config_pda();
+        let delivery = delivery_pda(&customer, delivery_id);
+        let vehicle = vehicle_pda(vehicle_id);
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::CompleteDelivery {
+                delivery,
+                escrow: escrow_pda(&customer, delivery_id),
+                vehicle,
+                geo_bucket: None,
+                fleet: None,
+                fleet_payout: None,
+                vehicle_operator: operator,
+                signer: operator,
+                dispatcher_key: None,
+                earnings: Pubkey::find_program_address(&[b"earnings", operator.as_ref()], &crate::ID).0,
+                vehicle_history: Pubkey::find_program_address(&[b"vehicle_history", vehicle.as_ref()], &crate::ID).0,
+                receipt: Pubkey::find_program_address(&[b"receipt", delivery.as_ref()], &crate::ID).0,
+                operator_profile: Pubkey::find_program_address(&[b"operator", operator.as_ref()], &crate::ID).0,
+                customer_profile: customer_profile_pda(&customer),
+                daily_stats: daily_stats_pda(now),
+                global_stats: global_stats_pda(delivery_id),
+                treasury: treasury_pda(&config),
+                tax_vault: tax_vault_pda(&config),
+                charity: None,
+                insurance_pool: None,
+                customer_referral: None,
+                operator_referral: None,
+                manifest: None,
+                route: None,
+                pyth_price,
+                receipt_mint: None,
+                receipt_token: None,
+                receipt_metadata: None,
+                receipt_edition: None,
+                token_metadata_program: None,
+                token_program: None,
+                associated_token_program: None,
+                rent: None,
+                receipt_tree: None,
+                receipt_tree_config: None,
+                bubblegum_program: None,
+                compression_program: None,
+                log_wrapper: None,
+                config,
+                region: region_pda(region_id),
+                customer,
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::CompleteDelivery { delivery_id }.data(),
+        }
+    }
+
+    // Funds a fresh customer (or operator) from the test payer
+    pub async fn fund(ctx: &mut ProgramTestContext, to: &Pubkey, lamports: u64) {
+        let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+        let tx = Transaction::new_signed_with_payer(
+            &[system_instruction::transfer(&ctx.payer.pubkey(), to, lamports)],
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer],
+            blockhash,
+        );
+        ctx.banks_client.process_transaction(tx).await.unwrap();
+    }
+
+    // Moves the cluster clock forward, e.g. past a timelock or an SLA deadline
+    pub async fn warp_seconds(ctx: &mut ProgramTestContext, seconds: i64) {
+        let mut clock: Clock = ctx.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp = clock.unix_timestamp.saturating_add(seconds);
+        ctx.set_sysvar(&clock);
+    }
+
+    // Moves to the start of a later epoch, e.g. to reset per-epoch limits
+    pub async fn warp_epochs(ctx: &mut ProgramTestContext, epochs: u64) {
+        let clock: Clock = ctx.banks_client.get_sysvar().await.unwrap();
+        let schedule = ctx.genesis_config().epoch_schedule.clone();
+        let slot = schedule.get_first_slot_in_epoch(clock.epoch.saturating_add(epochs));
+        ctx.warp_to_slot(slot).unwrap();
+    }
+
+    pub fn create_fleet_ix(manager: Pubkey, fleet_id: u32, payout_destination: Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::CreateFleet {
+                fleet: fleet_pda(&manager, fleet_id),
+                manager,
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::CreateFleet { fleet_id, payout_destination }.data(),
+        }
+    }
+
+    // Both the fleet manager and the vehicle's operator sign
+    pub fn register_vehicle_to_fleet_ix(manager: Pubkey, fleet_id: u32, operator: Pubkey, vehicle_id: &str) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::RegisterVehicleToFleet {
+                fleet: fleet_pda(&manager, fleet_id),
+                vehicle: vehicle_pda(vehicle_id),
+                manager,
+                operator,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::RegisterVehicleToFleet {}.data(),
+        }
+    }
+
+    pub fn recovery_pda(target: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"recovery", config_pda().as_ref(), target.as_ref()], &crate::ID).0
+    }
+
+    pub fn initiate_recovery_ix(authority: Pubkey, target: Pubkey, recovery_address: Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitiateRecovery {
+                recovery: recovery_pda(&target),
+                config: config_pda(),
+                proposal: None,
+                authority,
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitiateRecovery { target, recovery_address }.data(),
+        }
+    }
+
+    // `delivery` is the order (or channel) the target belongs to. A token
+    // vault also takes its seeds, the mint, the recovery address's token
+    // account and the token program
+    pub fn execute_recovery_ix(
+        authority: Pubkey,
+        target: Pubkey,
+        recovery_address: Pubkey,
+        delivery: Option<Pubkey>,
+        vault: Option<(crate::RecoveryVault, Pubkey, Pubkey, Pubkey)>,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::ExecuteRecovery {
+                recovery: recovery_pda(&target),
+                target,
+                recovery_address,
+                delivery,
+                mint: vault.map(|(_, mint, _, _)| mint),
+                recovery_token: vault.map(|(_, _, recovery_token, _)| recovery_token),
+                token_program: vault.map(|(_, _, _, token_program)| token_program),
+                config: config_pda(),
+                authority,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::ExecuteRecovery { target, vault: vault.map(|(seeds, _, _, _)| seeds) }.data(),
+        }
+    }
+
+    // The program deployed through the upgradeable loader, so initialize_config
+    // finds its program data. Loads the built program from target/deploy or
+    // BPF_OUT_DIR
+    pub fn program_test() -> ProgramTest {
+        let mut program_test = ProgramTest::default();
+        program_test.prefer_bpf(true);
+        program_test.add_upgradeable_program_to_genesis("autonomous_vehicle_payments", &crate::ID);
+        program_test
+    }
+
+    // Makes `authority` the program's upgrade authority, which initialize_config
+    // checks
+    pub async fn set_upgrade_authority(ctx: &mut ProgramTestContext, authority: &Pubkey) {
+        let program_data = program_data_pda();
+        let mut account = ctx.banks_client.get_account(program_data).await.unwrap().unwrap();
+        // ProgramData header: u32 variant, u64 slot, then the Option<Pubkey> authority
+        account.data[12] = 1;
+        account.data[13..45].copy_from_slice(authority.as_ref());
+        ctx.set_account(&program_data, &account.into());
+    }
+
+    // Signs with the test payer plus `signers` and sends
+    pub async fn send(
+        ctx: &mut ProgramTestContext,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> std::result::Result<(), BanksClientError> {
+        let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+        let mut all_signers = vec![&ctx.payer];
+        all_signers.extend_from_slice(signers);
+        let tx = Transaction::new_signed_with_payer(instructions, Some(&ctx.payer.pubkey()), &all_signers, blockhash);
+        ctx.banks_client.process_transaction(tx).await
+    }
+
+    // A fresh wallet holding `lamports`, for a customer, operator or manager
+    pub async fn funded_keypair(ctx: &mut ProgramTestContext, lamports: u64) -> Keypair {
+        let keypair = Keypair::new();
+        fund(ctx, &keypair.pubkey(), lamports).await;
+        keypair
+    }
+
+    // Stands up a deployment owned by the test payer: the config at `fee_bps`,
+    // region `region_id` and a USD feed the payer publishes at `usd_micros_per_sol`
+    pub async fn bootstrap(ctx: &mut ProgramTestContext, fee_bps: u16, region_id: u16, usd_micros_per_sol: u64) {
+        let authority = ctx.payer.pubkey();
+        set_upgrade_authority(ctx, &authority).await;
+        send(
+            ctx,
+            &[
+                initialize_config_ix(authority, fee_bps, authority),
+                initialize_region_ix(authority, region_id),
+                initialize_price_feed_ix(authority, *b"USD", authority),
+                update_price_feed_ix(authority, *b"USD", usd_micros_per_sol),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    }
+
+    pub async fn fetch<T: AccountDeserialize>(ctx: &mut ProgramTestContext, address: Pubkey) -> T {
+        let account = ctx.banks_client.get_account(address).await.unwrap().unwrap();
+        T::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    // Overwrites a program account in place, to reach states that need an
+    // oracle, a prover or a long wait to set up for real
+    pub async fn store<T: AccountSerialize>(ctx: &mut ProgramTestContext, address: Pubkey, value: &T) {
+        let mut account = ctx.banks_client.get_account(address).await.unwrap().unwrap();
+        let mut data: &mut [u8] = &mut account.data;
+        value.try_serialize(&mut data).unwrap();
+        ctx.set_account(&address, &account.into());
+    }
+
+    // Fails unless the transaction was rejected with the program's `error`
+    pub fn assert_error(result: std::result::Result<(), BanksClientError>, error: crate::ErrorCode) {
+        let expected = u32::from(error);
+        match result {
+            Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+                _,
+                InstructionError::Custom(code),
+            ))) => assert_eq!(code, expected),
+            other => panic!("expected error {expected}, got {other:?}"),
+        }
+    }
 }
@@ -0,0 +1,519 @@
+// End-to-end tests against the built program, on the test-utils fixtures.
+// Run with `anchor build && cargo test --features test-utils`
+#![cfg(feature = "test-utils")]
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::token_2022::spl_token_2022;
+use autonomous_vehicle_payments::test_utils::*;
+use autonomous_vehicle_payments::{
+    accounts, instruction, Config, ConfidentialLeg, ConfidentialPayee, Delivery, DeliveryStatus, ErrorCode, Fleet,
+    RecoveryVault, Region, Vehicle, VehicleClass, DISPUTE_TIMEOUT_SECONDS, MAX_REGION_READING_AGE_SECONDS,
+    PYTH_MAX_AGE_SECONDS, RECOVERY_TIMELOCK_SECONDS,
+};
+use pyth_solana_receiver_sdk::price_update::{PriceFeedMessage, PriceUpdateV2, VerificationLevel};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::signature::{Keypair, Signer as _};
+
+const REGION: u16 = 1;
+const SOL: u64 = 1_000_000_000;
+
+#[tokio::test]
+async fn books_an_order_on_a_bootstrapped_deployment() {
+    let mut ctx = program_test().start_with_context().await;
+    bootstrap(&mut ctx, 250, REGION, 150_000_000).await;
+
+    let customer = funded_keypair(&mut ctx, 2 * SOL).await;
+    let now = ctx.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp;
+    let authority = ctx.payer.pubkey();
+    send(
+        &mut ctx,
+        &[create_delivery_order_ix(authority, customer.pubkey(), 1, SOL / 10, REGION, now)],
+        &[&customer],
+    )
+    .await
+    .unwrap();
+
+    let delivery: Delivery = fetch(&mut ctx, delivery_pda(&customer.pubkey(), 1)).await;
+    assert!(delivery.status == DeliveryStatus::Pending);
+    assert_eq!(delivery.payment_amount, SOL / 10);
+}
+
+#[tokio::test]
+async fn adds_a_vehicle_to_a_fleet_only_with_both_signatures() {
+    let mut ctx = program_test().start_with_context().await;
+    bootstrap(&mut ctx, 250, REGION, 150_000_000).await;
+
+    let authority = ctx.payer.pubkey();
+    let operator = funded_keypair(&mut ctx, SOL).await;
+    let manager = funded_keypair(&mut ctx, SOL).await;
+    send(
+        &mut ctx,
+        &[
+            register_vehicle_ix(authority, "AV-FLEET", operator.pubkey(), VehicleClass::Road),
+            create_fleet_ix(manager.pubkey(), 1, manager.pubkey()),
+        ],
+        &[&manager],
+    )
+    .await
+    .unwrap();
+
+    // The operator has to agree to join
+    let mut unsigned = register_vehicle_to_fleet_ix(manager.pubkey(), 1, operator.pubkey(), "AV-FLEET");
+    unsigned.accounts[3].is_signer = false;
+    assert!(send(&mut ctx, &[unsigned], &[&manager]).await.is_err());
+
+    send(
+        &mut ctx,
+        &[register_vehicle_to_fleet_ix(manager.pubkey(), 1, operator.pubkey(), "AV-FLEET")],
+        &[&manager, &operator],
+    )
+    .await
+    .unwrap();
+    let vehicle: Vehicle = fetch(&mut ctx, vehicle_pda("AV-FLEET")).await;
+    assert_eq!(vehicle.fleet, Some(fleet_pda(&manager.pubkey(), 1)));
+    let fleet: Fleet = fetch(&mut ctx, fleet_pda(&manager.pubkey(), 1)).await;
+    assert_eq!(fleet.vehicle_count, 1);
+}
+
+// A plain Token-2022 mint, written straight into the bank
+fn inject_mint(ctx: &mut ProgramTestContext) -> Pubkey {
+    let mint = Pubkey::new_unique();
+    let mut data = vec![0u8; spl_token_2022::state::Mint::LEN];
+    spl_token_2022::state::Mint {
+        mint_authority: COption::None,
+        supply: 0,
+        decimals: 6,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    }
+    .pack_into_slice(&mut data);
+    let mint_account = solana_sdk::account::Account {
+        lamports: SOL,
+        data,
+        owner: spl_token_2022::ID,
+        executable: false,
+        rent_epoch: 0,
+    };
+    ctx.set_account(&mint, &mint_account.into());
+    mint
+}
+
+fn inject_token_account(ctx: &mut ProgramTestContext, address: Pubkey, mint: Pubkey, owner: Pubkey, amount: u64) {
+    let mut data = vec![0u8; spl_token_2022::state::Account::LEN];
+    spl_token_2022::state::Account {
+        mint,
+        owner,
+        amount,
+        delegate: COption::None,
+        state: spl_token_2022::state::AccountState::Initialized,
+        is_native: COption::None,
+        delegated_amount: 0,
+        close_authority: COption::None,
+    }
+    .pack_into_slice(&mut data);
+    let token_account = solana_sdk::account::Account {
+        lamports: SOL / 100,
+        data,
+        owner: spl_token_2022::ID,
+        executable: false,
+        rent_epoch: 0,
+    };
+    ctx.set_account(&address, &token_account.into());
+}
+
+async fn token_balance(ctx: &mut ProgramTestContext, address: Pubkey) -> u64 {
+    let account = ctx.banks_client.get_account(address).await.unwrap().unwrap();
+    spl_token_2022::state::Account::unpack_from_slice(&account.data).unwrap().amount
+}
+
+// Confidential orders need a prover's proofs to book, so a booked SOL order
+// is rewritten into a delivered confidential one in the given mint
+async fn delivered_confidential_order(
+    ctx: &mut ProgramTestContext,
+    customer: &Keypair,
+    operator: &Keypair,
+    vehicle_id: &str,
+    delivery_id: u64,
+) -> Pubkey {
+    let authority = ctx.payer.pubkey();
+    let now = ctx.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp;
+    send(
+        ctx,
+        &[
+            register_vehicle_ix(authority, vehicle_id, operator.pubkey(), VehicleClass::Road),
+            create_delivery_order_ix(authority, customer.pubkey(), delivery_id, SOL / 10, REGION, now),
+        ],
+        &[customer],
+    )
+    .await
+    .unwrap();
+
+    let mint = inject_mint(ctx);
+    let delivery_key = delivery_pda(&customer.pubkey(), delivery_id);
+    let mut delivery: Delivery = fetch(ctx, delivery_key).await;
+    delivery.confidential = true;
+    delivery.payment_mint = mint;
+    delivery.payment_amount = 0;
+    delivery.status = DeliveryStatus::InProgress;
+    delivery.assigned_vehicle = Some(vehicle_pda(vehicle_id));
+    delivery.delivered_at = Some(now);
+    store(ctx, delivery_key, &delivery).await;
+    mint
+}
+
+fn open_dispute_ix(customer: Pubkey, delivery_id: u64, vehicle_id: &str, party: Pubkey) -> Instruction {
+    let delivery = delivery_pda(&customer, delivery_id);
+    Instruction {
+        program_id: autonomous_vehicle_payments::ID,
+        accounts: accounts::OpenDispute {
+            delivery,
+            dispute: dispute_pda(&delivery),
+            vehicle: vehicle_pda(vehicle_id),
+            customer_profile: customer_profile_pda(&customer),
+            party,
+            system_program: anchor_lang::system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::OpenDispute { delivery_id, reason_hash: [7; 32] }.data(),
+    }
+}
+
+fn resolve_dispute_confidential_ix(
+    customer: Pubkey,
+    delivery_id: u64,
+    vehicle_id: &str,
+    mint: Pubkey,
+    opener: Pubkey,
+    resolver: Pubkey,
+    legs: Vec<ConfidentialLeg>,
+    now: i64,
+) -> Instruction {
+    let delivery = delivery_pda(&customer, delivery_id);
+    let config = config_pda();
+    Instruction {
+        program_id: autonomous_vehicle_payments::ID,
+        accounts: accounts::ResolveDisputeConfidential {
+            delivery,
+            escrow_token: escrow_token_pda(&customer, delivery_id),
+            mint,
+            zero_balance_proof: Pubkey::new_unique(),
+            dispute: dispute_pda(&delivery),
+            dispute_opener: opener,
+            customer_profile: customer_profile_pda(&customer),
+            vehicle: vehicle_pda(vehicle_id),
+            operator_profile: None,
+            geo_bucket: None,
+            fleet: None,
+            stake: None,
+            customer,
+            config,
+            treasury: treasury_pda(&config),
+            role: None,
+            resolver,
+            token_program: spl_token_2022::ID,
+            daily_stats: daily_stats_pda(now),
+            global_stats: global_stats_pda(delivery_id),
+            system_program: anchor_lang::system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::ResolveDisputeConfidential { delivery_id, customer_share_bps: 0, legs }.data(),
+    }
+}
+
+fn leg(payee: ConfidentialPayee) -> ConfidentialLeg {
+    ConfidentialLeg { payee, escrow_after: [0; 36] }
+}
+
+#[tokio::test]
+async fn timed_out_confidential_dispute_still_owes_the_platform_fee() {
+    let mut ctx = program_test().start_with_context().await;
+    bootstrap(&mut ctx, 250, REGION, 150_000_000).await;
+    let customer = funded_keypair(&mut ctx, 2 * SOL).await;
+    let operator = funded_keypair(&mut ctx, SOL).await;
+    let stranger = funded_keypair(&mut ctx, SOL).await;
+    let mint = delivered_confidential_order(&mut ctx, &customer, &operator, "AV-CONF", 9).await;
+
+    // The operator disputes its own delivered order
+    send(&mut ctx, &[open_dispute_ix(customer.pubkey(), 9, "AV-CONF", operator.pubkey())], &[&operator])
+        .await
+        .unwrap();
+    let resolve = |legs, at| {
+        resolve_dispute_confidential_ix(
+            customer.pubkey(),
+            9,
+            "AV-CONF",
+            mint,
+            operator.pubkey(),
+            stranger.pubkey(),
+            legs,
+            at,
+        )
+    };
+    let operator_only = vec![leg(ConfidentialPayee::Operator)];
+    let at = now(&mut ctx).await;
+    assert_error(
+        send(&mut ctx, &[resolve(operator_only.clone(), at)], &[&stranger]).await,
+        ErrorCode::ArbiterTimeoutPending,
+    );
+
+    // Past the timeout the delivered order is ruled for the operator, which
+    // still settles like a completion: the treasury's fee leg is required
+    // and has to come last
+    warp_seconds(&mut ctx, DISPUTE_TIMEOUT_SECONDS + 1).await;
+    let at = now(&mut ctx).await;
+    assert_error(
+        send(&mut ctx, &[resolve(operator_only, at)], &[&stranger]).await,
+        ErrorCode::InvalidConfidentialLeg,
+    );
+    assert_error(
+        send(
+            &mut ctx,
+            &[resolve(vec![leg(ConfidentialPayee::Treasury), leg(ConfidentialPayee::Operator)], at)],
+            &[&stranger],
+        )
+        .await,
+        ErrorCode::InvalidConfidentialLeg,
+    );
+    // Nothing may be refunded to the customer either
+    assert_error(
+        send(
+            &mut ctx,
+            &[resolve(vec![
+                leg(ConfidentialPayee::Operator),
+                leg(ConfidentialPayee::Customer),
+                leg(ConfidentialPayee::Treasury),
+            ], at)],
+            &[&stranger],
+        )
+        .await,
+        ErrorCode::InvalidConfidentialLeg,
+    );
+}
+
+// Books an order and starts a recovery sweep of each target, then waits out
+// the timelock
+async fn recovery_ready(ctx: &mut ProgramTestContext, customer: &Keypair, delivery_id: u64, targets: &[Pubkey]) -> Pubkey {
+    let authority = ctx.payer.pubkey();
+    let now = ctx.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp;
+    send(
+        ctx,
+        &[create_delivery_order_ix(authority, customer.pubkey(), delivery_id, SOL / 10, REGION, now)],
+        &[customer],
+    )
+    .await
+    .unwrap();
+    let recovery_address = Pubkey::new_unique();
+    let initiations: Vec<Instruction> =
+        targets.iter().map(|target| initiate_recovery_ix(authority, *target, recovery_address)).collect();
+    send(ctx, &initiations, &[]).await.unwrap();
+    warp_seconds(ctx, RECOVERY_TIMELOCK_SECONDS + 1).await;
+    recovery_address
+}
+
+#[tokio::test]
+async fn recovery_sweeps_an_escrow_only_once_its_order_is_finished() {
+    let mut ctx = program_test().start_with_context().await;
+    bootstrap(&mut ctx, 250, REGION, 150_000_000).await;
+    let authority = ctx.payer.pubkey();
+    let customer = funded_keypair(&mut ctx, 2 * SOL).await;
+    let operator = funded_keypair(&mut ctx, SOL).await;
+    send(&mut ctx, &[register_vehicle_ix(authority, "AV-REC", operator.pubkey(), VehicleClass::Road)], &[])
+        .await
+        .unwrap();
+
+    let delivery_key = delivery_pda(&customer.pubkey(), 1);
+    let escrow = escrow_pda(&customer.pubkey(), 1);
+    let vehicle = vehicle_pda("AV-REC");
+    let recovery_address = recovery_ready(&mut ctx, &customer, 1, &[escrow, vehicle]).await;
+    let execute = |target, delivery| execute_recovery_ix(authority, target, recovery_address, delivery, None);
+
+    // Live state is never swept, whatever order is passed alongside it
+    assert_error(send(&mut ctx, &[execute(vehicle, Some(delivery_key))], &[]).await, ErrorCode::RecoveryTargetProtected);
+    // Nor is the escrow of an order that can still settle
+    assert_error(send(&mut ctx, &[execute(escrow, Some(delivery_key))], &[]).await, ErrorCode::RecoveryTargetProtected);
+    assert_error(send(&mut ctx, &[execute(escrow, None)], &[]).await, ErrorCode::RecoveryTargetProtected);
+
+    // An escrow left funded behind a cancelled order is stranded
+    let mut delivery: Delivery = fetch(&mut ctx, delivery_key).await;
+    delivery.status = DeliveryStatus::Cancelled;
+    store(&mut ctx, delivery_key, &delivery).await;
+    let stranded = ctx.banks_client.get_balance(escrow).await.unwrap();
+    send(&mut ctx, &[execute(escrow, Some(delivery_key))], &[]).await.unwrap();
+    assert_eq!(ctx.banks_client.get_balance(recovery_address).await.unwrap(), stranded);
+    assert_eq!(ctx.banks_client.get_balance(escrow).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn recovery_drains_a_stranded_token_escrow_through_the_token_program() {
+    let mut ctx = program_test().start_with_context().await;
+    bootstrap(&mut ctx, 250, REGION, 150_000_000).await;
+    let authority = ctx.payer.pubkey();
+    let customer = funded_keypair(&mut ctx, 2 * SOL).await;
+
+    let mint = inject_mint(&mut ctx);
+    let vault = escrow_token_pda(&customer.pubkey(), 2);
+    inject_token_account(&mut ctx, vault, mint, vault, 500);
+    let recovery_address = recovery_ready(&mut ctx, &customer, 2, &[vault]).await;
+    let recovery_token = Pubkey::new_unique();
+    inject_token_account(&mut ctx, recovery_token, mint, recovery_address, 0);
+
+    let delivery_key = delivery_pda(&customer.pubkey(), 2);
+    let execute = |delivery_id| {
+        execute_recovery_ix(
+            authority,
+            vault,
+            recovery_address,
+            Some(delivery_key),
+            Some((
+                RecoveryVault::OrderEscrow { customer: customer.pubkey(), delivery_id },
+                mint,
+                recovery_token,
+                spl_token_2022::ID,
+            )),
+        )
+    };
+
+    // The order is still pending, and seeds of another order do not match
+    assert_error(send(&mut ctx, &[execute(2)], &[]).await, ErrorCode::RecoveryTargetProtected);
+    assert_error(send(&mut ctx, &[execute(3)], &[]).await, ErrorCode::RecoveryTargetProtected);
+
+    let mut delivery: Delivery = fetch(&mut ctx, delivery_key).await;
+    delivery.status = DeliveryStatus::Completed;
+    store(&mut ctx, delivery_key, &delivery).await;
+    send(&mut ctx, &[execute(2)], &[]).await.unwrap();
+    assert_eq!(token_balance(&mut ctx, recovery_token).await, 500);
+    assert!(ctx.banks_client.get_account(vault).await.unwrap().is_none());
+}
+
+const SOL_USD_FEED: [u8; 32] = [9; 32];
+
+// A verified Pyth SOL/USD update at `dollars`, with a confidence interval of
+// `conf_cents`, published at `publish_time`
+fn inject_pyth_price(ctx: &mut ProgramTestContext, address: Pubkey, dollars: i64, conf_cents: u64, publish_time: i64) {
+    let update = PriceUpdateV2 {
+        write_authority: Pubkey::new_unique(),
+        verification_level: VerificationLevel::Full,
+        price_message: PriceFeedMessage {
+            feed_id: SOL_USD_FEED,
+            price: dollars * 100_000_000,
+            conf: conf_cents * 1_000_000,
+            exponent: -8,
+            publish_time,
+            prev_publish_time: publish_time,
+            ema_price: dollars * 100_000_000,
+            ema_conf: conf_cents * 1_000_000,
+        },
+        posted_slot: 0,
+    };
+    let mut data = Vec::new();
+    update.try_serialize(&mut data).unwrap();
+    let account = solana_sdk::account::Account {
+        lamports: SOL / 100,
+        data,
+        owner: pyth_solana_receiver_sdk::ID,
+        executable: false,
+        rent_epoch: 0,
+    };
+    ctx.set_account(&address, &account.into());
+}
+
+async fn now(ctx: &mut ProgramTestContext) -> i64 {
+    ctx.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+}
+
+#[tokio::test]
+async fn usd_orders_are_priced_and_settled_against_a_sound_pyth_price() {
+    let mut ctx = program_test().start_with_context().await;
+    bootstrap(&mut ctx, 250, REGION, 150_000_000).await;
+    let authority = ctx.payer.pubkey();
+    let customer = funded_keypair(&mut ctx, 2 * SOL).await;
+    let operator = funded_keypair(&mut ctx, SOL).await;
+    send(
+        &mut ctx,
+        &[
+            register_vehicle_ix(authority, "AV-USD", operator.pubkey(), VehicleClass::Road),
+            initialize_operator_profile_ix(operator.pubkey()),
+        ],
+        &[&operator],
+    )
+    .await
+    .unwrap();
+    let pyth = Pubkey::new_unique();
+    let t = now(&mut ctx).await;
+    inject_pyth_price(&mut ctx, pyth, 140, 10, t);
+    let book = |id, pyth_price, t| create_delivery_order_usd_ix(customer.pubkey(), id, 1250, REGION, t, pyth_price);
+
+    // No USD orders until the authority names the feed
+    assert_error(send(&mut ctx, &[book(1, Some(pyth), t)], &[&customer]).await, ErrorCode::PythFeedNotConfigured);
+    send(&mut ctx, &[set_pyth_feed_ix(authority, pyth, SOL_USD_FEED)], &[]).await.unwrap();
+    assert_error(send(&mut ctx, &[book(1, None, t)], &[&customer]).await, ErrorCode::PythFeedRequired);
+    let other = Pubkey::new_unique();
+    inject_pyth_price(&mut ctx, other, 1, 0, t);
+    assert_error(send(&mut ctx, &[book(1, Some(other), t)], &[&customer]).await, ErrorCode::PythFeedMismatch);
+
+    // $12.50 at $140 a SOL
+    send(&mut ctx, &[book(1, Some(pyth), t)], &[&customer]).await.unwrap();
+    let delivery_key = delivery_pda(&customer.pubkey(), 1);
+    let mut delivery: Delivery = fetch(&mut ctx, delivery_key).await;
+    assert_eq!(delivery.usd_cents, 1250);
+    assert_eq!(delivery.payment_amount, 12_500_000 * SOL / 140_000_000);
+
+    // A confidence interval over 1% of the price is refused
+    inject_pyth_price(&mut ctx, pyth, 140, 200, t);
+    assert_error(send(&mut ctx, &[book(2, Some(pyth), t)], &[&customer]).await, ErrorCode::PythConfidenceTooWide);
+
+    // Delivered and confirmed, but the price has gone stale by settlement
+    inject_pyth_price(&mut ctx, pyth, 140, 10, t);
+    delivery.status = DeliveryStatus::InProgress;
+    delivery.assigned_vehicle = Some(vehicle_pda("AV-USD"));
+    delivery.accepted_at = Some(t);
+    delivery.delivered_at = Some(t);
+    delivery.confirmed_at = Some(t);
+    store(&mut ctx, delivery_key, &delivery).await;
+    warp_seconds(&mut ctx, PYTH_MAX_AGE_SECONDS as i64 + 1).await;
+    let t = now(&mut ctx).await;
+    let complete = |pyth_price| complete_delivery_ix(operator.pubkey(), customer.pubkey(), 1, "AV-USD", REGION, t, pyth_price);
+    assert_error(send(&mut ctx, &[complete(Some(pyth))], &[&operator]).await, ErrorCode::PythPriceStale);
+    assert_error(send(&mut ctx, &[complete(None)], &[&operator]).await, ErrorCode::PythFeedRequired);
+
+    inject_pyth_price(&mut ctx, pyth, 150, 10, t);
+    send(&mut ctx, &[complete(Some(pyth))], &[&operator]).await.unwrap();
+    let delivery: Delivery = fetch(&mut ctx, delivery_key).await;
+    assert!(delivery.status == DeliveryStatus::Completed);
+}
+
+#[tokio::test]
+async fn stale_hazard_readings_add_no_surcharge() {
+    let mut ctx = program_test().start_with_context().await;
+    bootstrap(&mut ctx, 250, REGION, 150_000_000).await;
+    let authority = ctx.payer.pubkey();
+    let customer = funded_keypair(&mut ctx, 2 * SOL).await;
+    let mut config: Config = fetch(&mut ctx, config_pda()).await;
+    config.hazard_surcharge_bps = 500;
+    store(&mut ctx, config_pda(), &config).await;
+
+    // A severe reading the weather oracle has not refreshed in over an hour
+    let t = now(&mut ctx).await;
+    let mut region: Region = fetch(&mut ctx, region_pda(REGION)).await;
+    region.hazard_level = 2;
+    region.hazard_updated_at = t - MAX_REGION_READING_AGE_SECONDS - 1;
+    store(&mut ctx, region_pda(REGION), &region).await;
+    send(&mut ctx, &[create_delivery_order_ix(authority, customer.pubkey(), 1, SOL / 10, REGION, t)], &[&customer])
+        .await
+        .unwrap();
+    let stale: Delivery = fetch(&mut ctx, delivery_pda(&customer.pubkey(), 1)).await;
+    assert_eq!(stale.hazard_surcharge, 0);
+
+    // The same level reported just now is charged at 5% per level
+    region.hazard_updated_at = t;
+    store(&mut ctx, region_pda(REGION), &region).await;
+    send(&mut ctx, &[create_delivery_order_ix(authority, customer.pubkey(), 2, SOL / 10, REGION, t)], &[&customer])
+        .await
+        .unwrap();
+    let fresh: Delivery = fetch(&mut ctx, delivery_pda(&customer.pubkey(), 2)).await;
+    assert_eq!(fresh.hazard_surcharge, SOL / 10 * 1000 / 10000);
+}